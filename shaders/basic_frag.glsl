@@ -1,17 +1,9 @@
-#version 330 core
-#extension GL_ARB_explicit_uniform_location : enable
+#version 450 core
 //#extension GL_EXT_texture_compression_s3tc : enable
 
 out vec4 frag_color;
 
-layout (location = 1) uniform mat4 model_mat = mat4(1);
-layout (location = 2) uniform mat4 view_mat = mat4(1);
-layout (location = 3) uniform mat4 modelview_mat = mat4(1);
-layout (location = 4) uniform mat4 proj_mat = mat4(1);
-layout (location = 5) uniform mat4 mvp_mat = mat4(1);
-layout (location = 6) uniform mat4 normal_mat = mat4(1);
-
-layout (location = 10) uniform vec3 view_pos = vec3(0.0, 0.0, 0.0);
+#include "include/common.glsl"
 
 layout(location = 11) uniform float sun_intensity = 1.0;
 layout(location = 12) uniform float specular_exponent = 2.0;
@@ -20,147 +12,397 @@ layout(location = 13) uniform float specular_intensity = 5.0;
 layout (location = 20) uniform sampler2D diffuse_texture;
 layout (location = 21) uniform sampler2D specular_texture;
 layout (location = 22) uniform sampler2D normal_texture;
+layout (location = 23) uniform sampler2D splat_texture;
+layout (location = 24) uniform sampler2D splat_layer0;
+layout (location = 25) uniform sampler2D splat_layer1;
+layout (location = 26) uniform sampler2D splat_layer2;
+layout (location = 27) uniform sampler2D splat_layer3;
+layout (location = 61) uniform sampler2DArray spot_cookies;
+// Sky cube map reflected by the specular map, see core/pipeline/sky.rs
+layout (location = 62) uniform samplerCube environment_map;
+layout (location = 63) uniform float environment_strength = 0.0;
+#ifdef USE_PBR
+// Occlusion, roughness and metallic like glTF, replaces the specular map
+layout (location = 64) uniform sampler2D material_texture;
+#endif
 
 // layout (location = 6) uniform vec3 sun_dir = vec3(1.0, -1.0, 0.0);
 // uniform vec3 sun_dir = vec3(0.3, 0.3, -0.3);
 
-layout (location = 30) uniform bool use_normalmap = true;
-layout (location = 31) uniform bool use_blinn = true;
+// Normal mapping, Blinn-Phong and shadows are compiled in through
+// USE_NORMALMAP, USE_BLINN and USE_SHADOWS, see core/pipeline/shader_cache.rs
+layout (location = 32) uniform bool use_splatmap = false;
+layout (location = 33) uniform float splat_detail_scale = 32.0;
+
+#ifdef USE_SHADOWS
+layout (location = 28) uniform sampler2DArrayShadow shadow_map;
+layout (location = 29) uniform samplerCubeArrayShadow point_shadow_maps;
+layout (location = 60) uniform sampler2DArrayShadow spot_shadow_maps;
+
+// Cascaded sun shadows, see core/pipeline/shadow.rs
+layout (location = 40) uniform mat4 cascade_view_proj[4];
+// View space distance where each cascade ends
+layout (location = 44) uniform vec4 cascade_splits;
+layout (location = 45) uniform int cascade_count = 0;
+// x constant depth bias, y slope scaled depth bias, z normal offset
+layout (location = 46) uniform vec3 shadow_bias = vec3(0.0005, 0.002, 0.02);
+layout (location = 47) uniform int shadow_pcf_radius = 1;
+#endif
+
+// Lights shading the current object as indices into lights[],
+// see core/pipeline/lighting.rs
+layout (location = 51) uniform int object_light_count = 0;
+layout (location = 52) uniform int object_lights[8];
+
+#define LIGHT_DIRECTIONAL 0
+#define LIGHT_POINT 1
+#define LIGHT_SPOT 2
+
+// Mirrors lighting::GpuLight
+struct Light {
+  vec4 position;  // xyz position, w range
+  vec4 direction; // xyz direction, w intensity
+  vec4 ambient;
+  vec4 diffuse;
+  vec4 specular;
+  vec4 params;    // x cos inner cone, y cos outer cone, z shadow bias
+  ivec4 info;     // x light type, y shadow slot or -1, z cookie layer or -1
+  mat4 projection; // spot lights only, places the shadow map and cookie
+};
+
+layout (std430, binding = 0) readonly buffer LightBuffer {
+  Light lights[];
+};
 
-layout (location = 50) uniform float time;
+// Vertex color tints the diffuse color, white when the mesh has none.
+// Painted ambient occlusion or baked lighting goes into the rgb channels.
+smooth in vec4 vert_color;
+// Second UV set for lightmaps and detail maps
+smooth in vec2 frag_uv2;
+smooth in vec3 frag_world_pos;
+smooth in vec3 frag_world_normal;
+smooth in vec3 vert_normal;
+smooth in vec3 frag_pos;
+smooth in vec2 frag_uv;
+in mat3 world_tbn;
 
-struct DirLight {
-  float intensity;
-  vec3 direction;
-  vec3 ambient;
-  vec3 diffuse;
-  vec3 specular;
+// Splat layers and the normal/specular maps tile across the terrain,
+// the splat map itself covers it once.
+vec2 detail_uv(vec2 uv)
+{
+  return use_splatmap ? uv * splat_detail_scale : uv;
 }
-;
 
-struct PointLight {
-  vec3 position;
-  vec3 ambient;
-  vec3 diffuse;
-  vec3 specular;
-};
+vec3 sample_diffuse(vec2 uv)
+{
+  if(use_splatmap) {
+    vec4 weights = texture(splat_texture, uv);
+    vec2 tiled = detail_uv(uv);
+    vec3 color = texture(splat_layer0, tiled).rgb * weights.r
+               + texture(splat_layer1, tiled).rgb * weights.g
+               + texture(splat_layer2, tiled).rgb * weights.b
+               + texture(splat_layer3, tiled).rgb * weights.a;
+    color = color / max(weights.r + weights.g + weights.b + weights.a, 0.0001);
+    return color * vert_color.rgb;
+  }
 
-uniform DirLight sun = DirLight (
-   2.0,
-   vec3(1.0, -1.0, 0.0),   // Direction
-   vec3(1.0, 1.0, 1.0),   // Ambient
-   vec3(1.0, 1.0, 1.0),   // Diffuse
-   vec3(1.0, 1.0, 1.0)     // Specular
-);
+  return texture(diffuse_texture, uv).rgb * vert_color.rgb;
+}
 
-uniform PointLight lamp = PointLight (
-   vec3 (10.0, 10.0, 10.0),
-   vec3(0.0, 0.0, 0.0),   // Ambient
-   vec3(1.0, 1.0, 1.0),   // Diffuse
-   vec3(0.5, 0.0, 0.5)     // Specular
-);
+#ifdef USE_SHADOWS
+// Fraction of directional light reaching the fragment, 1.0 is fully lit
+float calc_shadow(vec3 light_direction, float view_depth)
+{
+  if(cascade_count == 0) {
+    return 1.0;
+  }
 
+  int cascade = cascade_count - 1;
+  for(int i = 0; i < cascade_count; ++i) {
+    if(view_depth < cascade_splits[i]) {
+      cascade = i;
+      break;
+    }
+  }
 
-vec3 calc_dir_light( DirLight light, vec3 normal, vec2 uv, vec3 frag_pos, vec3 view_pos )
-{
-  vec3 diffuse, specular, ambient;
-  float specular_scalar;
+  if(view_depth > cascade_splits[cascade]) {
+    return 1.0;
+  }
 
-  vec3 view_dir = normalize(view_pos - frag_pos);
-  vec3 light_dir = normalize(-light.direction);
-  float diffuse_scalar = clamp(dot(normal, light_dir), 0.0, 1.0);
+  vec3 normal = normalize(frag_world_normal);
+  vec3 light_dir = normalize(-light_direction);
+  float n_dot_l = clamp(dot(normal, light_dir), 0.0, 1.0);
 
-  if(use_blinn) {
+  // Offsetting the receiver along its normal hides acne on steep surfaces
+  vec3 world_pos = frag_world_pos + normal * shadow_bias.z * (1.0 - n_dot_l);
+  vec4 light_pos = cascade_view_proj[cascade] * vec4(world_pos, 1.0);
+  vec3 coords = light_pos.xyz / light_pos.w * 0.5 + 0.5;
 
-    vec3 halfway_dir = normalize(view_dir + light_dir);
-    specular_scalar = pow(max(dot(normal, halfway_dir), 0.0), specular_exponent);
+  if(coords.z > 1.0) {
+    return 1.0;
+  }
 
-  } else {
+  float bias = shadow_bias.x + shadow_bias.y * (1.0 - n_dot_l);
+  float depth = coords.z - bias;
+  vec2 texel = 1.0 / vec2(textureSize(shadow_map, 0).xy);
 
-    vec3 reflect_dir = normalize(reflect(-light_dir, normal));
-    specular_scalar = pow(clamp(dot(view_dir, reflect_dir), 0.0, 1.0), specular_exponent/4.0);
+  float lit = 0.0;
+  for(int x = -shadow_pcf_radius; x <= shadow_pcf_radius; ++x) {
+    for(int y = -shadow_pcf_radius; y <= shadow_pcf_radius; ++y) {
+      vec2 offset = vec2(x, y) * texel;
+      lit += texture(shadow_map, vec4(coords.xy + offset, float(cascade), depth));
+    }
+  }
+
+  float width = float(shadow_pcf_radius * 2 + 1);
+  return lit / (width * width);
+}
+
+// Cubes store the distance to the light divided by its range
+float calc_point_shadow(vec3 light_pos, int cube, float range, float bias)
+{
+  vec3 to_frag = frag_world_pos - light_pos;
+  float dist = length(to_frag);
 
+  if(dist > range) {
+    return 1.0;
   }
 
-  // vec3 diffuse_color = vec3(0.5,0.0,0.0);
-  vec3 diffuse_color = texture(diffuse_texture, uv).rgb;
+  // Linear filtering of the comparison gives a 2x2 PCF for free
+  return texture(point_shadow_maps, vec4(to_frag, float(cube)), (dist - bias) / range);
+}
 
-  diffuse = diffuse_scalar *  diffuse_color * light.intensity ;
+// Spot shadow maps hold perspective depth, coords are the projected fragment
+float calc_spot_shadow(vec3 coords, int layer, float bias)
+{
+  if(coords.z > 1.0) {
+    return 1.0;
+  }
 
-  specular = specular_scalar * clamp( specular_scalar * light.specular, 0, 1) * vec3(1.0, 1.0, 1.0);//texture(specular_texture, uv).rgb;
-  specular = vec3(0.0, 0.0, 0.0);
+  vec2 texel = 1.0 / vec2(textureSize(spot_shadow_maps, 0).xy);
 
+  float lit = 0.0;
+  for(int x = -shadow_pcf_radius; x <= shadow_pcf_radius; ++x) {
+    for(int y = -shadow_pcf_radius; y <= shadow_pcf_radius; ++y) {
+      vec2 offset = vec2(x, y) * texel;
+      lit += texture(spot_shadow_maps, vec4(coords.xy + offset, float(layer), coords.z - bias));
+    }
+  }
 
-  // ambient = light.ambient * 0.1;
-  // ambient = vec3(0.0,0.0,0.0);
-  ambient = light.ambient * texture(diffuse_texture, uv).rgb;
+  float width = float(shadow_pcf_radius * 2 + 1);
+  return lit / (width * width);
+}
+#endif
 
-  return diffuse + specular + ambient;
+// Smoothly reaches zero at the range of the light
+float range_attenuation(float dist, float range)
+{
+  float ratio = dist / range;
+  float window = clamp(1.0 - ratio * ratio * ratio * ratio, 0.0, 1.0);
+  return window * window / (dist * dist + 1.0);
 }
 
-vec3 calc_point_light(PointLight light, vec3 normal, vec2 uv, vec3 frag_pos, vec3 view_pos )
+// Where the light comes from and how much of it arrives
+struct LightSample {
+  vec3 dir;
+  float attenuation;
+  // Shadow times cookie
+  vec3 visibility;
+};
+
+LightSample sample_light(Light light)
 {
-  vec3 diffuse, specular, ambient;
-  vec3 light_dir =  normalize((light.position - frag_pos));
-  vec3 view_dir = normalize(view_pos - frag_pos);
+  int type = light.info.x;
+  int shadow_slot = light.info.y;
+  vec3 light_dir;
+  float attenuation = 1.0;
+  float shadow = 1.0;
+  vec3 cookie = vec3(1.0);
+
+  if(type == LIGHT_DIRECTIONAL) {
+    light_dir = normalize(-light.direction.xyz);
+#ifdef USE_SHADOWS
+    if(shadow_slot >= 0) {
+      shadow = calc_shadow(light.direction.xyz, -frag_pos.z);
+    }
+#endif
+  } else {
+    vec3 to_light = light.position.xyz - frag_world_pos;
+    float dist = length(to_light);
+    light_dir = to_light / max(dist, 0.0001);
+    attenuation = range_attenuation(dist, light.position.w);
+
+    if(type == LIGHT_SPOT) {
+      float cos_angle = dot(-light_dir, normalize(light.direction.xyz));
+      attenuation *= smoothstep(light.params.y, light.params.x, cos_angle);
+
+      if(light.info.z >= 0) {
+        vec4 projected = light.projection * vec4(frag_world_pos, 1.0);
+        vec2 cookie_uv = projected.xy / projected.w * 0.5 + 0.5;
+        cookie = texture(spot_cookies, vec3(cookie_uv, float(light.info.z))).rgb;
+      }
+
+#ifdef USE_SHADOWS
+      if(shadow_slot >= 0) {
+        vec3 normal_offset = normalize(frag_world_normal) * shadow_bias.z;
+        vec4 projected = light.projection * vec4(frag_world_pos + normal_offset, 1.0);
+        vec3 coords = projected.xyz / projected.w * 0.5 + 0.5;
+        shadow = calc_spot_shadow(coords, shadow_slot, light.params.z);
+      }
+#endif
+    }
+
+#ifdef USE_SHADOWS
+    if(type == LIGHT_POINT && shadow_slot >= 0) {
+      shadow = calc_point_shadow(light.position.xyz, shadow_slot, light.position.w, light.params.z);
+    }
+#endif
+  }
+
+  return LightSample(light_dir, attenuation, shadow * cookie);
+}
+
+vec3 calc_light(Light light, vec3 normal, vec3 view_dir, vec3 diffuse_color, vec3 specular_color)
+{
+  LightSample s = sample_light(light);
+  vec3 light_dir = s.dir;
+
   float diffuse_scalar = clamp(dot(normal, light_dir), 0.0, 1.0);
   float specular_scalar;
 
-  if(use_blinn) {
-    vec3 halfway_dir = normalize(light_dir + view_dir);
-    specular_scalar = pow(max(dot(normal, halfway_dir), 0.0), specular_exponent);
-  } else {
-    vec3 reflect_dir = normalize( reflect(-light_dir, normal) );
-    specular_scalar = pow(max(dot(view_dir, reflect_dir), 0.0), specular_exponent/4.0);
-  }
+#ifdef USE_BLINN
+  vec3 halfway_dir = normalize(light_dir + view_dir);
+  specular_scalar = pow(max(dot(normal, halfway_dir), 0.0), specular_exponent);
+#else
+  vec3 reflect_dir = normalize(reflect(-light_dir, normal));
+  specular_scalar = pow(max(dot(view_dir, reflect_dir), 0.0), specular_exponent/4.0);
+#endif
 
-  diffuse = diffuse_scalar * light.diffuse * texture(diffuse_texture, uv).rgb;
-  specular = specular_scalar * clamp( specular_scalar * light.specular, 0, 1) * texture(specular_texture, uv).rgb ;
-  ambient = light.ambient * texture(diffuse_texture, uv).rgb;
+  float intensity = light.direction.w;
+  vec3 diffuse = diffuse_scalar * light.diffuse.rgb * diffuse_color;
+  vec3 specular = specular_scalar * clamp(specular_scalar * light.specular.rgb, 0, 1) * specular_color;
+  vec3 ambient = light.ambient.rgb * diffuse_color;
 
-  return diffuse + specular + ambient;
+  return ((diffuse + specular) * s.visibility * intensity + ambient) * s.attenuation;
 }
 
-smooth in vec3 vert_normal;
-smooth in vec3  frag_pos;
-smooth in vec2 frag_uv;
-in mat3 tbn_mat;
-in vec3 lamp_pos_tan_space;
-in vec3 sun_dir_tan_space;
-in vec3 frag_pos_tan_space;
-in vec3 view_pos_tan_space;
-in vec3 light_pos_tan_space;
+#ifdef USE_PBR
+#define PI 3.14159265359
+
+// Trowbridge-Reitz GGX normal distribution
+float distribution_ggx(float n_dot_h, float roughness)
+{
+  float a = roughness * roughness;
+  float a2 = a * a;
+  float d = n_dot_h * n_dot_h * (a2 - 1.0) + 1.0;
+  return a2 / (PI * d * d);
+}
+
+// Smith masking and shadowing with the Schlick-GGX approximation
+float geometry_smith(float n_dot_v, float n_dot_l, float roughness)
+{
+  float r = roughness + 1.0;
+  float k = r * r / 8.0;
+  float g_view = n_dot_v / (n_dot_v * (1.0 - k) + k);
+  float g_light = n_dot_l / (n_dot_l * (1.0 - k) + k);
+  return g_view * g_light;
+}
+
+vec3 fresnel_schlick(float cos_theta, vec3 f0)
+{
+  return f0 + (1.0 - f0) * pow(clamp(1.0 - cos_theta, 0.0, 1.0), 5.0);
+}
+
+// Rough surfaces reflect less of the environment at grazing angles
+vec3 fresnel_schlick_roughness(float cos_theta, vec3 f0, float roughness)
+{
+  return f0 + (max(vec3(1.0 - roughness), f0) - f0) * pow(clamp(1.0 - cos_theta, 0.0, 1.0), 5.0);
+}
+
+// Cook-Torrance BRDF with a Lambert diffuse lobe. The diffuse color of the
+// light times its intensity is the radiance arriving at 1 unit, dividing
+// the diffuse lobe by PI keeps the reflected energy below the incoming one.
+vec3 calc_light_pbr(Light light, vec3 normal, vec3 view_dir, vec3 albedo,
+                    float metallic, float roughness, float ao)
+{
+  LightSample s = sample_light(light);
+  vec3 halfway_dir = normalize(s.dir + view_dir);
+
+  float n_dot_l = max(dot(normal, s.dir), 0.0);
+  float n_dot_v = max(dot(normal, view_dir), 0.0001);
+  float n_dot_h = max(dot(normal, halfway_dir), 0.0);
+
+  vec3 f0 = mix(vec3(0.04), albedo, metallic);
+  vec3 fresnel = fresnel_schlick(max(dot(halfway_dir, view_dir), 0.0), f0);
+  float ndf = distribution_ggx(n_dot_h, roughness);
+  float geometry = geometry_smith(n_dot_v, n_dot_l, roughness);
+
+  vec3 specular = ndf * geometry * fresnel / max(4.0 * n_dot_v * n_dot_l, 0.0001);
+  // Metals have no diffuse lobe, what the surface reflects is not diffused
+  vec3 kd = (1.0 - fresnel) * (1.0 - metallic);
+
+  vec3 radiance = light.diffuse.rgb * light.direction.w * s.attenuation * s.visibility;
+  vec3 ambient = light.ambient.rgb * albedo * ao * s.attenuation;
+
+  return (kd * albedo / PI + specular) * radiance * n_dot_l + ambient;
+}
+
+// Specular and diffuse light from the environment cube map, blurrier mip
+// levels stand in for rougher surfaces
+vec3 calc_environment_pbr(vec3 normal, vec3 view_dir, vec3 albedo,
+                          float metallic, float roughness, float ao)
+{
+  float n_dot_v = max(dot(normal, view_dir), 0.0);
+  vec3 f0 = mix(vec3(0.04), albedo, metallic);
+  vec3 fresnel = fresnel_schlick_roughness(n_dot_v, f0, roughness);
+  vec3 kd = (1.0 - fresnel) * (1.0 - metallic);
+
+  float max_lod = float(textureQueryLevels(environment_map) - 1);
+  vec3 reflected = textureLod(environment_map, reflect(-view_dir, normal), roughness * max_lod).rgb;
+  vec3 irradiance = textureLod(environment_map, normal, max_lod).rgb;
+
+  return (kd * irradiance * albedo + fresnel * reflected) * ao * environment_strength;
+}
+#endif
 
 void main ()
 {
-  vec3 color;
-  vec3 frag_normal = vec3(vert_normal);
-  vec3 simple_normal = vec3(vert_normal);
-  // vec3 view_dir = normalize( vec3(0,0,0) - frag_pos.xyz );
-  vec3 fp, vp;
-
-  DirLight dir_light = sun;
-  PointLight lamp_light = lamp;
-
-  // if ( dot(simple_normal,  mat3(view_mat) * -dir_light.direction) > 0.0 ) {
-    if(use_normalmap) {
-
-      frag_normal = texture(normal_texture, frag_uv).rgb;
-      frag_normal = ( frag_normal * 2.0 - 1.0 );
-      frag_normal = normalize( frag_normal);
-      dir_light.direction = sun_dir_tan_space;
-      lamp_light.position = lamp_pos_tan_space;
-      vp = view_pos_tan_space;
-      fp = frag_pos_tan_space;
-    }
+  vec3 normal = normalize(vert_normal);
+
+#ifdef USE_NORMALMAP
+  vec3 mapped = texture(normal_texture, detail_uv(frag_uv)).rgb * 2.0 - 1.0;
+  normal = normalize(world_tbn * mapped);
+#endif
+
+  vec3 view_dir = normalize(view_pos - frag_world_pos);
+  vec3 diffuse_color = sample_diffuse(frag_uv);
+  vec3 color = vec3(0.0);
+
+#ifdef USE_PBR
+  vec3 orm = texture(material_texture, detail_uv(frag_uv)).rgb;
+  float ao = orm.r;
+  // Fully smooth surfaces turn the GGX highlight into a singularity
+  float roughness = clamp(orm.g, 0.04, 1.0);
+  float metallic = orm.b;
+
+  for(int i = 0; i < object_light_count; ++i) {
+    color += calc_light_pbr(lights[object_lights[i]], normal, view_dir, diffuse_color,
+                            metallic, roughness, ao);
+  }
+
+  color += calc_environment_pbr(normal, view_dir, diffuse_color, metallic, roughness, ao);
+#else
+  vec3 specular_color = texture(specular_texture, detail_uv(frag_uv)).rgb;
+
+  for(int i = 0; i < object_light_count; ++i) {
+    color += calc_light(lights[object_lights[i]], normal, view_dir, diffuse_color, specular_color);
+  }
 
-    color = calc_dir_light(dir_light, frag_normal, frag_uv, fp, vp);
-    color = calc_point_light(
-        lamp_light,
-        frag_normal,
-        frag_uv,
-        fp, vp
-    );
+  vec3 reflected = texture(environment_map, reflect(-view_dir, normal)).rgb;
+  color += reflected * specular_color * environment_strength;
+#endif
 
-  frag_color = vec4(color, 1.0);
+  // Only used by translucent draws, opaque ones are drawn without blending
+  float alpha = use_splatmap ? vert_color.a : texture(diffuse_texture, frag_uv).a * vert_color.a;
+  frag_color = vec4(color, alpha);
 }
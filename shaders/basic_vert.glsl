@@ -1,87 +1,75 @@
-#version 330 core
-#extension GL_ARB_explicit_uniform_location : enable
+#version 450 core
 
 layout (location = 0) in vec3 position;
 layout (location = 1) in vec3 normal;
 layout (location = 2) in vec2 uv;
 layout (location = 3) in vec3 tangent;
 layout (location = 4) in vec3 bitangent;
+layout (location = 5) in vec4 color;
+layout (location = 6) in vec2 uv2;
 
-layout (location = 1) uniform mat4 model_mat = mat4(1);
-layout (location = 2) uniform mat4 view_mat = mat4(1);
-layout (location = 3) uniform mat4 modelview_mat = mat4(1);
-layout (location = 4) uniform mat4 proj_mat = mat4(1);
-layout (location = 5) uniform mat4 mvp_mat = mat4(1);
-layout (location = 6) uniform mat4 normal_mat = mat4(1);
+// Per instance attributes, see core/pipeline/instancing.rs
+layout (location = 7) in mat4 instance_model;
+layout (location = 11) in mat3 instance_normal;
+layout (location = 14) in vec4 instance_tint;
 
-layout (location = 10) uniform vec3 view_pos;
-
-layout (location = 50) uniform float time;
-
-layout (location = 9) uniform vec4 sun_dir;
-layout (location = 30) uniform bool use_normalmap = true;
-
-struct DirLight {
-    float intensity;
-    vec3 direction;
-    vec3 ambient;
-    vec3 diffuse;
-    vec3 specular;
-};
-
-struct PointLight {
-    vec3 position;
-    vec3 ambient;
-    vec3 diffuse;
-    vec3 specular;
-};
-
-uniform DirLight sun;
-uniform PointLight lamp;
+#include "include/common.glsl"
+#include "include/vertex_decode.glsl"
 
 smooth out vec3 vert_normal;
 smooth out vec3 frag_pos;
 smooth out vec2 frag_uv;
-out mat3 tbn_mat;
-out vec3 frag_pos_tan_space;
-out vec3 view_pos_tan_space;
-out vec3 lamp_pos_tan_space;
-out vec3 sun_dir_tan_space;
+smooth out vec2 frag_uv2;
+smooth out vec4 vert_color;
+// World space position and normal for shadow map lookups
+smooth out vec3 frag_world_pos;
+smooth out vec3 frag_world_normal;
+// Tangent space to world space, lighting happens in world space
+out mat3 world_tbn;
 
 void main() {
 
-    mat4 mv = view_mat * model_mat;
-    vert_normal = vec3(normalize(normal_mat * vec4( normal, 0 )));
-    frag_uv     = vec2(uv.x, uv.y);
-    frag_pos    = vec3(mv * vec4(position, 1.0));
-
-    if(use_normalmap) {
-
-        vec3 tangent_viewspace   = normalize(vec3(mv * vec4(tangent, 0 )));
-        vec3 bitangent_viewspace = normalize(vec3(mv * vec4(bitangent, 0 )));
-        vec3 normal_viewspace    = normalize(vec3(mv * vec4(normal, 0)));
-
-        // tbn_mat = mat3(
-        //     1, 0, 0,
-        //     0, 1, 0,
-        //     0, 0, 1
-        //     );
-
-        tbn_mat = transpose(mat3 (tangent_viewspace,
-                                  bitangent_viewspace,
-                                  normal_viewspace));
+    vec3 in_position  = position;
+    vec3 in_normal    = normal;
+    vec2 in_uv        = uv;
+    vec3 in_tangent   = tangent;
+    vec3 in_bitangent = bitangent;
+    vec2 in_uv2       = uv2;
+
+    if(use_compressed_vertices) {
+        in_position  = decode_position(in_position);
+        in_normal    = oct_decode(in_normal.xy);
+        // Tangent z carries the handedness of the bitangent
+        in_bitangent = cross(in_normal, oct_decode(in_tangent.xy)) * in_tangent.z;
+        in_tangent   = oct_decode(in_tangent.xy);
+        in_uv        = uv_bounds.xy + in_uv * uv_bounds.zw;
+        in_uv2       = uv_bounds.xy + in_uv2 * uv_bounds.zw;
+    }
 
-        frag_pos_tan_space  = tbn_mat * vec3(model_mat * vec4(position.xyz, 1));
-        lamp_pos_tan_space  = tbn_mat * lamp.position;
-        sun_dir_tan_space   = normalize(tbn_mat * sun.direction);
-        view_pos_tan_space  = tbn_mat * view_pos;
+    mat4 model = model_mat;
+    mat4 normal_model = normal_mat;
+    vert_color = color;
 
-        // frag_pos_tan_space  = tbn_mat * vec3(modelview_mat * vec4( position, 1.0));
-        // lamp_pos_tan_space  = tbn_mat * vec3(view_mat * vec4( lamp.position, 1.0 ) );
-        // sun_dir_tan_space   = normalize(tbn_mat * vec3(view_mat * vec4( sun.direction, 0.0 ) ));
-        // view_pos_tan_space  = tbn_mat * vec3( view_mat *  view_pos);
+    if(use_instancing) {
+        model = instance_model;
+        normal_model = mat4(instance_normal);
+        vert_color *= instance_tint;
     }
 
-    mat4 mvp = (proj_mat * view_mat * model_mat);
-    gl_Position = mvp * vec4(position, 1.0);
+    mat4 mv = view_mat * model;
+    vert_normal = vec3(normalize(normal_model * vec4( in_normal, 0 )));
+    frag_uv     = vec2(in_uv.x, in_uv.y);
+    frag_uv2    = in_uv2;
+    frag_pos    = vec3(mv * vec4(in_position, 1.0));
+    frag_world_pos    = vec3(model * vec4(in_position, 1.0));
+    frag_world_normal = vert_normal;
+
+#ifdef USE_NORMALMAP
+    vec3 world_tangent   = normalize(vec3(model * vec4(in_tangent, 0)));
+    vec3 world_bitangent = normalize(vec3(model * vec4(in_bitangent, 0)));
+    world_tbn = mat3(world_tangent, world_bitangent, vert_normal);
+#endif
+
+    mat4 mvp = (proj_mat * view_mat * model);
+    gl_Position = mvp * vec4(in_position, 1.0);
 }
@@ -0,0 +1,40 @@
+#version 450 core
+
+// One step of the bloom chain, see core/pipeline/post.rs
+smooth in vec2 uv;
+out vec4 frag_color;
+
+layout (location = 0) uniform sampler2D source;
+layout (location = 1) uniform vec2 source_texel;
+// Only the first step keeps the bright parts of the scene
+layout (location = 2) uniform bool apply_threshold = false;
+// x threshold, y soft knee
+layout (location = 3) uniform vec2 threshold = vec2(1.0, 0.5);
+
+// Soft knee threshold, keeps the transition around the threshold smooth
+vec3 bright_pass(vec3 color)
+{
+  float brightness = max(color.r, max(color.g, color.b));
+  float knee = threshold.x * threshold.y;
+  float soft = clamp(brightness - threshold.x + knee, 0.0, 2.0 * knee);
+  soft = soft * soft / (4.0 * knee + 0.00001);
+  float contribution = max(soft, brightness - threshold.x) / max(brightness, 0.00001);
+  return color * contribution;
+}
+
+void main()
+{
+  // 4 bilinear taps cover a 4x4 texel box of the source
+  vec2 d = source_texel;
+  vec3 color = texture(source, uv + vec2(-d.x, -d.y)).rgb
+             + texture(source, uv + vec2( d.x, -d.y)).rgb
+             + texture(source, uv + vec2(-d.x,  d.y)).rgb
+             + texture(source, uv + vec2( d.x,  d.y)).rgb;
+  color *= 0.25;
+
+  if(apply_threshold) {
+    color = bright_pass(color);
+  }
+
+  frag_color = vec4(color, 1.0);
+}
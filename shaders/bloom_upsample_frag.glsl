@@ -0,0 +1,25 @@
+#version 450 core
+
+// Added on top of the next larger level of the bloom chain
+smooth in vec2 uv;
+out vec4 frag_color;
+
+layout (location = 0) uniform sampler2D source;
+layout (location = 1) uniform vec2 source_texel;
+
+void main()
+{
+  // 3x3 tent filter
+  vec2 d = source_texel;
+  vec3 color = texture(source, uv).rgb * 4.0;
+  color += (texture(source, uv + vec2(-d.x, 0.0)).rgb
+          + texture(source, uv + vec2( d.x, 0.0)).rgb
+          + texture(source, uv + vec2(0.0, -d.y)).rgb
+          + texture(source, uv + vec2(0.0,  d.y)).rgb) * 2.0;
+  color += texture(source, uv + vec2(-d.x, -d.y)).rgb
+         + texture(source, uv + vec2( d.x, -d.y)).rgb
+         + texture(source, uv + vec2(-d.x,  d.y)).rgb
+         + texture(source, uv + vec2( d.x,  d.y)).rgb;
+
+  frag_color = vec4(color / 16.0, 1.0);
+}
@@ -0,0 +1,52 @@
+#version 450 core
+
+// Averages the histogram of luminance_histogram_comp.glsl, moves the
+// adapted luminance towards it and clears the histogram for the next frame
+layout (local_size_x = 256) in;
+
+layout (location = 11) uniform float min_log_lum = -8.0;
+layout (location = 12) uniform float log_lum_range = 12.0;
+layout (location = 13) uniform float pixel_count;
+// Fraction of the way to the new average covered this frame
+layout (location = 14) uniform float adaptation = 1.0;
+
+layout (std430, binding = 2) buffer Histogram {
+  uint bins[256];
+};
+
+layout (std430, binding = 3) buffer Exposure {
+  float adapted_lum;
+};
+
+shared float weighted[256];
+
+void main()
+{
+  uint i = gl_LocalInvocationIndex;
+  uint count = bins[i];
+  weighted[i] = float(count) * float(i);
+  barrier();
+
+  for(uint stride = 128; stride > 0; stride >>= 1) {
+    if(i < stride) {
+      weighted[i] += weighted[i + stride];
+    }
+    barrier();
+  }
+
+  if(i == 0) {
+    // Black pixels in bin 0 don't take part in the average
+    float lit = max(pixel_count - float(count), 1.0);
+    float avg_bin = weighted[0] / lit - 1.0;
+    float log_avg = avg_bin / 254.0 * log_lum_range + min_log_lum;
+    float lum = exp2(log_avg);
+
+    if(isnan(adapted_lum) || adapted_lum <= 0.0) {
+      adapted_lum = lum;
+    } else {
+      adapted_lum += (lum - adapted_lum) * adaptation;
+    }
+  }
+
+  bins[i] = 0;
+}
@@ -0,0 +1,10 @@
+#version 450 core
+
+// Single triangle covering the screen, drawn without vertex buffers
+smooth out vec2 uv;
+
+void main() {
+    vec2 p = vec2((gl_VertexID << 1) & 2, gl_VertexID & 2);
+    uv = p;
+    gl_Position = vec4(p * 2.0 - 1.0, 0.0, 1.0);
+}
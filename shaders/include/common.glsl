@@ -0,0 +1,15 @@
+// Declarations shared by basic_vert.glsl and basic_frag.glsl
+
+layout (location = 1) uniform mat4 model_mat = mat4(1);
+layout (location = 3) uniform mat4 modelview_mat = mat4(1);
+layout (location = 5) uniform mat4 mvp_mat = mat4(1);
+layout (location = 6) uniform mat4 normal_mat = mat4(1);
+
+// Shared by every program, see CameraBlock in core/pipeline/mod.rs
+layout (std140, binding = 1) uniform Camera {
+  mat4 view_mat;
+  mat4 proj_mat;
+  vec3 view_pos;
+};
+
+layout (location = 50) uniform float time;
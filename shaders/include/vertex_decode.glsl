@@ -0,0 +1,26 @@
+// Vertex formats every vertex shader understands
+
+// Compressed meshes store positions and UVs relative to their bounds and
+// normals/tangents octahedral encoded, see mgl/attr/compressed.rs
+layout (location = 34) uniform bool use_compressed_vertices = false;
+layout (location = 35) uniform vec3 position_bounds_min = vec3(0.0);
+layout (location = 36) uniform vec3 position_bounds_extent = vec3(1.0);
+// xy is the minimum, zw the extent
+layout (location = 37) uniform vec4 uv_bounds = vec4(0.0, 0.0, 1.0, 1.0);
+
+// Model and normal matrices come from instance attributes instead of uniforms
+layout (location = 38) uniform bool use_instancing = false;
+
+vec3 decode_position(vec3 p)
+{
+    return use_compressed_vertices ? position_bounds_min + p * position_bounds_extent : p;
+}
+
+vec3 oct_decode(vec2 e)
+{
+    vec3 n = vec3(e.xy, 1.0 - abs(e.x) - abs(e.y));
+    float t = max(-n.z, 0.0);
+    n.x += n.x >= 0.0 ? -t : t;
+    n.y += n.y >= 0.0 ? -t : t;
+    return normalize(n);
+}
@@ -0,0 +1,40 @@
+#version 450 core
+
+// Histogram of log2 luminance, bin 0 counts black pixels and bins 1-255
+// cover [min_log_lum, min_log_lum + log_lum_range]
+layout (local_size_x = 16, local_size_y = 16) in;
+
+layout (location = 0) uniform sampler2D hdr_texture;
+layout (location = 11) uniform float min_log_lum = -8.0;
+layout (location = 12) uniform float log_lum_range = 12.0;
+
+layout (std430, binding = 2) buffer Histogram {
+  uint bins[256];
+};
+
+shared uint local_bins[256];
+
+void main()
+{
+  local_bins[gl_LocalInvocationIndex] = 0;
+  barrier();
+
+  ivec2 size = textureSize(hdr_texture, 0);
+  ivec2 pixel = ivec2(gl_GlobalInvocationID.xy);
+
+  if(all(lessThan(pixel, size))) {
+    vec3 color = texelFetch(hdr_texture, pixel, 0).rgb;
+    float lum = dot(color, vec3(0.2126, 0.7152, 0.0722));
+    uint bin = 0;
+
+    if(lum > 0.0001) {
+      float l = clamp((log2(lum) - min_log_lum) / log_lum_range, 0.0, 1.0);
+      bin = uint(l * 254.0 + 1.0);
+    }
+
+    atomicAdd(local_bins[bin], 1);
+  }
+
+  barrier();
+  atomicAdd(bins[gl_LocalInvocationIndex], local_bins[gl_LocalInvocationIndex]);
+}
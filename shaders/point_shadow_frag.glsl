@@ -0,0 +1,11 @@
+#version 450 core
+
+layout (location = 9) uniform vec3 light_pos;
+layout (location = 10) uniform float shadow_range = 25.0;
+
+in vec3 world_pos;
+
+// Linear distance to the light instead of projected depth
+void main() {
+    gl_FragDepth = length(world_pos - light_pos) / shadow_range;
+}
@@ -0,0 +1,25 @@
+#version 450 core
+
+layout (triangles) in;
+layout (triangle_strip, max_vertices = 18) out;
+
+// One matrix per cube face in GL face order
+layout (location = 2) uniform mat4 face_view_proj[6];
+// First layer of the light's cube inside the cube map array
+layout (location = 8) uniform int cube_layer = 0;
+
+out vec3 world_pos;
+
+void main() {
+    for(int face = 0; face < 6; ++face) {
+        gl_Layer = cube_layer * 6 + face;
+
+        for(int i = 0; i < 3; ++i) {
+            world_pos = gl_in[i].gl_Position.xyz;
+            gl_Position = face_view_proj[face] * gl_in[i].gl_Position;
+            EmitVertex();
+        }
+
+        EndPrimitive();
+    }
+}
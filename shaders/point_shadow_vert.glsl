@@ -0,0 +1,18 @@
+#version 450 core
+
+// Point light shadow pass, outputs world space positions which the
+// geometry shader projects onto every cube face. See core/pipeline/shadow.rs
+
+layout (location = 0) in vec3 position;
+layout (location = 7) in mat4 instance_model;
+
+layout (location = 1) uniform mat4 model_mat = mat4(1);
+
+#include "include/vertex_decode.glsl"
+
+void main() {
+    vec3 in_position = decode_position(position);
+
+    mat4 model = use_instancing ? instance_model : model_mat;
+    gl_Position = model * vec4(in_position, 1.0);
+}
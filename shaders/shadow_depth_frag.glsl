@@ -0,0 +1,5 @@
+#version 450 core
+
+// Only depth is written
+void main() {
+}
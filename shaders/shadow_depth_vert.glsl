@@ -0,0 +1,19 @@
+#version 450 core
+
+// Depth only pass rendering shadow casters from the sun, see
+// core/pipeline/shadow.rs
+
+layout (location = 0) in vec3 position;
+layout (location = 7) in mat4 instance_model;
+
+layout (location = 1) uniform mat4 model_mat = mat4(1);
+layout (location = 2) uniform mat4 light_view_proj = mat4(1);
+
+#include "include/vertex_decode.glsl"
+
+void main() {
+    vec3 in_position = decode_position(position);
+
+    mat4 model = use_instancing ? instance_model : model_mat;
+    gl_Position = light_view_proj * model * vec4(in_position, 1.0);
+}
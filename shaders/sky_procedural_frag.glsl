@@ -0,0 +1,37 @@
+#version 450 core
+
+// Gradient sky with a sun disc, baked into the environment cube map by
+// core/pipeline/sky.rs
+smooth in vec2 ndc;
+out vec4 frag_color;
+
+layout (location = 0) uniform mat4 inv_view_proj;
+layout (location = 2) uniform float intensity = 1.0;
+layout (location = 3) uniform vec3 zenith_color;
+layout (location = 4) uniform vec3 horizon_color;
+layout (location = 5) uniform vec3 ground_color;
+// Direction the sunlight travels in, like DirLight
+layout (location = 6) uniform vec3 sun_direction;
+layout (location = 7) uniform vec3 sun_color;
+layout (location = 8) uniform float sun_cos_radius = 0.9995;
+
+void main() {
+    vec4 p = inv_view_proj * vec4(ndc, 1.0, 1.0);
+    vec3 dir = normalize(p.xyz / p.w);
+
+    float up = dir.y;
+    vec3 color = up > 0.0
+        ? mix(horizon_color, zenith_color, pow(up, 0.5))
+        : mix(horizon_color, ground_color, pow(-up, 0.3));
+
+    vec3 to_sun = normalize(-sun_direction);
+    float cos_sun = dot(dir, to_sun);
+    // Hard disc plus a glow falling off around it
+    float disc = smoothstep(sun_cos_radius - 0.0005, sun_cos_radius, cos_sun);
+    float glow = pow(max(cos_sun, 0.0), 64.0) * 0.25;
+    // The sun sets below the ground
+    float above = smoothstep(-0.05, 0.0, up);
+    color += sun_color * (disc + glow) * above;
+
+    frag_color = vec4(color * intensity, 1.0);
+}
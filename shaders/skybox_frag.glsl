@@ -0,0 +1,16 @@
+#version 450 core
+
+// Environment cube map behind the scene, see core/pipeline/sky.rs
+smooth in vec2 ndc;
+out vec4 frag_color;
+
+// Inverse of the projection times the view without translation
+layout (location = 0) uniform mat4 inv_view_proj;
+layout (location = 1) uniform samplerCube environment_map;
+layout (location = 2) uniform float intensity = 1.0;
+
+void main() {
+    vec4 p = inv_view_proj * vec4(ndc, 1.0, 1.0);
+    vec3 dir = normalize(p.xyz / p.w);
+    frag_color = vec4(textureLod(environment_map, dir, 0.0).rgb * intensity, 1.0);
+}
@@ -0,0 +1,11 @@
+#version 450 core
+
+// Single triangle covering the screen on the far plane, the fragment
+// shaders turn ndc back into a view direction, see core/pipeline/sky.rs
+smooth out vec2 ndc;
+
+void main() {
+    vec2 p = vec2((gl_VertexID << 1) & 2, gl_VertexID & 2) * 2.0 - 1.0;
+    ndc = p;
+    gl_Position = vec4(p, 1.0, 1.0);
+}
@@ -0,0 +1,80 @@
+#version 450 core
+
+// Final pass from the HDR scene to the window, see core/pipeline/post.rs
+smooth in vec2 uv;
+out vec4 frag_color;
+
+layout (location = 0) uniform sampler2D hdr_texture;
+layout (location = 5) uniform sampler2D bloom_texture;
+layout (location = 6) uniform float bloom_intensity = 0.0;
+// Mirrors post::Tonemapper
+layout (location = 7) uniform int tonemapper = 1;
+layout (location = 8) uniform float exposure = 1.0;
+layout (location = 9) uniform bool auto_exposure = false;
+// Middle grey the adapted luminance is mapped to
+layout (location = 10) uniform float exposure_key = 0.18;
+
+layout (std430, binding = 3) readonly buffer Exposure {
+  float adapted_lum;
+};
+
+#define TONEMAP_REINHARD 0
+#define TONEMAP_ACES 1
+#define TONEMAP_FILMIC 2
+
+vec3 reinhard(vec3 color)
+{
+  return color / (1.0 + color);
+}
+
+// Fit of the ACES reference rendering transform by Krzysztof Narkowicz
+vec3 aces(vec3 color)
+{
+  const float a = 2.51;
+  const float b = 0.03;
+  const float c = 2.43;
+  const float d = 0.59;
+  const float e = 0.14;
+  return clamp((color * (a * color + b)) / (color * (c * color + d) + e), 0.0, 1.0);
+}
+
+// Uncharted 2 curve by John Hable
+vec3 hable(vec3 x)
+{
+  const float A = 0.15;
+  const float B = 0.50;
+  const float C = 0.10;
+  const float D = 0.20;
+  const float E = 0.02;
+  const float F = 0.30;
+  return ((x * (A * x + C * B) + D * E) / (x * (A * x + B) + D * F)) - E / F;
+}
+
+vec3 filmic(vec3 color)
+{
+  const float white_point = 11.2;
+  return hable(color * 2.0) / hable(vec3(white_point));
+}
+
+void main()
+{
+  vec3 color = texture(hdr_texture, uv).rgb;
+  color += texture(bloom_texture, uv).rgb * bloom_intensity;
+
+  float scale = exposure;
+  if(auto_exposure) {
+    scale = exposure_key / max(adapted_lum, 0.0001);
+  }
+
+  color *= scale;
+
+  if(tonemapper == TONEMAP_REINHARD) {
+    color = reinhard(color);
+  } else if(tonemapper == TONEMAP_FILMIC) {
+    color = filmic(color);
+  } else {
+    color = aces(color);
+  }
+
+  frag_color = vec4(color, 1.0);
+}
@@ -31,7 +31,17 @@ make_bitflags! {
 #[derive(Clone)]
 pub struct Arguments {
     pub game_dir: Option<PathBuf>,
-    pub print_errors: ErrorGroups
+    pub print_errors: ErrorGroups,
+    // Hidden window, the scene is only captured through --screenshot
+    pub headless: bool,
+    // Exit after rendering this many frames
+    pub frames: Option<u32>,
+    // PNG written from the last frame
+    pub screenshot: Option<PathBuf>,
+    // Scene file of a golden image test, implies headless
+    pub golden: Option<PathBuf>,
+    // Overwrite the reference image of the golden test instead of comparing
+    pub update_golden: bool
 }
 
 impl AppCore {
@@ -47,6 +57,14 @@ impl AppCore {
             }
         }
 
+        // Without a display server SDL can still create a context through
+        // its EGL based offscreen driver, e.g. with Mesa llvmpipe
+        if config.args.headless
+            && std::env::var_os("DISPLAY").is_none()
+            && std::env::var_os("WAYLAND_DISPLAY").is_none() {
+            sdl2::hint::set("SDL_VIDEODRIVER", "offscreen");
+        }
+
         let sdl = unwrap_or_fail!(sdl2::init(), |e| InitError::SDL2(e));
         let sdl_video = unwrap_or_fail!(sdl.video(), |e| InitError::SDL2(e));
 
@@ -56,12 +74,17 @@ impl AppCore {
         gl_attr.set_context_flags()
             .debug().set();
 
+        let mut window_builder = sdl_video.window(config.window_title.as_str(),
+                                                  config.window_size.0,
+                                                  config.window_size.1);
+        window_builder.opengl();
+
+        if config.args.headless {
+            window_builder.hidden();
+        }
+
         let window = unwrap_or_fail!(
-            sdl_video.window(config.window_title.as_str(),
-                             config.window_size.0,
-                             config.window_size.1)
-                     .opengl()
-                     .build(),
+            window_builder.build(),
             |_| InitError::SDL2("SDL2 Failed constructing window!".to_owned())
         );
 
@@ -0,0 +1,841 @@
+use super::{Aabb, Ray};
+use crate::core::pipeline::mgl::attr::mesh3d::IndexedMesh;
+use cgmath::prelude::*;
+
+type Vec2 = cgmath::Vector2<f32>;
+type Vec3 = cgmath::Vector3<f32>;
+
+// Bounding volume hierarchy over the triangles of an IndexedMesh.
+// Built with binned SAH and queried entirely on the CPU, it is used for picking,
+// hit tests, decal placement and lightmap baking.
+
+const BIN_COUNT: usize = 12;
+const MAX_LEAF_TRIANGLES: usize = 4;
+// Leaves larger than this are always split even if SAH prefers a leaf
+const MAX_SAH_LEAF_TRIANGLES: usize = 16;
+const TRAVERSAL_COST: f32 = 1.0;
+const TRIANGLE_EPSILON: f32 = 1e-7;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Triangle {
+    pub positions: [Vec3; 3],
+    pub uvs: [Vec2; 3],
+}
+
+impl Triangle {
+    fn bounds(&self) -> Aabb {
+        Aabb::from_points(self.positions.iter())
+    }
+
+    fn centroid(&self) -> Vec3 {
+        (self.positions[0] + self.positions[1] + self.positions[2]) / 3.0
+    }
+
+    pub fn normal(&self) -> Vec3 {
+        let n =
+            (self.positions[1] - self.positions[0]).cross(self.positions[2] - self.positions[0]);
+        if n.magnitude2() > 0.0 {
+            n.normalize()
+        } else {
+            n
+        }
+    }
+
+    pub fn interpolate_uv(&self, bary: Vec3) -> Vec2 {
+        self.uvs[0] * bary.x + self.uvs[1] * bary.y + self.uvs[2] * bary.z
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Node {
+    bounds: Aabb,
+    // Leaf when count > 0, first indexes into Bvh::order,
+    // otherwise first is the left child and the right child follows it
+    first: u32,
+    count: u32,
+}
+
+#[derive(Debug, Clone, Copy)]
+#[allow(dead_code)]
+pub struct RayHit {
+    pub distance: f32,
+    pub point: Vec3,
+    // Index of the triangle in the source mesh (index buffer offset / 3)
+    pub triangle: u32,
+    // Weights of the triangle vertices 0, 1 and 2
+    pub barycentrics: Vec3,
+    pub normal: Vec3,
+    pub uv: Vec2,
+}
+
+#[derive(Debug, Clone, Copy)]
+#[allow(dead_code)]
+pub struct ClosestPoint {
+    pub distance: f32,
+    pub point: Vec3,
+    pub triangle: u32,
+    pub barycentrics: Vec3,
+}
+
+#[derive(Debug)]
+pub struct Bvh {
+    nodes: Vec<Node>,
+    order: Vec<u32>,
+    triangles: Vec<Triangle>,
+}
+
+#[derive(Clone, Copy)]
+struct Bin {
+    bounds: Aabb,
+    count: usize,
+}
+
+#[allow(dead_code)]
+impl Bvh {
+    pub fn build(mesh: &IndexedMesh) -> Self {
+        let attrs = &mesh.attributes;
+        let zero_uv = Vec2::new(0.0, 0.0);
+
+        let triangles = attrs
+            .indices
+            .chunks_exact(3)
+            .map(|tri| {
+                let (a, b, c) = (tri[0] as usize, tri[1] as usize, tri[2] as usize);
+                let uv = |i: usize| *attrs.uvs.get(i).unwrap_or(&zero_uv);
+                Triangle {
+                    positions: [attrs.positions[a], attrs.positions[b], attrs.positions[c]],
+                    uvs: [uv(a), uv(b), uv(c)],
+                }
+            })
+            .collect();
+
+        Self::from_triangles(triangles)
+    }
+
+    pub fn from_triangles(triangles: Vec<Triangle>) -> Self {
+        let mut bvh = Self {
+            nodes: Vec::with_capacity(triangles.len() * 2),
+            order: (0..triangles.len() as u32).collect(),
+            triangles: triangles,
+        };
+
+        if bvh.triangles.is_empty() {
+            return bvh;
+        }
+
+        let bounds: Vec<Aabb> = bvh.triangles.iter().map(|t| t.bounds()).collect();
+        let centroids: Vec<Vec3> = bvh.triangles.iter().map(|t| t.centroid()).collect();
+
+        bvh.nodes.push(Node {
+            bounds: Aabb::empty(),
+            first: 0,
+            count: 0,
+        });
+        bvh.build_node(0, 0, bvh.triangles.len(), &bounds, &centroids);
+        bvh
+    }
+
+    pub fn triangle(&self, index: u32) -> &Triangle {
+        &self.triangles[index as usize]
+    }
+
+    pub fn triangle_count(&self) -> usize {
+        self.triangles.len()
+    }
+
+    pub fn node_count(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn bounds(&self) -> Aabb {
+        self.nodes.first().map_or(Aabb::empty(), |n| n.bounds)
+    }
+
+    fn build_node(
+        &mut self,
+        node: usize,
+        start: usize,
+        end: usize,
+        bounds: &[Aabb],
+        centroids: &[Vec3],
+    ) {
+        let count = end - start;
+
+        let mut node_bounds = Aabb::empty();
+        let mut centroid_bounds = Aabb::empty();
+        for &t in &self.order[start..end] {
+            node_bounds = node_bounds.union(&bounds[t as usize]);
+            centroid_bounds.grow(centroids[t as usize]);
+        }
+
+        self.nodes[node].bounds = node_bounds;
+
+        let make_leaf = |nodes: &mut Vec<Node>| {
+            nodes[node].first = start as u32;
+            nodes[node].count = count as u32;
+        };
+
+        if count <= MAX_LEAF_TRIANGLES {
+            return make_leaf(&mut self.nodes);
+        }
+
+        let split = self.find_sah_split(
+            start,
+            end,
+            &node_bounds,
+            &centroid_bounds,
+            bounds,
+            centroids,
+        );
+
+        let mid = match split {
+            Some((axis, bin, cost)) => {
+                let leaf_cost = count as f32;
+                if cost >= leaf_cost && count <= MAX_SAH_LEAF_TRIANGLES {
+                    return make_leaf(&mut self.nodes);
+                }
+                self.partition(start, end, &centroid_bounds, centroids, axis, bin)
+            }
+            None => start + count / 2,
+        };
+
+        // Degenerate partition (all centroids in one bin), fall back to a median split
+        let mid = if mid == start || mid == end {
+            let axis = largest_axis(&centroid_bounds.extent());
+            self.order[start..end].sort_by(|a, b| {
+                centroids[*a as usize][axis]
+                    .partial_cmp(&centroids[*b as usize][axis])
+                    .unwrap_or(std::cmp::Ordering::Equal)
+            });
+            start + count / 2
+        } else {
+            mid
+        };
+
+        let left = self.nodes.len();
+        let empty = Node {
+            bounds: Aabb::empty(),
+            first: 0,
+            count: 0,
+        };
+        self.nodes.push(empty);
+        self.nodes.push(empty);
+
+        self.nodes[node].first = left as u32;
+        self.nodes[node].count = 0;
+
+        self.build_node(left, start, mid, bounds, centroids);
+        self.build_node(left + 1, mid, end, bounds, centroids);
+    }
+
+    // Returns (axis, first bin of the right side, normalized cost)
+    fn find_sah_split(
+        &self,
+        start: usize,
+        end: usize,
+        node_bounds: &Aabb,
+        centroid_bounds: &Aabb,
+        bounds: &[Aabb],
+        centroids: &[Vec3],
+    ) -> Option<(usize, usize, f32)> {
+        let extent = centroid_bounds.extent();
+        let node_area = node_bounds.surface_area().max(std::f32::MIN_POSITIVE);
+
+        let mut best: Option<(usize, usize, f32)> = None;
+
+        for axis in 0..3 {
+            if extent[axis] <= std::f32::EPSILON {
+                continue;
+            }
+
+            let mut bins = [Bin {
+                bounds: Aabb::empty(),
+                count: 0,
+            }; BIN_COUNT];
+
+            for &t in &self.order[start..end] {
+                let b = bin_index(
+                    centroids[t as usize][axis],
+                    centroid_bounds.min[axis],
+                    extent[axis],
+                );
+                bins[b].count += 1;
+                bins[b].bounds = bins[b].bounds.union(&bounds[t as usize]);
+            }
+
+            // Sweep from the right to collect area * count of every suffix
+            let mut right_cost = [0.0f32; BIN_COUNT];
+            let mut acc_bounds = Aabb::empty();
+            let mut acc_count = 0;
+            for i in (1..BIN_COUNT).rev() {
+                acc_bounds = acc_bounds.union(&bins[i].bounds);
+                acc_count += bins[i].count;
+                right_cost[i] = acc_bounds.surface_area() * acc_count as f32;
+            }
+
+            let mut acc_bounds = Aabb::empty();
+            let mut acc_count = 0;
+            for i in 1..BIN_COUNT {
+                acc_bounds = acc_bounds.union(&bins[i - 1].bounds);
+                acc_count += bins[i - 1].count;
+
+                if acc_count == 0 || acc_count == end - start {
+                    continue;
+                }
+
+                let cost = TRAVERSAL_COST
+                    + (acc_bounds.surface_area() * acc_count as f32 + right_cost[i]) / node_area;
+
+                if best.map_or(true, |(_, _, c)| cost < c) {
+                    best = Some((axis, i, cost));
+                }
+            }
+        }
+
+        best
+    }
+
+    fn partition(
+        &mut self,
+        start: usize,
+        end: usize,
+        centroid_bounds: &Aabb,
+        centroids: &[Vec3],
+        axis: usize,
+        split_bin: usize,
+    ) -> usize {
+        let min = centroid_bounds.min[axis];
+        let extent = centroid_bounds.extent()[axis];
+
+        let mut i = start;
+        let mut j = end;
+        while i < j {
+            let t = self.order[i] as usize;
+            if bin_index(centroids[t][axis], min, extent) < split_bin {
+                i += 1;
+            } else {
+                j -= 1;
+                self.order.swap(i, j);
+            }
+        }
+        i
+    }
+
+    // Closest hit along the ray up to max_dist
+    pub fn raycast(&self, ray: &Ray, max_dist: f32) -> Option<RayHit> {
+        if self.nodes.is_empty() {
+            return None;
+        }
+
+        let inv_dir = Vec3::new(
+            1.0 / ray.direction.x,
+            1.0 / ray.direction.y,
+            1.0 / ray.direction.z,
+        );
+
+        let mut closest: Option<(f32, u32, f32, f32)> = None;
+        let mut limit = max_dist;
+        let mut stack: Vec<usize> = Vec::with_capacity(64);
+        stack.push(0);
+
+        while let Some(n) = stack.pop() {
+            let node = &self.nodes[n];
+            if node
+                .bounds
+                .ray_intersect(ray.origin, inv_dir, limit)
+                .is_none()
+            {
+                continue;
+            }
+
+            if node.count > 0 {
+                let first = node.first as usize;
+                for &t in &self.order[first..first + node.count as usize] {
+                    let tri = &self.triangles[t as usize];
+                    if let Some((dist, u, v)) = intersect_triangle(ray, tri) {
+                        if dist <= limit {
+                            limit = dist;
+                            closest = Some((dist, t, u, v));
+                        }
+                    }
+                }
+            } else {
+                let left = node.first as usize;
+                let right = left + 1;
+                let dl = self.nodes[left]
+                    .bounds
+                    .ray_intersect(ray.origin, inv_dir, limit);
+                let dr = self.nodes[right]
+                    .bounds
+                    .ray_intersect(ray.origin, inv_dir, limit);
+
+                // Push the farther child first so the nearer one is visited first
+                match (dl, dr) {
+                    (Some(a), Some(b)) => {
+                        if a <= b {
+                            stack.push(right);
+                            stack.push(left);
+                        } else {
+                            stack.push(left);
+                            stack.push(right);
+                        }
+                    }
+                    (Some(_), None) => stack.push(left),
+                    (None, Some(_)) => stack.push(right),
+                    (None, None) => {}
+                }
+            }
+        }
+
+        closest.map(|(dist, t, u, v)| {
+            let tri = &self.triangles[t as usize];
+            let bary = Vec3::new(1.0 - u - v, u, v);
+            RayHit {
+                distance: dist,
+                point: ray.at(dist),
+                triangle: t,
+                barycentrics: bary,
+                normal: tri.normal(),
+                uv: tri.interpolate_uv(bary),
+            }
+        })
+    }
+
+    // Closest hit on the segment from start to end, distance is measured from start
+    pub fn segment(&self, start: Vec3, end: Vec3) -> Option<RayHit> {
+        let delta = end - start;
+        let length = delta.magnitude();
+        if length <= std::f32::EPSILON {
+            return None;
+        }
+
+        self.raycast(
+            &Ray {
+                origin: start,
+                direction: delta / length,
+            },
+            length,
+        )
+    }
+
+    // Indices of every triangle touching the sphere
+    pub fn sphere_overlap(&self, center: Vec3, radius: f32) -> Vec<u32> {
+        let mut result = vec![];
+        if self.nodes.is_empty() {
+            return result;
+        }
+
+        let r2 = radius * radius;
+        let mut stack: Vec<usize> = vec![0];
+
+        while let Some(n) = stack.pop() {
+            let node = &self.nodes[n];
+            if node.bounds.distance_squared_to_point(center) > r2 {
+                continue;
+            }
+
+            if node.count > 0 {
+                let first = node.first as usize;
+                for &t in &self.order[first..first + node.count as usize] {
+                    let (p, _) = closest_point_on_triangle(center, &self.triangles[t as usize]);
+                    if (p - center).magnitude2() <= r2 {
+                        result.push(t);
+                    }
+                }
+            } else {
+                stack.push(node.first as usize);
+                stack.push(node.first as usize + 1);
+            }
+        }
+
+        result
+    }
+
+    // Closest point on the mesh surface within max_dist of p
+    pub fn closest_point(&self, p: Vec3, max_dist: f32) -> Option<ClosestPoint> {
+        if self.nodes.is_empty() {
+            return None;
+        }
+
+        let mut best_d2 = max_dist * max_dist;
+        let mut best: Option<(Vec3, u32, Vec3)> = None;
+        let mut stack: Vec<(usize, f32)> =
+            vec![(0, self.nodes[0].bounds.distance_squared_to_point(p))];
+
+        while let Some((n, box_d2)) = stack.pop() {
+            if box_d2 > best_d2 {
+                continue;
+            }
+
+            let node = &self.nodes[n];
+            if node.count > 0 {
+                let first = node.first as usize;
+                for &t in &self.order[first..first + node.count as usize] {
+                    let (q, bary) = closest_point_on_triangle(p, &self.triangles[t as usize]);
+                    let d2 = (q - p).magnitude2();
+                    if d2 <= best_d2 {
+                        best_d2 = d2;
+                        best = Some((q, t, bary));
+                    }
+                }
+            } else {
+                let left = node.first as usize;
+                let right = left + 1;
+                let dl = self.nodes[left].bounds.distance_squared_to_point(p);
+                let dr = self.nodes[right].bounds.distance_squared_to_point(p);
+                if dl <= dr {
+                    stack.push((right, dr));
+                    stack.push((left, dl));
+                } else {
+                    stack.push((left, dl));
+                    stack.push((right, dr));
+                }
+            }
+        }
+
+        best.map(|(q, t, bary)| ClosestPoint {
+            distance: best_d2.sqrt(),
+            point: q,
+            triangle: t,
+            barycentrics: bary,
+        })
+    }
+}
+
+fn largest_axis(v: &Vec3) -> usize {
+    if v.x >= v.y && v.x >= v.z {
+        0
+    } else if v.y >= v.z {
+        1
+    } else {
+        2
+    }
+}
+
+fn bin_index(value: f32, min: f32, extent: f32) -> usize {
+    let b = ((value - min) / extent * BIN_COUNT as f32) as usize;
+    b.min(BIN_COUNT - 1)
+}
+
+// Moller-Trumbore, double sided. Returns (distance, u, v) where u and v are the
+// weights of the second and third vertex.
+fn intersect_triangle(ray: &Ray, tri: &Triangle) -> Option<(f32, f32, f32)> {
+    let [v0, v1, v2] = tri.positions;
+    let e1 = v1 - v0;
+    let e2 = v2 - v0;
+
+    let p = ray.direction.cross(e2);
+    let det = e1.dot(p);
+    if det.abs() < TRIANGLE_EPSILON {
+        return None;
+    }
+
+    let inv_det = 1.0 / det;
+    let s = ray.origin - v0;
+    let u = s.dot(p) * inv_det;
+    if u < 0.0 || u > 1.0 {
+        return None;
+    }
+
+    let q = s.cross(e1);
+    let v = ray.direction.dot(q) * inv_det;
+    if v < 0.0 || u + v > 1.0 {
+        return None;
+    }
+
+    let t = e2.dot(q) * inv_det;
+    if t < 0.0 {
+        return None;
+    }
+
+    Some((t, u, v))
+}
+
+// Ericson, Real-Time Collision Detection 5.1.5
+fn closest_point_on_triangle(p: Vec3, tri: &Triangle) -> (Vec3, Vec3) {
+    let [a, b, c] = tri.positions;
+    let ab = b - a;
+    let ac = c - a;
+    let ap = p - a;
+
+    let d1 = ab.dot(ap);
+    let d2 = ac.dot(ap);
+    if d1 <= 0.0 && d2 <= 0.0 {
+        return (a, Vec3::new(1.0, 0.0, 0.0));
+    }
+
+    let bp = p - b;
+    let d3 = ab.dot(bp);
+    let d4 = ac.dot(bp);
+    if d3 >= 0.0 && d4 <= d3 {
+        return (b, Vec3::new(0.0, 1.0, 0.0));
+    }
+
+    let vc = d1 * d4 - d3 * d2;
+    if vc <= 0.0 && d1 >= 0.0 && d3 <= 0.0 {
+        let v = d1 / (d1 - d3);
+        return (a + ab * v, Vec3::new(1.0 - v, v, 0.0));
+    }
+
+    let cp = p - c;
+    let d5 = ab.dot(cp);
+    let d6 = ac.dot(cp);
+    if d6 >= 0.0 && d5 <= d6 {
+        return (c, Vec3::new(0.0, 0.0, 1.0));
+    }
+
+    let vb = d5 * d2 - d1 * d6;
+    if vb <= 0.0 && d2 >= 0.0 && d6 <= 0.0 {
+        let w = d2 / (d2 - d6);
+        return (a + ac * w, Vec3::new(1.0 - w, 0.0, w));
+    }
+
+    let va = d3 * d6 - d5 * d4;
+    if va <= 0.0 && (d4 - d3) >= 0.0 && (d5 - d6) >= 0.0 {
+        let w = (d4 - d3) / ((d4 - d3) + (d5 - d6));
+        return (b + (c - b) * w, Vec3::new(0.0, 1.0 - w, w));
+    }
+
+    let denom = 1.0 / (va + vb + vc);
+    let v = vb * denom;
+    let w = vc * denom;
+    (a + ab * v + ac * w, Vec3::new(1.0 - v - w, v, w))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Small deterministic generator, the tests must not depend on a seed crate
+    struct Lcg(u64);
+
+    impl Lcg {
+        fn next(&mut self) -> f32 {
+            self.0 = self
+                .0
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            (self.0 >> 40) as f32 / (1u64 << 24) as f32
+        }
+
+        fn range(&mut self, min: f32, max: f32) -> f32 {
+            min + (max - min) * self.next()
+        }
+
+        fn vec3(&mut self, extent: f32) -> Vec3 {
+            Vec3::new(
+                self.range(-extent, extent),
+                self.range(-extent, extent),
+                self.range(-extent, extent),
+            )
+        }
+    }
+
+    fn triangle(a: Vec3, b: Vec3, c: Vec3) -> Triangle {
+        Triangle {
+            positions: [a, b, c],
+            uvs: [
+                Vec2::new(0.0, 0.0),
+                Vec2::new(1.0, 0.0),
+                Vec2::new(0.0, 1.0),
+            ],
+        }
+    }
+
+    // Every eighth triangle collapses to a line and every eighth to a point
+    fn soup(rng: &mut Lcg, count: usize) -> Vec<Triangle> {
+        (0..count)
+            .map(|i| {
+                let a = rng.vec3(10.0);
+                let b = a + rng.vec3(2.0);
+                let c = a + rng.vec3(2.0);
+                match i % 8 {
+                    3 => triangle(a, b, a + (b - a) * 0.5),
+                    6 => triangle(a, a, a),
+                    _ => triangle(a, b, c),
+                }
+            })
+            .collect()
+    }
+
+    fn brute_raycast(triangles: &[Triangle], ray: &Ray, max_dist: f32) -> Option<f32> {
+        triangles
+            .iter()
+            .filter_map(|t| intersect_triangle(ray, t))
+            .map(|(dist, _, _)| dist)
+            .filter(|&dist| dist <= max_dist)
+            .fold(None, |best: Option<f32>, d| {
+                Some(best.map_or(d, |b| b.min(d)))
+            })
+    }
+
+    fn brute_closest(triangles: &[Triangle], p: Vec3) -> Option<f32> {
+        triangles
+            .iter()
+            .map(|t| (closest_point_on_triangle(p, t).0 - p).magnitude())
+            .fold(None, |best: Option<f32>, d| {
+                Some(best.map_or(d, |b| b.min(d)))
+            })
+    }
+
+    fn assert_close(a: Option<f32>, b: Option<f32>) {
+        match (a, b) {
+            (Some(a), Some(b)) => assert!((a - b).abs() < 1e-4, "{} != {}", a, b),
+            (None, None) => {}
+            _ => panic!("{:?} != {:?}", a, b),
+        }
+    }
+
+    #[test]
+    fn raycast_matches_brute_force() {
+        let mut rng = Lcg(1);
+        for &count in &[1, 5, 64, 300] {
+            let triangles = soup(&mut rng, count);
+            let bvh = Bvh::from_triangles(triangles.clone());
+
+            for _ in 0..200 {
+                let ray = Ray::new(rng.vec3(15.0), rng.vec3(1.0));
+                let max_dist = rng.range(1.0, 40.0);
+                let hit = bvh.raycast(&ray, max_dist);
+                assert_close(
+                    hit.map(|h| h.distance),
+                    brute_raycast(&triangles, &ray, max_dist),
+                );
+
+                if let Some(hit) = hit {
+                    let tri = bvh.triangle(hit.triangle);
+                    let [a, b, c] = tri.positions;
+                    let point =
+                        a * hit.barycentrics.x + b * hit.barycentrics.y + c * hit.barycentrics.z;
+                    assert!((point - hit.point).magnitude() < 1e-3);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn segment_matches_brute_force() {
+        let mut rng = Lcg(2);
+        let triangles = soup(&mut rng, 200);
+        let bvh = Bvh::from_triangles(triangles.clone());
+
+        for _ in 0..300 {
+            let start = rng.vec3(15.0);
+            let end = rng.vec3(15.0);
+            let length = (end - start).magnitude();
+            let ray = Ray::new(start, end - start);
+
+            assert_close(
+                bvh.segment(start, end).map(|h| h.distance),
+                brute_raycast(&triangles, &ray, length),
+            );
+        }
+
+        let p = rng.vec3(5.0);
+        assert!(bvh.segment(p, p).is_none());
+    }
+
+    #[test]
+    fn sphere_overlap_matches_brute_force() {
+        let mut rng = Lcg(3);
+        let triangles = soup(&mut rng, 250);
+        let bvh = Bvh::from_triangles(triangles.clone());
+
+        for _ in 0..200 {
+            let center = rng.vec3(12.0);
+            let radius = rng.range(0.0, 6.0);
+
+            let mut found = bvh.sphere_overlap(center, radius);
+            found.sort();
+            let expected: Vec<u32> = (0..triangles.len() as u32)
+                .filter(|&t| {
+                    let (p, _) = closest_point_on_triangle(center, &triangles[t as usize]);
+                    (p - center).magnitude2() <= radius * radius
+                })
+                .collect();
+
+            assert_eq!(found, expected);
+        }
+    }
+
+    #[test]
+    fn closest_point_matches_brute_force() {
+        let mut rng = Lcg(4);
+        for &count in &[1, 7, 250] {
+            let triangles = soup(&mut rng, count);
+            let bvh = Bvh::from_triangles(triangles.clone());
+
+            for _ in 0..200 {
+                let p = rng.vec3(20.0);
+                let expected = brute_closest(&triangles, p);
+
+                let unbounded = bvh.closest_point(p, std::f32::INFINITY);
+                assert_close(unbounded.map(|c| c.distance), expected);
+
+                // A limit below the closest distance finds nothing
+                let limited = bvh.closest_point(p, expected.unwrap() * 0.5);
+                assert!(expected.unwrap() < 1e-6 || limited.is_none());
+            }
+        }
+    }
+
+    #[test]
+    fn empty_bvh_finds_nothing() {
+        let bvh = Bvh::from_triangles(vec![]);
+        let ray = Ray::new(Vec3::zero(), Vec3::unit_z());
+
+        assert_eq!(bvh.triangle_count(), 0);
+        assert_eq!(bvh.node_count(), 0);
+        assert!(bvh.bounds().is_empty());
+        assert!(bvh.raycast(&ray, 100.0).is_none());
+        assert!(bvh.segment(Vec3::zero(), Vec3::unit_x()).is_none());
+        assert!(bvh.sphere_overlap(Vec3::zero(), 100.0).is_empty());
+        assert!(bvh.closest_point(Vec3::zero(), 100.0).is_none());
+    }
+
+    #[test]
+    fn degenerate_triangles_are_never_hit_by_rays() {
+        let mut rng = Lcg(5);
+        let triangles: Vec<Triangle> = (0..50)
+            .map(|i| {
+                let a = rng.vec3(5.0);
+                if i % 2 == 0 {
+                    triangle(a, a, a)
+                } else {
+                    let b = rng.vec3(5.0);
+                    triangle(a, b, a + (b - a) * 0.25)
+                }
+            })
+            .collect();
+        let bvh = Bvh::from_triangles(triangles.clone());
+
+        for _ in 0..100 {
+            let ray = Ray::new(rng.vec3(10.0), rng.vec3(1.0));
+            assert!(bvh.raycast(&ray, 100.0).is_none());
+        }
+
+        // They still have a surface for proximity queries
+        let p = triangles[0].positions[0];
+        let closest = bvh.closest_point(p, 1.0).unwrap();
+        assert!(closest.distance < 1e-6);
+        assert!(bvh.sphere_overlap(p, 1e-3).contains(&0));
+    }
+
+    #[test]
+    fn identical_triangles_build_a_valid_tree() {
+        let tri = triangle(
+            Vec3::new(0.0, 0.0, 0.0),
+            Vec3::new(1.0, 0.0, 0.0),
+            Vec3::new(0.0, 1.0, 0.0),
+        );
+        let bvh = Bvh::from_triangles(vec![tri; 40]);
+        let ray = Ray::new(Vec3::new(0.25, 0.25, 5.0), -Vec3::unit_z());
+
+        let hit = bvh.raycast(&ray, 10.0).unwrap();
+        assert!((hit.distance - 5.0).abs() < 1e-5);
+        assert_eq!(
+            bvh.sphere_overlap(Vec3::new(0.25, 0.25, 0.0), 0.1).len(),
+            40
+        );
+    }
+}
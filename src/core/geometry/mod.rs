@@ -0,0 +1,331 @@
+pub mod bvh;
+
+use cgmath::prelude::*;
+
+type Vec3 = cgmath::Vector3<f32>;
+type Vec4 = cgmath::Vector4<f32>;
+type Mat4 = cgmath::Matrix4<f32>;
+
+// Axis aligned bounding box, an empty box has min > max on every axis
+#[derive(Debug, Clone, Copy)]
+pub struct Aabb {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+#[allow(dead_code)]
+impl Aabb {
+    pub fn new(min: Vec3, max: Vec3) -> Self {
+        Self { min: min, max: max }
+    }
+
+    pub fn empty() -> Self {
+        Self {
+            min: Vec3::new(std::f32::MAX, std::f32::MAX, std::f32::MAX),
+            max: Vec3::new(std::f32::MIN, std::f32::MIN, std::f32::MIN),
+        }
+    }
+
+    pub fn from_points<'a, I: IntoIterator<Item = &'a Vec3>>(points: I) -> Self {
+        let mut b = Self::empty();
+        for p in points {
+            b.grow(*p);
+        }
+        b
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.min.x > self.max.x || self.min.y > self.max.y || self.min.z > self.max.z
+    }
+
+    pub fn grow(&mut self, p: Vec3) {
+        self.min = Vec3::new(
+            self.min.x.min(p.x),
+            self.min.y.min(p.y),
+            self.min.z.min(p.z),
+        );
+        self.max = Vec3::new(
+            self.max.x.max(p.x),
+            self.max.y.max(p.y),
+            self.max.z.max(p.z),
+        );
+    }
+
+    pub fn union(&self, other: &Aabb) -> Aabb {
+        if other.is_empty() {
+            return *self;
+        }
+        let mut b = *self;
+        b.grow(other.min);
+        b.grow(other.max);
+        b
+    }
+
+    pub fn center(&self) -> Vec3 {
+        (self.min + self.max) * 0.5
+    }
+
+    pub fn extent(&self) -> Vec3 {
+        self.max - self.min
+    }
+
+    pub fn surface_area(&self) -> f32 {
+        if self.is_empty() {
+            return 0.0;
+        }
+        let e = self.extent();
+        2.0 * (e.x * e.y + e.y * e.z + e.z * e.x)
+    }
+
+    // Slab test, returns distance along the ray to the entry point (0 if inside)
+    pub fn ray_intersect(&self, origin: Vec3, inv_dir: Vec3, max_dist: f32) -> Option<f32> {
+        let t1 = (self.min - origin).mul_element_wise(inv_dir);
+        let t2 = (self.max - origin).mul_element_wise(inv_dir);
+
+        let t_near = t1.x.min(t2.x).max(t1.y.min(t2.y)).max(t1.z.min(t2.z));
+        let t_far = t1.x.max(t2.x).min(t1.y.max(t2.y)).min(t1.z.max(t2.z));
+
+        if t_far >= t_near.max(0.0) && t_near <= max_dist {
+            Some(t_near.max(0.0))
+        } else {
+            None
+        }
+    }
+
+    pub fn distance_squared_to_point(&self, p: Vec3) -> f32 {
+        let dx = (self.min.x - p.x).max(0.0).max(p.x - self.max.x);
+        let dy = (self.min.y - p.y).max(0.0).max(p.y - self.max.y);
+        let dz = (self.min.z - p.z).max(0.0).max(p.z - self.max.z);
+        dx * dx + dy * dy + dz * dz
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Ray {
+    pub origin: Vec3,
+    // Expected to be normalized, hit distances are measured along it
+    pub direction: Vec3,
+}
+
+#[allow(dead_code)]
+impl Ray {
+    pub fn new(origin: Vec3, direction: Vec3) -> Self {
+        Self {
+            origin: origin,
+            direction: direction.normalize(),
+        }
+    }
+
+    pub fn at(&self, t: f32) -> Vec3 {
+        self.origin + self.direction * t
+    }
+}
+
+// View frustum as six planes facing inwards, in the order left, right,
+// bottom, top, near, far. A point p is inside a plane when
+// dot(plane.xyz, p) + plane.w >= 0.
+#[derive(Debug, Clone, Copy)]
+pub struct Frustum {
+    pub planes: [Vec4; 6],
+}
+
+#[allow(dead_code)]
+impl Frustum {
+    // Extracts the planes from the rows of projection * view (Gribb and
+    // Hartmann), for GL clip space with depth from -w to w. The planes are
+    // in the space the matrix transforms from, world space for
+    // projection * view.
+    pub fn from_matrix(m: &Mat4) -> Self {
+        let (x, y, z, w) = (m.row(0), m.row(1), m.row(2), m.row(3));
+        let mut planes = [w + x, w - x, w + y, w - y, w + z, w - z];
+
+        for p in planes.iter_mut() {
+            let length = p.truncate().magnitude();
+            if length > 0.0 {
+                *p /= length;
+            }
+        }
+
+        Self { planes: planes }
+    }
+
+    pub fn contains_point(&self, p: Vec3) -> bool {
+        self.planes
+            .iter()
+            .all(|plane| plane.truncate().dot(p) + plane.w >= 0.0)
+    }
+
+    // Conservative like intersects_aabb, spheres near the frustum corners
+    // can pass without touching it
+    pub fn intersects_sphere(&self, center: Vec3, radius: f32) -> bool {
+        self.planes
+            .iter()
+            .all(|plane| plane.truncate().dot(center) + plane.w >= -radius)
+    }
+
+    // Conservative, boxes near the frustum corners can pass without
+    // touching it. Empty boxes are never visible.
+    pub fn intersects_aabb(&self, bounds: &Aabb) -> bool {
+        if bounds.is_empty() {
+            return false;
+        }
+
+        self.planes.iter().all(|plane| {
+            // Corner furthest along the plane normal
+            let n = plane.truncate();
+            let corner = Vec3::new(
+                if n.x >= 0.0 {
+                    bounds.max.x
+                } else {
+                    bounds.min.x
+                },
+                if n.y >= 0.0 {
+                    bounds.max.y
+                } else {
+                    bounds.min.y
+                },
+                if n.z >= 0.0 {
+                    bounds.max.z
+                } else {
+                    bounds.min.z
+                },
+            );
+            n.dot(corner) + plane.w >= 0.0
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EPSILON: f32 = 1e-3;
+
+    fn assert_plane(plane: Vec4, expected: Vec4) {
+        assert!(
+            (plane - expected).magnitude() < EPSILON,
+            "{:?} != {:?}",
+            plane,
+            expected
+        );
+    }
+
+    // 90 degree perspective at the origin looking down -z
+    fn perspective() -> Frustum {
+        Frustum::from_matrix(&cgmath::perspective(cgmath::Deg(90.0), 1.0, 1.0, 100.0))
+    }
+
+    fn cube(center: Vec3, half: f32) -> Aabb {
+        let h = Vec3::new(half, half, half);
+        Aabb::new(center - h, center + h)
+    }
+
+    #[test]
+    fn perspective_planes() {
+        let f = perspective();
+        let s = std::f32::consts::FRAC_1_SQRT_2;
+
+        assert_plane(f.planes[0], Vec4::new(s, 0.0, -s, 0.0));
+        assert_plane(f.planes[1], Vec4::new(-s, 0.0, -s, 0.0));
+        assert_plane(f.planes[2], Vec4::new(0.0, s, -s, 0.0));
+        assert_plane(f.planes[3], Vec4::new(0.0, -s, -s, 0.0));
+        assert_plane(f.planes[4], Vec4::new(0.0, 0.0, -1.0, -1.0));
+        assert_plane(f.planes[5], Vec4::new(0.0, 0.0, 1.0, 100.0));
+    }
+
+    #[test]
+    fn orthographic_planes() {
+        let f = Frustum::from_matrix(&cgmath::ortho(-2.0, 2.0, -1.0, 1.0, 0.5, 10.0));
+
+        assert_plane(f.planes[0], Vec4::new(1.0, 0.0, 0.0, 2.0));
+        assert_plane(f.planes[1], Vec4::new(-1.0, 0.0, 0.0, 2.0));
+        assert_plane(f.planes[2], Vec4::new(0.0, 1.0, 0.0, 1.0));
+        assert_plane(f.planes[3], Vec4::new(0.0, -1.0, 0.0, 1.0));
+        assert_plane(f.planes[4], Vec4::new(0.0, 0.0, -1.0, -0.5));
+        assert_plane(f.planes[5], Vec4::new(0.0, 0.0, 1.0, 10.0));
+    }
+
+    #[test]
+    fn planes_in_world_space() {
+        // Camera at x = 10 looking back at the origin
+        let view = Mat4::look_at_rh(
+            cgmath::Point3::new(10.0, 0.0, 0.0),
+            cgmath::Point3::new(0.0, 0.0, 0.0),
+            Vec3::unit_y(),
+        );
+        let proj = cgmath::perspective(cgmath::Deg(90.0), 1.0, 1.0, 100.0);
+        let f = Frustum::from_matrix(&(proj * view));
+
+        assert_plane(f.planes[4], Vec4::new(-1.0, 0.0, 0.0, 9.0));
+        assert!(f.contains_point(Vec3::new(0.0, 0.0, 0.0)));
+        assert!(f.contains_point(Vec3::new(0.0, 9.0, 0.0)));
+        assert!(!f.contains_point(Vec3::new(0.0, 11.0, 0.0)));
+        assert!(!f.contains_point(Vec3::new(20.0, 0.0, 0.0)));
+        assert!(!f.contains_point(Vec3::new(-91.0, 0.0, 0.0)));
+    }
+
+    #[test]
+    fn contains_point() {
+        let f = perspective();
+
+        assert!(f.contains_point(Vec3::new(0.0, 0.0, -1.0)));
+        assert!(f.contains_point(Vec3::new(0.0, 0.0, -99.9)));
+        assert!(f.contains_point(Vec3::new(4.9, -4.9, -5.0)));
+        assert!(!f.contains_point(Vec3::new(0.0, 0.0, -0.5)));
+        assert!(!f.contains_point(Vec3::new(0.0, 0.0, -101.0)));
+        assert!(!f.contains_point(Vec3::new(5.1, 0.0, -5.0)));
+        assert!(!f.contains_point(Vec3::new(0.0, 0.0, 5.0)));
+    }
+
+    #[test]
+    fn aabb_intersection() {
+        let f = perspective();
+
+        assert!(f.intersects_aabb(&cube(Vec3::new(0.0, 0.0, -10.0), 1.0)));
+        // Straddling a side plane, the near plane and the far plane
+        assert!(f.intersects_aabb(&cube(Vec3::new(10.5, 0.0, -10.0), 1.0)));
+        assert!(f.intersects_aabb(&cube(Vec3::new(0.0, 0.0, 0.0), 1.5)));
+        assert!(f.intersects_aabb(&cube(Vec3::new(0.0, 0.0, -100.5), 1.0)));
+        // Enclosing the whole frustum
+        assert!(f.intersects_aabb(&cube(Vec3::new(0.0, 0.0, 0.0), 1000.0)));
+
+        assert!(!f.intersects_aabb(&cube(Vec3::new(13.0, 0.0, -10.0), 1.0)));
+        assert!(!f.intersects_aabb(&cube(Vec3::new(0.0, -13.0, -10.0), 1.0)));
+        assert!(!f.intersects_aabb(&cube(Vec3::new(0.0, 0.0, 5.0), 1.0)));
+        assert!(!f.intersects_aabb(&cube(Vec3::new(0.0, 0.0, -102.0), 1.0)));
+        assert!(!f.intersects_aabb(&Aabb::empty()));
+    }
+
+    #[test]
+    fn aabb_intersection_is_conservative_at_corners() {
+        let f = perspective();
+
+        // Beyond the far right edge, each plane alone has a corner inside
+        let b = Aabb::new(Vec3::new(101.0, -1.0, -110.0), Vec3::new(110.0, 1.0, -99.0));
+        assert!(f.intersects_aabb(&b));
+        assert!(!f.contains_point(b.center()));
+    }
+
+    #[test]
+    fn sphere_intersection() {
+        let f = perspective();
+
+        assert!(f.intersects_sphere(Vec3::new(0.0, 0.0, -10.0), 1.0));
+        // Center outside, radius reaching in
+        assert!(f.intersects_sphere(Vec3::new(11.0, 0.0, -10.0), 1.0));
+        assert!(f.intersects_sphere(Vec3::new(0.0, 0.0, 0.5), 2.0));
+        assert!(!f.intersects_sphere(Vec3::new(12.0, 0.0, -10.0), 1.0));
+        assert!(!f.intersects_sphere(Vec3::new(0.0, 0.0, -102.0), 1.0));
+
+        // A zero radius is a point test
+        for p in [
+            Vec3::new(4.9, 0.0, -5.0),
+            Vec3::new(5.1, 0.0, -5.0),
+            Vec3::new(0.0, 0.0, -0.5),
+        ]
+        .iter()
+        {
+            assert_eq!(f.intersects_sphere(*p, 0.0), f.contains_point(*p));
+        }
+    }
+}
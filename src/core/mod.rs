@@ -1,4 +1,6 @@
 #[macro_use]
 pub mod macros;
 pub mod app;
+pub mod geometry;
 pub mod pipeline;
+pub mod terrain;
@@ -0,0 +1,285 @@
+use crate::core::pipeline::mgl::attr::mesh3d::{IndexedMesh, VertexAttributes};
+use cgmath::prelude::*;
+
+type Mat3 = cgmath::Matrix3<f32>;
+type Mat4 = cgmath::Matrix4<f32>;
+type Vec3 = cgmath::Vector3<f32>;
+type Point3 = cgmath::Point3<f32>;
+
+// Static batching merges meshes that never move relative to each other and share
+// the same material into a single vertex/index buffer. Vertices are pre-transformed
+// into world space on the CPU so the whole batch can be drawn with one draw call
+// using identity model and normal matrices.
+
+#[derive(Debug, Clone)]
+pub struct BatchMember {
+    pub mesh: IndexedMesh,
+    pub transform: Mat4,
+}
+
+#[derive(Debug)]
+pub struct StaticBatch {
+    members: Vec<BatchMember>,
+    dirty: bool,
+}
+
+#[allow(dead_code)]
+impl StaticBatch {
+    pub fn new() -> Self {
+        Self {
+            members: vec![],
+            dirty: true,
+        }
+    }
+
+    // Returns index of the new member within the batch
+    pub fn add_member(&mut self, mesh: &IndexedMesh, transform: Mat4) -> usize {
+        self.members.push(BatchMember {
+            mesh: mesh.clone(),
+            transform: transform,
+        });
+        self.dirty = true;
+        self.members.len() - 1
+    }
+
+    // NOTE: Removing a member shifts the indices of all members after it.
+    pub fn remove_member(&mut self, index: usize) -> BatchMember {
+        self.dirty = true;
+        self.members.remove(index)
+    }
+
+    pub fn update_transform(&mut self, index: usize, transform: Mat4) {
+        self.members[index].transform = transform;
+        self.dirty = true;
+    }
+
+    pub fn members(&self) -> &[BatchMember] {
+        &self.members
+    }
+
+    pub fn member_count(&self) -> usize {
+        self.members.len()
+    }
+
+    pub fn is_dirty(&self) -> bool {
+        self.dirty
+    }
+
+    pub fn mark_clean(&mut self) {
+        self.dirty = false;
+    }
+
+    pub fn merge(&self) -> IndexedMesh {
+        let parts: Vec<(&IndexedMesh, Mat4)> = self
+            .members
+            .iter()
+            .map(|m| (&m.mesh, m.transform))
+            .collect();
+
+        merge_transformed(&parts)
+    }
+}
+
+pub fn normal_matrix_of(transform: &Mat4) -> Mat3 {
+    let upper = Mat3::from_cols(
+        transform.x.truncate(),
+        transform.y.truncate(),
+        transform.z.truncate(),
+    );
+
+    // Fall back to the plain rotation/scale part for degenerate transforms
+    upper
+        .invert()
+        .map(|inv| inv.transpose())
+        .unwrap_or(upper)
+}
+
+// Merges meshes into a single indexed mesh with positions and normals transformed
+// into the space of the given matrices. Tangents are regenerated per vertex for
+// the merged mesh if any of the parts carried tangents.
+pub fn merge_transformed(parts: &[(&IndexedMesh, Mat4)]) -> IndexedMesh {
+    let (vertex_total, index_total) = parts.iter().fold((0, 0), |(v, i), (m, _)| {
+        (
+            v + m.attributes.positions.len(),
+            i + m.attributes.indices.len(),
+        )
+    });
+
+    let mut attrs = VertexAttributes {
+        indices: Vec::with_capacity(index_total),
+        positions: Vec::with_capacity(vertex_total),
+        normals: Vec::with_capacity(vertex_total),
+        uvs: Vec::with_capacity(vertex_total),
+        tangents: vec![],
+        bitangents: vec![],
+        colors: vec![],
+        uvs2: vec![],
+    };
+
+    // Optional streams are only kept when at least one part provides them
+    let has_colors = parts.iter().any(|(m, _)| !m.attributes.colors.is_empty());
+    let has_uvs2 = parts.iter().any(|(m, _)| !m.attributes.uvs2.is_empty());
+
+    let mut needs_tangents = false;
+
+    for (mesh, transform) in parts {
+        let src = &mesh.attributes;
+        let base = attrs.positions.len() as u32;
+        let normal_mat = normal_matrix_of(transform);
+
+        attrs
+            .indices
+            .extend(src.indices.iter().map(|i| base + *i));
+
+        attrs.positions.extend(
+            src.positions
+                .iter()
+                .map(|p| transform.transform_point(Point3::from_vec(*p)).to_vec()),
+        );
+
+        attrs.normals.extend(
+            src.normals
+                .iter()
+                .map(|n| (normal_mat * *n).normalize()),
+        );
+
+        // Keep attribute streams aligned with positions even if a part lacks them
+        attrs
+            .normals
+            .resize(attrs.positions.len(), Vec3::new(0.0, 0.0, 1.0));
+
+        attrs.uvs.extend(src.uvs.iter().cloned());
+        attrs
+            .uvs
+            .resize(attrs.positions.len(), cgmath::Vector2::new(0.0, 0.0));
+
+        if has_colors {
+            attrs.colors.extend(src.colors.iter().cloned());
+            attrs.colors.resize(
+                attrs.positions.len(),
+                cgmath::Vector4::new(1.0, 1.0, 1.0, 1.0),
+            );
+        }
+
+        if has_uvs2 {
+            // Parts without a second set reuse their first one like the GPU upload does
+            let uvs2 = if src.uvs2.is_empty() {
+                &src.uvs
+            } else {
+                &src.uvs2
+            };
+            attrs.uvs2.extend(uvs2.iter().cloned());
+            attrs
+                .uvs2
+                .resize(attrs.positions.len(), cgmath::Vector2::new(0.0, 0.0));
+        }
+
+        needs_tangents |= !src.tangents.is_empty();
+    }
+
+    let mut merged = IndexedMesh::new(attrs);
+
+    if needs_tangents {
+        merged.generate_vertex_tangents();
+    }
+
+    merged
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cgmath::Vector2;
+
+    type Vec2 = Vector2<f32>;
+
+    // Unit quad in the XY plane, U along +X and V along +Y, with shared
+    // corners and tangents like the OBJ loader produces
+    fn quad() -> IndexedMesh {
+        let mut mesh = IndexedMesh::new(VertexAttributes {
+            indices: vec![0, 1, 2, 0, 2, 3],
+            positions: vec![
+                Vec3::new(0.0, 0.0, 0.0),
+                Vec3::new(1.0, 0.0, 0.0),
+                Vec3::new(1.0, 1.0, 0.0),
+                Vec3::new(0.0, 1.0, 0.0),
+            ],
+            normals: vec![Vec3::unit_z(); 4],
+            uvs: vec![
+                Vec2::new(0.0, 0.0),
+                Vec2::new(1.0, 0.0),
+                Vec2::new(1.0, 1.0),
+                Vec2::new(0.0, 1.0),
+            ],
+            tangents: vec![],
+            bitangents: vec![],
+            colors: vec![],
+            uvs2: vec![],
+        });
+        mesh.generate_tangents();
+        mesh
+    }
+
+    fn assert_close(a: Vec3, b: Vec3) {
+        assert!((a - b).magnitude() < 1e-5, "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn merged_tangents_match_vertices() {
+        let q = quad();
+        let merged = merge_transformed(&[
+            (&q, Mat4::identity()),
+            (&q, Mat4::from_translation(Vec3::new(5.0, 0.0, 0.0))),
+        ]);
+
+        let attrs = &merged.attributes;
+        assert_eq!(attrs.positions.len(), 8);
+        assert_eq!(attrs.indices.len(), 12);
+        assert_eq!(attrs.tangents.len(), attrs.positions.len());
+        assert_eq!(attrs.bitangents.len(), attrs.positions.len());
+
+        for (t, b) in attrs.tangents.iter().zip(attrs.bitangents.iter()) {
+            assert_close(*t, Vec3::unit_x());
+            assert_close(*b, Vec3::unit_y());
+        }
+    }
+
+    #[test]
+    fn merged_tangents_follow_the_transform() {
+        let q = quad();
+        // Quarter turn around Z moves U from +X to +Y
+        let rotation = Mat4::from_angle_z(cgmath::Deg(90.0));
+        let merged = merge_transformed(&[(&q, rotation)]);
+
+        for t in merged.attributes.tangents.iter() {
+            assert_close(*t, Vec3::unit_y());
+        }
+
+        for n in merged.attributes.normals.iter() {
+            assert_close(*n, Vec3::unit_z());
+        }
+    }
+
+    #[test]
+    fn parts_without_tangents_skip_generation() {
+        let mut q = quad();
+        q.attributes.tangents.clear();
+        q.attributes.bitangents.clear();
+
+        let merged = merge_transformed(&[(&q, Mat4::identity())]);
+        assert!(merged.attributes.tangents.is_empty());
+    }
+
+    #[test]
+    fn degenerate_uvs_leave_zero_tangents() {
+        let mut q = quad();
+        q.attributes.uvs = vec![Vec2::new(0.5, 0.5); 4];
+
+        let merged = merge_transformed(&[(&q, Mat4::identity())]);
+        assert_eq!(merged.attributes.tangents.len(), 4);
+
+        for t in merged.attributes.tangents.iter() {
+            assert!(t.x.is_finite() && t.y.is_finite() && t.z.is_finite());
+        }
+    }
+}
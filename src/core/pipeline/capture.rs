@@ -0,0 +1,119 @@
+use gl::types::*;
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::path::Path;
+
+// Reading back the window framebuffer and saving it as PNG, used for
+// screenshots, headless runs and the golden image tests
+
+#[derive(Debug)]
+pub enum CaptureError {
+    Io(std::io::Error),
+    Encoding(png::EncodingError),
+    Decoding(png::DecodingError),
+    // Only 8-bit RGB and RGBA images can be read back
+    UnsupportedFormat(png::ColorType, png::BitDepth),
+}
+
+impl std::fmt::Display for CaptureError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "Failed writing capture: {}", e),
+            Self::Encoding(e) => write!(f, "Failed encoding capture: {}", e),
+            Self::Decoding(e) => write!(f, "Failed decoding image: {}", e),
+            Self::UnsupportedFormat(color, depth) => {
+                write!(f, "Unsupported image format: {:?} {:?}", color, depth)
+            }
+        }
+    }
+}
+
+impl From<std::io::Error> for CaptureError {
+    fn from(e: std::io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+impl From<png::EncodingError> for CaptureError {
+    fn from(e: png::EncodingError) -> Self {
+        Self::Encoding(e)
+    }
+}
+
+impl From<png::DecodingError> for CaptureError {
+    fn from(e: png::DecodingError) -> Self {
+        Self::Decoding(e)
+    }
+}
+
+// RGBA8 pixels of the back buffer of the default framebuffer, rows go from
+// top to bottom
+pub fn read_framebuffer(width: u32, height: u32) -> Vec<u8> {
+    let row = width as usize * 4;
+    let mut pixels = vec![0u8; row * height as usize];
+
+    unsafe {
+        gl::BindFramebuffer(gl::READ_FRAMEBUFFER, 0);
+        gl::ReadBuffer(gl::BACK);
+        gl::PixelStorei(gl::PACK_ALIGNMENT, 1);
+        gl::ReadPixels(
+            0,
+            0,
+            width as GLsizei,
+            height as GLsizei,
+            gl::RGBA,
+            gl::UNSIGNED_BYTE,
+            pixels.as_mut_ptr() as *mut GLvoid,
+        );
+    }
+
+    // GL starts at the bottom row
+    let mut flipped = Vec::with_capacity(pixels.len());
+
+    for y in (0..height as usize).rev() {
+        flipped.extend_from_slice(&pixels[y * row..(y + 1) * row]);
+    }
+
+    flipped
+}
+
+pub fn write_png(path: &Path, width: u32, height: u32, rgba: &[u8]) -> Result<(), CaptureError> {
+    let file = File::create(path)?;
+    let mut encoder = png::Encoder::new(BufWriter::new(file), width, height);
+    encoder.set_color(png::ColorType::RGBA);
+    encoder.set_depth(png::BitDepth::Eight);
+
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(rgba)?;
+    Ok(())
+}
+
+// Reads the back buffer and saves it, call before swapping buffers
+pub fn save_screenshot(path: &Path, width: u32, height: u32) -> Result<(), CaptureError> {
+    let pixels = read_framebuffer(width, height);
+    write_png(path, width, height, &pixels)
+}
+
+// Reads an image written by write_png, or any other 8-bit RGB(A) PNG, as
+// RGBA8 rows from top to bottom. Returns the width, height and pixels.
+pub fn read_png(path: &Path) -> Result<(u32, u32, Vec<u8>), CaptureError> {
+    let file = File::open(path)?;
+    let mut decoder = png::Decoder::new(BufReader::new(file));
+    // Palettes and low bit depths come out as 8-bit RGB(A)
+    decoder.set_transformations(png::Transformations::EXPAND);
+
+    let (info, mut reader) = decoder.read_info()?;
+    let mut data = vec![0u8; info.buffer_size()];
+    reader.next_frame(&mut data)?;
+
+    let pixels = match (info.color_type, info.bit_depth) {
+        (png::ColorType::RGBA, png::BitDepth::Eight) => data,
+        (png::ColorType::RGB, png::BitDepth::Eight) => data
+            .chunks(3)
+            .flat_map(|p| vec![p[0], p[1], p[2], 255])
+            .collect(),
+        (color, depth) => return Err(CaptureError::UnsupportedFormat(color, depth)),
+    };
+
+    Ok((info.width, info.height, pixels))
+}
@@ -2,6 +2,8 @@ pub mod textures;
 
 // use std::convert::TryInto;
 // use crate::core::pipeline::mgl::s3tc::Image;
+use crate::core::pipeline::mgl::attr::layout::VertexLayout;
+use crate::core::pipeline::mgl::attr::mesh3d;
 use gl::types::*;
 pub type IdVal = GLuint;
 
@@ -18,6 +20,13 @@ pub mod attrs {
     pub const UV_LOCATION: IdVal = 2;
     pub const TANGENT_LOCATION: IdVal = 3;
     pub const BITANGENT_LOCATION: IdVal = 4;
+    pub const COLOR_LOCATION: IdVal = 5;
+    pub const UV2_LOCATION: IdVal = 6;
+
+    // Instance attributes, matrices occupy one location per column
+    pub const INSTANCE_MODEL_LOCATION: IdVal = 7;
+    pub const INSTANCE_NORMAL_LOCATION: IdVal = 11;
+    pub const INSTANCE_TINT_LOCATION: IdVal = 14;
 
     pub const DIFFUSE_TEXTURE_UNIT: IdVal = 0;
     pub const DIFFUSE_SAMPLER_LOCATION: UniformId = 20;
@@ -28,224 +37,288 @@ pub mod attrs {
     pub const NORMAL_TEXTURE_UNIT: IdVal = 2;
     pub const NORMAL_SAMPLER_LOCATION: UniformId = 22;
 
-    pub const USE_NORMALMAP_FLAG: UniformId = 30;
-    pub const USE_BLINN_FLAG: UniformId = 31;
+    pub const SPLAT_TEXTURE_UNIT: IdVal = 3;
+    pub const SPLAT_SAMPLER_LOCATION: UniformId = 23;
+
+    // Layers occupy consecutive texture units and sampler locations
+    pub const SPLAT_LAYER_COUNT: usize = 4;
+    pub const SPLAT_LAYER_TEXTURE_UNIT: IdVal = 4;
+    pub const SPLAT_LAYER_SAMPLER_LOCATION: UniformId = 24;
+
+    pub const USE_SPLATMAP_FLAG: UniformId = 32;
+    pub const SPLAT_DETAIL_SCALE_LOCATION: UniformId = 33;
+
+    // Decoding of compressed vertex streams in basic_vert.glsl
+    pub const USE_COMPRESSED_VERTICES_FLAG: UniformId = 34;
+    pub const POSITION_BOUNDS_MIN_LOCATION: UniformId = 35;
+    pub const POSITION_BOUNDS_EXTENT_LOCATION: UniformId = 36;
+    pub const UV_BOUNDS_LOCATION: UniformId = 37;
+
+    pub const USE_INSTANCING_FLAG: UniformId = 38;
+
+    // Cascaded sun shadows, see core/pipeline/shadow.rs
+    pub const SHADOW_TEXTURE_UNIT: IdVal = 8;
+    pub const SHADOW_SAMPLER_LOCATION: UniformId = 28;
+    // One matrix per cascade in consecutive locations
+    pub const CASCADE_VIEW_PROJ_LOCATION: UniformId = 40;
+    pub const CASCADE_SPLITS_LOCATION: UniformId = 44;
+    pub const CASCADE_COUNT_LOCATION: UniformId = 45;
+    pub const SHADOW_BIAS_LOCATION: UniformId = 46;
+    pub const SHADOW_PCF_RADIUS_LOCATION: UniformId = 47;
+
+    pub const POINT_SHADOW_TEXTURE_UNIT: IdVal = 9;
+    pub const POINT_SHADOW_SAMPLER_LOCATION: UniformId = 29;
+
+    pub const SPOT_SHADOW_TEXTURE_UNIT: IdVal = 10;
+    pub const SPOT_SHADOW_SAMPLER_LOCATION: UniformId = 60;
+    pub const SPOT_COOKIE_TEXTURE_UNIT: IdVal = 11;
+    pub const SPOT_COOKIE_SAMPLER_LOCATION: UniformId = 61;
+
+    // Lights of the scene live in a storage buffer, see core/pipeline/lighting.rs
+    pub const LIGHT_BUFFER_BINDING: IdVal = 0;
+    pub const OBJECT_LIGHT_COUNT_LOCATION: UniformId = 51;
+    // Indices into the light buffer in consecutive locations
+    pub const OBJECT_LIGHTS_LOCATION: UniformId = 52;
+
+    // Camera uniform block shared by every program
+    pub const CAMERA_BLOCK_BINDING: IdVal = 1;
+
+    // Uniforms of shadow_depth_vert.glsl not shared with the main shader
+    pub const LIGHT_VIEW_PROJ_LOCATION: UniformId = 2;
+
+    // Uniforms of the point_shadow_*.glsl programs, matrices use one
+    // location per cube face
+    pub const FACE_VIEW_PROJ_LOCATION: UniformId = 2;
+    pub const CUBE_LAYER_LOCATION: UniformId = 8;
+    pub const POINT_LIGHT_POS_LOCATION: UniformId = 9;
+    pub const POINT_SHADOW_RANGE_LOCATION: UniformId = 10;
+
+    // Post processing programs, see core/pipeline/post.rs. Every program
+    // reads its input through POST_SOURCE_SAMPLER_LOCATION.
+    pub const POST_SOURCE_TEXTURE_UNIT: IdVal = 0;
+    pub const POST_SOURCE_SAMPLER_LOCATION: UniformId = 0;
+    pub const POST_SOURCE_TEXEL_LOCATION: UniformId = 1;
+    pub const BLOOM_APPLY_THRESHOLD_FLAG: UniformId = 2;
+    pub const BLOOM_THRESHOLD_LOCATION: UniformId = 3;
+    pub const BLOOM_TEXTURE_UNIT: IdVal = 1;
+    pub const BLOOM_SAMPLER_LOCATION: UniformId = 5;
+    pub const BLOOM_INTENSITY_LOCATION: UniformId = 6;
+    pub const TONEMAPPER_LOCATION: UniformId = 7;
+    pub const EXPOSURE_LOCATION: UniformId = 8;
+    pub const AUTO_EXPOSURE_FLAG: UniformId = 9;
+    pub const EXPOSURE_KEY_LOCATION: UniformId = 10;
+    pub const MIN_LOG_LUMINANCE_LOCATION: UniformId = 11;
+    pub const LOG_LUMINANCE_RANGE_LOCATION: UniformId = 12;
+    pub const PIXEL_COUNT_LOCATION: UniformId = 13;
+    pub const EXPOSURE_ADAPTATION_LOCATION: UniformId = 14;
+    pub const HISTOGRAM_BUFFER_BINDING: IdVal = 2;
+    pub const EXPOSURE_BUFFER_BINDING: IdVal = 3;
+
+    // Environment cube map shared by the main program and the sky
+    pub const ENVIRONMENT_TEXTURE_UNIT: IdVal = 12;
+    pub const ENVIRONMENT_SAMPLER_LOCATION: UniformId = 62;
+    pub const ENVIRONMENT_STRENGTH_LOCATION: UniformId = 63;
+
+    // Occlusion, roughness and metallic of PBR materials in R, G and B
+    pub const MATERIAL_TEXTURE_UNIT: IdVal = 13;
+    pub const MATERIAL_SAMPLER_LOCATION: UniformId = 64;
+
+    // Sky programs (skybox_frag.glsl, sky_procedural_frag.glsl)
+    pub const SKY_INV_VIEW_PROJ_LOCATION: UniformId = 0;
+    pub const SKY_ENVIRONMENT_SAMPLER_LOCATION: UniformId = 1;
+    pub const SKY_INTENSITY_LOCATION: UniformId = 2;
+    pub const SKY_ZENITH_COLOR_LOCATION: UniformId = 3;
+    pub const SKY_HORIZON_COLOR_LOCATION: UniformId = 4;
+    pub const SKY_GROUND_COLOR_LOCATION: UniformId = 5;
+    pub const SKY_SUN_DIRECTION_LOCATION: UniformId = 6;
+    pub const SKY_SUN_COLOR_LOCATION: UniformId = 7;
+    pub const SKY_SUN_COS_RADIUS_LOCATION: UniformId = 8;
 
     pub mod uniforms {
 
         pub type UniformId = gl::types::GLint;
 
         pub const MODEL_MAT_LOCATION: UniformId = 1;
-        pub const VIEW_MAT_LOCATION: UniformId = 2;
         pub const MODELVIEW_MAT_LOCATION: UniformId = 3;
-        pub const PROJECTION_MAT_LOCATION: UniformId = 4;
         pub const MVP_MAT_LOCATION: UniformId = 5;
         pub const NORMAL_MAT_LOCATION: UniformId = 6;
-        pub const VIEW_POS_LOCATION: UniformId = 10;
     }
 }
 
-macro_rules! define_buffers {
-    {$name:ident { $first_field:ident, $($other_fields:ident),+} } => {
-
-        #[derive(Default,Debug)]
-        pub struct $name {
-            pub $first_field : IdVal,
-            $(pub $other_fields : IdVal,)*
-        }
-
-        impl $name {
-            fn new () -> Self {
-                let mut buffs = Self {
-                    $first_field : 0,
-                    $($other_fields : 0,)*
-                };
-
-                unsafe {
-                    gl::GenBuffers((std::mem::size_of::<Self>()/std::mem::size_of::<IdVal>()) as GLsizei,
-                                   (&mut buffs.$first_field) as *mut GLuint);
-                }
-
-                buffs
-            }
+// One buffer per stream of a vertex layout plus the index buffer
+#[derive(Debug)]
+pub struct VertexBuffers {
+    pub index: IdVal,
+    pub streams: Vec<IdVal>,
+}
 
-            #[allow(dead_code)]
-            unsafe fn as_ptr(&self) -> *const IdVal {
-                &self.$first_field as *const IdVal
-            }
+impl VertexBuffers {
+    pub fn new(stream_count: usize) -> Self {
+        let mut index = 0;
+        let mut streams = vec![0; stream_count];
 
-            #[allow(dead_code)]
-            unsafe fn as_mut_ptr(&mut self) -> *mut IdVal {
-                &mut self.$first_field as *mut IdVal
+        unsafe {
+            gl::CreateBuffers(1, &mut index);
+            if stream_count > 0 {
+                gl::CreateBuffers(stream_count as GLsizei, streams.as_mut_ptr());
             }
+        }
 
+        Self {
+            index: index,
+            streams: streams,
+        }
+    }
 
-            #[allow(dead_code)]
-            fn print_ids(&self) {
-                println!("struct {}", stringify!($name));
-                println!("\t{} : {}", stringify!($first_field), self.$first_field);
-                $(println!("\t{} : {}", stringify!($other_fields), self.$other_fields);)+
-                    println!("}}");
-            }
-
+    // Indices are GLuint or GLushort, the draw call has to use the same type
+    pub fn upload_indices<T>(&self, indices: &[T]) {
+        unsafe {
+            gl::NamedBufferData(
+                self.index,
+                size_of_slice(indices),
+                indices.as_ptr() as *const GLvoid,
+                gl::STATIC_DRAW,
+            );
         }
+    }
 
-        impl Drop for $name {
-            fn drop (&mut self) {
-                unsafe {
-                    gl::DeleteBuffers((std::mem::size_of::<Self>()/std::mem::size_of::<IdVal>()) as GLsizei,
-                                      (&mut self.$first_field) as *mut GLuint);
-                }
-            }
+    pub fn upload_stream<T>(&self, stream: usize, data: &[T]) {
+        unsafe {
+            gl::NamedBufferData(
+                self.streams[stream],
+                size_of_slice(data),
+                data.as_ptr() as *const GLvoid,
+                gl::STATIC_DRAW,
+            );
         }
     }
-}
 
-#[allow(unused_macros)]
-macro_rules! buffer_bind_target {
-    (element_array) => {
-        gl::ELEMENT_ARRAY_BUFFER
-    };
-    (array) => {
-        gl::ARRAY_BUFFER
-    };
-}
+    // Fills every stream of the layout from the matching attributes of the
+    // mesh. Single attribute streams are uploaded as they are, interleaved
+    // streams are assembled per vertex. Float attributes the mesh does not
+    // have get the default value of their semantic, others are left zeroed.
+    pub fn upload_attributes(&self, layout: &VertexLayout, data: &mesh3d::VertexAttributes) {
+        let vertex_count = data.positions.len();
+
+        for (i, stream) in layout.streams.iter().enumerate() {
+            // Per instance streams are filled by their owners
+            if stream.divisor != 0 {
+                continue;
+            }
 
-macro_rules! vertex_attrib_type {
-    (float) => {
-        gl::FLOAT
-    };
-    (vec2) => {
-        gl::FLOAT
-    };
-    (vec3) => {
-        gl::FLOAT
-    };
-    (vec4) => {
-        gl::FLOAT
-    };
-    (mat2) => {
-        gl::FLOAT
-    };
-    (mat3) => {
-        gl::FLOAT
-    };
-    (mat4) => {
-        gl::FLOAT
-    };
-}
+            let stride = stream.stride as usize;
 
-macro_rules! vertex_attrib_component_count {
-    (float) => {
-        1
-    };
-    (vec2) => {
-        2
-    };
-    (vec3) => {
-        3
-    };
-    (vec4) => {
-        4
-    };
-    (mat2) => {
-        4
-    };
-    (mat3) => {
-        9
-    };
-    (mat4) => {
-        16
-    };
-}
+            let direct = match stream.attributes.as_slice() {
+                [a] => data
+                    .semantic_data(a.semantic)
+                    .filter(|(src, size)| *size == stride && src.len() >= stride * vertex_count),
+                _ => None,
+            };
 
-macro_rules! vertex_attrib_ptr {
-    (target: element_array $($rest:tt)*) => {};
-    (target: $target:tt, id: $id:expr, location: $loc:expr, config: packed $type:tt array) => {
-        gl::EnableVertexAttribArray($loc);
-        gl::BindBuffer(buffer_bind_target!($target), $id);
-
-        println!("Vertex attrib point {}", $loc);
-        gl::VertexAttribPointer(
-            $loc,
-            vertex_attrib_component_count!($type),
-            vertex_attrib_type!($type),
-            gl::FALSE,
-            0,
-            std::ptr::null(),
-        );
-    };
-}
+            if let Some((bytes, _)) = direct {
+                self.upload_stream(i, bytes);
+                continue;
+            }
 
-#[allow(unused_macros)]
-macro_rules! buffer_access_method {
-    (static_draw) => {
-        gl::STATIC_DRAW
-    };
-}
+            let mut bytes = vec![0u8; stride * vertex_count];
 
-macro_rules! buffer_data  {
-    (generate_vao; $(($id:expr) => { data: $data:expr, target: $target:tt, access: $access:tt$(, $($rest:tt)*)? }),+) => {{
-        $(
-            gl::BindBuffer(buffer_bind_target!($target), $id);
-            gl::BufferData(
-                buffer_bind_target!($target),
-                size_of_vec(&$data),
-                $data.as_ptr() as *const GLvoid,
-                buffer_access_method!($access)
-            );
-            // println!("SIZE_OF_VEC({}) = {}", stringify!($data), size_of_vec(&$data));
-        )+
+            for a in &stream.attributes {
+                let offset = a.offset as usize;
+                let size = a.format.size() as usize;
+                let mut filled = 0;
 
-            let mut vao = 0;
-        gl::GenVertexArrays(1, &mut vao);
-        gl::BindVertexArray(vao);
+                if let Some((src, src_size)) = data.semantic_data(a.semantic) {
+                    let copy = src_size.min(size);
+                    filled = vertex_count.min(src.len() / src_size);
 
-        $(
-            vertex_attrib_ptr!(
-                target: $target, $(id: $id,$($rest)*)?
-            );
-        )+
+                    for v in 0..filled {
+                        let dst = v * stride + offset;
+                        bytes[dst..dst + copy]
+                            .copy_from_slice(&src[v * src_size..v * src_size + copy]);
+                    }
+                }
+
+                if a.format.gl_type() == gl::FLOAT {
+                    let default: Vec<u8> = a
+                        .semantic
+                        .default_value()
+                        .iter()
+                        .take(a.format.count() as usize)
+                        .flat_map(|c| c.to_ne_bytes().to_vec())
+                        .collect();
+
+                    for v in filled..vertex_count {
+                        let dst = v * stride + offset;
+                        bytes[dst..dst + size].copy_from_slice(&default);
+                    }
+                }
+            }
 
-            gl::BindBuffer(gl::ARRAY_BUFFER, 0);
-        gl::BindBuffer(gl::ELEMENT_ARRAY_BUFFER, 0);
-        gl::BindVertexArray(0);
+            self.upload_stream(i, &bytes);
+        }
+    }
+}
 
-        vao
-    }}
+impl Drop for VertexBuffers {
+    fn drop(&mut self) {
+        unsafe {
+            gl::DeleteBuffers(1, &self.index);
+            if !self.streams.is_empty() {
+                gl::DeleteBuffers(self.streams.len() as GLsizei, self.streams.as_ptr());
+            }
+        }
+    }
 }
 
-fn size_of_vec<T>(v: &Vec<T>) -> GLsizeiptr {
+fn size_of_slice<T>(v: &[T]) -> GLsizeiptr {
     (std::mem::size_of::<T>() * v.len()) as GLsizeiptr
 }
 
 pub mod basic_mesh {
 
-    use super::{attrs, size_of_vec, textures, IdVal};
+    use super::{attrs, textures, IdVal, VertexBuffers};
+    use crate::core::pipeline::mgl::attr::layout::{Semantic, VertexAttribute, VertexLayout};
     use crate::core::pipeline::mgl::attr::mesh3d;
+    use crate::core::pipeline::mgl::attr::AttributeType;
     use gl::types::*;
 
-    define_buffers!(Buffers {
-        index,
-        position,
-        normal,
-        uv
-    });
+    pub fn layout() -> VertexLayout {
+        VertexLayout::new().separate(vec![
+            VertexAttribute::new(
+                Semantic::Position,
+                AttributeType::Vec3,
+                attrs::POSITION_LOCATION,
+            ),
+            VertexAttribute::new(
+                Semantic::Normal,
+                AttributeType::Vec3,
+                attrs::NORMAL_LOCATION,
+            ),
+            VertexAttribute::new(Semantic::Uv, AttributeType::Vec2, attrs::UV_LOCATION),
+            VertexAttribute::new(Semantic::Color, AttributeType::Vec4, attrs::COLOR_LOCATION),
+            VertexAttribute::new(Semantic::Uv2, AttributeType::Vec2, attrs::UV2_LOCATION),
+        ])
+    }
 
     #[derive(Debug)]
     pub struct Mesh {
         pub vao: IdVal,
         pub element_count: GLsizei,
-        pub buffers: Buffers,
+        pub layout: VertexLayout,
+        pub buffers: VertexBuffers,
         pub textures: textures::Basic,
     }
 
     impl Mesh {
         pub fn new() -> Self {
+            let layout = layout();
+            let buffers = VertexBuffers::new(layout.stream_count());
+
             Self {
-                vao: 0,
+                vao: layout.create_vao(&buffers.streams, Some(buffers.index)),
                 element_count: 0,
-                buffers: Buffers::new(),
+                layout: layout,
+                buffers: buffers,
                 textures: textures::Basic::new(),
             }
         }
@@ -258,73 +331,77 @@ pub mod basic_mesh {
             let mut mesh: Mesh = Mesh::new();
 
             mesh.element_count = data.attributes.indices.len().try_into().unwrap();
+            mesh.buffers.upload_indices(&data.attributes.indices);
+            mesh.buffers
+                .upload_attributes(&mesh.layout, &data.attributes);
+
+            mesh
+        }
+    }
 
+    impl Drop for Mesh {
+        fn drop(&mut self) {
             unsafe {
-                mesh.vao = buffer_data!(
-                    generate_vao;
-
-                    (mesh.buffers.index) => {
-                        data: data.attributes.indices,
-                        target: element_array,
-                        access: static_draw
-                    },
-                    (mesh.buffers.position) => {
-                        data: data.attributes.positions,
-                        target: array,
-                        access: static_draw,
-                        location: attrs::POSITION_LOCATION,
-                        config: packed vec3 array
-                    },
-                    (mesh.buffers.normal) => {
-                        data: data.attributes.normals,
-                        target: array,
-                        access: static_draw,
-                        location: attrs::NORMAL_LOCATION,
-                        config: packed vec3 array
-                    },
-                    (mesh.buffers.uv) => {
-                        data: data.attributes.uvs,
-                        target: array,
-                        access: static_draw,
-                        location: attrs::UV_LOCATION,
-                        config: packed vec2 array
-                    }
-                );
+                gl::DeleteVertexArrays(1, &self.vao);
             }
-
-            mesh
         }
     }
 }
 
 pub mod normal_mapped_mesh {
 
-    use super::{attrs, size_of_vec, textures, IdVal};
+    use super::{attrs, textures, IdVal, VertexBuffers};
+    use crate::core::pipeline::mgl::attr::layout::{Semantic, VertexAttribute, VertexLayout};
+    use crate::core::pipeline::mgl::attr::AttributeType;
     use gl::types::*;
 
-    define_buffers!(Buffers {
-        index,
-        position,
-        normal,
-        uv,
-        tangent,
-        bitangent
-    });
+    pub fn layout() -> VertexLayout {
+        VertexLayout::new().separate(vec![
+            VertexAttribute::new(
+                Semantic::Position,
+                AttributeType::Vec3,
+                attrs::POSITION_LOCATION,
+            ),
+            VertexAttribute::new(
+                Semantic::Normal,
+                AttributeType::Vec3,
+                attrs::NORMAL_LOCATION,
+            ),
+            VertexAttribute::new(Semantic::Uv, AttributeType::Vec2, attrs::UV_LOCATION),
+            VertexAttribute::new(
+                Semantic::Tangent,
+                AttributeType::Vec3,
+                attrs::TANGENT_LOCATION,
+            ),
+            VertexAttribute::new(
+                Semantic::Bitangent,
+                AttributeType::Vec3,
+                attrs::BITANGENT_LOCATION,
+            ),
+            VertexAttribute::new(Semantic::Color, AttributeType::Vec4, attrs::COLOR_LOCATION),
+            VertexAttribute::new(Semantic::Uv2, AttributeType::Vec2, attrs::UV2_LOCATION),
+        ])
+    }
 
     #[derive(Debug)]
     pub struct Mesh {
         pub vao: IdVal,
         pub element_count: GLsizei,
-        pub buffers: Buffers,
+        pub layout: VertexLayout,
+        pub buffers: VertexBuffers,
         pub textures: textures::NormalMapped,
     }
 
     impl Mesh {
         pub fn new() -> Self {
+            let layout = layout();
+            let buffers = VertexBuffers::new(layout.stream_count());
+
             Self {
-                vao: 0,
+                vao: layout.create_vao(&buffers.streams, Some(buffers.index)),
                 element_count: 0,
-                buffers: Buffers::new(),
+                layout: layout,
+                buffers: buffers,
                 textures: textures::NormalMapped::new(),
             }
         }
@@ -333,60 +410,310 @@ pub mod normal_mapped_mesh {
     use crate::core::pipeline::mgl::attr::mesh3d;
     use std::convert::TryInto;
 
+    impl Mesh {
+        // Replaces the vertex and index data of the mesh while keeping its
+        // buffers and textures. Used when static batches are rebuilt.
+        pub fn upload_geometry(&mut self, data: &mesh3d::IndexedMesh) {
+            self.element_count = data.attributes.indices.len().try_into().unwrap();
+            self.buffers.upload_indices(&data.attributes.indices);
+            self.buffers
+                .upload_attributes(&self.layout, &data.attributes);
+        }
+    }
+
+    impl Mesh {
+        // Replaces only the index buffer, vertices stay untouched.
+        // Terrain chunks use this when their level of detail changes.
+        pub fn upload_indices(&mut self, indices: &Vec<GLuint>) {
+            self.element_count = indices.len().try_into().unwrap();
+            self.buffers.upload_indices(indices);
+        }
+    }
+
+    impl From<&mesh3d::IndexedMesh> for Mesh {
+        fn from(data: &mesh3d::IndexedMesh) -> Self {
+            let mut mesh: Mesh = Mesh::new();
+            mesh.upload_geometry(data);
+            mesh
+        }
+    }
+
+    impl Drop for Mesh {
+        fn drop(&mut self) {
+            unsafe {
+                gl::DeleteVertexArrays(1, &self.vao);
+            }
+        }
+    }
+}
+
+// Same vertex streams as normal_mapped_mesh with a metallic-roughness
+// material
+pub mod pbr_mesh {
+
+    use super::{normal_mapped_mesh, textures, IdVal, VertexBuffers};
+    use crate::core::pipeline::mgl::attr::layout::VertexLayout;
+    use crate::core::pipeline::mgl::attr::mesh3d;
+    use gl::types::*;
+
+    #[derive(Debug)]
+    pub struct Mesh {
+        pub vao: IdVal,
+        pub element_count: GLsizei,
+        pub layout: VertexLayout,
+        pub buffers: VertexBuffers,
+        pub textures: textures::Pbr,
+    }
+
+    impl Mesh {
+        pub fn new() -> Self {
+            let layout = normal_mapped_mesh::layout();
+            let buffers = VertexBuffers::new(layout.stream_count());
+
+            Self {
+                vao: layout.create_vao(&buffers.streams, Some(buffers.index)),
+                element_count: 0,
+                layout: layout,
+                buffers: buffers,
+                textures: textures::Pbr::new(),
+            }
+        }
+    }
+
+    use std::convert::TryInto;
+
     impl From<&mesh3d::IndexedMesh> for Mesh {
         fn from(data: &mesh3d::IndexedMesh) -> Self {
             let mut mesh: Mesh = Mesh::new();
 
             mesh.element_count = data.attributes.indices.len().try_into().unwrap();
+            mesh.buffers.upload_indices(&data.attributes.indices);
+            mesh.buffers
+                .upload_attributes(&mesh.layout, &data.attributes);
+
+            mesh
+        }
+    }
 
+    impl Drop for Mesh {
+        fn drop(&mut self) {
             unsafe {
-                mesh.vao = buffer_data!(
-                    generate_vao;
-
-                    (mesh.buffers.index) => {
-                        data: data.attributes.indices,
-                        target: element_array,
-                        access: static_draw
-                    },
-                    (mesh.buffers.position) => {
-                        data: data.attributes.positions,
-                        target: array,
-                        access: static_draw,
-                        location: attrs::POSITION_LOCATION,
-                        config: packed vec3 array
-                    },
-                    (mesh.buffers.normal) => {
-                        data: data.attributes.normals,
-                        target: array,
-                        access: static_draw,
-                        location: attrs::NORMAL_LOCATION,
-                        config: packed vec3 array
-                    },
-                    (mesh.buffers.uv) => {
-                        data: data.attributes.uvs,
-                        target: array,
-                        access: static_draw,
-                        location: attrs::UV_LOCATION,
-                        config: packed vec2 array
-                    },
-                    (mesh.buffers.tangent) => {
-                        data: data.attributes.tangents,
-                        target: array,
-                        access: static_draw,
-                        location: attrs::TANGENT_LOCATION,
-                        config: packed vec3 array
-                    },
-                    (mesh.buffers.bitangent) => {
-                        data: data.attributes.bitangents,
-                        target: array,
-                        access: static_draw,
-                        location: attrs::BITANGENT_LOCATION,
-                        config: packed vec3 array
-                    }
-                );
+                gl::DeleteVertexArrays(1, &self.vao);
+            }
+        }
+    }
+}
+
+pub mod compressed_mesh {
+
+    use super::{attrs, textures, IdVal, VertexBuffers};
+    use crate::core::pipeline::mgl::attr::compressed::{
+        CompressedAttributes, Indices, PositionFormat,
+    };
+    use crate::core::pipeline::mgl::attr::layout::{Semantic, VertexAttribute, VertexLayout};
+    use crate::core::pipeline::mgl::attr::AttributeType;
+    use gl::types::*;
+    use std::convert::TryInto;
+
+    type Vec3 = cgmath::Vector3<f32>;
+    type Vec4 = cgmath::Vector4<f32>;
+
+    // Normals and tangents arrive as octahedral pairs in the xy components,
+    // the tangent z component holds the bitangent sign
+    pub fn layout(position_format: PositionFormat) -> VertexLayout {
+        let position = match position_format {
+            PositionFormat::Half => VertexAttribute::new(
+                Semantic::Position,
+                AttributeType::Half4,
+                attrs::POSITION_LOCATION,
+            ),
+            PositionFormat::Unorm16 => VertexAttribute::new(
+                Semantic::Position,
+                AttributeType::UShort4,
+                attrs::POSITION_LOCATION,
+            )
+            .normalized(),
+        };
+
+        VertexLayout::new().separate(vec![
+            position,
+            VertexAttribute::new(
+                Semantic::Normal,
+                AttributeType::Short2,
+                attrs::NORMAL_LOCATION,
+            )
+            .normalized(),
+            VertexAttribute::new(Semantic::Uv, AttributeType::UShort2, attrs::UV_LOCATION)
+                .normalized(),
+            VertexAttribute::new(
+                Semantic::Tangent,
+                AttributeType::Short4,
+                attrs::TANGENT_LOCATION,
+            )
+            .normalized(),
+            VertexAttribute::new(
+                Semantic::Color,
+                AttributeType::UByte4,
+                attrs::COLOR_LOCATION,
+            )
+            .normalized(),
+            VertexAttribute::new(Semantic::Uv2, AttributeType::UShort2, attrs::UV2_LOCATION)
+                .normalized(),
+        ])
+    }
+
+    #[derive(Debug)]
+    #[allow(dead_code)]
+    pub struct Mesh {
+        pub vao: IdVal,
+        pub element_count: GLsizei,
+        pub index_type: GLenum,
+        pub layout: VertexLayout,
+        pub buffers: VertexBuffers,
+        pub textures: textures::NormalMapped,
+        // Uniforms the shader needs to decode positions and UVs
+        pub position_min: Vec3,
+        pub position_extent: Vec3,
+        pub uv_bounds: Vec4,
+    }
+
+    impl From<&CompressedAttributes> for Mesh {
+        fn from(data: &CompressedAttributes) -> Self {
+            let layout = layout(data.position_format);
+            let buffers = VertexBuffers::new(layout.stream_count());
+
+            match &data.indices {
+                Indices::U16(i) => buffers.upload_indices(i),
+                Indices::U32(i) => buffers.upload_indices(i),
+            }
+
+            buffers.upload_stream(0, &data.positions);
+            buffers.upload_stream(1, &data.normals);
+            buffers.upload_stream(2, &data.uvs);
+            buffers.upload_stream(3, &data.tangents);
+            buffers.upload_stream(4, &data.colors);
+            buffers.upload_stream(5, &data.uvs2);
+
+            Self {
+                vao: layout.create_vao(&buffers.streams, Some(buffers.index)),
+                element_count: data.indices.len().try_into().unwrap(),
+                index_type: data.indices.gl_type(),
+                layout: layout,
+                buffers: buffers,
+                textures: textures::NormalMapped::new(),
+                position_min: data.bounds.min,
+                position_extent: data.bounds.extent(),
+                uv_bounds: Vec4::new(
+                    data.uv_min.x,
+                    data.uv_min.y,
+                    data.uv_extent.x,
+                    data.uv_extent.y,
+                ),
+            }
+        }
+    }
+
+    impl Drop for Mesh {
+        fn drop(&mut self) {
+            unsafe {
+                gl::DeleteVertexArrays(1, &self.vao);
+            }
+        }
+    }
+}
+
+pub mod instanced_mesh {
+
+    use super::{attrs, normal_mapped_mesh, textures, IdVal, VertexBuffers};
+    use crate::core::pipeline::instancing::InstanceData;
+    use crate::core::pipeline::mgl::attr::layout::{
+        Semantic, VertexAttribute, VertexLayout, VertexStream,
+    };
+    use crate::core::pipeline::mgl::attr::mesh3d;
+    use crate::core::pipeline::mgl::attr::AttributeType;
+    use gl::types::*;
+    use std::convert::TryInto;
+
+    // Normal mapped vertex streams followed by one interleaved per instance
+    // stream matching the layout of InstanceData
+    pub fn layout() -> VertexLayout {
+        let mut instance = vec![];
+
+        for column in 0..4 {
+            instance.push(VertexAttribute::new(
+                Semantic::InstanceModel,
+                AttributeType::Vec4,
+                attrs::INSTANCE_MODEL_LOCATION + column,
+            ));
+        }
+
+        for column in 0..3 {
+            instance.push(VertexAttribute::new(
+                Semantic::InstanceNormal,
+                AttributeType::Vec3,
+                attrs::INSTANCE_NORMAL_LOCATION + column,
+            ));
+        }
+
+        instance.push(VertexAttribute::new(
+            Semantic::InstanceTint,
+            AttributeType::Vec4,
+            attrs::INSTANCE_TINT_LOCATION,
+        ));
+
+        normal_mapped_mesh::layout().stream(VertexStream::interleaved(instance).per_instance(1))
+    }
+
+    #[derive(Debug)]
+    pub struct Mesh {
+        pub vao: IdVal,
+        pub element_count: GLsizei,
+        pub instance_count: GLsizei,
+        pub layout: VertexLayout,
+        pub buffers: VertexBuffers,
+        pub textures: textures::NormalMapped,
+    }
+
+    impl Mesh {
+        pub fn new() -> Self {
+            let layout = layout();
+            let buffers = VertexBuffers::new(layout.stream_count());
+
+            Self {
+                vao: layout.create_vao(&buffers.streams, Some(buffers.index)),
+                element_count: 0,
+                instance_count: 0,
+                layout: layout,
+                buffers: buffers,
+                textures: textures::NormalMapped::new(),
             }
+        }
+
+        pub fn upload_instances(&mut self, instances: &[InstanceData]) {
+            self.instance_count = instances.len().try_into().unwrap();
+            self.buffers
+                .upload_stream(self.layout.stream_count() - 1, instances);
+        }
+    }
+
+    impl From<&mesh3d::IndexedMesh> for Mesh {
+        fn from(data: &mesh3d::IndexedMesh) -> Self {
+            let mut mesh = Mesh::new();
+
+            mesh.element_count = data.attributes.indices.len().try_into().unwrap();
+            mesh.buffers.upload_indices(&data.attributes.indices);
+            mesh.buffers
+                .upload_attributes(&mesh.layout, &data.attributes);
 
             mesh
         }
     }
+
+    impl Drop for Mesh {
+        fn drop(&mut self) {
+            unsafe {
+                gl::DeleteVertexArrays(1, &self.vao);
+            }
+        }
+    }
 }
@@ -3,7 +3,7 @@ use crate::core::pipeline::mgl;
 use mgl::attr::mesh3d::lightmaps::{
     LightMaps,
 };
-use mgl::s3tc::Image;
+use mgl::s3tc::{Image, ImageError, S3MipmapIter};
 
 use gl::types::*;
 
@@ -11,6 +11,7 @@ use gl::types::*;
 pub enum Textures {
     Basic(Basic),
     NormalMapped(NormalMapped),
+    Pbr(Pbr),
 }
 
 #[derive(Default,Debug)]
@@ -54,6 +55,25 @@ impl Drop for Basic {
     }
 }
 
+#[derive(Default,Debug)]
+#[repr(C)]
+pub struct Pbr {
+    // Only id values allowed, fields are generated and deleted as one array
+    pub albedo: IdVal,
+    pub normal: IdVal,
+    pub material: IdVal,
+}
+
+#[derive(Default,Debug)]
+#[repr(C)]
+pub struct Splat {
+    // Only id values allowed, fields are generated and deleted as one array
+    pub splat: IdVal,
+    pub specular: IdVal,
+    pub normal: IdVal,
+    pub layers: [IdVal; attrs::SPLAT_LAYER_COUNT],
+}
+
 impl NormalMapped {
     pub fn new() -> Self {
         let mut texs : Self = Default::default();
@@ -82,6 +102,67 @@ impl Drop for NormalMapped {
     }
 }
 
+impl Pbr {
+    pub fn new() -> Self {
+        let mut texs : Self = Default::default();
+        unsafe {
+            gl::GenTextures((std::mem::size_of::<Self>()/std::mem::size_of::<IdVal>()) as GLsizei,
+                           (&mut texs.albedo) as *mut GLuint);
+        }
+        texs
+    }
+
+    pub fn upload_all_textures(&mut self, lm: &mgl::attr::mesh3d::lightmaps::Pbr) {
+        upload_s3_texture(&lm.albedo, attrs::DIFFUSE_TEXTURE_UNIT, self.albedo);
+        upload_s3_texture(&lm.normal, attrs::NORMAL_TEXTURE_UNIT, self.normal);
+        upload_s3_texture(&lm.material, attrs::MATERIAL_TEXTURE_UNIT, self.material);
+    }
+}
+
+impl Drop for Pbr {
+    fn drop(&mut self) {
+        unsafe {
+            gl::DeleteTextures((std::mem::size_of::<Self>()/std::mem::size_of::<IdVal>()) as GLsizei,
+                           (&mut self.albedo) as *mut GLuint);
+        }
+    }
+}
+
+impl Splat {
+    pub fn new() -> Self {
+        let mut texs : Self = Default::default();
+        unsafe {
+            gl::GenTextures((std::mem::size_of::<Self>()/std::mem::size_of::<IdVal>()) as GLsizei,
+                           (&mut texs.splat) as *mut GLuint);
+        }
+        texs
+    }
+
+    pub fn upload_all_textures(&mut self, lm: &mgl::attr::mesh3d::lightmaps::Splat) {
+        upload_s3_texture(&lm.splat, attrs::SPLAT_TEXTURE_UNIT, self.splat);
+        upload_s3_texture(&lm.specular, attrs::SPECULAR_TEXTURE_UNIT, self.specular);
+        upload_s3_texture(&lm.normal, attrs::NORMAL_TEXTURE_UNIT, self.normal);
+
+        if lm.layers.len() > attrs::SPLAT_LAYER_COUNT {
+            println!("Splat material has {} layers, only {} are used!",
+                     lm.layers.len(), attrs::SPLAT_LAYER_COUNT);
+        }
+
+        for (i, layer) in lm.layers.iter().take(attrs::SPLAT_LAYER_COUNT).enumerate() {
+            upload_s3_texture(layer, attrs::SPLAT_LAYER_TEXTURE_UNIT + i as GLuint, self.layers[i]);
+        }
+    }
+}
+
+impl Drop for Splat {
+    fn drop(&mut self) {
+        unsafe {
+            gl::DeleteTextures((std::mem::size_of::<Self>()/std::mem::size_of::<IdVal>()) as GLsizei,
+                           (&mut self.splat) as *mut GLuint);
+        }
+    }
+}
+
 #[allow(dead_code)]
 impl Textures {
     pub fn new_basic() -> Self {
@@ -91,6 +172,10 @@ impl Textures {
     pub fn new_normal_mapped() -> Self {
         Self::NormalMapped(NormalMapped::new())
     }
+
+    pub fn new_pbr() -> Self {
+        Self::Pbr(Pbr::new())
+    }
 }
 
 impl From<Basic> for Textures {
@@ -105,6 +190,12 @@ impl From<NormalMapped> for Textures {
     }
 }
 
+impl From<Pbr> for Textures {
+    fn from(other: Pbr) -> Self {
+        Textures::Pbr(other)
+    }
+}
+
 fn upload_s3_texture (tex: &Image, tex_unit: GLuint, tex_id: IdVal) {
 
     // FIXME: Block size is here for a reason
@@ -140,9 +231,110 @@ impl From<&LightMaps> for Textures {
                 let mut t = NormalMapped::new();
                 t.upload_all_textures(&lm);
                 t.into()
+            },
+            LightMaps::Pbr(lm) => {
+                let mut t = Pbr::new();
+                t.upload_all_textures(&lm);
+                t.into()
             }
         };
 
         prepared_textures
     }
 }
+
+// Cube map with faces in GL order: +X, -X, +Y, -Y, +Z, -Z
+#[derive(Debug)]
+pub struct Cubemap {
+    pub id: IdVal,
+    // Width and height of every face
+    pub size: i32,
+}
+
+#[allow(dead_code)]
+impl Cubemap {
+    fn create(size: i32, levels: usize, format: GLenum) -> Self {
+        let mut id = 0;
+        unsafe {
+            gl::CreateTextures(gl::TEXTURE_CUBE_MAP, 1, &mut id);
+            gl::TextureStorage2D(id, levels as GLsizei, format, size, size);
+            gl::TextureParameteri(id, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE as i32);
+            gl::TextureParameteri(id, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE as i32);
+            gl::TextureParameteri(id, gl::TEXTURE_WRAP_R, gl::CLAMP_TO_EDGE as i32);
+            gl::TextureParameteri(id, gl::TEXTURE_MIN_FILTER, gl::LINEAR_MIPMAP_LINEAR as i32);
+            gl::TextureParameteri(id, gl::TEXTURE_MAG_FILTER, gl::LINEAR as i32);
+            gl::TextureParameteri(id, gl::TEXTURE_MAX_LEVEL, levels as i32 - 1);
+        }
+        Self { id: id, size: size }
+    }
+
+    // Uncompressed cube map to render into, gets a full mip chain
+    pub fn new_renderable(size: i32, format: GLenum) -> Self {
+        let levels = 32 - (size.max(1) as u32).leading_zeros();
+        Self::create(size, levels as usize, format)
+    }
+
+    // DDS file holding all six faces
+    pub fn from_dds(image: &Image) -> Result<Self, ImageError> {
+        if !image.is_cubemap() {
+            return Err(ImageError::InvalidData("DDS image is not a cube map!".to_owned()));
+        }
+
+        let cube = Self::create_for(image)?;
+        for face in 0..6 {
+            cube.upload_face(face, image.face_mipmap_iter(face), image.format.gl_format());
+        }
+        Ok(cube)
+    }
+
+    // Six square DDS images of the same size and format, one per face
+    pub fn from_faces(faces: &[Image]) -> Result<Self, ImageError> {
+        if faces.len() != 6 {
+            return Err(ImageError::InvalidData(
+                format!("Cube map needs 6 faces, got {}", faces.len())));
+        }
+
+        let first = &faces[0];
+        for f in faces.iter() {
+            if f.width != first.width || f.height != first.height
+                || f.format.gl_format() != first.format.gl_format()
+                || f.mipmaps.len() != first.mipmaps.len() {
+                return Err(ImageError::InvalidData(
+                    "Cube map faces differ in size or format!".to_owned()));
+            }
+        }
+
+        let cube = Self::create_for(first)?;
+        for (face, f) in faces.iter().enumerate() {
+            cube.upload_face(face as u32, f.mipmap_iter(), f.format.gl_format());
+        }
+        Ok(cube)
+    }
+
+    fn create_for(image: &Image) -> Result<Self, ImageError> {
+        if image.width != image.height {
+            return Err(ImageError::InvalidData(
+                format!("Cube map faces must be square, got {}x{}", image.width, image.height)));
+        }
+
+        Ok(Self::create(image.width, image.mipmaps.len(), image.format.gl_format()))
+    }
+
+    fn upload_face(&self, face: u32, mipmaps: S3MipmapIter, format: GLenum) {
+        unsafe {
+            for (level,m) in mipmaps.enumerate() {
+                gl::CompressedTextureSubImage3D(self.id, level as i32, 0, 0, face as i32,
+                                                m.width, m.height, 1, format,
+                                                m.data.len() as i32, m.data.as_ptr() as *const GLvoid);
+            }
+        }
+    }
+}
+
+impl Drop for Cubemap {
+    fn drop(&mut self) {
+        unsafe {
+            gl::DeleteTextures(1, &self.id);
+        }
+    }
+}
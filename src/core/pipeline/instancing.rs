@@ -0,0 +1,127 @@
+use super::batch::normal_matrix_of;
+use super::lighting::transform_bounds;
+use crate::core::geometry::Aabb;
+
+type Mat4 = cgmath::Matrix4<f32>;
+type Vec4 = cgmath::Vector4<f32>;
+
+// Instanced rendering draws one mesh many times with a single draw call.
+// Per instance data lives in a vertex buffer advanced once per instance,
+// the vertex shader reads model and normal matrices from it instead of
+// the model_mat and normal_mat uniforms.
+
+// Layout must match the instance stream of gpu::instanced_mesh::layout()
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct InstanceData {
+    pub model: [[f32; 4]; 4],
+    pub normal: [[f32; 3]; 3],
+    pub tint: [f32; 4],
+}
+
+impl InstanceData {
+    pub fn new(model: Mat4, tint: Option<Vec4>) -> Self {
+        let normal = normal_matrix_of(&model);
+        let tint = tint.unwrap_or(Vec4::new(1.0, 1.0, 1.0, 1.0));
+
+        Self {
+            model: model.into(),
+            normal: normal.into(),
+            tint: tint.into(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct InstanceSet {
+    instances: Vec<InstanceData>,
+    dirty: bool,
+}
+
+#[allow(dead_code)]
+impl InstanceSet {
+    pub fn new() -> Self {
+        Self {
+            instances: vec![],
+            dirty: false,
+        }
+    }
+
+    // Returns index of the new instance within the set
+    pub fn push(&mut self, model: Mat4, tint: Option<Vec4>) -> usize {
+        self.instances.push(InstanceData::new(model, tint));
+        self.dirty = true;
+        self.instances.len() - 1
+    }
+
+    pub fn update(&mut self, index: usize, model: Mat4, tint: Option<Vec4>) {
+        self.instances[index] = InstanceData::new(model, tint);
+        self.dirty = true;
+    }
+
+    // NOTE: Swaps the last instance into the removed slot, its index changes.
+    pub fn swap_remove(&mut self, index: usize) {
+        self.instances.swap_remove(index);
+        self.dirty = true;
+    }
+
+    pub fn clear(&mut self) {
+        self.instances.clear();
+        self.dirty = true;
+    }
+
+    pub fn instances(&self) -> &[InstanceData] {
+        &self.instances
+    }
+
+    pub fn len(&self) -> usize {
+        self.instances.len()
+    }
+
+    // World bounds covering every instance of a mesh with the given local
+    // bounds, empty without instances
+    pub fn bounds(&self, mesh_bounds: &Aabb) -> Aabb {
+        self.instances.iter().fold(Aabb::empty(), |acc, d| {
+            acc.union(&transform_bounds(mesh_bounds, &Mat4::from(d.model)))
+        })
+    }
+
+    pub fn is_dirty(&self) -> bool {
+        self.dirty
+    }
+
+    pub fn mark_clean(&mut self) {
+        self.dirty = false;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    type Vec3 = cgmath::Vector3<f32>;
+
+    #[test]
+    fn bounds_cover_every_instance() {
+        let mesh = Aabb::new(Vec3::new(-1.0, -1.0, -1.0), Vec3::new(1.0, 1.0, 1.0));
+        let mut set = InstanceSet::new();
+
+        assert!(set.bounds(&mesh).is_empty());
+
+        set.push(Mat4::from_translation(Vec3::new(10.0, 0.0, 0.0)), None);
+        set.push(
+            Mat4::from_translation(Vec3::new(-5.0, 2.0, 0.0)) * Mat4::from_scale(2.0),
+            None,
+        );
+
+        let b = set.bounds(&mesh);
+        assert_eq!(b.min, Vec3::new(-7.0, -1.0, -2.0));
+        assert_eq!(b.max, Vec3::new(11.0, 4.0, 2.0));
+
+        set.swap_remove(0);
+        assert_eq!(set.bounds(&mesh).max.x, -3.0);
+
+        set.clear();
+        assert!(set.bounds(&mesh).is_empty());
+    }
+}
@@ -1,20 +1,60 @@
+use super::lighting::CookieId;
+
 type Vec3 = cgmath::Vector3<f32>;
 
 #[allow(dead_code)]
+#[derive(Debug, Clone)]
 pub struct DirLight {
-    intensity: f32,
-    direction: Vec3,
-    ambient: Vec3,
-    diffuse: Vec3,
-    specular: Vec3,
+    pub intensity: f32,
+    pub direction: Vec3,
+    pub ambient: Vec3,
+    pub diffuse: Vec3,
+    pub specular: Vec3,
+    // Only the first shadow casting directional light gets cascades
+    pub casts_shadow: bool,
+}
+
+#[allow(dead_code)]
+#[derive(Debug, Clone)]
+pub struct PointLight {
+    pub intensity: f32,
+    pub position: Vec3,
+    pub ambient: Vec3,
+    pub diffuse: Vec3,
+    pub specular: Vec3,
+    // Light fades out to zero at this distance, it also limits the shadow
+    pub range: f32,
+    pub casts_shadow: bool,
+    // Bias of the shadow distance comparison in world units
+    pub shadow_bias: f32,
+}
+
+#[allow(dead_code)]
+#[derive(Debug, Clone)]
+pub struct SpotLight {
+    pub intensity: f32,
+    pub position: Vec3,
+    pub direction: Vec3,
+    pub ambient: Vec3,
+    pub diffuse: Vec3,
+    pub specular: Vec3,
+    pub range: f32,
+    // Full intensity inside the inner cone, fades out towards the outer cone
+    pub inner_angle: cgmath::Rad<f32>,
+    pub outer_angle: cgmath::Rad<f32>,
+    // Texture projected along the cone, see Pipeline3D::add_spot_cookie
+    pub cookie: Option<CookieId>,
+    pub casts_shadow: bool,
+    // Bias of the shadow depth comparison in normalized depth units
+    pub shadow_bias: f32,
 }
 
 #[allow(dead_code)]
-struct PointLight {
-    position: Vec3,
-    ambient: Vec3,
-    diffuse: Vec3,
-    specular: Vec3,
+#[derive(Debug, Clone)]
+pub enum Light {
+    Directional(DirLight),
+    Point(PointLight),
+    Spot(SpotLight),
 }
 
 #[allow(dead_code)]
@@ -22,10 +62,11 @@ impl DirLight {
     pub fn default() -> Self {
         Self {
             intensity: 0.5,
-            direction: Vec3::new(0.0, -1.0, 1.0),
+            direction: Vec3::new(1.0, -1.0, 0.0),
             ambient: Vec3::new(0.5, 0.5, 0.5),
             diffuse: Vec3::new(0.5, 0.5, 0.5),
             specular: Vec3::new(0.5, 0.5, 0.5),
+            casts_shadow: true,
         }
     }
 }
@@ -34,10 +75,54 @@ impl DirLight {
 impl PointLight {
     pub fn default() -> Self {
         Self {
-            position: Vec3::new(1.0, 1.0, 1.0),
-            ambient: Vec3::new(0.5, 0.5, 0.5),
-            diffuse: Vec3::new(0.5, 0.5, 0.5),
+            intensity: 1.0,
+            position: Vec3::new(10.0, 10.0, 10.0),
+            ambient: Vec3::new(0.0, 0.0, 0.0),
+            diffuse: Vec3::new(1.0, 1.0, 1.0),
+            specular: Vec3::new(0.5, 0.0, 0.5),
+            range: 25.0,
+            casts_shadow: false,
+            shadow_bias: 0.05,
+        }
+    }
+}
+
+#[allow(dead_code)]
+impl SpotLight {
+    pub fn default() -> Self {
+        Self {
+            intensity: 1.0,
+            position: Vec3::new(0.0, 5.0, 0.0),
+            direction: Vec3::new(0.0, -1.0, 0.0),
+            ambient: Vec3::new(0.0, 0.0, 0.0),
+            diffuse: Vec3::new(1.0, 1.0, 1.0),
             specular: Vec3::new(0.5, 0.5, 0.5),
+            range: 20.0,
+            inner_angle: cgmath::Deg(20.0).into(),
+            outer_angle: cgmath::Deg(30.0).into(),
+            cookie: None,
+            casts_shadow: false,
+            shadow_bias: 0.0002,
+        }
+    }
+}
+
+#[allow(dead_code)]
+impl Light {
+    pub fn casts_shadow(&self) -> bool {
+        match self {
+            Self::Directional(l) => l.casts_shadow,
+            Self::Point(l) => l.casts_shadow,
+            Self::Spot(l) => l.casts_shadow,
+        }
+    }
+
+    // None for directional lights, they reach everything
+    pub fn position_and_range(&self) -> Option<(Vec3, f32)> {
+        match self {
+            Self::Directional(_) => None,
+            Self::Point(l) => Some((l.position, l.range)),
+            Self::Spot(l) => Some((l.position, l.range)),
         }
     }
 }
@@ -0,0 +1,566 @@
+use super::light_info::Light;
+use super::mgl::s3tc;
+use super::shadow;
+use crate::core::geometry::Aabb;
+use cgmath::prelude::*;
+use gl::types::*;
+
+type Vec3 = cgmath::Vector3<f32>;
+
+// Every light of the scene lives in one shader storage buffer, objects get
+// a short list of indices into it so the fragment shader only loops over
+// the lights that can reach them.
+
+pub const DEFAULT_LIGHTS_PER_OBJECT: usize = 8;
+// Size of the object_lights array in basic_frag.glsl
+pub const MAX_LIGHTS_PER_OBJECT: usize = 8;
+
+pub const LIGHT_DIRECTIONAL: i32 = 0;
+pub const LIGHT_POINT: i32 = 1;
+pub const LIGHT_SPOT: i32 = 2;
+
+// Mirrors struct Light of basic_frag.glsl with std430 layout
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct GpuLight {
+    // xyz position, w range
+    pub position: [f32; 4],
+    // xyz direction, w intensity
+    pub direction: [f32; 4],
+    pub ambient: [f32; 4],
+    pub diffuse: [f32; 4],
+    pub specular: [f32; 4],
+    // x cosine of the inner cone, y cosine of the outer cone, z shadow bias
+    pub params: [f32; 4],
+    // x light type, y shadow slot or -1, z cookie layer or -1
+    pub info: [i32; 4],
+    // Spot lights only, places the shadow map and cookie
+    pub projection: [[f32; 4]; 4],
+}
+
+impl GpuLight {
+    pub fn new(light: &Light, shadow: i32) -> Self {
+        let v4 = |v: Vec3, w: f32| [v.x, v.y, v.z, w];
+
+        match light {
+            Light::Directional(l) => Self {
+                position: [0.0; 4],
+                direction: v4(l.direction.normalize(), l.intensity),
+                ambient: v4(l.ambient, 0.0),
+                diffuse: v4(l.diffuse, 0.0),
+                specular: v4(l.specular, 0.0),
+                params: [0.0; 4],
+                info: [LIGHT_DIRECTIONAL, shadow, -1, 0],
+                projection: [[0.0; 4]; 4],
+            },
+            Light::Point(l) => Self {
+                position: v4(l.position, l.range),
+                direction: v4(Vec3::zero(), l.intensity),
+                ambient: v4(l.ambient, 0.0),
+                diffuse: v4(l.diffuse, 0.0),
+                specular: v4(l.specular, 0.0),
+                params: [0.0, 0.0, l.shadow_bias, 0.0],
+                info: [LIGHT_POINT, shadow, -1, 0],
+                projection: [[0.0; 4]; 4],
+            },
+            Light::Spot(l) => Self {
+                position: v4(l.position, l.range),
+                direction: v4(l.direction.normalize(), l.intensity),
+                ambient: v4(l.ambient, 0.0),
+                diffuse: v4(l.diffuse, 0.0),
+                specular: v4(l.specular, 0.0),
+                params: [
+                    l.inner_angle.0.cos(),
+                    l.outer_angle.0.cos(),
+                    l.shadow_bias,
+                    0.0,
+                ],
+                info: [LIGHT_SPOT, shadow, l.cookie.map_or(-1, |c| c.0 as i32), 0],
+                projection: shadow::spot_view_proj(l.position, l.direction, l.outer_angle, l.range)
+                    .into(),
+            },
+        }
+    }
+}
+
+// Stays valid until the light is removed, slots of removed lights are reused
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct LightId(usize);
+
+#[derive(Debug)]
+pub struct LightManager {
+    slots: Vec<Option<Light>>,
+    free: Vec<usize>,
+    buffer: GLuint,
+    per_object_limit: usize,
+}
+
+#[allow(dead_code)]
+impl LightManager {
+    pub fn new() -> Self {
+        let mut buffer = 0;
+
+        unsafe {
+            gl::CreateBuffers(1, &mut buffer);
+        }
+
+        Self {
+            slots: vec![],
+            free: vec![],
+            buffer: buffer,
+            per_object_limit: DEFAULT_LIGHTS_PER_OBJECT,
+        }
+    }
+
+    pub fn add(&mut self, light: Light) -> LightId {
+        match self.free.pop() {
+            Some(i) => {
+                self.slots[i] = Some(light);
+                LightId(i)
+            }
+            None => {
+                self.slots.push(Some(light));
+                LightId(self.slots.len() - 1)
+            }
+        }
+    }
+
+    pub fn remove(&mut self, id: LightId) -> Option<Light> {
+        let light = self.slots.get_mut(id.0).and_then(|s| s.take());
+        if light.is_some() {
+            self.free.push(id.0);
+        }
+        light
+    }
+
+    pub fn get(&self, id: LightId) -> Option<&Light> {
+        self.slots.get(id.0).and_then(|s| s.as_ref())
+    }
+
+    pub fn get_mut(&mut self, id: LightId) -> Option<&mut Light> {
+        self.slots.get_mut(id.0).and_then(|s| s.as_mut())
+    }
+
+    // Lights in buffer order, the position in this sequence is the index the
+    // shader uses
+    pub fn iter(&self) -> impl Iterator<Item = (LightId, &Light)> {
+        self.slots
+            .iter()
+            .enumerate()
+            .filter_map(|(i, s)| s.as_ref().map(|l| (LightId(i), l)))
+    }
+
+    pub fn len(&self) -> usize {
+        self.slots.len() - self.free.len()
+    }
+
+    pub fn per_object_limit(&self) -> usize {
+        self.per_object_limit
+    }
+
+    pub fn set_per_object_limit(&mut self, limit: usize) {
+        self.per_object_limit = limit.min(MAX_LIGHTS_PER_OBJECT);
+    }
+
+    // Writes every light into the storage buffer and binds it, shadows holds
+    // the shadow slot of each light in buffer order.
+    pub fn upload(&self, shadows: &[i32], binding: GLuint) {
+        let mut data: Vec<GpuLight> = self
+            .iter()
+            .enumerate()
+            .map(|(i, (_, l))| GpuLight::new(l, shadows.get(i).cloned().unwrap_or(-1)))
+            .collect();
+
+        // Zero sized buffers cannot be bound
+        if data.is_empty() {
+            data.push(GpuLight::default());
+        }
+
+        unsafe {
+            gl::NamedBufferData(
+                self.buffer,
+                (data.len() * std::mem::size_of::<GpuLight>()) as GLsizeiptr,
+                data.as_ptr() as *const GLvoid,
+                gl::DYNAMIC_DRAW,
+            );
+            gl::BindBufferBase(gl::SHADER_STORAGE_BUFFER, binding, self.buffer);
+        }
+    }
+
+    // Lights affecting an object, see select_lights
+    pub fn select(&self, bounds: Option<&Aabb>, eye: Vec3) -> Vec<i32> {
+        select_lights(
+            self.iter().map(|(_, l)| l),
+            bounds,
+            eye,
+            self.per_object_limit,
+        )
+    }
+}
+
+// Picks the lights affecting an object with the given world bounds, at most
+// limit of them, as indices into lights. Directional lights come first,
+// local lights follow ordered by how deep the object is inside their range.
+// Objects without bounds rank local lights by distance to the eye.
+pub fn select_lights<'l, I>(lights: I, bounds: Option<&Aabb>, eye: Vec3, limit: usize) -> Vec<i32>
+where
+    I: IntoIterator<Item = &'l Light>,
+{
+    let mut ranked: Vec<(f32, i32)> = vec![];
+
+    for (index, light) in lights.into_iter().enumerate() {
+        let score = match light.position_and_range() {
+            None => -1.0,
+            Some((position, range)) => {
+                let dist2 = match bounds {
+                    Some(b) => b.distance_squared_to_point(position),
+                    None => (position - eye).magnitude2(),
+                };
+
+                if bounds.is_some() && dist2 > range * range {
+                    continue;
+                }
+
+                dist2.sqrt() / range.max(std::f32::EPSILON)
+            }
+        };
+
+        ranked.push((score, index as i32));
+    }
+
+    ranked.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(std::cmp::Ordering::Equal));
+    ranked.truncate(limit);
+    ranked.into_iter().map(|(_, i)| i).collect()
+}
+
+impl Drop for LightManager {
+    fn drop(&mut self) {
+        // 0 is never a buffer name, managers built without a context hold it
+        if self.buffer == 0 {
+            return;
+        }
+
+        unsafe {
+            gl::DeleteBuffers(1, &self.buffer);
+        }
+    }
+}
+
+// Cookies are textures projected by spot lights, all of them are resampled
+// to one size and kept as layers of a single texture array.
+
+pub const MAX_COOKIES: usize = 8;
+pub const COOKIE_RESOLUTION: u32 = 256;
+
+// Layer of the cookie array
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct CookieId(usize);
+
+#[derive(Debug)]
+pub struct CookieArray {
+    pub texture: GLuint,
+    pub count: usize,
+}
+
+#[allow(dead_code)]
+impl CookieArray {
+    pub fn new() -> Self {
+        let mut texture = 0;
+        let levels = (COOKIE_RESOLUTION as f32).log2() as GLsizei + 1;
+
+        unsafe {
+            gl::CreateTextures(gl::TEXTURE_2D_ARRAY, 1, &mut texture);
+            gl::TextureStorage3D(
+                texture,
+                levels,
+                gl::RGBA8,
+                COOKIE_RESOLUTION as GLsizei,
+                COOKIE_RESOLUTION as GLsizei,
+                MAX_COOKIES as GLsizei,
+            );
+
+            gl::TextureParameteri(
+                texture,
+                gl::TEXTURE_MIN_FILTER,
+                gl::LINEAR_MIPMAP_LINEAR as GLint,
+            );
+            gl::TextureParameteri(texture, gl::TEXTURE_MAG_FILTER, gl::LINEAR as GLint);
+            gl::TextureParameteri(texture, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_BORDER as GLint);
+            gl::TextureParameteri(texture, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_BORDER as GLint);
+            // No light outside of the cookie
+            let border = [0.0f32; 4];
+            gl::TextureParameterfv(texture, gl::TEXTURE_BORDER_COLOR, border.as_ptr());
+        }
+
+        Self {
+            texture: texture,
+            count: 0,
+        }
+    }
+
+    // Adds a cookie from tightly packed RGBA8 pixels, None when the array is
+    // full or the pixels do not match the size.
+    pub fn add_rgba(&mut self, width: u32, height: u32, pixels: &[u8]) -> Option<CookieId> {
+        if self.count >= MAX_COOKIES
+            || width == 0
+            || height == 0
+            || pixels.len() < (width * height * 4) as usize
+        {
+            return None;
+        }
+
+        let res = COOKIE_RESOLUTION as usize;
+        let mut resampled = vec![0u8; res * res * 4];
+
+        // Nearest sampling is enough, the cookie is filtered by its mipmaps
+        for y in 0..res {
+            let sy = y * height as usize / res;
+            for x in 0..res {
+                let sx = x * width as usize / res;
+                let src = (sy * width as usize + sx) * 4;
+                let dst = (y * res + x) * 4;
+                resampled[dst..dst + 4].copy_from_slice(&pixels[src..src + 4]);
+            }
+        }
+
+        unsafe {
+            gl::TextureSubImage3D(
+                self.texture,
+                0,
+                0,
+                0,
+                self.count as GLint,
+                COOKIE_RESOLUTION as GLsizei,
+                COOKIE_RESOLUTION as GLsizei,
+                1,
+                gl::RGBA,
+                gl::UNSIGNED_BYTE,
+                resampled.as_ptr() as *const GLvoid,
+            );
+            gl::GenerateTextureMipmap(self.texture);
+        }
+
+        self.count += 1;
+        Some(CookieId(self.count - 1))
+    }
+
+    // Adds a cookie from a compressed image, the driver decompresses the
+    // smallest mipmap that is still at least as large as the cookie.
+    pub fn add_image(&mut self, image: &s3tc::Image) -> Option<CookieId> {
+        let mip = image
+            .mipmap_iter()
+            .take_while(|m| {
+                m.width as u32 >= COOKIE_RESOLUTION && m.height as u32 >= COOKIE_RESOLUTION
+            })
+            .last()
+            .or_else(|| image.mipmap_iter().next())?;
+
+        let (width, height) = (mip.width.max(1), mip.height.max(1));
+        let mut pixels = vec![0u8; (width * height * 4) as usize];
+        let mut texture = 0;
+        let mut pack_alignment: GLint = 0;
+
+        unsafe {
+            gl::GetIntegerv(gl::PACK_ALIGNMENT, &mut pack_alignment);
+            gl::CreateTextures(gl::TEXTURE_2D, 1, &mut texture);
+            gl::TextureStorage2D(texture, 1, image.format.gl_format(), width, height);
+            gl::CompressedTextureSubImage2D(
+                texture,
+                0,
+                0,
+                0,
+                width,
+                height,
+                image.format.gl_format(),
+                mip.data.len() as GLsizei,
+                mip.data.as_ptr() as *const GLvoid,
+            );
+            gl::PixelStorei(gl::PACK_ALIGNMENT, 1);
+            gl::GetTextureImage(
+                texture,
+                0,
+                gl::RGBA,
+                gl::UNSIGNED_BYTE,
+                pixels.len() as GLsizei,
+                pixels.as_mut_ptr() as *mut GLvoid,
+            );
+            gl::PixelStorei(gl::PACK_ALIGNMENT, pack_alignment);
+            gl::DeleteTextures(1, &texture);
+        }
+
+        self.add_rgba(width as u32, height as u32, &pixels)
+    }
+}
+
+impl Drop for CookieArray {
+    fn drop(&mut self) {
+        unsafe {
+            gl::DeleteTextures(1, &self.texture);
+        }
+    }
+}
+
+// World space bounds of local bounds moved by a transform
+pub fn transform_bounds(bounds: &Aabb, transform: &cgmath::Matrix4<f32>) -> Aabb {
+    if bounds.is_empty() {
+        return *bounds;
+    }
+
+    let mut out = Aabb::empty();
+
+    for i in 0..8 {
+        let corner = Vec3::new(
+            if i & 1 == 0 {
+                bounds.min.x
+            } else {
+                bounds.max.x
+            },
+            if i & 2 == 0 {
+                bounds.min.y
+            } else {
+                bounds.max.y
+            },
+            if i & 4 == 0 {
+                bounds.min.z
+            } else {
+                bounds.max.z
+            },
+        );
+        out.grow((transform * corner.extend(1.0)).truncate());
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::light_info::{DirLight, PointLight, SpotLight};
+    use super::*;
+
+    fn point(position: Vec3, range: f32) -> Light {
+        Light::Point(PointLight {
+            position: position,
+            range: range,
+            ..PointLight::default()
+        })
+    }
+
+    fn spot(position: Vec3, range: f32) -> Light {
+        Light::Spot(SpotLight {
+            position: position,
+            range: range,
+            ..SpotLight::default()
+        })
+    }
+
+    // Without a buffer, nothing here touches GL
+    fn manager() -> LightManager {
+        LightManager {
+            slots: vec![],
+            free: vec![],
+            buffer: 0,
+            per_object_limit: DEFAULT_LIGHTS_PER_OBJECT,
+        }
+    }
+
+    fn unit_box() -> Aabb {
+        Aabb::new(Vec3::new(-1.0, -1.0, -1.0), Vec3::new(1.0, 1.0, 1.0))
+    }
+
+    #[test]
+    fn directional_lights_come_first() {
+        let lights = [
+            point(Vec3::new(0.0, 0.0, 0.0), 10.0),
+            Light::Directional(DirLight::default()),
+        ];
+
+        assert_eq!(
+            select_lights(&lights, Some(&unit_box()), Vec3::zero(), 8),
+            vec![1, 0]
+        );
+    }
+
+    #[test]
+    fn lights_out_of_range_are_dropped() {
+        let lights = [
+            point(Vec3::new(5.0, 0.0, 0.0), 3.0),
+            spot(Vec3::new(0.0, 10.0, 0.0), 8.0),
+            point(Vec3::new(0.0, 0.0, 4.0), 2.0),
+        ];
+
+        // Distances to the box are 4, 9 and 3
+        assert_eq!(
+            select_lights(&lights, Some(&unit_box()), Vec3::zero(), 8),
+            Vec::<i32>::new()
+        );
+
+        // Without bounds every light is kept
+        assert_eq!(select_lights(&lights, None, Vec3::zero(), 8).len(), 3);
+    }
+
+    #[test]
+    fn local_lights_are_ranked_by_relative_distance() {
+        let lights = [
+            // 4 units away with range 8
+            point(Vec3::new(5.0, 0.0, 0.0), 8.0),
+            // 2 units away with range 8
+            spot(Vec3::new(0.0, 3.0, 0.0), 8.0),
+            // 2 units away with range 20
+            point(Vec3::new(0.0, 0.0, -3.0), 20.0),
+            // Inside the box
+            point(Vec3::new(0.5, 0.0, 0.0), 1.0),
+        ];
+
+        assert_eq!(
+            select_lights(&lights, Some(&unit_box()), Vec3::zero(), 8),
+            vec![3, 2, 1, 0]
+        );
+
+        // Without bounds the distance to the eye counts
+        let eye = Vec3::new(5.0, 0.0, 0.0);
+        assert_eq!(select_lights(&lights, None, eye, 8)[0], 0);
+    }
+
+    #[test]
+    fn selection_is_truncated_to_the_limit() {
+        let mut lights = manager();
+        lights.add(point(Vec3::new(4.0, 0.0, 0.0), 8.0));
+        lights.add(point(Vec3::new(2.0, 0.0, 0.0), 8.0));
+        lights.add(Light::Directional(DirLight::default()));
+
+        lights.set_per_object_limit(2);
+        assert_eq!(lights.select(Some(&unit_box()), Vec3::zero()), vec![2, 1]);
+
+        // Clamped to what the shader can hold
+        lights.set_per_object_limit(MAX_LIGHTS_PER_OBJECT + 4);
+        assert_eq!(lights.per_object_limit(), MAX_LIGHTS_PER_OBJECT);
+    }
+
+    #[test]
+    fn removed_slots_are_reused() {
+        let mut lights = manager();
+        let a = lights.add(point(Vec3::new(1.0, 0.0, 0.0), 1.0));
+        let b = lights.add(point(Vec3::new(2.0, 0.0, 0.0), 1.0));
+        let c = lights.add(point(Vec3::new(3.0, 0.0, 0.0), 1.0));
+
+        assert!(lights.remove(b).is_some());
+        assert!(lights.remove(b).is_none());
+        assert!(lights.get(b).is_none());
+        assert_eq!(lights.len(), 2);
+
+        let d = lights.add(Light::Directional(DirLight::default()));
+        assert_eq!(d, b);
+        assert_eq!(lights.len(), 3);
+
+        // Buffer order follows the slots
+        let ids: Vec<LightId> = lights.iter().map(|(id, _)| id).collect();
+        assert_eq!(ids, vec![a, d, c]);
+        assert!(match lights.get(d) {
+            Some(Light::Directional(_)) => true,
+            _ => false,
+        });
+
+        let e = lights.add(point(Vec3::new(4.0, 0.0, 0.0), 1.0));
+        assert_eq!(e, LightId(3));
+    }
+}
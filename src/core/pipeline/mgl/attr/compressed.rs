@@ -0,0 +1,820 @@
+use super::mesh3d::VertexAttributes;
+use crate::core::geometry::Aabb;
+use cgmath::prelude::*;
+use gl::types::*;
+
+type Vector4 = cgmath::Vector4<f32>;
+type Vector3 = cgmath::Vector3<f32>;
+type Vector2 = cgmath::Vector2<f32>;
+
+// Compact vertex storage decoded in the vertex shader. Positions are stored
+// relative to the mesh bounds, UVs relative to the UV bounds, normals and
+// tangents as octahedral snorm16 pairs. Per vertex size goes from 80 bytes
+// (position, normal, uv, tangent, bitangent, color, uv2) down to 32.
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[allow(dead_code)]
+pub enum PositionFormat {
+    // Half floats of the position normalized to the bounds
+    Half,
+    // UNORM16 of the position normalized to the bounds
+    Unorm16,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct CompressionOptions {
+    pub position_format: PositionFormat,
+    // Use u16 indices when every vertex can be addressed with them
+    pub allow_u16_indices: bool,
+}
+
+impl Default for CompressionOptions {
+    fn default() -> Self {
+        Self {
+            position_format: PositionFormat::Unorm16,
+            allow_u16_indices: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum Indices {
+    U16(Vec<u16>),
+    U32(Vec<u32>),
+}
+
+impl Indices {
+    pub fn len(&self) -> usize {
+        match self {
+            Self::U16(v) => v.len(),
+            Self::U32(v) => v.len(),
+        }
+    }
+
+    pub fn gl_type(&self) -> GLenum {
+        match self {
+            Self::U16(_) => gl::UNSIGNED_SHORT,
+            Self::U32(_) => gl::UNSIGNED_INT,
+        }
+    }
+
+    pub fn get(&self, i: usize) -> u32 {
+        match self {
+            Self::U16(v) => v[i] as u32,
+            Self::U32(v) => v[i],
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CompressedAttributes {
+    pub indices: Indices,
+    pub position_format: PositionFormat,
+    // xyz plus padding to keep the stream 8 byte aligned, u16 bits of
+    // either halfs or unorms depending on the position format
+    pub positions: Vec<[u16; 4]>,
+    pub normals: Vec<[i16; 2]>,
+    // Octahedral tangent, bitangent sign and padding
+    pub tangents: Vec<[i16; 4]>,
+    pub uvs: Vec<[u16; 2]>,
+    pub uvs2: Vec<[u16; 2]>,
+    pub colors: Vec<[u8; 4]>,
+    pub bounds: Aabb,
+    // Shared by both UV sets
+    pub uv_min: Vector2,
+    pub uv_extent: Vector2,
+}
+
+pub fn f32_to_half(value: f32) -> u16 {
+    let bits = value.to_bits();
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    let exp = ((bits >> 23) & 0xff) as i32;
+    let mantissa = bits & 0x007f_ffff;
+
+    if exp == 0xff {
+        // Infinity or NaN
+        let nan = if mantissa != 0 { 0x0200 } else { 0 };
+        return sign | 0x7c00 | nan;
+    }
+
+    let half_exp = exp - 127 + 15;
+
+    if half_exp >= 0x1f {
+        return sign | 0x7c00;
+    }
+
+    if half_exp <= 0 {
+        // Subnormal or zero
+        if half_exp < -10 {
+            return sign;
+        }
+        let m = mantissa | 0x0080_0000;
+        let shift = (14 - half_exp) as u32;
+        let half_m = m >> shift;
+        // Round to nearest even
+        let rest = m & ((1 << shift) - 1);
+        let halfway = 1 << (shift - 1);
+        let round = (rest > halfway || (rest == halfway && (half_m & 1) != 0)) as u32;
+        return sign | (half_m + round) as u16;
+    }
+
+    let half_m = mantissa >> 13;
+    let rest = mantissa & 0x1fff;
+    let round = (rest > 0x1000 || (rest == 0x1000 && (half_m & 1) != 0)) as u32;
+
+    // A mantissa overflow from rounding carries into the exponent as it should
+    sign | (((half_exp as u32) << 10) + half_m + round) as u16
+}
+
+pub fn half_to_f32(half: u16) -> f32 {
+    let sign = ((half & 0x8000) as u32) << 16;
+    let exp = ((half >> 10) & 0x1f) as u32;
+    let mantissa = (half & 0x03ff) as u32;
+
+    let bits = match exp {
+        0 if mantissa == 0 => sign,
+        0 => {
+            // Normalize the subnormal
+            let mut e = 127 - 15 + 1;
+            let mut m = mantissa;
+            while m & 0x0400 == 0 {
+                m <<= 1;
+                e -= 1;
+            }
+            sign | (e << 23) | ((m & 0x03ff) << 13)
+        }
+        0x1f => sign | 0x7f80_0000 | (mantissa << 13),
+        _ => sign | ((exp + 127 - 15) << 23) | (mantissa << 13),
+    };
+
+    f32::from_bits(bits)
+}
+
+fn to_unorm16(v: f32) -> u16 {
+    (v.max(0.0).min(1.0) * 65535.0).round() as u16
+}
+
+fn from_unorm16(v: u16) -> f32 {
+    v as f32 / 65535.0
+}
+
+fn to_snorm16(v: f32) -> i16 {
+    (v.max(-1.0).min(1.0) * 32767.0).round() as i16
+}
+
+// Matches the GL conversion of normalized signed integers
+fn from_snorm16(v: i16) -> f32 {
+    (v as f32 / 32767.0).max(-1.0)
+}
+
+fn sign_not_zero(v: f32) -> f32 {
+    if v >= 0.0 {
+        1.0
+    } else {
+        -1.0
+    }
+}
+
+pub fn oct_encode(n: Vector3) -> [i16; 2] {
+    let l1 = n.x.abs() + n.y.abs() + n.z.abs();
+    if l1 <= 0.0 {
+        return [0, 0];
+    }
+
+    let (mut x, mut y) = (n.x / l1, n.y / l1);
+
+    // Fold the lower hemisphere over the diagonals
+    if n.z < 0.0 {
+        let (fx, fy) = (
+            (1.0 - y.abs()) * sign_not_zero(x),
+            (1.0 - x.abs()) * sign_not_zero(y),
+        );
+        x = fx;
+        y = fy;
+    }
+
+    [to_snorm16(x), to_snorm16(y)]
+}
+
+pub fn oct_decode(e: [i16; 2]) -> Vector3 {
+    let (x, y) = (from_snorm16(e[0]), from_snorm16(e[1]));
+    let mut n = Vector3::new(x, y, 1.0 - x.abs() - y.abs());
+    let t = (-n.z).max(0.0);
+    n.x += if n.x >= 0.0 { -t } else { t };
+    n.y += if n.y >= 0.0 { -t } else { t };
+    n.normalize()
+}
+
+fn normalize_to(v: f32, min: f32, extent: f32) -> f32 {
+    if extent > 0.0 {
+        (v - min) / extent
+    } else {
+        0.0
+    }
+}
+
+// Tangents from IndexedMesh::generate_tangents are stored per index, those are
+// summed onto their vertices. Returns empty streams when the mesh has neither
+// layout.
+fn vertex_tangents(attrs: &VertexAttributes) -> (Vec<Vector3>, Vec<Vector3>) {
+    let vertex_count = attrs.positions.len();
+
+    if attrs.tangents.len() == vertex_count {
+        return (attrs.tangents.clone(), attrs.bitangents.clone());
+    }
+
+    if attrs.tangents.len() != attrs.indices.len() {
+        return (vec![], vec![]);
+    }
+
+    let mut tangents = vec![Vector3::zero(); vertex_count];
+    let mut bitangents = vec![Vector3::zero(); vertex_count];
+
+    for (k, i) in attrs.indices.iter().enumerate() {
+        let v = *i as usize;
+        let t = attrs.tangents[k];
+        // Degenerate UVs produce infinite tangents
+        if t.x.is_finite() && t.y.is_finite() && t.z.is_finite() {
+            tangents[v] += t;
+        }
+        if let Some(b) = attrs.bitangents.get(k) {
+            if b.x.is_finite() && b.y.is_finite() && b.z.is_finite() {
+                bitangents[v] += *b;
+            }
+        }
+    }
+
+    let normalize = |v: &mut Vector3| {
+        if v.magnitude2() > 0.0 {
+            *v = v.normalize();
+        }
+    };
+    tangents.iter_mut().for_each(normalize);
+    bitangents.iter_mut().for_each(normalize);
+
+    (tangents, bitangents)
+}
+
+impl CompressedAttributes {
+    pub fn compress(attrs: &VertexAttributes, options: &CompressionOptions) -> Self {
+        let vertex_count = attrs.positions.len();
+
+        let bounds = if vertex_count > 0 {
+            Aabb::from_points(attrs.positions.iter())
+        } else {
+            Aabb::new(Vector3::zero(), Vector3::zero())
+        };
+        let extent = bounds.extent();
+
+        let uvs2 = if attrs.uvs2.is_empty() {
+            &attrs.uvs
+        } else {
+            &attrs.uvs2
+        };
+
+        let (mut uv_min, mut uv_max) = (
+            Vector2::new(std::f32::MAX, std::f32::MAX),
+            Vector2::new(std::f32::MIN, std::f32::MIN),
+        );
+        for uv in attrs.uvs.iter().chain(uvs2.iter()) {
+            uv_min = Vector2::new(uv_min.x.min(uv.x), uv_min.y.min(uv.y));
+            uv_max = Vector2::new(uv_max.x.max(uv.x), uv_max.y.max(uv.y));
+        }
+        if uv_min.x > uv_max.x {
+            uv_min = Vector2::zero();
+            uv_max = Vector2::zero();
+        }
+        let uv_extent = uv_max - uv_min;
+
+        let positions = attrs
+            .positions
+            .iter()
+            .map(|p| {
+                let n = [
+                    normalize_to(p.x, bounds.min.x, extent.x),
+                    normalize_to(p.y, bounds.min.y, extent.y),
+                    normalize_to(p.z, bounds.min.z, extent.z),
+                ];
+                match options.position_format {
+                    PositionFormat::Half => {
+                        [f32_to_half(n[0]), f32_to_half(n[1]), f32_to_half(n[2]), 0]
+                    }
+                    PositionFormat::Unorm16 => {
+                        [to_unorm16(n[0]), to_unorm16(n[1]), to_unorm16(n[2]), 0]
+                    }
+                }
+            })
+            .collect();
+
+        let normal_at = |v: usize| {
+            attrs
+                .normals
+                .get(v)
+                .cloned()
+                .unwrap_or(Vector3::new(0.0, 0.0, 1.0))
+        };
+
+        let normals = (0..vertex_count)
+            .map(|v| oct_encode(normal_at(v)))
+            .collect();
+
+        let (vertex_tangents, vertex_bitangents) = vertex_tangents(attrs);
+        let tangents = (0..vertex_count)
+            .map(
+                |v| match (vertex_tangents.get(v), vertex_bitangents.get(v)) {
+                    (Some(t), b) => {
+                        let b = b.cloned().unwrap_or(normal_at(v).cross(*t));
+                        let sign = if normal_at(v).cross(*t).dot(b) < 0.0 {
+                            -32767
+                        } else {
+                            32767
+                        };
+                        let e = oct_encode(*t);
+                        [e[0], e[1], sign, 0]
+                    }
+                    (None, _) => [0, 0, 32767, 0],
+                },
+            )
+            .collect();
+
+        let encode_uvs = |src: &Vec<Vector2>| -> Vec<[u16; 2]> {
+            (0..vertex_count)
+                .map(|v| {
+                    let uv = src.get(v).cloned().unwrap_or(uv_min);
+                    [
+                        to_unorm16(normalize_to(uv.x, uv_min.x, uv_extent.x)),
+                        to_unorm16(normalize_to(uv.y, uv_min.y, uv_extent.y)),
+                    ]
+                })
+                .collect()
+        };
+
+        let colors = (0..vertex_count)
+            .map(|v| {
+                let c = attrs
+                    .colors
+                    .get(v)
+                    .cloned()
+                    .unwrap_or(Vector4::new(1.0, 1.0, 1.0, 1.0));
+                let to_u8 = |c: f32| (c.max(0.0).min(1.0) * 255.0).round() as u8;
+                [to_u8(c.x), to_u8(c.y), to_u8(c.z), to_u8(c.w)]
+            })
+            .collect();
+
+        let indices = if options.allow_u16_indices && vertex_count <= u16::MAX as usize + 1 {
+            Indices::U16(attrs.indices.iter().map(|i| *i as u16).collect())
+        } else {
+            Indices::U32(attrs.indices.clone())
+        };
+
+        Self {
+            indices: indices,
+            position_format: options.position_format,
+            positions: positions,
+            normals: normals,
+            tangents: tangents,
+            uvs: encode_uvs(&attrs.uvs),
+            uvs2: encode_uvs(uvs2),
+            colors: colors,
+            bounds: bounds,
+            uv_min: uv_min,
+            uv_extent: uv_extent,
+        }
+    }
+
+    pub fn vertex_count(&self) -> usize {
+        self.positions.len()
+    }
+
+    // Size of vertex and index data in bytes
+    pub fn byte_size(&self) -> usize {
+        let index_size = match self.indices {
+            Indices::U16(_) => 2,
+            Indices::U32(_) => 4,
+        };
+        self.vertex_count() * (8 + 4 + 8 + 4 + 4 + 4) + self.indices.len() * index_size
+    }
+
+    // CPU mirror of the decoding done in basic_vert.glsl
+    pub fn decompress(&self) -> VertexAttributes {
+        let extent = self.bounds.extent();
+
+        let positions = self
+            .positions
+            .iter()
+            .map(|p| {
+                let n: Vec<f32> = p[..3]
+                    .iter()
+                    .map(|c| match self.position_format {
+                        PositionFormat::Half => half_to_f32(*c),
+                        PositionFormat::Unorm16 => from_unorm16(*c),
+                    })
+                    .collect();
+                self.bounds.min + Vector3::new(n[0], n[1], n[2]).mul_element_wise(extent)
+            })
+            .collect::<Vec<Vector3>>();
+
+        let normals: Vec<Vector3> = self.normals.iter().map(|n| oct_decode(*n)).collect();
+
+        let tangents: Vec<Vector3> = self
+            .tangents
+            .iter()
+            .map(|t| oct_decode([t[0], t[1]]))
+            .collect();
+
+        let bitangents = self
+            .tangents
+            .iter()
+            .enumerate()
+            .map(|(v, t)| normals[v].cross(tangents[v]) * from_snorm16(t[2]))
+            .collect();
+
+        let decode_uvs = |src: &Vec<[u16; 2]>| -> Vec<Vector2> {
+            src.iter()
+                .map(|uv| {
+                    self.uv_min
+                        + Vector2::new(from_unorm16(uv[0]), from_unorm16(uv[1]))
+                            .mul_element_wise(self.uv_extent)
+                })
+                .collect()
+        };
+
+        let colors = self
+            .colors
+            .iter()
+            .map(|c| {
+                Vector4::new(
+                    c[0] as f32 / 255.0,
+                    c[1] as f32 / 255.0,
+                    c[2] as f32 / 255.0,
+                    c[3] as f32 / 255.0,
+                )
+            })
+            .collect();
+
+        VertexAttributes {
+            indices: (0..self.indices.len())
+                .map(|i| self.indices.get(i))
+                .collect(),
+            positions: positions,
+            normals: normals,
+            uvs: decode_uvs(&self.uvs),
+            tangents: tangents,
+            bitangents: bitangents,
+            colors: colors,
+            uvs2: decode_uvs(&self.uvs2),
+        }
+    }
+}
+
+// Largest errors of the compressed data against the original
+#[derive(Debug, Clone, Default)]
+pub struct AccuracyReport {
+    pub max_position_error: f32,
+    pub mean_position_error: f32,
+    // Angles in degrees
+    pub max_normal_error: f32,
+    pub max_tangent_error: f32,
+    pub max_uv_error: f32,
+    pub max_color_error: f32,
+    pub indices_match: bool,
+    pub original_size: usize,
+    pub compressed_size: usize,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Tolerances {
+    pub position: f32,
+    pub normal_degrees: f32,
+    pub uv: f32,
+    pub color: f32,
+}
+
+impl Tolerances {
+    // Worst case quantization error of each encoding plus a little slack
+    pub fn expected(compressed: &CompressedAttributes) -> Self {
+        // Per axis errors add up along the diagonal of the bounds
+        let diagonal = compressed.bounds.extent().magnitude();
+
+        let position_step = match compressed.position_format {
+            // 11 significant bits, the step is largest just below 1.0
+            PositionFormat::Half => 0.5 / 2048.0,
+            PositionFormat::Unorm16 => 0.5 / 65535.0,
+        };
+
+        let uv_extent = compressed.uv_extent.magnitude();
+
+        Self {
+            position: diagonal * position_step * 1.01 + 1e-6,
+            // Octahedral snorm16 stays below 0.03 degrees
+            normal_degrees: 0.05,
+            uv: uv_extent * 0.5 / 65535.0 * 1.01 + 1e-6,
+            color: 0.5 / 255.0 + 1e-6,
+        }
+    }
+}
+
+fn angle_degrees(a: Vector3, b: Vector3) -> f32 {
+    if a.magnitude2() <= 0.0 || b.magnitude2() <= 0.0 {
+        return 0.0;
+    }
+    a.normalize()
+        .dot(b.normalize())
+        .max(-1.0)
+        .min(1.0)
+        .acos()
+        .to_degrees()
+}
+
+#[allow(dead_code)]
+impl AccuracyReport {
+    pub fn measure(original: &VertexAttributes, compressed: &CompressedAttributes) -> Self {
+        let decoded = compressed.decompress();
+        let mut report = Self::default();
+
+        for (a, b) in original.positions.iter().zip(decoded.positions.iter()) {
+            let err = (a - b).magnitude();
+            report.max_position_error = report.max_position_error.max(err);
+            report.mean_position_error += err;
+        }
+        if !original.positions.is_empty() {
+            report.mean_position_error /= original.positions.len() as f32;
+        }
+
+        for (a, b) in original.normals.iter().zip(decoded.normals.iter()) {
+            report.max_normal_error = report.max_normal_error.max(angle_degrees(*a, *b));
+        }
+
+        let (tangents, _) = vertex_tangents(original);
+        for (a, b) in tangents.iter().zip(decoded.tangents.iter()) {
+            report.max_tangent_error = report.max_tangent_error.max(angle_degrees(*a, *b));
+        }
+
+        let uvs2 = if original.uvs2.is_empty() {
+            &original.uvs
+        } else {
+            &original.uvs2
+        };
+        let uv_pairs = original
+            .uvs
+            .iter()
+            .zip(decoded.uvs.iter())
+            .chain(uvs2.iter().zip(decoded.uvs2.iter()));
+        for (a, b) in uv_pairs {
+            let err = (a.x - b.x).abs().max((a.y - b.y).abs());
+            report.max_uv_error = report.max_uv_error.max(err);
+        }
+
+        for (a, b) in original.colors.iter().zip(decoded.colors.iter()) {
+            let d = a - b;
+            let err = d.x.abs().max(d.y.abs()).max(d.z.abs()).max(d.w.abs());
+            report.max_color_error = report.max_color_error.max(err);
+        }
+
+        report.indices_match = original.indices == decoded.indices;
+        report.original_size = original.positions.len() * 80 + original.indices.len() * 4;
+        report.compressed_size = compressed.byte_size();
+
+        report
+    }
+
+    // Lists every measurement that exceeds its tolerance
+    pub fn check(&self, tolerances: &Tolerances) -> Result<(), Vec<String>> {
+        let mut failures = vec![];
+
+        let mut expect = |name: &str, value: f32, limit: f32| {
+            if value > limit {
+                failures.push(format!("{} error {} exceeds {}", name, value, limit));
+            }
+        };
+
+        expect("Position", self.max_position_error, tolerances.position);
+        expect("Normal", self.max_normal_error, tolerances.normal_degrees);
+        expect("Tangent", self.max_tangent_error, tolerances.normal_degrees);
+        expect("UV", self.max_uv_error, tolerances.uv);
+        expect("Color", self.max_color_error, tolerances.color);
+
+        if !self.indices_match {
+            failures.push("Indices differ".to_owned());
+        }
+
+        if failures.is_empty() {
+            Ok(())
+        } else {
+            Err(failures)
+        }
+    }
+}
+
+// Compresses the mesh and checks the decoded data against the expected
+// quantization error of every encoding
+#[allow(dead_code)]
+pub fn verify_accuracy(
+    attrs: &VertexAttributes,
+    options: &CompressionOptions,
+) -> Result<AccuracyReport, Vec<String>> {
+    let compressed = CompressedAttributes::compress(attrs, options);
+    let report = AccuracyReport::measure(attrs, &compressed);
+    report.check(&Tolerances::expected(&compressed))?;
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::mesh3d::IndexedMesh;
+    use super::*;
+
+    // Cube with four vertices per face, every face a quad of two triangles.
+    // UVs span uv_min to uv_max on every face, U follows the first face axis.
+    fn cube(center: Vector3, size: f32, uv_min: Vector2, uv_max: Vector2) -> IndexedMesh {
+        let axes = [
+            Vector3::unit_x(),
+            Vector3::unit_y(),
+            Vector3::unit_z(),
+            -Vector3::unit_x(),
+            -Vector3::unit_y(),
+            -Vector3::unit_z(),
+        ];
+
+        let mut attrs = VertexAttributes {
+            indices: vec![],
+            positions: vec![],
+            normals: vec![],
+            uvs: vec![],
+            tangents: vec![],
+            bitangents: vec![],
+            colors: vec![],
+            uvs2: vec![],
+        };
+
+        for (f, n) in axes.iter().enumerate() {
+            let u = axes[(f + 1) % 6];
+            let v = n.cross(u);
+            let base = attrs.positions.len() as u32;
+
+            for &(s, t) in &[(0.0, 0.0), (1.0, 0.0), (1.0, 1.0), (0.0, 1.0)] {
+                let corner = *n * 0.5 + u * (s - 0.5) + v * (t - 0.5);
+                attrs.positions.push(center + corner * size);
+                attrs.normals.push(*n);
+                attrs.uvs.push(Vector2::new(
+                    uv_min.x + (uv_max.x - uv_min.x) * s,
+                    uv_min.y + (uv_max.y - uv_min.y) * t,
+                ));
+                attrs.colors.push(Vector4::new(s, t, f as f32 / 5.0, 1.0));
+            }
+
+            attrs
+                .indices
+                .extend_from_slice(&[base, base + 1, base + 2, base, base + 2, base + 3]);
+        }
+
+        let mut im = IndexedMesh::new(attrs);
+        // One tangent per index, the layout compress has to cope with
+        im.generate_tangents();
+        im
+    }
+
+    fn all_options() -> Vec<CompressionOptions> {
+        vec![
+            CompressionOptions::default(),
+            CompressionOptions {
+                position_format: PositionFormat::Half,
+                allow_u16_indices: false,
+            },
+        ]
+    }
+
+    fn assert_within_tolerances(im: &IndexedMesh) -> Vec<AccuracyReport> {
+        all_options()
+            .iter()
+            .map(|options| {
+                let compressed = CompressedAttributes::compress(&im.attributes, options);
+                let tolerances = Tolerances::expected(&compressed);
+                let report = AccuracyReport::measure(&im.attributes, &compressed);
+
+                assert!(report.max_position_error <= tolerances.position);
+                assert!(report.max_normal_error <= tolerances.normal_degrees);
+                assert!(report.max_tangent_error <= tolerances.normal_degrees);
+                assert!(report.max_uv_error <= tolerances.uv);
+                assert!(report.max_color_error <= tolerances.color);
+                assert!(report.indices_match);
+                assert_eq!(
+                    verify_accuracy(&im.attributes, options)
+                        .unwrap()
+                        .compressed_size,
+                    report.compressed_size
+                );
+                report
+            })
+            .collect()
+    }
+
+    #[test]
+    fn unit_cube_stays_within_tolerances() {
+        let im = cube(
+            Vector3::zero(),
+            1.0,
+            Vector2::new(0.0, 0.0),
+            Vector2::new(1.0, 1.0),
+        );
+
+        for report in assert_within_tolerances(&im) {
+            assert!(report.compressed_size < report.original_size);
+        }
+    }
+
+    #[test]
+    fn negative_and_large_coordinates_stay_within_tolerances() {
+        assert_within_tolerances(&cube(
+            Vector3::new(-2500.0, 40.0, 9000.0),
+            3000.0,
+            Vector2::new(0.0, 0.0),
+            Vector2::new(1.0, 1.0),
+        ));
+        assert_within_tolerances(&cube(
+            Vector3::new(-0.001, -0.002, -0.003),
+            0.0005,
+            Vector2::new(0.0, 0.0),
+            Vector2::new(1.0, 1.0),
+        ));
+    }
+
+    #[test]
+    fn uvs_outside_the_unit_range_stay_within_tolerances() {
+        let mut im = cube(
+            Vector3::new(1.0, 2.0, 3.0),
+            2.0,
+            Vector2::new(-3.5, 2.0),
+            Vector2::new(6.0, -4.25),
+        );
+        // A second set with a different range widens the shared bounds
+        im.attributes.uvs2 = im
+            .attributes
+            .uvs
+            .iter()
+            .map(|uv| Vector2::new(uv.x * 2.0 + 10.0, -uv.y))
+            .collect();
+
+        assert_within_tolerances(&im);
+    }
+
+    #[test]
+    fn per_index_tangents_are_moved_onto_vertices() {
+        let im = cube(
+            Vector3::zero(),
+            1.0,
+            Vector2::new(0.0, 0.0),
+            Vector2::new(1.0, 1.0),
+        );
+        let attrs = &im.attributes;
+        assert_eq!(attrs.tangents.len(), attrs.indices.len());
+
+        let compressed = CompressedAttributes::compress(attrs, &CompressionOptions::default());
+        let decoded = compressed.decompress();
+        assert_eq!(decoded.tangents.len(), attrs.positions.len());
+
+        // Every vertex tangent follows the U direction of its own face
+        for (k, i) in attrs.indices.iter().enumerate() {
+            let v = *i as usize;
+            assert!(angle_degrees(attrs.tangents[k], decoded.tangents[v]) < 0.05);
+
+            let n = attrs.normals[v];
+            let expected_sign = n.cross(attrs.tangents[k]).dot(attrs.bitangents[k]);
+            let sign = n.cross(decoded.tangents[v]).dot(decoded.bitangents[v]);
+            assert_eq!(expected_sign < 0.0, sign < 0.0);
+        }
+    }
+
+    #[test]
+    fn exceeded_tolerances_are_listed() {
+        let report = AccuracyReport {
+            max_position_error: 1.0,
+            max_uv_error: 0.5,
+            indices_match: false,
+            ..AccuracyReport::default()
+        };
+        let tolerances = Tolerances {
+            position: 0.1,
+            normal_degrees: 0.05,
+            uv: 0.1,
+            color: 0.01,
+        };
+
+        assert_eq!(report.check(&tolerances).unwrap_err().len(), 3);
+        assert!(AccuracyReport {
+            indices_match: true,
+            ..AccuracyReport::default()
+        }
+        .check(&tolerances)
+        .is_ok());
+    }
+
+    #[test]
+    fn half_conversion_round_trips() {
+        for &v in &[0.0, 1.0, -2.5, 0.333, 65504.0, 6.1e-5, 1e-7] {
+            let err = (half_to_f32(f32_to_half(v)) - v).abs();
+            assert!(err <= v.abs() / 1024.0 + 6e-8, "{}", v);
+        }
+        assert!(half_to_f32(f32_to_half(1e6)).is_infinite());
+    }
+}
@@ -0,0 +1,213 @@
+use super::AttributeType;
+use gl::types::*;
+
+// Runtime description of how vertex data is laid out in buffers. A layout is
+// a list of streams, each stream is backed by one buffer and holds one
+// (separate) or several (interleaved) attributes.
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[allow(dead_code)]
+pub enum Semantic {
+    Position,
+    Normal,
+    Uv,
+    Uv2,
+    Tangent,
+    Bitangent,
+    Color,
+    Joints,
+    Weights,
+    // Per instance data, matrices take one attribute per column
+    InstanceModel,
+    InstanceNormal,
+    InstanceTint,
+    Custom(u32),
+}
+
+impl Semantic {
+    // Value used for vertices whose mesh does not provide the attribute
+    pub fn default_value(&self) -> [f32; 4] {
+        match self {
+            Self::Color => [1.0, 1.0, 1.0, 1.0],
+            Self::Weights => [1.0, 0.0, 0.0, 0.0],
+            _ => [0.0, 0.0, 0.0, 0.0],
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct VertexAttribute {
+    pub semantic: Semantic,
+    pub format: AttributeType,
+    pub location: GLuint,
+    // Integer formats are converted to [0,1] or [-1,1] instead of plain floats
+    pub normalized: bool,
+    // Integer formats are passed to the shader as ints (ivec/uvec inputs)
+    pub integer: bool,
+    // Byte offset inside the stream, filled in by the stream
+    pub offset: GLuint,
+}
+
+impl VertexAttribute {
+    pub fn new(semantic: Semantic, format: AttributeType, location: GLuint) -> Self {
+        Self {
+            semantic: semantic,
+            format: format,
+            location: location,
+            normalized: false,
+            integer: false,
+            offset: 0,
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn normalized(mut self) -> Self {
+        self.normalized = true;
+        self
+    }
+
+    #[allow(dead_code)]
+    pub fn integer(mut self) -> Self {
+        self.integer = true;
+        self
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct VertexStream {
+    pub attributes: Vec<VertexAttribute>,
+    pub stride: GLuint,
+    // Zero advances per vertex, N advances once every N instances
+    pub divisor: GLuint,
+}
+
+impl VertexStream {
+    // Attributes are packed one after another in the given order
+    pub fn interleaved(attributes: Vec<VertexAttribute>) -> Self {
+        let mut attributes = attributes;
+        let mut offset = 0;
+
+        for a in attributes.iter_mut() {
+            a.offset = offset;
+            offset += a.format.size();
+        }
+
+        Self {
+            attributes: attributes,
+            stride: offset,
+            divisor: 0,
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn per_instance(mut self, divisor: GLuint) -> Self {
+        self.divisor = divisor;
+        self
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct VertexLayout {
+    pub streams: Vec<VertexStream>,
+}
+
+impl VertexLayout {
+    pub fn new() -> Self {
+        Self { streams: vec![] }
+    }
+
+    // Every attribute gets its own stream
+    pub fn separate(mut self, attributes: Vec<VertexAttribute>) -> Self {
+        for a in attributes {
+            self.streams.push(VertexStream::interleaved(vec![a]));
+        }
+        self
+    }
+
+    #[allow(dead_code)]
+    pub fn interleaved(mut self, attributes: Vec<VertexAttribute>) -> Self {
+        self.streams.push(VertexStream::interleaved(attributes));
+        self
+    }
+
+    #[allow(dead_code)]
+    pub fn stream(mut self, stream: VertexStream) -> Self {
+        self.streams.push(stream);
+        self
+    }
+
+    pub fn stream_count(&self) -> usize {
+        self.streams.len()
+    }
+
+    #[allow(dead_code)]
+    pub fn find(&self, semantic: Semantic) -> Option<(usize, &VertexAttribute)> {
+        self.streams.iter().enumerate().find_map(|(i, s)| {
+            s.attributes
+                .iter()
+                .find(|a| a.semantic == semantic)
+                .map(|a| (i, a))
+        })
+    }
+
+    // Creates a vertex array object with stream N bound to vertex_buffers[N].
+    // Buffers can be reallocated later without touching the VAO.
+    pub fn create_vao(&self, vertex_buffers: &[GLuint], index_buffer: Option<GLuint>) -> GLuint {
+        assert!(
+            vertex_buffers.len() >= self.streams.len(),
+            "Vertex layout has {} streams but only {} buffers were given",
+            self.streams.len(),
+            vertex_buffers.len()
+        );
+
+        let mut vao = 0;
+
+        unsafe {
+            gl::CreateVertexArrays(1, &mut vao);
+
+            for (binding, stream) in self.streams.iter().enumerate() {
+                let binding = binding as GLuint;
+
+                gl::VertexArrayVertexBuffer(
+                    vao,
+                    binding,
+                    vertex_buffers[binding as usize],
+                    0,
+                    stream.stride as GLsizei,
+                );
+                gl::VertexArrayBindingDivisor(vao, binding, stream.divisor);
+
+                for a in &stream.attributes {
+                    gl::EnableVertexArrayAttrib(vao, a.location);
+
+                    if a.integer {
+                        gl::VertexArrayAttribIFormat(
+                            vao,
+                            a.location,
+                            a.format.count() as GLint,
+                            a.format.gl_type(),
+                            a.offset,
+                        );
+                    } else {
+                        gl::VertexArrayAttribFormat(
+                            vao,
+                            a.location,
+                            a.format.count() as GLint,
+                            a.format.gl_type(),
+                            if a.normalized { gl::TRUE } else { gl::FALSE },
+                            a.offset,
+                        );
+                    }
+
+                    gl::VertexArrayAttribBinding(vao, a.location, binding);
+                }
+            }
+
+            if let Some(index) = index_buffer {
+                gl::VertexArrayElementBuffer(vao, index);
+            }
+        }
+
+        vao
+    }
+}
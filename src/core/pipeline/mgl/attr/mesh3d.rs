@@ -1,13 +1,15 @@
+use super::layout::Semantic;
 use gl::types::*;
 use std::convert::TryInto;
 // use super::AttributeType;
 
 type MeshIndex = GLuint;
 
+type Vector4 = cgmath::Vector4<f32>;
 type Vector3 = cgmath::Vector3<f32>;
 type Vector2 = cgmath::Vector2<f32>;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct VertexAttributes {
     // NOTE: we do not use vector types for attributes because we may want
     // different number of components for some attributes
@@ -17,6 +19,33 @@ pub struct VertexAttributes {
     pub uvs: Vec<Vector2>,
     pub tangents: Vec<Vector3>,
     pub bitangents: Vec<Vector3>, //
+    // Optional streams, left empty when the source has none. RGBA colors
+    // default to white and the second UV set falls back to the first one.
+    pub colors: Vec<Vector4>,
+    pub uvs2: Vec<Vector2>,
+}
+
+fn as_bytes<T>(v: &[T]) -> (&[u8], usize) {
+    let size = std::mem::size_of::<T>();
+    let bytes = unsafe { std::slice::from_raw_parts(v.as_ptr() as *const u8, v.len() * size) };
+    (bytes, size)
+}
+
+impl VertexAttributes {
+    // Raw bytes and per vertex size of the stream matching a layout semantic
+    pub fn semantic_data(&self, semantic: Semantic) -> Option<(&[u8], usize)> {
+        match semantic {
+            Semantic::Position => Some(as_bytes(&self.positions)),
+            Semantic::Normal => Some(as_bytes(&self.normals)),
+            Semantic::Uv => Some(as_bytes(&self.uvs)),
+            Semantic::Tangent => Some(as_bytes(&self.tangents)),
+            Semantic::Bitangent => Some(as_bytes(&self.bitangents)),
+            Semantic::Color if !self.colors.is_empty() => Some(as_bytes(&self.colors)),
+            Semantic::Uv2 if !self.uvs2.is_empty() => Some(as_bytes(&self.uvs2)),
+            Semantic::Uv2 => Some(as_bytes(&self.uvs)),
+            _ => None,
+        }
+    }
 }
 
 pub mod lightmaps {
@@ -27,6 +56,7 @@ pub mod lightmaps {
     pub enum LightMaps {
         Basic(Basic),
         NormalMapped(NormalMapped),
+        Pbr(Pbr),
     }
 
     pub struct Basic {
@@ -39,9 +69,27 @@ pub mod lightmaps {
         pub specular: Image,
         pub normal: Image,
     }
+
+    // Metallic-roughness material shaded with the GGX BRDF. The material map
+    // holds ambient occlusion, roughness and metallic in its R, G and B
+    // channels like glTF, it takes the place of the specular map.
+    pub struct Pbr {
+        pub albedo: Image,
+        pub normal: Image,
+        pub material: Image,
+    }
+
+    // Up to four diffuse layers blended by the RGBA channels of the splat map,
+    // normal and specular maps are tiled together with the layers.
+    pub struct Splat {
+        pub splat: Image,
+        pub layers: Vec<Image>,
+        pub specular: Image,
+        pub normal: Image,
+    }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct IndexedMesh {
     pub attributes: VertexAttributes,
 }
@@ -71,6 +119,98 @@ impl IndexedMesh {
         self.attributes.indices.as_ptr() as *const GLvoid
     }
 
+    // Smooth per-vertex normals weighted by triangle area
+    #[allow(dead_code)]
+    pub fn generate_normals(&mut self) {
+        use cgmath::InnerSpace;
+
+        let indices = &self.attributes.indices;
+        let pos = &self.attributes.positions;
+        let normals = &mut self.attributes.normals;
+
+        normals.clear();
+        normals.resize(pos.len(), Vector3::new(0.0, 0.0, 0.0));
+
+        for tri in indices.chunks_exact(3) {
+            let (a, b, c) = (tri[0] as usize, tri[1] as usize, tri[2] as usize);
+            // Cross product length is twice the area of the triangle
+            let n = (pos[b] - pos[a]).cross(pos[c] - pos[a]);
+            normals[a] += n;
+            normals[b] += n;
+            normals[c] += n;
+        }
+
+        for n in normals.iter_mut() {
+            if n.magnitude2() > 0.0 {
+                *n = n.normalize();
+            }
+        }
+    }
+
+    // One tangent and bitangent per vertex accumulated over the triangles
+    // sharing it, made orthogonal to the normal when there is one. Unlike
+    // generate_tangents the streams line up with positions, so vertices
+    // shared by several indices get a single smooth tangent.
+    pub fn generate_vertex_tangents(&mut self) {
+        use cgmath::InnerSpace;
+
+        let indices = &self.attributes.indices;
+        let pos = &self.attributes.positions;
+        let uvs = &self.attributes.uvs;
+        let normals = &self.attributes.normals;
+        let tans = &mut self.attributes.tangents;
+        let bitans = &mut self.attributes.bitangents;
+
+        tans.clear();
+        bitans.clear();
+        tans.resize(pos.len(), Vector3::new(0.0, 0.0, 0.0));
+        bitans.resize(pos.len(), Vector3::new(0.0, 0.0, 0.0));
+
+        if uvs.len() < pos.len() {
+            return;
+        }
+
+        for tri in indices.chunks_exact(3) {
+            let (a, b, c) = (tri[0] as usize, tri[1] as usize, tri[2] as usize);
+
+            let edge1 = pos[b] - pos[a];
+            let edge2 = pos[c] - pos[a];
+            let delta_uv1 = uvs[b] - uvs[a];
+            let delta_uv2 = uvs[c] - uvs[a];
+
+            let d = delta_uv1.x * delta_uv2.y - delta_uv2.x * delta_uv1.y;
+
+            // Degenerate UVs give no direction, leave the vertices alone
+            if d.abs() <= std::f32::EPSILON {
+                continue;
+            }
+
+            let f = 1.0 / d;
+            let tan = (edge1 * delta_uv2.y - edge2 * delta_uv1.y) * f;
+            let bitan = (edge2 * delta_uv1.x - edge1 * delta_uv2.x) * f;
+
+            for v in [a, b, c].iter() {
+                tans[*v] += tan;
+                bitans[*v] += bitan;
+            }
+        }
+
+        for (i, (t, b)) in tans.iter_mut().zip(bitans.iter_mut()).enumerate() {
+            if let Some(n) = normals.get(i) {
+                *t -= n * n.dot(*t);
+                *b -= n * n.dot(*b);
+            }
+
+            if t.magnitude2() > 0.0 {
+                *t = t.normalize();
+            }
+
+            if b.magnitude2() > 0.0 {
+                *b = b.normalize();
+            }
+        }
+    }
+
     pub fn generate_tangents(&mut self) {
         let indices = &self.attributes.indices;
         let pos = &self.attributes.positions;
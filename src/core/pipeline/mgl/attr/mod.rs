@@ -1,24 +1,46 @@
+pub mod compressed;
+pub mod layout;
 pub mod mesh3d;
 pub mod uniform;
 pub use gl::types::*;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 #[allow(dead_code)]
 pub enum AttributeType {
     Vec2,
     Vec3,
     Vec4,
     Float,
+    Half2,
+    Half4,
+    Byte4,
+    UByte4,
+    Short2,
+    Short4,
+    UShort2,
+    UShort4,
+    Int,
+    UInt,
+    UInt4,
+    // Three 10 bit components and a 2 bit one packed into 32 bits
+    Int2101010Rev,
 }
 
 impl AttributeType {
     #[allow(dead_code)]
     pub fn count(&self) -> GLuint {
         match self {
-            Self::Float => 1,
-            Self::Vec2 => 2,
+            Self::Float | Self::Int | Self::UInt => 1,
+            Self::Vec2 | Self::Half2 | Self::Short2 | Self::UShort2 => 2,
             Self::Vec3 => 3,
-            Self::Vec4 => 4,
+            Self::Vec4
+            | Self::Half4
+            | Self::Byte4
+            | Self::UByte4
+            | Self::Short4
+            | Self::UShort4
+            | Self::UInt4
+            | Self::Int2101010Rev => 4,
         }
     }
 
@@ -29,6 +51,30 @@ impl AttributeType {
             Self::Vec2 => gl::FLOAT,
             Self::Vec3 => gl::FLOAT,
             Self::Vec4 => gl::FLOAT,
+            Self::Half2 | Self::Half4 => gl::HALF_FLOAT,
+            Self::Byte4 => gl::BYTE,
+            Self::UByte4 => gl::UNSIGNED_BYTE,
+            Self::Short2 | Self::Short4 => gl::SHORT,
+            Self::UShort2 | Self::UShort4 => gl::UNSIGNED_SHORT,
+            Self::Int => gl::INT,
+            Self::UInt | Self::UInt4 => gl::UNSIGNED_INT,
+            Self::Int2101010Rev => gl::INT_2_10_10_10_REV,
+        }
+    }
+
+    // Size of one attribute value in bytes
+    #[allow(dead_code)]
+    pub fn size(&self) -> GLuint {
+        match self {
+            Self::Int2101010Rev => 4,
+            _ => {
+                let component = match self.gl_type() {
+                    gl::BYTE | gl::UNSIGNED_BYTE => 1,
+                    gl::SHORT | gl::UNSIGNED_SHORT | gl::HALF_FLOAT => 2,
+                    _ => 4,
+                };
+                component * self.count()
+            }
         }
     }
 }
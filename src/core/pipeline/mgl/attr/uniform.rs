@@ -1,26 +1,87 @@
 use crate::core::pipeline::mgl::shader::ShaderIssue;
+use gl::types::*;
 use std::convert::TryFrom;
 
 #[derive(Debug, Clone)]
 pub struct UniformDefinition {
-    pub id: gl::types::GLint,
+    // Index of the active uniform, not usable with glUniform*
+    #[allow(dead_code)]
+    pub index: GLint,
+    // -1 for members of uniform blocks
+    pub location: GLint,
+    pub program: GLuint,
     pub name: String,
-    pub data_size: gl::types::GLint,
+    // Number of array elements, 1 for plain uniforms
+    pub data_size: GLint,
 }
 
 impl UniformDefinition {
-    pub fn new(id: gl::types::GLint, name: String, data_size: gl::types::GLint) -> Self {
+    pub fn new(
+        index: GLint,
+        location: GLint,
+        program: GLuint,
+        name: String,
+        data_size: GLint,
+    ) -> Self {
         Self {
-            id: id,
+            index: index,
+            location: location,
+            program: program,
             name: name,
             data_size: data_size,
         }
     }
+
+    // Number of elements to pass to glProgramUniform*
+    fn upload_count(&self, count: usize) -> Result<GLsizei, ShaderIssue> {
+        if self.location < 0 {
+            return Err(ShaderIssue::UnsupportedUniformOperation(format!(
+                "{} has no location, members of uniform blocks are set through their buffer",
+                self.name
+            )));
+        }
+
+        if count == 0 || count > self.data_size as usize {
+            return Err(ShaderIssue::UnsupportedUniformOperation(format!(
+                "{} holds {} element(s) but {} were given",
+                self.name, self.data_size, count
+            )));
+        }
+
+        Ok(count as GLsizei)
+    }
 }
 
-trait UniformUpload {
+// Typed upload of uniform values, arrays are filled from their first
+// element. The program does not have to be active.
+pub trait UniformUpload {
     type Item;
-    fn upload(&self, item: Self::Item);
+
+    fn upload_array(&self, items: &[Self::Item]) -> Result<(), ShaderIssue>;
+
+    fn upload(&self, item: Self::Item) -> Result<(), ShaderIssue> {
+        self.upload_array(std::slice::from_ref(&item))
+    }
+}
+
+macro_rules! impl_uniform_upload {
+    ($($struct:ident),+: $item:ty => |$def:ident, $count:ident, $items:ident| $body:expr) => {
+        $(
+            impl UniformUpload for $struct {
+                type Item = $item;
+
+                fn upload_array(&self, items: &[$item]) -> Result<(), ShaderIssue> {
+                    let $def = &self.def;
+                    let $count = $def.upload_count(items.len())?;
+                    let $items = items;
+                    unsafe {
+                        $body;
+                    }
+                    Ok(())
+                }
+            }
+        )+
+    };
 }
 
 macro_rules! impl_from_uniform_def {
@@ -66,7 +127,7 @@ macro_rules! impl_from_uniform_defs {
                 fn try_from(other: Uniform) -> Result<$struct_rest, Self::Error> {
                     match other {
                         Uniform::$enum_rest(unif) => Ok(unif),
-                        _ => Err(ShaderIssue::UnsupportedUniformOperation(format!("Incorrect uniform type it cannot be converted to {}: {:?} ", stringify!($enum), other)))
+                        _ => Err(ShaderIssue::UnsupportedUniformOperation(format!("Incorrect uniform type it cannot be converted to {}: {:?} ", stringify!($enum_rest), other)))
                     }
                 }
             }
@@ -101,6 +162,10 @@ impl_from_uniform_defs! {
     (INT_VEC4, Vec4iUniform, Vec4i),
     (UNSIGNED_INT_VEC2, Vec2uiUniform, Vec2ui),
     (UNSIGNED_INT_VEC3, Vec3uiUniform, Vec3ui),
+    (UNSIGNED_INT_VEC4, Vec4uiUniform, Vec4ui),
+    (DOUBLE_VEC2, Vec2dUniform, Vec2d),
+    (DOUBLE_VEC3, Vec3dUniform, Vec3d),
+    (DOUBLE_VEC4, Vec4dUniform, Vec4d),
     (BOOL_VEC2, Vec2bUniform, Vec2b),
     (BOOL_VEC3, Vec3bUniform, Vec3b),
     (BOOL_VEC4, Vec4bUniform, Vec4b),
@@ -109,6 +174,7 @@ impl_from_uniform_defs! {
     (FLOAT_MAT3x2, Mat3x2Uniform, Mat3x2),
     (FLOAT_MAT3x4, Mat3x4Uniform, Mat3x4),
     (FLOAT_MAT4x2, Mat4x2Uniform, Mat4x2),
+    (FLOAT_MAT4x3, Mat4x3Uniform, Mat4x3),
     (DOUBLE_MAT2, Mat2dUniform, Mat2d),
     (DOUBLE_MAT3, Mat3dUniform, Mat3d),
     (DOUBLE_MAT4, Mat4dUniform, Mat4d),
@@ -131,6 +197,10 @@ impl_from_uniform_defs! {
     (SAMPLER_2D_MULTISAMPLE, Sampler2DMSUniform, Sampler2DMS),
     (SAMPLER_2D_MULTISAMPLE_ARRAY, Sampler2DMSArrayUniform, Sampler2DMSArray),
     (SAMPLER_CUBE_SHADOW, SamplerCubeShadowUniform, SamplerCubeShadow),
+    (SAMPLER_CUBE_MAP_ARRAY, SamplerCubeArrayUniform, SamplerCubeArray),
+    (SAMPLER_CUBE_MAP_ARRAY_SHADOW, SamplerCubeArrayShadowUniform, SamplerCubeArrayShadow),
+    (INT_SAMPLER_CUBE_MAP_ARRAY, SamplerCubeArrayiUniform, SamplerCubeArrayi),
+    (UNSIGNED_INT_SAMPLER_CUBE_MAP_ARRAY, SamplerCubeArrayuUniform, SamplerCubeArrayu),
     (SAMPLER_BUFFER, SamplerBufferUniform, SamplerBuffer),
     (SAMPLER_2D_RECT, Sampler2DRectUniform, Sampler2DRect),
     (SAMPLER_2D_RECT_SHADOW, Sampler2DRectShadowUniform, Sampler2DRectShadow),
@@ -160,6 +230,7 @@ impl_from_uniform_defs! {
     (IMAGE_2D_RECT, Image2DRectUniform, Image2DRect),
     (IMAGE_CUBE, ImageCubeUniform, ImageCube),
     (IMAGE_BUFFER, ImageBufferUniform, ImageBuffer),
+    (IMAGE_CUBE_MAP_ARRAY, ImageCubeArrayUniform, ImageCubeArray),
     (IMAGE_1D_ARRAY, Image1DArrayUniform, Image1DArray),
     (IMAGE_2D_ARRAY, Image2DArrayUniform, Image2DArray),
     (IMAGE_2D_MULTISAMPLE, Image2DMSUniform, Image2DMS),
@@ -186,3 +257,107 @@ impl_from_uniform_defs! {
     (UNSIGNED_INT_IMAGE_2D_MULTISAMPLE_ARRAY, Image2DMSArrayuUniform, Image2DMSArrayu),
     (UNSIGNED_INT_ATOMIC_COUNTER, AtomicCounteruiUniform, AtomicCounterui),
 }
+
+type Vector2<T> = cgmath::Vector2<T>;
+type Vector3<T> = cgmath::Vector3<T>;
+type Vector4<T> = cgmath::Vector4<T>;
+type Matrix2<T> = cgmath::Matrix2<T>;
+type Matrix3<T> = cgmath::Matrix3<T>;
+type Matrix4<T> = cgmath::Matrix4<T>;
+
+// cgmath vectors and matrices are repr(C) arrays of their components, so a
+// slice of them can be passed as a pointer to the first component.
+// Non square matrices have no cgmath type and take arrays of columns.
+
+impl_uniform_upload!(IntUniform: i32 => |d, n, v| gl::ProgramUniform1iv(d.program, d.location, n, v.as_ptr()));
+impl_uniform_upload!(UIntUniform: u32 => |d, n, v| gl::ProgramUniform1uiv(d.program, d.location, n, v.as_ptr()));
+impl_uniform_upload!(FloatUniform: f32 => |d, n, v| gl::ProgramUniform1fv(d.program, d.location, n, v.as_ptr()));
+impl_uniform_upload!(DoubleUniform: f64 => |d, n, v| gl::ProgramUniform1dv(d.program, d.location, n, v.as_ptr()));
+
+// Booleans are set as integers
+impl_uniform_upload!(BoolUniform: bool => |d, n, v| {
+    let ints: Vec<i32> = v.iter().map(|b| *b as i32).collect();
+    gl::ProgramUniform1iv(d.program, d.location, n, ints.as_ptr())
+});
+impl_uniform_upload!(Vec2bUniform: [bool; 2] => |d, n, v| {
+    let ints: Vec<i32> = v.iter().flatten().map(|b| *b as i32).collect();
+    gl::ProgramUniform2iv(d.program, d.location, n, ints.as_ptr())
+});
+impl_uniform_upload!(Vec3bUniform: [bool; 3] => |d, n, v| {
+    let ints: Vec<i32> = v.iter().flatten().map(|b| *b as i32).collect();
+    gl::ProgramUniform3iv(d.program, d.location, n, ints.as_ptr())
+});
+impl_uniform_upload!(Vec4bUniform: [bool; 4] => |d, n, v| {
+    let ints: Vec<i32> = v.iter().flatten().map(|b| *b as i32).collect();
+    gl::ProgramUniform4iv(d.program, d.location, n, ints.as_ptr())
+});
+
+impl_uniform_upload!(Vec2Uniform: Vector2<f32> => |d, n, v| gl::ProgramUniform2fv(d.program, d.location, n, v.as_ptr() as *const f32));
+impl_uniform_upload!(Vec3Uniform: Vector3<f32> => |d, n, v| gl::ProgramUniform3fv(d.program, d.location, n, v.as_ptr() as *const f32));
+impl_uniform_upload!(Vec4Uniform: Vector4<f32> => |d, n, v| gl::ProgramUniform4fv(d.program, d.location, n, v.as_ptr() as *const f32));
+impl_uniform_upload!(Vec2iUniform: Vector2<i32> => |d, n, v| gl::ProgramUniform2iv(d.program, d.location, n, v.as_ptr() as *const i32));
+impl_uniform_upload!(Vec3iUniform: Vector3<i32> => |d, n, v| gl::ProgramUniform3iv(d.program, d.location, n, v.as_ptr() as *const i32));
+impl_uniform_upload!(Vec4iUniform: Vector4<i32> => |d, n, v| gl::ProgramUniform4iv(d.program, d.location, n, v.as_ptr() as *const i32));
+impl_uniform_upload!(Vec2uiUniform: Vector2<u32> => |d, n, v| gl::ProgramUniform2uiv(d.program, d.location, n, v.as_ptr() as *const u32));
+impl_uniform_upload!(Vec3uiUniform: Vector3<u32> => |d, n, v| gl::ProgramUniform3uiv(d.program, d.location, n, v.as_ptr() as *const u32));
+impl_uniform_upload!(Vec4uiUniform: Vector4<u32> => |d, n, v| gl::ProgramUniform4uiv(d.program, d.location, n, v.as_ptr() as *const u32));
+impl_uniform_upload!(Vec2dUniform: Vector2<f64> => |d, n, v| gl::ProgramUniform2dv(d.program, d.location, n, v.as_ptr() as *const f64));
+impl_uniform_upload!(Vec3dUniform: Vector3<f64> => |d, n, v| gl::ProgramUniform3dv(d.program, d.location, n, v.as_ptr() as *const f64));
+impl_uniform_upload!(Vec4dUniform: Vector4<f64> => |d, n, v| gl::ProgramUniform4dv(d.program, d.location, n, v.as_ptr() as *const f64));
+
+impl_uniform_upload!(Mat2Uniform: Matrix2<f32> => |d, n, v| gl::ProgramUniformMatrix2fv(d.program, d.location, n, gl::FALSE, v.as_ptr() as *const f32));
+impl_uniform_upload!(Mat3Uniform: Matrix3<f32> => |d, n, v| gl::ProgramUniformMatrix3fv(d.program, d.location, n, gl::FALSE, v.as_ptr() as *const f32));
+impl_uniform_upload!(Mat4Uniform: Matrix4<f32> => |d, n, v| gl::ProgramUniformMatrix4fv(d.program, d.location, n, gl::FALSE, v.as_ptr() as *const f32));
+impl_uniform_upload!(Mat2dUniform: Matrix2<f64> => |d, n, v| gl::ProgramUniformMatrix2dv(d.program, d.location, n, gl::FALSE, v.as_ptr() as *const f64));
+impl_uniform_upload!(Mat3dUniform: Matrix3<f64> => |d, n, v| gl::ProgramUniformMatrix3dv(d.program, d.location, n, gl::FALSE, v.as_ptr() as *const f64));
+impl_uniform_upload!(Mat4dUniform: Matrix4<f64> => |d, n, v| gl::ProgramUniformMatrix4dv(d.program, d.location, n, gl::FALSE, v.as_ptr() as *const f64));
+
+// MatCxR has C columns of R rows
+impl_uniform_upload!(Mat2x3Uniform: [[f32; 3]; 2] => |d, n, v| gl::ProgramUniformMatrix2x3fv(d.program, d.location, n, gl::FALSE, v.as_ptr() as *const f32));
+impl_uniform_upload!(Mat2x4Uniform: [[f32; 4]; 2] => |d, n, v| gl::ProgramUniformMatrix2x4fv(d.program, d.location, n, gl::FALSE, v.as_ptr() as *const f32));
+impl_uniform_upload!(Mat3x2Uniform: [[f32; 2]; 3] => |d, n, v| gl::ProgramUniformMatrix3x2fv(d.program, d.location, n, gl::FALSE, v.as_ptr() as *const f32));
+impl_uniform_upload!(Mat3x4Uniform: [[f32; 4]; 3] => |d, n, v| gl::ProgramUniformMatrix3x4fv(d.program, d.location, n, gl::FALSE, v.as_ptr() as *const f32));
+impl_uniform_upload!(Mat4x2Uniform: [[f32; 2]; 4] => |d, n, v| gl::ProgramUniformMatrix4x2fv(d.program, d.location, n, gl::FALSE, v.as_ptr() as *const f32));
+impl_uniform_upload!(Mat4x3Uniform: [[f32; 3]; 4] => |d, n, v| gl::ProgramUniformMatrix4x3fv(d.program, d.location, n, gl::FALSE, v.as_ptr() as *const f32));
+impl_uniform_upload!(Mat2x3dUniform: [[f64; 3]; 2] => |d, n, v| gl::ProgramUniformMatrix2x3dv(d.program, d.location, n, gl::FALSE, v.as_ptr() as *const f64));
+impl_uniform_upload!(Mat2x4dUniform: [[f64; 4]; 2] => |d, n, v| gl::ProgramUniformMatrix2x4dv(d.program, d.location, n, gl::FALSE, v.as_ptr() as *const f64));
+impl_uniform_upload!(Mat3x2dUniform: [[f64; 2]; 3] => |d, n, v| gl::ProgramUniformMatrix3x2dv(d.program, d.location, n, gl::FALSE, v.as_ptr() as *const f64));
+impl_uniform_upload!(Mat3x4dUniform: [[f64; 4]; 3] => |d, n, v| gl::ProgramUniformMatrix3x4dv(d.program, d.location, n, gl::FALSE, v.as_ptr() as *const f64));
+impl_uniform_upload!(Mat4x2dUniform: [[f64; 2]; 4] => |d, n, v| gl::ProgramUniformMatrix4x2dv(d.program, d.location, n, gl::FALSE, v.as_ptr() as *const f64));
+impl_uniform_upload!(Mat4x3dUniform: [[f64; 3]; 4] => |d, n, v| gl::ProgramUniformMatrix4x3dv(d.program, d.location, n, gl::FALSE, v.as_ptr() as *const f64));
+
+// Samplers and images take the texture or image unit
+impl_uniform_upload!(
+    Sampler1DUniform, Sampler2DUniform, Sampler3DUniform, SamplerCubeUniform,
+    Sampler1DShadowUniform, Sampler2DShadowUniform, Sampler1DArrayUniform,
+    Sampler2DArrayUniform, Sampler1DArrayShadowUniform, Sampler2DArrayShadowUniform,
+    Sampler2DMSUniform, Sampler2DMSArrayUniform, SamplerCubeShadowUniform,
+    SamplerCubeArrayUniform, SamplerCubeArrayShadowUniform, SamplerCubeArrayiUniform,
+    SamplerCubeArrayuUniform, SamplerBufferUniform, Sampler2DRectUniform,
+    Sampler2DRectShadowUniform, Sampler1DiUniform, Sampler2DiUniform, Sampler3DiUniform,
+    SamplerCubeiUniform, Sampler1DArrayiUniform, Sampler2DArrayiUniform, Sampler2DMSiUniform,
+    Sampler2DMSArrayiUniform, SamplerBufferiUniform, Sampler2DRectiUniform, Sampler1DuUniform,
+    Sampler2DuUniform, Sampler3DuUniform, SamplerCubeuUniform, Sampler1DArrayuUniform,
+    Sampler2DArrayuUniform, Sampler2DMSuUniform, Sampler2DMSArrayuUniform,
+    SamplerBufferuUniform, Sampler2DuiUniform, Image1DUniform, Image2DUniform,
+    Image3DUniform, Image2DRectUniform, ImageCubeUniform, ImageBufferUniform,
+    ImageCubeArrayUniform, Image1DArrayUniform, Image2DArrayUniform, Image2DMSUniform,
+    Image2DMSArrayUniform, Image1DiUniform, Image2DiUniform, Image3DiUniform,
+    Image2DRectiUniform, ImageCubeiUniform, ImageBufferiUniform, Image1DArrayiUniform,
+    Image2DArrayiUniform, Image2DMSiUniform, Image2DMSArrayiUniform, Image1DuUniform,
+    Image2DuUniform, Image3DuUniform, Image2DRectuUniform, ImageCubeuUniform,
+    ImageBufferuUniform, Image1DArrayuUniform, Image2DArrayuUniform, Image2DMSuUniform,
+    Image2DMSArrayuUniform: i32 => |d, n, v| gl::ProgramUniform1iv(d.program, d.location, n, v.as_ptr())
+);
+
+// Atomic counter bindings are fixed in the shader
+impl UniformUpload for AtomicCounteruiUniform {
+    type Item = u32;
+
+    fn upload_array(&self, _items: &[u32]) -> Result<(), ShaderIssue> {
+        Err(ShaderIssue::UnsupportedUniformOperation(format!(
+            "{} is an atomic counter and cannot be set",
+            self.def.name
+        )))
+    }
+}
@@ -1,3 +1,4 @@
 pub mod attr;
 pub mod s3tc;
 pub mod shader;
+pub mod ubo;
@@ -14,6 +14,10 @@ impl Format {
     }
 }
 
+// DDSCAPS2 flags of cube maps, every face has to be present
+const DDSCAPS2_CUBEMAP: u32 = 0x200;
+const DDSCAPS2_CUBEMAP_ALL_FACES: u32 = 0xfc00;
+
 pub struct S3MipmapDesc {
     pub offset: usize,
     pub size: usize,
@@ -34,18 +38,22 @@ pub struct Image {
     pub format: Format,
     pub block_size: u32,
     pub data: Vec<u8>,
+    // Mipmaps of one face, offsets are relative to the face
     pub mipmaps: Vec<S3MipmapDesc>,
+    // 6 for cube maps stored face after face in GL order, 1 otherwise
+    pub faces: u32,
+    pub face_size: usize,
 }
 
 pub type MipmapDescIter<'a> = std::slice::Iter<'a, S3MipmapDesc>;
 
 pub struct S3MipmapIter<'a> {
-    pub data: &'a Vec<u8>,
+    pub data: &'a [u8],
     pub desc_iter: MipmapDescIter<'a>,
 }
 
 impl<'a> S3MipmapIter<'a> {
-    pub fn new(data: &'a Vec<u8>, desc_iter: MipmapDescIter<'a>) -> Self {
+    pub fn new(data: &'a [u8], desc_iter: MipmapDescIter<'a>) -> Self {
         Self {
             data: data,
             desc_iter: desc_iter,
@@ -104,13 +112,25 @@ impl Image {
             ));
         }
 
-        let buffer = header.drain(128..).collect();
+        let buffer: Vec<u8> = header.drain(128..).collect();
 
         let width = get_u32!(header, 12);
         let height = get_u32!(header, 16);
         let linear_size = get_u32!(header, 20);
-        let mipmap_count = get_u32!(header, 28);
+        // Files without mipmaps may leave the count at 0
+        let mipmap_count = get_u32!(header, 28).max(1);
         let four_cc = String::from_utf8(header[84..88].to_vec()).unwrap();
+        let caps2 = get_u32!(header, 112);
+
+        let faces = if caps2 & DDSCAPS2_CUBEMAP == 0 {
+            1
+        } else if caps2 & DDSCAPS2_CUBEMAP_ALL_FACES == DDSCAPS2_CUBEMAP_ALL_FACES {
+            6
+        } else {
+            return Err(ImageError::InvalidData(
+                "Cube map is missing faces!".to_owned(),
+            ));
+        };
 
         println!("DXT format: {}", four_cc);
         println!("DXT image size: {},{}", width, height);
@@ -143,6 +163,14 @@ impl Image {
             mip_h /= 2;
         }
 
+        if buffer.len() < mip_offset * faces as usize {
+            return Err(ImageError::InvalidData(format!(
+                "DDS data is {} bytes, expected {}",
+                buffer.len(),
+                mip_offset * faces as usize
+            )));
+        }
+
         Ok(Image {
             width: width as i32,
             height: height as i32,
@@ -151,10 +179,22 @@ impl Image {
             block_size: block_size,
             data: buffer,
             mipmaps: mipmaps,
+            faces: faces,
+            face_size: mip_offset,
         })
     }
 
+    pub fn is_cubemap(&self) -> bool {
+        self.faces == 6
+    }
+
     pub fn mipmap_iter(&self) -> S3MipmapIter {
         S3MipmapIter::new(&self.data, self.mipmaps.iter())
     }
+
+    // Mipmaps of one cube map face, face 0 for plain images
+    pub fn face_mipmap_iter(&self, face: u32) -> S3MipmapIter<'_> {
+        let start = face as usize * self.face_size;
+        S3MipmapIter::new(&self.data[start..], self.mipmaps.iter())
+    }
 }
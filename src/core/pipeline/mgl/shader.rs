@@ -7,6 +7,8 @@ pub enum ShaderIssue {
     LinkError(String),
     StringConversionError(String),
     UnsupportedUniformOperation(String),
+    UniformBlockMismatch(String),
+    MissingUniform(String),
 }
 
 impl std::fmt::Display for ShaderIssue {
@@ -20,6 +22,8 @@ impl std::fmt::Display for ShaderIssue {
             Self::UnsupportedUniformOperation(msg) => {
                 write!(f, "Invalid Uniform operation: {}", msg)
             }
+            Self::UniformBlockMismatch(msg) => write!(f, "Uniform block layout mismatch: {}", msg),
+            Self::MissingUniform(name) => write!(f, "Uniform {} is not active", name),
         }
     }
 }
@@ -51,6 +55,15 @@ impl ShaderProgram {
 
         let program_id = unsafe { gl::CreateProgram() };
 
+        unsafe {
+            // Needed by some drivers for glGetProgramBinary to return anything
+            gl::ProgramParameteri(
+                program_id,
+                gl::PROGRAM_BINARY_RETRIEVABLE_HINT,
+                gl::TRUE as GLint,
+            );
+        }
+
         for shader in shaders {
             unsafe {
                 gl::AttachShader(program_id, shader.id());
@@ -65,6 +78,69 @@ impl ShaderProgram {
             unsafe { gl::DetachShader(program_id, shader.id()) };
         }
 
+        Self::from_linked(program_id)
+    }
+
+    // Program previously returned by ShaderProgram::binary, fails with a link
+    // error when the driver rejects the binary
+    pub fn from_binary(format: gl::types::GLenum, binary: &[u8]) -> Result<Self, ShaderIssue> {
+        use gl::types::*;
+
+        let program_id = unsafe { gl::CreateProgram() };
+
+        unsafe {
+            gl::ProgramBinary(
+                program_id,
+                format,
+                binary.as_ptr() as *const GLvoid,
+                binary.len() as GLsizei,
+            );
+        }
+
+        Self::from_linked(program_id)
+    }
+
+    // Format and data of the linked program, None if the driver does not
+    // support retrieving it
+    pub fn binary(&self) -> Option<(gl::types::GLenum, Vec<u8>)> {
+        use gl::types::*;
+
+        let mut len: GLint = 0;
+        unsafe {
+            gl::GetProgramiv(self.id, gl::PROGRAM_BINARY_LENGTH, &mut len);
+        }
+
+        if len <= 0 {
+            return None;
+        }
+
+        let mut data = vec![0u8; len as usize];
+        let mut written: GLsizei = 0;
+        let mut format: GLenum = 0;
+
+        unsafe {
+            gl::GetProgramBinary(
+                self.id,
+                len,
+                &mut written,
+                &mut format,
+                data.as_mut_ptr() as *mut GLvoid,
+            );
+        }
+
+        if written <= 0 {
+            return None;
+        }
+
+        data.truncate(written as usize);
+        Some((format, data))
+    }
+
+    // Takes ownership of a program after glLinkProgram or glProgramBinary,
+    // the program is deleted if linking failed
+    fn from_linked(program_id: gl::types::GLuint) -> Result<Self, ShaderIssue> {
+        use gl::types::*;
+
         let mut success: GLint = 1;
         unsafe {
             gl::GetProgramiv(program_id, gl::LINK_STATUS, &mut success);
@@ -84,7 +160,8 @@ impl ShaderProgram {
                     mesg_len,
                     std::ptr::null_mut(),
                     mesg.as_ptr() as *mut GLchar,
-                )
+                );
+                gl::DeleteProgram(program_id);
             }
 
             return Err(ShaderIssue::LinkError(mesg.to_string_lossy().into_owned()));
@@ -127,7 +204,15 @@ impl ShaderProgram {
                     }
                 };
 
-                let def = UniformDefinition::new(uid, unif_name, unif_size);
+                let location = gl::GetUniformLocation(program_id, name_buf.as_ptr());
+
+                // Arrays are reported as name[0], they are looked up without the suffix
+                let unif_name = match unif_name.strip_suffix("[0]") {
+                    Some(base) => base.to_owned(),
+                    None => unif_name,
+                };
+
+                let def = UniformDefinition::new(uid, location, program_id, unif_name, unif_size);
 
                 let unif = Uniform::from_type(unif_type, def);
 
@@ -145,6 +230,19 @@ impl ShaderProgram {
         });
     }
 
+    pub fn id(&self) -> gl::types::GLuint {
+        self.id
+    }
+
+    #[allow(dead_code)]
+    // Location usable with glUniform*, -1 if the uniform is not active
+    pub fn uniform_location(&self, name: &str) -> gl::types::GLint {
+        match CString::new(name) {
+            Ok(cname) => unsafe { gl::GetUniformLocation(self.id, cname.as_ptr()) },
+            Err(_) => -1,
+        }
+    }
+
     pub fn uniform_by_name(&self, name: &str) -> Option<Uniform> {
         for unif in &self.uniform_defs {
             let def = unif.get_def();
@@ -0,0 +1,459 @@
+use crate::core::pipeline::mgl::shader::{ShaderIssue, ShaderProgram};
+use gl::types::*;
+use std::ffi::CString;
+use std::marker::PhantomData;
+
+type Vec2 = cgmath::Vector2<f32>;
+type Vec3 = cgmath::Vector3<f32>;
+type Vec4 = cgmath::Vector4<f32>;
+type Point3 = cgmath::Point3<f32>;
+type Mat3 = cgmath::Matrix3<f32>;
+type Mat4 = cgmath::Matrix4<f32>;
+
+// Values that can be members of a std140 uniform block. ALIGN is the base
+// alignment and SIZE the bytes written, both as defined by the std140 rules.
+pub trait Std140 {
+    const ALIGN: usize;
+    const SIZE: usize;
+    const GL_TYPE: GLenum;
+    fn write_std140(&self, out: &mut [u8]);
+}
+
+fn write_f32s(values: &[f32], out: &mut [u8]) {
+    for (i, v) in values.iter().enumerate() {
+        out[i * 4..i * 4 + 4].copy_from_slice(&v.to_ne_bytes());
+    }
+}
+
+macro_rules! impl_std140_scalar {
+    ($ty:ty, $gl_type:ident) => {
+        impl Std140 for $ty {
+            const ALIGN: usize = 4;
+            const SIZE: usize = 4;
+            const GL_TYPE: GLenum = gl::$gl_type;
+            fn write_std140(&self, out: &mut [u8]) {
+                out[..4].copy_from_slice(&self.to_ne_bytes());
+            }
+        }
+    };
+}
+
+impl_std140_scalar!(f32, FLOAT);
+impl_std140_scalar!(i32, INT);
+impl_std140_scalar!(u32, UNSIGNED_INT);
+
+impl Std140 for bool {
+    const ALIGN: usize = 4;
+    const SIZE: usize = 4;
+    const GL_TYPE: GLenum = gl::BOOL;
+    fn write_std140(&self, out: &mut [u8]) {
+        (*self as u32).write_std140(out);
+    }
+}
+
+impl Std140 for Vec2 {
+    const ALIGN: usize = 8;
+    const SIZE: usize = 8;
+    const GL_TYPE: GLenum = gl::FLOAT_VEC2;
+    fn write_std140(&self, out: &mut [u8]) {
+        write_f32s(&[self.x, self.y], out);
+    }
+}
+
+// vec3 is aligned like vec4 but only takes 12 bytes, a following scalar
+// fills the gap
+impl Std140 for Vec3 {
+    const ALIGN: usize = 16;
+    const SIZE: usize = 12;
+    const GL_TYPE: GLenum = gl::FLOAT_VEC3;
+    fn write_std140(&self, out: &mut [u8]) {
+        write_f32s(&[self.x, self.y, self.z], out);
+    }
+}
+
+impl Std140 for Point3 {
+    const ALIGN: usize = 16;
+    const SIZE: usize = 12;
+    const GL_TYPE: GLenum = gl::FLOAT_VEC3;
+    fn write_std140(&self, out: &mut [u8]) {
+        write_f32s(&[self.x, self.y, self.z], out);
+    }
+}
+
+impl Std140 for Vec4 {
+    const ALIGN: usize = 16;
+    const SIZE: usize = 16;
+    const GL_TYPE: GLenum = gl::FLOAT_VEC4;
+    fn write_std140(&self, out: &mut [u8]) {
+        write_f32s(&[self.x, self.y, self.z, self.w], out);
+    }
+}
+
+// Matrix columns are padded to vec4
+impl Std140 for Mat3 {
+    const ALIGN: usize = 16;
+    const SIZE: usize = 48;
+    const GL_TYPE: GLenum = gl::FLOAT_MAT3;
+    fn write_std140(&self, out: &mut [u8]) {
+        self.x.write_std140(&mut out[0..]);
+        self.y.write_std140(&mut out[16..]);
+        self.z.write_std140(&mut out[32..]);
+    }
+}
+
+impl Std140 for Mat4 {
+    const ALIGN: usize = 16;
+    const SIZE: usize = 64;
+    const GL_TYPE: GLenum = gl::FLOAT_MAT4;
+    fn write_std140(&self, out: &mut [u8]) {
+        self.x.write_std140(&mut out[0..]);
+        self.y.write_std140(&mut out[16..]);
+        self.z.write_std140(&mut out[32..]);
+        self.w.write_std140(&mut out[48..]);
+    }
+}
+
+pub const fn align_to(offset: usize, align: usize) -> usize {
+    (offset + align - 1) / align * align
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BlockMember {
+    pub name: &'static str,
+    pub offset: usize,
+    pub gl_type: GLenum,
+}
+
+// Implemented by uniform_block!, the member names match the GLSL block
+// and SIZE includes the padding at the end of the block.
+pub trait UniformBlock {
+    const NAME: &'static str;
+    const SIZE: usize;
+    fn members() -> Vec<BlockMember>;
+    fn write_std140(&self, out: &mut [u8]);
+}
+
+// Declares a struct and implements UniformBlock for it, the GLSL block
+// name follows the struct name:
+//
+// uniform_block! {
+//     pub struct CameraBlock: "Camera" {
+//         pub view_mat: Mat4,
+//         pub view_pos: Vec3,
+//     }
+// }
+//
+// Offsets are computed at compile time from the Std140 impls of the fields.
+#[macro_export]
+macro_rules! uniform_block {
+    (
+        $(#[$meta:meta])*
+        $vis:vis struct $name:ident: $block:literal {
+            $($field_vis:vis $field:ident: $ty:ty,)*
+        }
+    ) => {
+        $(#[$meta])*
+        $vis struct $name {
+            $($field_vis $field: $ty,)*
+        }
+
+        impl $crate::core::pipeline::mgl::ubo::UniformBlock for $name {
+            const NAME: &'static str = $block;
+
+            const SIZE: usize = {
+                use $crate::core::pipeline::mgl::ubo::{align_to, Std140};
+                let offset = 0;
+                $(let offset = align_to(offset, <$ty as Std140>::ALIGN) + <$ty as Std140>::SIZE;)*
+                align_to(offset, 16)
+            };
+
+            fn members() -> Vec<$crate::core::pipeline::mgl::ubo::BlockMember> {
+                use $crate::core::pipeline::mgl::ubo::{align_to, BlockMember, Std140};
+                let mut members = vec![];
+                let mut offset = 0;
+                $(
+                    offset = align_to(offset, <$ty as Std140>::ALIGN);
+                    members.push(BlockMember {
+                        name: stringify!($field),
+                        offset: offset,
+                        gl_type: <$ty as Std140>::GL_TYPE,
+                    });
+                    offset += <$ty as Std140>::SIZE;
+                )*
+                let _ = offset;
+                members
+            }
+
+            fn write_std140(&self, out: &mut [u8]) {
+                use $crate::core::pipeline::mgl::ubo::{align_to, Std140};
+                let mut offset = 0;
+                $(
+                    offset = align_to(offset, <$ty as Std140>::ALIGN);
+                    self.$field.write_std140(&mut out[offset..offset + <$ty as Std140>::SIZE]);
+                    offset += <$ty as Std140>::SIZE;
+                )*
+                let _ = offset;
+            }
+        }
+    };
+}
+
+// Buffer holding one instance of a uniform block
+#[derive(Debug)]
+pub struct UniformBuffer<T: UniformBlock> {
+    id: GLuint,
+    _block: PhantomData<T>,
+}
+
+#[allow(dead_code)]
+impl<T: UniformBlock> UniformBuffer<T> {
+    pub fn new() -> Self {
+        let mut id = 0;
+
+        unsafe {
+            gl::CreateBuffers(1, &mut id);
+            gl::NamedBufferData(
+                id,
+                T::SIZE as GLsizeiptr,
+                std::ptr::null(),
+                gl::DYNAMIC_DRAW,
+            );
+        }
+
+        Self {
+            id: id,
+            _block: PhantomData,
+        }
+    }
+
+    pub fn id(&self) -> GLuint {
+        self.id
+    }
+
+    pub fn upload(&self, block: &T) {
+        let mut data = vec![0u8; T::SIZE];
+        block.write_std140(&mut data);
+
+        unsafe {
+            gl::NamedBufferSubData(
+                self.id,
+                0,
+                data.len() as GLsizeiptr,
+                data.as_ptr() as *const GLvoid,
+            );
+        }
+    }
+
+    pub fn bind(&self, binding: GLuint) {
+        unsafe {
+            gl::BindBufferBase(gl::UNIFORM_BUFFER, binding, self.id);
+        }
+    }
+
+    // Compares the layout of T with the block of a linked program, fails if
+    // the block is missing or any member differs in name, type or offset.
+    pub fn verify(&self, program: &ShaderProgram) -> Result<(), ShaderIssue> {
+        let mismatch = |msg: String| {
+            Err(ShaderIssue::UniformBlockMismatch(format!(
+                "{}: {}",
+                T::NAME,
+                msg
+            )))
+        };
+
+        let block_name = CString::new(T::NAME).unwrap();
+        let block_index = unsafe { gl::GetUniformBlockIndex(program.id(), block_name.as_ptr()) };
+
+        if block_index == gl::INVALID_INDEX {
+            return mismatch("block is not active in the program".to_owned());
+        }
+
+        let mut data_size = 0;
+        let mut member_count = 0;
+
+        unsafe {
+            gl::GetActiveUniformBlockiv(
+                program.id(),
+                block_index,
+                gl::UNIFORM_BLOCK_DATA_SIZE,
+                &mut data_size,
+            );
+            gl::GetActiveUniformBlockiv(
+                program.id(),
+                block_index,
+                gl::UNIFORM_BLOCK_ACTIVE_UNIFORMS,
+                &mut member_count,
+            );
+        }
+
+        let members = T::members();
+
+        if member_count as usize != members.len() {
+            return mismatch(format!(
+                "{} members in the program, {} in the struct",
+                member_count,
+                members.len()
+            ));
+        }
+
+        if data_size as usize > T::SIZE {
+            return mismatch(format!(
+                "{} bytes in the program, {} in the struct",
+                data_size,
+                T::SIZE
+            ));
+        }
+
+        for m in members {
+            let name = CString::new(m.name).unwrap();
+            let name_ptr = name.as_ptr();
+            let mut index = gl::INVALID_INDEX;
+            let mut offset = -1;
+            let mut gl_type = 0;
+
+            unsafe {
+                gl::GetUniformIndices(program.id(), 1, &name_ptr, &mut index);
+            }
+
+            if index == gl::INVALID_INDEX {
+                return mismatch(format!("member {} is missing", m.name));
+            }
+
+            unsafe {
+                gl::GetActiveUniformsiv(program.id(), 1, &index, gl::UNIFORM_OFFSET, &mut offset);
+                gl::GetActiveUniformsiv(program.id(), 1, &index, gl::UNIFORM_TYPE, &mut gl_type);
+            }
+
+            if offset as usize != m.offset {
+                return mismatch(format!(
+                    "member {} is at offset {} in the program, {} in the struct",
+                    m.name, offset, m.offset
+                ));
+            }
+
+            if gl_type as GLenum != m.gl_type {
+                return mismatch(format!(
+                    "member {} has type 0x{:x} in the program, 0x{:x} in the struct",
+                    m.name, gl_type, m.gl_type
+                ));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl<T: UniformBlock> Drop for UniformBuffer<T> {
+    fn drop(&mut self) {
+        unsafe {
+            gl::DeleteBuffers(1, &self.id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::pipeline::CameraBlock;
+    use cgmath::prelude::*;
+
+    crate::uniform_block! {
+        // Every std140 padding rule in one block
+        struct PackingBlock: "Packing" {
+            scalar: f32,
+            uv: Vec2,
+            direction: Vec3,
+            // Fills the gap after the vec3
+            intensity: f32,
+            flag: bool,
+            normal_mat: Mat3,
+            color: Vec4,
+            count: i32,
+        }
+    }
+
+    fn offsets<T: UniformBlock>() -> Vec<(&'static str, usize)> {
+        T::members().iter().map(|m| (m.name, m.offset)).collect()
+    }
+
+    fn f32_at(data: &[u8], offset: usize) -> f32 {
+        let mut b = [0u8; 4];
+        b.copy_from_slice(&data[offset..offset + 4]);
+        f32::from_ne_bytes(b)
+    }
+
+    #[test]
+    fn camera_block_layout() {
+        assert_eq!(CameraBlock::NAME, "Camera");
+        assert_eq!(
+            offsets::<CameraBlock>(),
+            vec![("view_mat", 0), ("proj_mat", 64), ("view_pos", 128)]
+        );
+        assert_eq!(CameraBlock::SIZE, 144);
+    }
+
+    #[test]
+    fn members_follow_std140_alignment() {
+        assert_eq!(
+            offsets::<PackingBlock>(),
+            vec![
+                ("scalar", 0),
+                ("uv", 8),
+                ("direction", 16),
+                ("intensity", 28),
+                ("flag", 32),
+                ("normal_mat", 48),
+                ("color", 96),
+                ("count", 112),
+            ]
+        );
+        // Rounded up to a whole vec4
+        assert_eq!(PackingBlock::SIZE, 128);
+
+        let types: Vec<GLenum> = PackingBlock::members().iter().map(|m| m.gl_type).collect();
+        assert_eq!(types[2], gl::FLOAT_VEC3);
+        assert_eq!(types[5], gl::FLOAT_MAT3);
+    }
+
+    #[test]
+    fn blocks_are_written_at_their_offsets() {
+        let camera = CameraBlock {
+            view_mat: Mat4::from_translation(Vec3::new(1.0, 2.0, 3.0)),
+            proj_mat: Mat4::from_scale(5.0),
+            view_pos: Point3::new(7.0, 8.0, 9.0),
+        };
+        let mut data = vec![0u8; CameraBlock::SIZE];
+        camera.write_std140(&mut data);
+
+        // Column major, the translation is in the last column
+        assert_eq!(f32_at(&data, 48), 1.0);
+        assert_eq!(f32_at(&data, 56), 3.0);
+        assert_eq!(f32_at(&data, 64), 5.0);
+        assert_eq!(f32_at(&data, 128), 7.0);
+        assert_eq!(f32_at(&data, 136), 9.0);
+
+        let block = PackingBlock {
+            scalar: 1.0,
+            uv: Vec2::new(2.0, 3.0),
+            direction: Vec3::new(4.0, 5.0, 6.0),
+            intensity: 7.0,
+            flag: true,
+            normal_mat: Mat3::identity() * 8.0,
+            color: Vec4::new(9.0, 10.0, 11.0, 12.0),
+            count: 13,
+        };
+        let mut data = vec![0u8; PackingBlock::SIZE];
+        block.write_std140(&mut data);
+
+        assert_eq!(f32_at(&data, 12), 3.0);
+        assert_eq!(f32_at(&data, 28), 7.0);
+        assert_eq!(&data[32..36], &1u32.to_ne_bytes());
+        // Mat3 columns are padded to vec4
+        assert_eq!(f32_at(&data, 48), 8.0);
+        assert_eq!(f32_at(&data, 60), 0.0);
+        assert_eq!(f32_at(&data, 68), 8.0);
+        assert_eq!(f32_at(&data, 88), 8.0);
+        assert_eq!(f32_at(&data, 108), 12.0);
+        assert_eq!(&data[112..116], &13i32.to_ne_bytes());
+    }
+}
@@ -1,17 +1,35 @@
+pub mod batch;
+pub mod capture;
 pub mod gpu;
+pub mod instancing;
 pub mod light_info;
+pub mod lighting;
 pub mod mgl;
+pub mod post;
+pub mod program_cache;
+pub mod render_graph;
+pub mod render_queue;
+pub mod render_target;
+pub mod shader_cache;
+pub mod shadow;
+pub mod sky;
 
 use crate::core::app;
-use crate::core::pipeline::mgl::attr::uniform;
-use crate::core::pipeline::mgl::shader::ShaderProgram;
+use crate::core::pipeline::mgl::attr::uniform::{self, UniformUpload};
+use crate::core::pipeline::mgl::shader::{ShaderIssue, ShaderProgram};
+use crate::core::pipeline::mgl::ubo::UniformBuffer;
 use crate::resource::BufferLoaderError;
 use std::convert::TryFrom;
 // use crate::core::macros;
 use crate::cgmath::Array;
-use cgmath::prelude::{Matrix, SquareMatrix};
+use crate::core::geometry::{Aabb, Frustum};
+use cgmath::prelude::{EuclideanSpace, Matrix, SquareMatrix};
 use gl::types::*;
-use light_info::DirLight;
+use light_info::Light;
+use lighting::{CookieId, LightId};
+use render_queue::{RenderQueue, SortKey, StateCache};
+use shader_cache::{ProgramVariant, ShaderCache, ShaderFeatures};
+use std::cell::{Cell, RefCell};
 use std::convert::From;
 use std::path::Path;
 
@@ -24,7 +42,7 @@ use std::path::Path;
 pub mod resource {
     cenum::enumerate_vals! {
         type ResourceType = u8;
-        TEXTURED_MESH = 24, NORMAL_MAPPED_MESH
+        TEXTURED_MESH = 24, NORMAL_MAPPED_MESH, STATIC_BATCH, COMPRESSED_MESH, INSTANCED_MESH, PBR_MESH
     }
 
     // Upper bits 8-bits are resource type identifier
@@ -60,25 +78,68 @@ use resource::ResourceID;
 
 #[allow(dead_code)]
 pub struct Render3D {
-    main_shader: ShaderProgram,
+    main_shaders: ShaderCache<MainProgram>,
+    shadow_shader: ShaderProgram,
+    point_shadow_shader: ShaderProgram,
+    camera_block: UniformBuffer<CameraBlock>,
+}
+
+// One permutation of basic_vert.glsl and basic_frag.glsl
+pub struct MainProgram {
+    program: ShaderProgram,
     model_mat_unif: uniform::Mat4Uniform,
-    view_mat_unif: uniform::Mat4Uniform,
     modelview_mat_unif: uniform::Mat4Uniform,
-    proj_mat_unif: uniform::Mat4Uniform,
     mvp_mat_unif: uniform::Mat4Uniform,
     normal_mat_unif: uniform::Mat4Uniform,
-    view_pos_unif: uniform::Vec3Uniform,
     // time_unif: uniform::FloatUniform,
-    use_normalmap_unif: uniform::BoolUniform,
-    sun_intensity_unif: uniform::FloatUniform,
-    sun_direction_unif: uniform::Vec3Uniform,
-    sun_ambient_unif: uniform::Vec3Uniform,
-    sun_diffuse_unif: uniform::Vec3Uniform,
-    sun_specular_unif: uniform::Vec3Uniform,
-    lamp_position_unif: uniform::Vec3Uniform,
-    lamp_ambient_unif: uniform::Vec3Uniform,
-    lamp_diffuse_unif: uniform::Vec3Uniform,
-    lamp_specular_unif: uniform::Vec3Uniform,
+}
+
+// Sampler names of the main program and the texture units they read from
+const MAIN_SAMPLER_UNITS: [(&str, GLuint); 14] = [
+    ("diffuse_texture", gpu::attrs::DIFFUSE_TEXTURE_UNIT),
+    ("specular_texture", gpu::attrs::SPECULAR_TEXTURE_UNIT),
+    ("normal_texture", gpu::attrs::NORMAL_TEXTURE_UNIT),
+    ("splat_texture", gpu::attrs::SPLAT_TEXTURE_UNIT),
+    ("splat_layer0", gpu::attrs::SPLAT_LAYER_TEXTURE_UNIT),
+    ("splat_layer1", gpu::attrs::SPLAT_LAYER_TEXTURE_UNIT + 1),
+    ("splat_layer2", gpu::attrs::SPLAT_LAYER_TEXTURE_UNIT + 2),
+    ("splat_layer3", gpu::attrs::SPLAT_LAYER_TEXTURE_UNIT + 3),
+    ("shadow_map", gpu::attrs::SHADOW_TEXTURE_UNIT),
+    ("point_shadow_maps", gpu::attrs::POINT_SHADOW_TEXTURE_UNIT),
+    ("spot_shadow_maps", gpu::attrs::SPOT_SHADOW_TEXTURE_UNIT),
+    ("spot_cookies", gpu::attrs::SPOT_COOKIE_TEXTURE_UNIT),
+    ("environment_map", gpu::attrs::ENVIRONMENT_TEXTURE_UNIT),
+    ("material_texture", gpu::attrs::MATERIAL_TEXTURE_UNIT),
+];
+
+impl ProgramVariant for MainProgram {
+    fn from_program(program: ShaderProgram) -> Result<Self, ShaderIssue> {
+        let get_unif = |name: &str| {
+            program
+                .uniform_by_name(name)
+                .ok_or_else(|| ShaderIssue::MissingUniform(name.to_owned()))
+        };
+
+        let u_mat4 = |name| uniform::Mat4Uniform::try_from(get_unif(name)?);
+
+        // Sampler units never change, samplers compiled out of the
+        // permutation have location -1 and are skipped by GL
+        for (name, unit) in MAIN_SAMPLER_UNITS.iter() {
+            unsafe {
+                gl::ProgramUniform1i(program.id(), program.uniform_location(name), *unit as GLint);
+            }
+        }
+
+        Ok(Self {
+            model_mat_unif: u_mat4("model_mat")?,
+            modelview_mat_unif: u_mat4("modelview_mat")?,
+            mvp_mat_unif: u_mat4("mvp_mat")?,
+            normal_mat_unif: u_mat4("normal_mat")?,
+            // FIXME: The time uniform cannot be requested for some reasons.
+            // time_unif: u_float("time")?,
+            program: program,
+        })
+    }
 }
 
 type Mat4 = cgmath::Matrix4<f32>;
@@ -86,23 +147,101 @@ type Vec3 = cgmath::Vector3<f32>;
 type Vec4 = cgmath::Vector4<f32>;
 type Point3 = cgmath::Point3<f32>;
 
+// Linked program binaries, relative to the resource root
+const PROGRAM_CACHE_DIR: &str = "cache/programs";
+
+// MSAA samples of the HDR scene target
+const SCENE_SAMPLES: u32 = 4;
+
+// Pass bits of the sort keys, shadow passes draw without the queue
+const MAIN_PASS: u32 = 0;
+
+crate::uniform_block! {
+    // Camera data shared by every program through CAMERA_BLOCK_BINDING
+    #[derive(Debug, Clone, Copy)]
+    pub struct CameraBlock: "Camera" {
+        pub view_mat: Mat4,
+        pub proj_mat: Mat4,
+        pub view_pos: Point3,
+    }
+}
+
 pub mod mesh_data {
 
     use super::gpu;
+    use super::Aabb;
     use super::Mat4;
 
     #[derive(Debug)]
     pub struct Basic {
+        // Local space, moved by the model matrix
+        pub bounds: Aabb,
         pub resource: gpu::basic_mesh::Mesh,
         pub model_matrix: Mat4,
         pub normal_matrix: Mat4,
+        // Drawn back to front after the opaque meshes with alpha blending
+        pub translucent: bool,
     }
 
     #[derive(Debug)]
     pub struct NormalMapped {
+        // Local space, moved by the model matrix
+        pub bounds: Aabb,
         pub resource: gpu::normal_mapped_mesh::Mesh,
         pub model_matrix: Mat4,
         pub normal_matrix: Mat4,
+        // Drawn back to front after the opaque meshes with alpha blending
+        pub translucent: bool,
+    }
+
+    #[derive(Debug)]
+    pub struct Pbr {
+        // Local space, moved by the model matrix
+        pub bounds: Aabb,
+        pub resource: gpu::pbr_mesh::Mesh,
+        pub model_matrix: Mat4,
+        pub normal_matrix: Mat4,
+        // Drawn back to front after the opaque meshes with alpha blending
+        pub translucent: bool,
+    }
+
+    #[derive(Debug)]
+    pub struct Compressed {
+        // Local space, moved by the model matrix
+        pub bounds: Aabb,
+        pub resource: gpu::compressed_mesh::Mesh,
+        pub model_matrix: Mat4,
+        pub normal_matrix: Mat4,
+        // Drawn back to front after the opaque meshes with alpha blending
+        pub translucent: bool,
+    }
+
+    // One mesh drawn once per instance of the set
+    #[derive(Debug)]
+    pub struct Instanced {
+        // Local space, moved by the model matrix of each instance
+        pub bounds: Aabb,
+        // Covers the instances last uploaded
+        pub world_bounds: Aabb,
+        pub resource: gpu::instanced_mesh::Mesh,
+        pub instances: super::instancing::InstanceSet,
+    }
+
+    // Vertices of a static batch are already in world space
+    #[derive(Debug)]
+    pub struct StaticBatch {
+        pub bounds: Aabb,
+        pub resource: gpu::normal_mapped_mesh::Mesh,
+        pub batch: super::batch::StaticBatch,
+    }
+
+    // Terrain chunks share one splat material, chunk vertices are in world space
+    #[derive(Debug)]
+    pub struct Terrain {
+        pub textures: gpu::textures::Splat,
+        pub chunks: Vec<gpu::normal_mapped_mesh::Mesh>,
+        pub chunk_bounds: Vec<Aabb>,
+        pub detail_scale: f32,
     }
 }
 
@@ -113,18 +252,182 @@ pub struct Pipeline3D {
     view_matrix: Mat4,
     basic_tex_meshes: Vec<mesh_data::Basic>,
     normal_mapped_tex_meshes: Vec<mesh_data::NormalMapped>,
-    sun: DirLight,
+    pbr_meshes: Vec<mesh_data::Pbr>,
+    static_batches: Vec<mesh_data::StaticBatch>,
+    compressed_meshes: Vec<mesh_data::Compressed>,
+    instanced_meshes: Vec<mesh_data::Instanced>,
+    terrain: Option<mesh_data::Terrain>,
     view_pos: Point3,
+    lights: lighting::LightManager,
+    shadow_map: shadow::ShadowMap,
+    point_shadow_map: shadow::PointShadowMap,
+    spot_shadow_map: shadow::ShadowMap,
+    spot_cookies: lighting::CookieArray,
+    shadow_config: shadow::ShadowConfig,
+    blinn: bool,
+    post: post::PostProcess,
+    sky: sky::Sky,
+    culling: bool,
+    frame_stats: Cell<FrameStats>,
+    // Kept between frames to reuse its allocations
+    queue: RefCell<RenderQueue<QueuedDraw>>,
+}
+
+// Counters of the last draw_textured_meshes call. Objects are meshes,
+// static batches, terrain chunks and instanced meshes as a whole.
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FrameStats {
+    pub drawn_objects: u32,
+    pub culled_objects: u32,
+    pub draw_calls: u32,
+    // Binds the render queue could not skip
+    pub program_binds: u32,
+    pub texture_binds: u32,
+}
+
+// Frustum test of the main pass counting into FrameStats
+struct Culler {
+    frustum: Frustum,
+    enabled: bool,
+    stats: FrameStats,
+}
+
+// Uniform flags of the main program changing how a draw is decoded
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum DrawMode {
+    Mesh = 0,
+    Compressed = 1,
+    Instanced = 2,
+    Terrain = 3,
+}
+
+impl DrawMode {
+    fn flag(&self) -> Option<GLint> {
+        match self {
+            DrawMode::Mesh => None,
+            DrawMode::Compressed => Some(gpu::attrs::USE_COMPRESSED_VERTICES_FLAG),
+            DrawMode::Instanced => Some(gpu::attrs::USE_INSTANCING_FLAG),
+            DrawMode::Terrain => Some(gpu::attrs::USE_SPLATMAP_FLAG),
+        }
+    }
+}
+
+// Indices into the mesh lists of Pipeline3D
+#[derive(Debug, Clone, Copy)]
+enum DrawSource {
+    Basic(usize),
+    NormalMapped(usize),
+    Pbr(usize),
+    StaticBatch(usize),
+    Compressed(usize),
+    Instanced(usize),
+    TerrainChunk(usize),
+}
+
+impl DrawSource {
+    fn features(&self) -> ShaderFeatures {
+        match self {
+            DrawSource::Basic(_) => ShaderFeatures::NONE,
+            DrawSource::Pbr(_) => ShaderFeatures::NORMAL_MAP | ShaderFeatures::PBR,
+            _ => ShaderFeatures::NORMAL_MAP,
+        }
+    }
+
+    fn mode(&self) -> DrawMode {
+        match self {
+            DrawSource::Compressed(_) => DrawMode::Compressed,
+            DrawSource::Instanced(_) => DrawMode::Instanced,
+            DrawSource::TerrainChunk(_) => DrawMode::Terrain,
+            _ => DrawMode::Mesh,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct QueuedDraw {
+    source: DrawSource,
+    // World space, None for instanced meshes
+    bounds: Option<Aabb>,
+}
+
+// What the main pass uploaded last. Uniforms belong to the program, so
+// everything but the GL binds is forgotten when the program changes.
+struct MainPassState {
+    state: StateCache,
+    features: Option<ShaderFeatures>,
+    mode: DrawMode,
+    // Model and normal matrix
+    object: Option<(Mat4, Mat4)>,
+    object_lights: Option<Vec<i32>>,
+    draw_calls: u32,
+    program_binds: u32,
+}
+
+impl MainPassState {
+    fn new() -> Self {
+        Self {
+            state: StateCache::new(),
+            features: None,
+            mode: DrawMode::Mesh,
+            object: None,
+            object_lights: None,
+            draw_calls: 0,
+            program_binds: 0,
+        }
+    }
+
+    // Switches the flags of the active program, returns whether they changed
+    fn set_mode(&mut self, mode: DrawMode) -> bool {
+        if self.mode == mode {
+            return false;
+        }
+
+        unsafe {
+            if let Some(flag) = self.mode.flag() {
+                gl::Uniform1i(flag, 0);
+            }
+
+            if let Some(flag) = mode.flag() {
+                gl::Uniform1i(flag, 1);
+            }
+        }
+
+        self.mode = mode;
+        true
+    }
+
+    // Leaves the program in mesh mode and blending disabled for whatever
+    // draws next
+    fn finish(&mut self) {
+        self.set_mode(DrawMode::Mesh);
+        self.state.set_blending(false);
+    }
+}
+
+impl Culler {
+    // Bounds are in world space
+    fn visible(&mut self, bounds: &Aabb) -> bool {
+        if self.enabled && !self.frustum.intersects_aabb(bounds) {
+            self.stats.culled_objects += 1;
+            false
+        } else {
+            self.stats.drawn_objects += 1;
+            true
+        }
+    }
 }
 
 #[derive(Debug)]
 pub enum InitError {
     FailedLoadingResource(BufferLoaderError),
     ShaderIssue(mgl::shader::ShaderIssue),
+    ShaderPreprocess(shader_cache::PreprocessError),
 }
 
 impl_error_conv!(BufferLoaderError, InitError, FailedLoadingResource);
 impl_error_conv!(mgl::shader::ShaderIssue, InitError, ShaderIssue);
+impl_error_conv!(shader_cache::PreprocessError, InitError, ShaderPreprocess);
 
 fn configure_texture_parameters() {
     unsafe {
@@ -151,50 +454,91 @@ fn configure_texture_parameters() {
 
 impl Pipeline3D {
     pub fn create_and_prepare(app: &app::AppCore) -> Result<Self, InitError> {
-        let main_shader = Self::load_and_compile_shader(app)?;
-        let get_unif = |name| {
-            if let Some(unif) = main_shader.uniform_by_name(name) {
-                println!("Found uniform name: {}", name);
-                return unif;
-            } else {
-                panic!("Could find uniform called {}.", name);
-            }
+        let binaries = program_cache::ProgramBinaryCache::new(
+            &app.buffer_loader.root().join(PROGRAM_CACHE_DIR),
+        );
+        let main_shaders = Self::load_and_compile_shaders(app, &binaries)?;
+        let shadow_shader = Self::load_program(
+            app,
+            &binaries,
+            &[
+                ("shaders/shadow_depth_vert.glsl", gl::VERTEX_SHADER),
+                ("shaders/shadow_depth_frag.glsl", gl::FRAGMENT_SHADER),
+            ],
+        )?;
+        let point_shadow_shader = Self::load_program(
+            app,
+            &binaries,
+            &[
+                ("shaders/point_shadow_vert.glsl", gl::VERTEX_SHADER),
+                ("shaders/point_shadow_geom.glsl", gl::GEOMETRY_SHADER),
+                ("shaders/point_shadow_frag.glsl", gl::FRAGMENT_SHADER),
+            ],
+        )?;
+        let post_programs = post::PostPrograms {
+            bloom_downsample: Self::load_post_program(app, &binaries, "bloom_downsample_frag")?,
+            bloom_upsample: Self::load_post_program(app, &binaries, "bloom_upsample_frag")?,
+            tonemap: Self::load_post_program(app, &binaries, "tonemap_frag")?,
+            histogram: Self::load_program(
+                app,
+                &binaries,
+                &[("shaders/luminance_histogram_comp.glsl", gl::COMPUTE_SHADER)],
+            )?,
+            exposure: Self::load_program(
+                app,
+                &binaries,
+                &[("shaders/exposure_average_comp.glsl", gl::COMPUTE_SHADER)],
+            )?,
+        };
+        let sky_programs = sky::SkyPrograms {
+            skybox: Self::load_sky_program(app, &binaries, "skybox_frag")?,
+            procedural: Self::load_sky_program(app, &binaries, "sky_procedural_frag")?,
         };
+        let shadow_config = shadow::ShadowConfig::default();
+        let camera_block = UniformBuffer::<CameraBlock>::new();
 
-        let u_mat4 = |name| uniform::Mat4Uniform::try_from(get_unif(name));
-        let u_vec3 = |name| uniform::Vec3Uniform::try_from(get_unif(name));
-        let u_bool = |name| uniform::BoolUniform::try_from(get_unif(name));
-        let u_float = |name| uniform::FloatUniform::try_from(get_unif(name));
+        for v in main_shaders.variants() {
+            camera_block.verify(&v.program)?;
+        }
 
         let p3d = Self {
             render: Render3D {
-                model_mat_unif: u_mat4("model_mat")?,
-                view_mat_unif: u_mat4("view_mat")?,
-                modelview_mat_unif: u_mat4("modelview_mat")?,
-                proj_mat_unif: u_mat4("proj_mat")?,
-                mvp_mat_unif: u_mat4("mvp_mat")?,
-                normal_mat_unif: u_mat4("normal_mat")?,
-                // FIXME: The time uniform cannot be requested for some reasons.
-                // time_unif: u_float("time")?,
-                use_normalmap_unif: u_bool("use_normalmap")?,
-                sun_intensity_unif: u_float("sun.intensity")?,
-                sun_direction_unif: u_vec3("sun.direction")?,
-                sun_ambient_unif: u_vec3("sun.ambient")?,
-                sun_diffuse_unif: u_vec3("sun.diffuse")?,
-                sun_specular_unif: u_vec3("sun.specular")?,
-                lamp_position_unif: u_vec3("lamp.position")?,
-                lamp_ambient_unif: u_vec3("lamp.ambient")?,
-                lamp_diffuse_unif: u_vec3("lamp.diffuse")?,
-                lamp_specular_unif: u_vec3("lamp.specular")?,
-                view_pos_unif: u_vec3("view_pos")?,
-                main_shader: main_shader,
+                camera_block: camera_block,
+                main_shaders: main_shaders,
+                shadow_shader: shadow_shader,
+                point_shadow_shader: point_shadow_shader,
             },
             projection_matrix: Mat4::identity(),
             view_matrix: Mat4::identity(),
             basic_tex_meshes: vec![],
             normal_mapped_tex_meshes: vec![],
+            pbr_meshes: vec![],
+            static_batches: vec![],
+            compressed_meshes: vec![],
+            instanced_meshes: vec![],
+            terrain: None,
             view_pos: cgmath::Point3::<f32>::new(0.0f32, 0.0, 0.0),
-            sun: DirLight::default(),
+            shadow_map: shadow::ShadowMap::new(
+                shadow_config.resolution,
+                shadow_config.cascade_count,
+            ),
+            lights: lighting::LightManager::new(),
+            point_shadow_map: shadow::PointShadowMap::new(
+                shadow_config.point_resolution,
+                shadow::MAX_POINT_SHADOWS,
+            ),
+            spot_shadow_map: shadow::ShadowMap::new(
+                shadow_config.spot_resolution,
+                shadow::MAX_SPOT_SHADOWS,
+            ),
+            spot_cookies: lighting::CookieArray::new(),
+            shadow_config: shadow_config,
+            blinn: true,
+            post: post::PostProcess::new(post_programs),
+            sky: sky::Sky::new(sky_programs),
+            culling: true,
+            frame_stats: Cell::new(FrameStats::default()),
+            queue: RefCell::new(RenderQueue::new()),
         };
 
         p3d.configure_gl_parameters();
@@ -204,7 +548,22 @@ impl Pipeline3D {
     }
 
     pub fn activate_shader(&self) {
-        self.render.main_shader.set_active();
+        self.main_variant(ShaderFeatures::NONE).program.set_active();
+    }
+
+    // Features every main program permutation of the frame is compiled with
+    fn base_features(&self) -> ShaderFeatures {
+        ShaderFeatures::NONE
+            .with(ShaderFeatures::BLINN, self.blinn)
+            .with(ShaderFeatures::SHADOWS, self.shadow_config.enabled)
+    }
+
+    // Every permutation is compiled when the pipeline is created
+    fn main_variant(&self, extra: ShaderFeatures) -> &MainProgram {
+        self.render
+            .main_shaders
+            .get(self.base_features() | extra)
+            .expect("Main program permutation was not compiled")
     }
 
     fn configure_gl_parameters(&self) {
@@ -240,9 +599,11 @@ impl Pipeline3D {
             ids.push(ResourceID::new(resource::TEXTURED_MESH, i as u32));
 
             self.basic_tex_meshes.push(mesh_data::Basic {
+                bounds: Aabb::from_points(im.attributes.positions.iter()),
                 resource: tm,
                 model_matrix: Mat4::identity(),
                 normal_matrix: Mat4::identity(),
+                translucent: false,
             });
         }
         ids
@@ -271,32 +632,359 @@ impl Pipeline3D {
 
             ids.push(new_id);
             self.normal_mapped_tex_meshes.push(mesh_data::NormalMapped {
+                bounds: Aabb::from_points(im.attributes.positions.iter()),
                 resource: tm,
                 model_matrix: Mat4::identity(),
                 normal_matrix: Mat4::identity(),
+                translucent: false,
             });
         }
 
         ids
     }
 
-    fn load_and_compile_shader(app: &app::AppCore) -> Result<ShaderProgram, InitError> {
-        let vert_shader = mgl::shader::Shader::from_source(
-            &app.buffer_loader
-                .load_cstring(Path::new("shaders/basic_vert.glsl"))?,
-            gl::VERTEX_SHADER,
-        )?;
+    // Meshes shaded with the metallic-roughness model, the Blinn-Phong
+    // setting does not affect them. Added to the meshes prepared before.
+    pub fn prepare_pbr_meshes(
+        &mut self,
+        data: &[(
+            &mgl::attr::mesh3d::lightmaps::Pbr,
+            &mgl::attr::mesh3d::IndexedMesh,
+        )],
+    ) -> Vec<ResourceID> {
+        let mut ids: Vec<ResourceID> = vec![];
+        ids.reserve(data.len());
 
-        let frag_shader = mgl::shader::Shader::from_source(
-            &app.buffer_loader
-                .load_cstring(Path::new("shaders/basic_frag.glsl"))?,
-            gl::FRAGMENT_SHADER,
-        )?;
+        self.pbr_meshes.reserve(data.len());
+        for (lm, im) in data.iter() {
+            let mut tm = gpu::pbr_mesh::Mesh::from(*im);
+            tm.textures.upload_all_textures(&lm);
+
+            let new_id = ResourceID::new(resource::PBR_MESH, self.pbr_meshes.len() as u32);
+
+            ids.push(new_id);
+            self.pbr_meshes.push(mesh_data::Pbr {
+                bounds: Aabb::from_points(im.attributes.positions.iter()),
+                resource: tm,
+                model_matrix: Mat4::identity(),
+                normal_matrix: Mat4::identity(),
+                translucent: false,
+            });
+        }
+
+        ids
+    }
+
+    // Same as prepare_normal_mapped_textured_meshes but the vertex data is
+    // quantized and decoded in the vertex shader
+    #[allow(dead_code)]
+    pub fn prepare_compressed_meshes(
+        &mut self,
+        data: &[(
+            &mgl::attr::mesh3d::lightmaps::NormalMapped,
+            &mgl::attr::mesh3d::IndexedMesh,
+        )],
+        options: &mgl::attr::compressed::CompressionOptions,
+    ) -> Vec<ResourceID> {
+        let mut ids: Vec<ResourceID> = vec![];
+        ids.reserve(data.len());
+
+        for (lm, im) in data.iter() {
+            let compressed =
+                mgl::attr::compressed::CompressedAttributes::compress(&im.attributes, options);
+
+            let mut tm = gpu::compressed_mesh::Mesh::from(&compressed);
+            tm.textures.upload_all_textures(&lm);
+
+            ids.push(ResourceID::new(
+                resource::COMPRESSED_MESH,
+                self.compressed_meshes.len() as u32,
+            ));
 
-        Ok(mgl::shader::ShaderProgram::from_shaders(&[
-            vert_shader,
-            frag_shader,
-        ])?)
+            self.compressed_meshes.push(mesh_data::Compressed {
+                bounds: Aabb::from_points(im.attributes.positions.iter()),
+                resource: tm,
+                model_matrix: Mat4::identity(),
+                normal_matrix: Mat4::identity(),
+                translucent: false,
+            });
+        }
+
+        ids
+    }
+
+    // Registers a mesh for instanced drawing, it is not drawn until instances
+    // are pushed and uploaded with upload_instances()
+    #[allow(dead_code)]
+    pub fn prepare_instanced_mesh(
+        &mut self,
+        lm: &mgl::attr::mesh3d::lightmaps::NormalMapped,
+        im: &mgl::attr::mesh3d::IndexedMesh,
+    ) -> ResourceID {
+        let mut tm = gpu::instanced_mesh::Mesh::from(im);
+        tm.textures.upload_all_textures(lm);
+
+        let id = ResourceID::new(resource::INSTANCED_MESH, self.instanced_meshes.len() as u32);
+
+        self.instanced_meshes.push(mesh_data::Instanced {
+            bounds: Aabb::from_points(im.attributes.positions.iter()),
+            world_bounds: Aabb::empty(),
+            resource: tm,
+            instances: instancing::InstanceSet::new(),
+        });
+
+        id
+    }
+
+    // Returns index of the instance within the mesh, tint defaults to white
+    #[allow(dead_code)]
+    pub fn push_instance(
+        &mut self,
+        id: ResourceID,
+        model: Mat4,
+        tint: Option<Vec4>,
+    ) -> Option<usize> {
+        self.instances_mut(id).map(|set| set.push(model, tint))
+    }
+
+    #[allow(dead_code)]
+    pub fn instances_mut(&mut self, id: ResourceID) -> Option<&mut instancing::InstanceSet> {
+        match id.get_type() {
+            resource::INSTANCED_MESH => self
+                .instanced_meshes
+                .get_mut(id.as_index())
+                .map(|m| &mut m.instances),
+            _ => None,
+        }
+    }
+
+    // Re-uploads instance buffers of every set changed since the last call
+    #[allow(dead_code)]
+    pub fn upload_instances(&mut self) {
+        for m in self.instanced_meshes.iter_mut() {
+            if m.instances.is_dirty() {
+                m.resource.upload_instances(m.instances.instances());
+                m.world_bounds = m.instances.bounds(&m.bounds);
+                m.instances.mark_clean();
+            }
+        }
+    }
+
+    // Meshes sharing the same lightmaps (compared by reference) are merged into
+    // one batch. Returns one ID per created batch in order of first appearance.
+    #[allow(dead_code)]
+    pub fn prepare_static_batches(
+        &mut self,
+        data: &[(
+            &mgl::attr::mesh3d::lightmaps::NormalMapped,
+            &mgl::attr::mesh3d::IndexedMesh,
+            Mat4,
+        )],
+    ) -> Vec<ResourceID> {
+        let mut groups: Vec<(
+            &mgl::attr::mesh3d::lightmaps::NormalMapped,
+            batch::StaticBatch,
+        )> = vec![];
+
+        for (lm, im, transform) in data.iter() {
+            let group = match groups.iter().position(|(g, _)| std::ptr::eq(*g, *lm)) {
+                Some(idx) => idx,
+                None => {
+                    groups.push((lm, batch::StaticBatch::new()));
+                    groups.len() - 1
+                }
+            };
+
+            groups[group].1.add_member(im, *transform);
+        }
+
+        let mut ids: Vec<ResourceID> = vec![];
+        ids.reserve(groups.len());
+
+        for (lm, mut b) in groups.into_iter() {
+            let merged = b.merge();
+            b.mark_clean();
+
+            let mut tm = gpu::normal_mapped_mesh::Mesh::from(&merged);
+            tm.textures.upload_all_textures(&lm);
+
+            ids.push(ResourceID::new(
+                resource::STATIC_BATCH,
+                self.static_batches.len() as u32,
+            ));
+
+            self.static_batches.push(mesh_data::StaticBatch {
+                bounds: Aabb::from_points(merged.attributes.positions.iter()),
+                resource: tm,
+                batch: b,
+            });
+        }
+
+        ids
+    }
+
+    // Members of the batch can be added, removed or moved through the returned
+    // reference, the batch is re-uploaded by rebuild_static_batches()
+    #[allow(dead_code)]
+    pub fn static_batch_mut(&mut self, id: ResourceID) -> Option<&mut batch::StaticBatch> {
+        match id.get_type() {
+            resource::STATIC_BATCH => self
+                .static_batches
+                .get_mut(id.as_index())
+                .map(|sb| &mut sb.batch),
+            _ => None,
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn rebuild_static_batches(&mut self) {
+        for sb in self.static_batches.iter_mut() {
+            if sb.batch.is_dirty() {
+                let merged = sb.batch.merge();
+                sb.bounds = Aabb::from_points(merged.attributes.positions.iter());
+                sb.resource.upload_geometry(&merged);
+                sb.batch.mark_clean();
+            }
+        }
+    }
+
+    // Replaces the currently prepared terrain. Detail scale sets how many times
+    // the splat layers repeat across the whole terrain.
+    #[allow(dead_code)]
+    pub fn prepare_terrain(
+        &mut self,
+        terrain: &crate::core::terrain::Terrain,
+        material: &mgl::attr::mesh3d::lightmaps::Splat,
+        detail_scale: f32,
+    ) {
+        let mut textures = gpu::textures::Splat::new();
+        textures.upload_all_textures(material);
+
+        let chunks = terrain
+            .chunks()
+            .iter()
+            .map(|c| {
+                let mut m = gpu::normal_mapped_mesh::Mesh::new();
+                m.upload_geometry(&c.mesh);
+                m
+            })
+            .collect();
+
+        let chunk_bounds = terrain
+            .chunks()
+            .iter()
+            .map(|c| Aabb::from_points(c.mesh.attributes.positions.iter()))
+            .collect();
+
+        self.terrain = Some(mesh_data::Terrain {
+            textures: textures,
+            chunks: chunks,
+            chunk_bounds: chunk_bounds,
+            detail_scale: detail_scale,
+        });
+    }
+
+    // Updates chunk LODs for the camera position and re-uploads the index
+    // buffers of chunks that changed.
+    #[allow(dead_code)]
+    pub fn update_terrain_lod(&mut self, terrain: &mut crate::core::terrain::Terrain, camera: Point3) {
+        use cgmath::EuclideanSpace;
+
+        let gpu_terrain = match self.terrain.as_mut() {
+            Some(t) => t,
+            None => return,
+        };
+
+        for i in terrain.update_lod(camera.to_vec()) {
+            gpu_terrain.chunks[i].upload_indices(&terrain.chunks()[i].mesh.attributes.indices);
+        }
+    }
+
+    fn load_and_compile_shaders(
+        app: &app::AppCore,
+        binaries: &program_cache::ProgramBinaryCache,
+    ) -> Result<ShaderCache<MainProgram>, InitError> {
+        let mut cache = ShaderCache::load(
+            &app.buffer_loader,
+            &[
+                ("shaders/basic_vert.glsl", gl::VERTEX_SHADER),
+                ("shaders/basic_frag.glsl", gl::FRAGMENT_SHADER),
+            ],
+        )?
+        .with_binary_cache(binaries.clone());
+
+        // Switching features at runtime must not stall on a compile
+        for i in 0..16 {
+            let features = ShaderFeatures::NONE
+                .with(ShaderFeatures::NORMAL_MAP, i & 1 != 0)
+                .with(ShaderFeatures::BLINN, i & 2 != 0)
+                .with(ShaderFeatures::SHADOWS, i & 4 != 0)
+                .with(ShaderFeatures::PBR, i & 8 != 0);
+
+            // PBR materials always come with a normal map
+            if features.contains(ShaderFeatures::PBR)
+                && !features.contains(ShaderFeatures::NORMAL_MAP)
+            {
+                continue;
+            }
+
+            cache.compile(features)?;
+        }
+
+        Ok(cache)
+    }
+
+    // Stages are given as (source path, shader type) pairs
+    fn load_program(
+        app: &app::AppCore,
+        binaries: &program_cache::ProgramBinaryCache,
+        stages: &[(&str, GLenum)],
+    ) -> Result<ShaderProgram, InitError> {
+        let mut sources = vec![];
+
+        for (path, kind) in stages {
+            let source = shader_cache::ShaderSource::load(&app.buffer_loader, Path::new(path))?;
+            sources.push((source, *kind));
+        }
+
+        let stages: Vec<_> = sources.iter().map(|(s, k)| (s, *k)).collect();
+        Ok(shader_cache::ShaderSource::link(
+            &stages,
+            ShaderFeatures::NONE,
+            Some(binaries),
+        )?)
+    }
+
+    // Fullscreen pass of fullscreen_vert.glsl and the given fragment shader
+    fn load_post_program(
+        app: &app::AppCore,
+        binaries: &program_cache::ProgramBinaryCache,
+        frag: &str,
+    ) -> Result<ShaderProgram, InitError> {
+        Self::load_program(
+            app,
+            binaries,
+            &[
+                ("shaders/fullscreen_vert.glsl", gl::VERTEX_SHADER),
+                (&format!("shaders/{}.glsl", frag), gl::FRAGMENT_SHADER),
+            ],
+        )
+    }
+
+    // Sky pass of skybox_vert.glsl and the given fragment shader
+    fn load_sky_program(
+        app: &app::AppCore,
+        binaries: &program_cache::ProgramBinaryCache,
+        frag: &str,
+    ) -> Result<ShaderProgram, InitError> {
+        Self::load_program(
+            app,
+            binaries,
+            &[
+                ("shaders/skybox_vert.glsl", gl::VERTEX_SHADER),
+                (&format!("shaders/{}.glsl", frag), gl::FRAGMENT_SHADER),
+            ],
+        )
     }
 
     pub fn update_model_matrix(&mut self, id: ResourceID, mat: Mat4) {
@@ -305,6 +993,8 @@ impl Pipeline3D {
             resource::NORMAL_MAPPED_MESH => {
                 self.normal_mapped_tex_meshes[id.as_index()].model_matrix = mat
             }
+            resource::PBR_MESH => self.pbr_meshes[id.as_index()].model_matrix = mat,
+            resource::COMPRESSED_MESH => self.compressed_meshes[id.as_index()].model_matrix = mat,
             _ => {}
         }
     }
@@ -315,6 +1005,26 @@ impl Pipeline3D {
             resource::NORMAL_MAPPED_MESH => {
                 self.normal_mapped_tex_meshes[id.as_index()].normal_matrix = mat
             }
+            resource::PBR_MESH => self.pbr_meshes[id.as_index()].normal_matrix = mat,
+            resource::COMPRESSED_MESH => self.compressed_meshes[id.as_index()].normal_matrix = mat,
+            _ => {}
+        }
+    }
+
+    // Translucent meshes blend with the diffuse alpha and skip depth writes
+    #[allow(dead_code)]
+    pub fn set_translucent(&mut self, id: ResourceID, translucent: bool) {
+        match id.get_type() {
+            resource::TEXTURED_MESH => {
+                self.basic_tex_meshes[id.as_index()].translucent = translucent
+            }
+            resource::NORMAL_MAPPED_MESH => {
+                self.normal_mapped_tex_meshes[id.as_index()].translucent = translucent
+            }
+            resource::PBR_MESH => self.pbr_meshes[id.as_index()].translucent = translucent,
+            resource::COMPRESSED_MESH => {
+                self.compressed_meshes[id.as_index()].translucent = translucent
+            }
             _ => {}
         }
     }
@@ -332,140 +1042,939 @@ impl Pipeline3D {
     }
 
     pub fn upload_common_uniforms(&self) {
-        unsafe {
-            gl::Uniform3fv(
-                // gpu::attrs::uniforms::VIEW_POS_LOCATION,
-                self.render.view_pos_unif.def.id,
-                1,
-                self.view_pos.as_ptr(),
-            );
-        }
+        self.render.camera_block.upload(&CameraBlock {
+            view_mat: self.view_matrix,
+            proj_mat: self.projection_matrix,
+            view_pos: self.view_pos,
+        });
+        self.render
+            .camera_block
+            .bind(gpu::attrs::CAMERA_BLOCK_BINDING);
     }
 
-    pub fn draw_textured_meshes(&self) {
-        // disable normal maps
+    pub fn add_light(&mut self, light: Light) -> LightId {
+        self.lights.add(light)
+    }
+
+    #[allow(dead_code)]
+    pub fn remove_light(&mut self, id: LightId) -> Option<Light> {
+        self.lights.remove(id)
+    }
+
+    #[allow(dead_code)]
+    pub fn light(&self, id: LightId) -> Option<&Light> {
+        self.lights.get(id)
+    }
+
+    // Changes are picked up by the next draw
+    pub fn light_mut(&mut self, id: LightId) -> Option<&mut Light> {
+        self.lights.get_mut(id)
+    }
+
+    // Upper bound of lights shading a single object, at most
+    // lighting::MAX_LIGHTS_PER_OBJECT
+    #[allow(dead_code)]
+    pub fn set_max_lights_per_object(&mut self, limit: usize) {
+        self.lights.set_per_object_limit(limit);
+    }
+
+    // Cookies are resampled to lighting::COOKIE_RESOLUTION, None once
+    // lighting::MAX_COOKIES are loaded
+    #[allow(dead_code)]
+    pub fn add_spot_cookie(&mut self, image: &mgl::s3tc::Image) -> Option<CookieId> {
+        self.spot_cookies.add_image(image)
+    }
+
+    #[allow(dead_code)]
+    pub fn add_spot_cookie_rgba(
+        &mut self,
+        width: u32,
+        height: u32,
+        pixels: &[u8],
+    ) -> Option<CookieId> {
+        self.spot_cookies.add_rgba(width, height, pixels)
+    }
+
+    // Selects and uploads the lights shading an object with the given world
+    // bounds, objects without bounds get the lights closest to the camera.
+    // Indices into the light buffer of the lights reaching the next draw
+    fn upload_object_lights(&self, selected: &[i32]) {
         unsafe {
-            gl::Uniform1ui(self.render.use_normalmap_unif.def.id, 0);
-            self.render.main_shader.set_active();
-            gl::Uniform1i(
-                gpu::attrs::DIFFUSE_SAMPLER_LOCATION,
-                gpu::attrs::DIFFUSE_TEXTURE_UNIT as i32,
-            ); // Texture Unit 0 : DIFFUSE
             gl::Uniform1i(
-                gpu::attrs::SPECULAR_SAMPLER_LOCATION,
-                gpu::attrs::SPECULAR_TEXTURE_UNIT as i32,
-            ); // Texture Unit 1 : SPECULAR
-            gl::Uniform1i(
-                gpu::attrs::NORMAL_SAMPLER_LOCATION,
-                gpu::attrs::NORMAL_TEXTURE_UNIT as i32,
-            ); // Texture Unit 2 : NORMAL
+                gpu::attrs::OBJECT_LIGHT_COUNT_LOCATION,
+                selected.len() as GLint,
+            );
+            if !selected.is_empty() {
+                gl::Uniform1iv(
+                    gpu::attrs::OBJECT_LIGHTS_LOCATION,
+                    selected.len() as GLsizei,
+                    selected.as_ptr(),
+                );
+            }
         }
+    }
 
-        self.upload_common_uniforms();
+    // Shadow slot of every light in buffer order, -1 for lights without one.
+    // The first shadow casting directional light uses the cascades (slot 0),
+    // point lights get consecutive cubes of the point shadow map and spot
+    // lights consecutive layers of the spot shadow map.
+    fn shadow_slots(&self) -> Vec<i32> {
+        let mut has_cascades = false;
+        let mut next_cube = 0;
+        let mut next_spot = 0;
+
+        self.lights
+            .iter()
+            .map(|(_, l)| {
+                if !self.shadow_config.enabled || !l.casts_shadow() {
+                    return -1;
+                }
+
+                match l {
+                    Light::Directional(_) if !has_cascades => {
+                        has_cascades = true;
+                        0
+                    }
+                    Light::Point(_) if next_cube < self.point_shadow_map.cubes => {
+                        next_cube += 1;
+                        next_cube as i32 - 1
+                    }
+                    Light::Spot(_) if next_spot < self.spot_shadow_map.layers => {
+                        next_spot += 1;
+                        next_spot as i32 - 1
+                    }
+                    _ => -1,
+                }
+            })
+            .collect()
+    }
 
-        for m in self.basic_tex_meshes.iter() {
-            unsafe {
-                let mv = self.view_matrix * m.model_matrix;
-                let mvp = self.projection_matrix * mv;
+    #[allow(dead_code)]
+    pub fn post(&self) -> &post::PostProcess {
+        &self.post
+    }
 
+    pub fn post_config(&self) -> &post::PostConfig {
+        &self.post.config
+    }
+
+    pub fn set_post_config(&mut self, config: post::PostConfig) {
+        self.post.config = config;
+    }
+
+    pub fn sky_config(&self) -> &sky::SkyConfig {
+        &self.sky.config
+    }
+
+    pub fn set_sky_config(&mut self, config: sky::SkyConfig) {
+        self.sky.config = config;
+    }
+
+    // Replaces the environment with a cube map loaded from DDS files
+    #[allow(dead_code)]
+    pub fn set_sky_cubemap(&mut self, cubemap: gpu::textures::Cubemap) {
+        self.sky.set_cubemap(cubemap);
+    }
+
+    // Bakes a gradient sky into the environment
+    #[allow(dead_code)]
+    pub fn set_procedural_sky(&mut self, procedural: sky::ProceduralSky) {
+        self.sky.set_procedural(procedural);
+    }
+
+    pub fn blinn_enabled(&self) -> bool {
+        self.blinn
+    }
+
+    // Picks the Blinn-Phong or Phong permutations of the main program
+    pub fn set_blinn_enabled(&mut self, enabled: bool) {
+        self.blinn = enabled;
+    }
+
+    pub fn culling_enabled(&self) -> bool {
+        self.culling
+    }
+
+    // Frustum culling of the main pass, shadow passes draw every caster
+    pub fn set_culling_enabled(&mut self, enabled: bool) {
+        self.culling = enabled;
+    }
+
+    // Drawn and culled counts of the last rendered frame
+    pub fn frame_stats(&self) -> FrameStats {
+        self.frame_stats.get()
+    }
+
+    pub fn shadows_enabled(&self) -> bool {
+        self.shadow_config.enabled
+    }
+
+    pub fn set_shadows_enabled(&mut self, enabled: bool) {
+        self.shadow_config.enabled = enabled;
+    }
+
+    pub fn shadow_config(&self) -> &shadow::ShadowConfig {
+        &self.shadow_config
+    }
+
+    // The shadow map is recreated when its size or cascade count changes
+    pub fn set_shadow_config(&mut self, config: shadow::ShadowConfig) {
+        let config = shadow::ShadowConfig {
+            cascade_count: config.cascade_count.max(1).min(shadow::MAX_CASCADES),
+            ..config
+        };
+
+        if config.resolution != self.shadow_map.resolution
+            || config.cascade_count != self.shadow_map.layers
+        {
+            self.shadow_map = shadow::ShadowMap::new(config.resolution, config.cascade_count);
+        }
+
+        if config.point_resolution != self.point_shadow_map.resolution {
+            self.point_shadow_map =
+                shadow::PointShadowMap::new(config.point_resolution, shadow::MAX_POINT_SHADOWS);
+        }
+
+        if config.spot_resolution != self.spot_shadow_map.resolution {
+            self.spot_shadow_map =
+                shadow::ShadowMap::new(config.spot_resolution, shadow::MAX_SPOT_SHADOWS);
+        }
+
+        self.shadow_config = config;
+    }
+
+    // Renders every shadow caster into the cascades of the shadow map and
+    // returns the cascades used, leaves the shadow program active.
+    fn draw_shadow_casters(&self, light_dir: Vec3) -> Vec<shadow::Cascade> {
+        let cascades = shadow::compute_cascades(
+            &self.view_matrix,
+            &self.projection_matrix,
+            light_dir,
+            &self.shadow_config,
+        );
+
+        unsafe {
+            // Casters between the sun and the near plane still write depth
+            gl::Enable(gl::DEPTH_CLAMP);
+        }
+
+        self.render.shadow_shader.set_active();
+
+        for (layer, cascade) in cascades.iter().enumerate() {
+            self.shadow_map.bind_layer(layer);
+
+            unsafe {
                 gl::UniformMatrix4fv(
-                    self.render.model_mat_unif.def.id,
-                    1,
-                    gl::FALSE,
-                    m.model_matrix.as_ptr(),
-                );
-                gl::UniformMatrix4fv(
-                    self.render.modelview_mat_unif.def.id,
+                    gpu::attrs::LIGHT_VIEW_PROJ_LOCATION,
                     1,
                     gl::FALSE,
-                    mv.as_ptr(),
+                    cascade.view_proj.as_ptr(),
                 );
-                gl::UniformMatrix4fv(
-                    self.render.proj_mat_unif.def.id,
+            }
+
+            self.draw_depth_casters();
+        }
+
+        unsafe {
+            gl::Disable(gl::DEPTH_CLAMP);
+        }
+
+        cascades
+    }
+
+    // Renders shadow casting point lights into their cubes of the cube map
+    // array, leaves the point shadow program active.
+    fn draw_point_shadow_casters(&self, slots: &[i32]) {
+        let casters: Vec<(&light_info::PointLight, i32)> = self
+            .lights
+            .iter()
+            .zip(slots.iter())
+            .filter_map(|((_, l), slot)| match l {
+                Light::Point(p) if *slot >= 0 => Some((p, *slot)),
+                _ => None,
+            })
+            .collect();
+
+        if casters.is_empty() {
+            return;
+        }
+
+        self.render.point_shadow_shader.set_active();
+        self.point_shadow_map.bind();
+
+        for (light, cube) in casters {
+            let faces = shadow::cube_face_view_projs(light.position, light.range);
+
+            unsafe {
+                for (i, f) in faces.iter().enumerate() {
+                    gl::UniformMatrix4fv(
+                        gpu::attrs::FACE_VIEW_PROJ_LOCATION + i as GLint,
+                        1,
+                        gl::FALSE,
+                        f.as_ptr(),
+                    );
+                }
+                gl::Uniform1i(gpu::attrs::CUBE_LAYER_LOCATION, cube as GLint);
+                gl::Uniform3fv(
+                    gpu::attrs::POINT_LIGHT_POS_LOCATION,
                     1,
-                    gl::FALSE,
-                    self.projection_matrix.as_ptr(),
+                    light.position.as_ptr(),
                 );
-                gl::UniformMatrix4fv(self.render.mvp_mat_unif.def.id, 1, gl::FALSE, mvp.as_ptr());
+                gl::Uniform1f(gpu::attrs::POINT_SHADOW_RANGE_LOCATION, light.range);
+            }
+
+            self.draw_depth_casters();
+        }
+    }
+
+    // Renders shadow casting spot lights into their layers of the spot
+    // shadow map, leaves the shadow program active.
+    fn draw_spot_shadow_casters(&self, slots: &[i32]) {
+        let casters: Vec<(&light_info::SpotLight, i32)> = self
+            .lights
+            .iter()
+            .zip(slots.iter())
+            .filter_map(|((_, l), slot)| match l {
+                Light::Spot(s) if *slot >= 0 => Some((s, *slot)),
+                _ => None,
+            })
+            .collect();
+
+        if casters.is_empty() {
+            return;
+        }
+
+        self.render.shadow_shader.set_active();
+
+        for (light, layer) in casters {
+            let view_proj = shadow::spot_view_proj(
+                light.position,
+                light.direction,
+                light.outer_angle,
+                light.range,
+            );
+
+            self.spot_shadow_map.bind_layer(layer as usize);
+
+            unsafe {
                 gl::UniformMatrix4fv(
-                    self.render.normal_mat_unif.def.id,
+                    gpu::attrs::LIGHT_VIEW_PROJ_LOCATION,
                     1,
                     gl::FALSE,
-                    m.normal_matrix.as_ptr(),
+                    view_proj.as_ptr(),
                 );
             }
 
-            self.render.draw(&m.resource);
+            self.draw_depth_casters();
         }
+    }
 
-        // enable normal maps
-        // unsafe {
-        //     gl::Uniform1ui(gpu::attrs::USE_NORMALMAP_FLAG, 1);
-        // }
+    // Draws every mesh with only the model matrix and position stream,
+    // used by the depth only programs of the shadow passes.
+    fn draw_depth_casters(&self) {
+        for m in self.basic_tex_meshes.iter() {
+            draw_depth(&m.model_matrix, m.resource.vao, m.resource.element_count);
+        }
 
-        pub use gpu::attrs::uniforms;
+        for m in self.normal_mapped_tex_meshes.iter() {
+            draw_depth(&m.model_matrix, m.resource.vao, m.resource.element_count);
+        }
 
-        self.upload_common_uniforms();
+        for m in self.pbr_meshes.iter() {
+            draw_depth(&m.model_matrix, m.resource.vao, m.resource.element_count);
+        }
 
-        for m in self.normal_mapped_tex_meshes.iter() {
-            unsafe {
-                let mv = self.view_matrix * m.model_matrix;
-                let mvp = self.projection_matrix * self.view_matrix * m.model_matrix;
+        for sb in self.static_batches.iter() {
+            draw_depth(
+                &Mat4::identity(),
+                sb.resource.vao,
+                sb.resource.element_count,
+            );
+        }
 
-                gl::UniformMatrix4fv(
-                    self.render.model_mat_unif.def.id,
+        if let Some(terrain) = &self.terrain {
+            for chunk in terrain.chunks.iter() {
+                draw_depth(&Mat4::identity(), chunk.vao, chunk.element_count);
+            }
+        }
+
+        unsafe {
+            gl::Uniform1i(gpu::attrs::USE_COMPRESSED_VERTICES_FLAG, 1);
+
+            for m in self.compressed_meshes.iter() {
+                let e = &m.resource;
+                gl::Uniform3fv(
+                    gpu::attrs::POSITION_BOUNDS_MIN_LOCATION,
                     1,
-                    gl::FALSE,
-                    m.model_matrix.as_ptr(),
+                    e.position_min.as_ptr(),
                 );
-                gl::UniformMatrix4fv(
-                    self.render.view_mat_unif.def.id,
+                gl::Uniform3fv(
+                    gpu::attrs::POSITION_BOUNDS_EXTENT_LOCATION,
                     1,
-                    gl::FALSE,
-                    self.view_matrix.as_ptr(),
+                    e.position_extent.as_ptr(),
                 );
                 gl::UniformMatrix4fv(
-                    self.render.modelview_mat_unif.def.id,
+                    gpu::attrs::uniforms::MODEL_MAT_LOCATION,
                     1,
                     gl::FALSE,
-                    mv.as_ptr(),
+                    m.model_matrix.as_ptr(),
                 );
-                gl::UniformMatrix4fv(
-                    self.render.proj_mat_unif.def.id,
-                    1,
-                    gl::FALSE,
-                    self.projection_matrix.as_ptr(),
+                gl::BindVertexArray(e.vao);
+                gl::DrawElements(
+                    gl::TRIANGLES,
+                    e.element_count,
+                    e.index_type,
+                    0 as *const GLvoid,
                 );
-                gl::UniformMatrix4fv(self.render.mvp_mat_unif.def.id, 1, gl::FALSE, mvp.as_ptr());
+            }
+
+            gl::Uniform1i(gpu::attrs::USE_COMPRESSED_VERTICES_FLAG, 0);
+            gl::Uniform1i(gpu::attrs::USE_INSTANCING_FLAG, 1);
+
+            for m in self.instanced_meshes.iter() {
+                let e = &m.resource;
+                if e.instance_count == 0 {
+                    continue;
+                }
+                gl::BindVertexArray(e.vao);
+                gl::DrawElementsInstanced(
+                    gl::TRIANGLES,
+                    e.element_count,
+                    gl::UNSIGNED_INT,
+                    0 as *const GLvoid,
+                    e.instance_count,
+                );
+            }
+
+            gl::Uniform1i(gpu::attrs::USE_INSTANCING_FLAG, 0);
+        }
+    }
+
+    // Draws the scene into the target and resolves it, the color and depth
+    // textures of the target can be sampled afterwards. The default
+    // framebuffer is bound again when done.
+    pub fn render_to(&self, target: &render_target::RenderTarget, clear_color: [f32; 4]) {
+        let mut viewport = [0 as GLint; 4];
+
+        unsafe {
+            gl::GetIntegerv(gl::VIEWPORT, viewport.as_mut_ptr());
+        }
+
+        target.bind();
+        target.clear(clear_color);
+        self.draw_textured_meshes();
+        target.resolve();
+
+        unsafe {
+            gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+            gl::Viewport(viewport[0], viewport[1], viewport[2], viewport[3]);
+        }
+    }
+
+    // Renders one frame into the window: the scene is drawn in HDR with
+    // MSAA and post processing maps it into the default framebuffer.
+    // Transient targets are kept in pool between frames, dump prints the
+    // compiled graph.
+    pub fn render_frame(
+        &self,
+        pool: &mut render_graph::TransientPool,
+        window_size: (u32, u32),
+        frame_time: f32,
+        clear_color: [f32; 4],
+        dump: bool,
+    ) -> Result<(), render_graph::RenderGraphError> {
+        let mut graph = render_graph::RenderGraph::new();
+        let backbuffer = graph.import("backbuffer");
+        graph.mark_output(backbuffer);
+
+        let scene = graph.create_target(
+            "scene",
+            post::PostProcess::scene_target_config(window_size.0, window_size.1, SCENE_SAMPLES),
+        );
+
+        graph.add_pass(
+            "scene",
+            |pass| {
+                pass.write(scene);
+            },
+            move |ctx| self.render_to(ctx.target(scene).unwrap(), clear_color),
+        );
+        self.post
+            .add_passes(&mut graph, scene, backbuffer, window_size, frame_time);
+
+        let compiled = graph.compile()?;
+
+        if dump {
+            println!("{}", graph.dump(&compiled));
+            println!("{}", graph.to_dot(&compiled));
+        }
+
+        graph.execute(&compiled, pool)
+    }
+
+    fn bind_light_textures(&self) {
+        unsafe {
+            gl::ActiveTexture(gl::TEXTURE0 + gpu::attrs::SHADOW_TEXTURE_UNIT);
+            gl::BindTexture(gl::TEXTURE_2D_ARRAY, self.shadow_map.texture); // Texture Unit 8 : SHADOW MAP
+            gl::ActiveTexture(gl::TEXTURE0 + gpu::attrs::POINT_SHADOW_TEXTURE_UNIT);
+            gl::BindTexture(gl::TEXTURE_CUBE_MAP_ARRAY, self.point_shadow_map.texture); // Texture Unit 9 : POINT SHADOW MAPS
+            gl::ActiveTexture(gl::TEXTURE0 + gpu::attrs::SPOT_SHADOW_TEXTURE_UNIT);
+            gl::BindTexture(gl::TEXTURE_2D_ARRAY, self.spot_shadow_map.texture); // Texture Unit 10 : SPOT SHADOW MAPS
+            gl::ActiveTexture(gl::TEXTURE0 + gpu::attrs::SPOT_COOKIE_TEXTURE_UNIT);
+            gl::BindTexture(gl::TEXTURE_2D_ARRAY, self.spot_cookies.texture); // Texture Unit 11 : SPOT COOKIES
+            gl::ActiveTexture(gl::TEXTURE0 + gpu::attrs::ENVIRONMENT_TEXTURE_UNIT);
+            gl::BindTexture(gl::TEXTURE_CUBE_MAP, self.sky.environment().id); // Texture Unit 12 : ENVIRONMENT
+        }
+    }
+
+    // Makes the variant current, shadow uniforms only exist in permutations
+    // compiled with SHADOWS
+    fn begin_variant(&self, variant: &MainProgram, cascades: &[shadow::Cascade]) {
+        variant.program.set_active();
+
+        unsafe {
+            gl::Uniform1f(
+                gpu::attrs::ENVIRONMENT_STRENGTH_LOCATION,
+                self.sky.reflection_strength(),
+            );
+        }
+
+        if self.shadow_config.enabled {
+            self.upload_shadow_uniforms(cascades);
+        }
+    }
+
+    fn upload_shadow_uniforms(&self, cascades: &[shadow::Cascade]) {
+        let config = &self.shadow_config;
+        let mut splits = [0.0f32; shadow::MAX_CASCADES];
+
+        for (i, c) in cascades.iter().enumerate() {
+            splits[i] = c.split_far;
+        }
+
+        unsafe {
+            gl::Uniform1i(gpu::attrs::CASCADE_COUNT_LOCATION, cascades.len() as GLint);
+
+            for (i, c) in cascades.iter().enumerate() {
                 gl::UniformMatrix4fv(
-                    self.render.normal_mat_unif.def.id,
+                    gpu::attrs::CASCADE_VIEW_PROJ_LOCATION + i as GLint,
                     1,
                     gl::FALSE,
-                    m.normal_matrix.as_ptr(),
+                    c.view_proj.as_ptr(),
                 );
+            }
+
+            gl::Uniform4fv(gpu::attrs::CASCADE_SPLITS_LOCATION, 1, splits.as_ptr());
+            gl::Uniform3f(
+                gpu::attrs::SHADOW_BIAS_LOCATION,
+                config.depth_bias,
+                config.slope_bias,
+                config.normal_offset,
+            );
+            gl::Uniform1i(gpu::attrs::SHADOW_PCF_RADIUS_LOCATION, config.pcf_radius);
+        }
+    }
+
+    pub fn draw_textured_meshes(&self) {
+        let mut viewport = [0 as GLint; 4];
+        let mut framebuffer: GLint = 0;
+
+        // Shadow passes bind their own framebuffers, the scene goes to
+        // whatever was bound before, see render_to
+        unsafe {
+            gl::GetIntegerv(gl::VIEWPORT, viewport.as_mut_ptr());
+            gl::GetIntegerv(gl::DRAW_FRAMEBUFFER_BINDING, &mut framebuffer);
+        }
 
-                self.render.main_shader.set_active();
+        let slots = self.shadow_slots();
+
+        let sun = self
+            .lights
+            .iter()
+            .zip(slots.iter())
+            .find_map(|((_, l), slot)| match l {
+                Light::Directional(d) if *slot == 0 => Some(d.direction),
+                _ => None,
+            });
+
+        let cascades = match sun {
+            Some(dir) => self.draw_shadow_casters(dir),
+            None => vec![],
+        };
+
+        self.draw_point_shadow_casters(&slots);
+        self.draw_spot_shadow_casters(&slots);
+
+        unsafe {
+            gl::BindFramebuffer(gl::FRAMEBUFFER, framebuffer as GLuint);
+            gl::Viewport(viewport[0], viewport[1], viewport[2], viewport[3]);
+        }
+
+        let mut culler = Culler {
+            frustum: Frustum::from_matrix(&(self.projection_matrix * self.view_matrix)),
+            enabled: self.culling,
+            stats: FrameStats::default(),
+        };
+
+        let mut queue = self.queue.borrow_mut();
+        queue.clear();
+        self.queue_draws(&mut queue, &mut culler);
+        queue.sort();
+
+        self.bind_light_textures();
+        self.upload_common_uniforms();
+        self.lights.upload(&slots, gpu::attrs::LIGHT_BUFFER_BINDING);
+
+        let mut pass = MainPassState::new();
+
+        let mut sky_drawn = false;
+
+        for (key, draw) in queue.items() {
+            // The sky goes between the opaque and the translucent draws
+            if key.is_translucent() && !sky_drawn {
+                self.draw_sky(&mut pass);
+                sky_drawn = true;
             }
 
-            self.render.draw(&m.resource);
+            self.submit(draw, key.is_translucent(), &cascades, &mut pass);
+        }
+
+        if !sky_drawn {
+            self.draw_sky(&mut pass);
         }
+
+        pass.finish();
+
+        self.frame_stats.set(FrameStats {
+            draw_calls: pass.draw_calls,
+            program_binds: pass.program_binds,
+            texture_binds: pass.state.texture_binds,
+            ..culler.stats
+        });
+    }
+
+    // Culls everything drawn by the main pass and queues what is visible
+    fn queue_draws(&self, queue: &mut RenderQueue<QueuedDraw>, culler: &mut Culler) {
+        for (i, m) in self.basic_tex_meshes.iter().enumerate() {
+            let bounds = lighting::transform_bounds(&m.bounds, &m.model_matrix);
+
+            if culler.visible(&bounds) {
+                let t = &m.resource.textures;
+                self.queue_draw(
+                    queue,
+                    DrawSource::Basic(i),
+                    Some(bounds),
+                    [t.diffuse, t.specular, 0, 0],
+                    m.translucent,
+                );
+            }
+        }
+
+        for (i, m) in self.normal_mapped_tex_meshes.iter().enumerate() {
+            let bounds = lighting::transform_bounds(&m.bounds, &m.model_matrix);
+
+            if culler.visible(&bounds) {
+                let t = &m.resource.textures;
+                self.queue_draw(
+                    queue,
+                    DrawSource::NormalMapped(i),
+                    Some(bounds),
+                    [t.diffuse, t.specular, t.normal, 0],
+                    m.translucent,
+                );
+            }
+        }
+
+        for (i, m) in self.pbr_meshes.iter().enumerate() {
+            let bounds = lighting::transform_bounds(&m.bounds, &m.model_matrix);
+
+            if culler.visible(&bounds) {
+                let t = &m.resource.textures;
+                self.queue_draw(
+                    queue,
+                    DrawSource::Pbr(i),
+                    Some(bounds),
+                    [t.albedo, t.normal, t.material, 0],
+                    m.translucent,
+                );
+            }
+        }
+
+        // Static batches are pre-transformed so one draw call covers every member
+        for (i, sb) in self.static_batches.iter().enumerate() {
+            if culler.visible(&sb.bounds) {
+                let t = &sb.resource.textures;
+                self.queue_draw(
+                    queue,
+                    DrawSource::StaticBatch(i),
+                    Some(sb.bounds),
+                    [t.diffuse, t.specular, t.normal, 0],
+                    false,
+                );
+            }
+        }
+
+        for (i, m) in self.compressed_meshes.iter().enumerate() {
+            let bounds = lighting::transform_bounds(&m.bounds, &m.model_matrix);
+
+            if culler.visible(&bounds) {
+                let t = &m.resource.textures;
+                self.queue_draw(
+                    queue,
+                    DrawSource::Compressed(i),
+                    Some(bounds),
+                    [t.diffuse, t.specular, t.normal, 0],
+                    m.translucent,
+                );
+            }
+        }
+
+        // Culled as a whole by the bounds of every instance. Instances spread
+        // anywhere, they share the lights near the camera.
+        for (i, m) in self.instanced_meshes.iter().enumerate() {
+            if m.resource.instance_count == 0 || !culler.visible(&m.world_bounds) {
+                continue;
+            }
+
+            let t = &m.resource.textures;
+            self.queue_draw(
+                queue,
+                DrawSource::Instanced(i),
+                None,
+                [t.diffuse, t.specular, t.normal, 0],
+                false,
+            );
+        }
+
+        if let Some(terrain) = &self.terrain {
+            let t = &terrain.textures;
+
+            for (i, bounds) in terrain.chunk_bounds.iter().enumerate() {
+                if culler.visible(bounds) {
+                    self.queue_draw(
+                        queue,
+                        DrawSource::TerrainChunk(i),
+                        Some(*bounds),
+                        [t.splat, t.specular, t.normal, 0],
+                        false,
+                    );
+                }
+            }
+        }
+    }
+
+    fn queue_draw(
+        &self,
+        queue: &mut RenderQueue<QueuedDraw>,
+        source: DrawSource,
+        bounds: Option<Aabb>,
+        textures: [GLuint; 4],
+        translucent: bool,
+    ) {
+        // View space distance of the center, the camera looks down -z
+        let depth = bounds.map_or(0.0, |b| -(self.view_matrix * b.center().extend(1.0)).z);
+        // Permutation features in the low bits, the draw mode above them
+        let shader = source.features().bits() | (source.mode() as u32) << 4;
+        let material = queue.material(textures);
+
+        let key = if translucent {
+            SortKey::translucent(MAIN_PASS, shader, material, depth)
+        } else {
+            SortKey::opaque(MAIN_PASS, shader, material, depth)
+        };
+
+        queue.push(
+            key,
+            QueuedDraw {
+                source: source,
+                bounds: bounds,
+            },
+        );
+    }
+
+    fn submit(
+        &self,
+        draw: &QueuedDraw,
+        translucent: bool,
+        cascades: &[shadow::Cascade],
+        pass: &mut MainPassState,
+    ) {
+        let features = draw.source.features();
+        let variant = self.main_variant(features);
+
+        if pass.features != Some(features) {
+            // Flags are uniforms of the program, leave it in mesh mode
+            pass.set_mode(DrawMode::Mesh);
+            self.begin_variant(variant, cascades);
+            pass.features = Some(features);
+            pass.object = None;
+            pass.object_lights = None;
+            pass.program_binds += 1;
+        }
+
+        let mode_changed = pass.set_mode(draw.source.mode());
+        pass.state.set_blending(translucent);
+
+        let identity = Mat4::identity();
+
+        match draw.source {
+            DrawSource::Basic(i) => {
+                let m = &self.basic_tex_meshes[i];
+                self.upload_object(variant, pass, &m.model_matrix, &m.normal_matrix, draw);
+                self.render.draw(&m.resource, &mut pass.state);
+            }
+            DrawSource::NormalMapped(i) => {
+                let m = &self.normal_mapped_tex_meshes[i];
+                self.upload_object(variant, pass, &m.model_matrix, &m.normal_matrix, draw);
+                self.render.draw(&m.resource, &mut pass.state);
+            }
+            DrawSource::Pbr(i) => {
+                let m = &self.pbr_meshes[i];
+                self.upload_object(variant, pass, &m.model_matrix, &m.normal_matrix, draw);
+                self.render.draw(&m.resource, &mut pass.state);
+            }
+            DrawSource::StaticBatch(i) => {
+                let sb = &self.static_batches[i];
+                self.upload_object(variant, pass, &identity, &identity, draw);
+                self.render.draw(&sb.resource, &mut pass.state);
+            }
+            DrawSource::Compressed(i) => {
+                let m = &self.compressed_meshes[i];
+                self.upload_object(variant, pass, &m.model_matrix, &m.normal_matrix, draw);
+                self.render.draw(&m.resource, &mut pass.state);
+            }
+            // Model and normal matrices come from the instance buffer
+            DrawSource::Instanced(i) => {
+                let m = &self.instanced_meshes[i];
+                self.upload_object(variant, pass, &identity, &identity, draw);
+                self.render.draw(&m.resource, &mut pass.state);
+            }
+            DrawSource::TerrainChunk(i) => {
+                let terrain = self.terrain.as_ref().unwrap();
+
+                if mode_changed {
+                    unsafe {
+                        gl::Uniform1f(
+                            gpu::attrs::SPLAT_DETAIL_SCALE_LOCATION,
+                            terrain.detail_scale,
+                        );
+                    }
+                }
+
+                self.upload_object(variant, pass, &identity, &identity, draw);
+                draw_terrain_chunk(terrain, i, &mut pass.state);
+            }
+        }
+
+        pass.draw_calls += 1;
+    }
+
+    // Switches programs, the next draw begins its variant again
+    fn draw_sky(&self, pass: &mut MainPassState) {
+        if !self.sky.config.enabled {
+            return;
+        }
+
+        pass.finish();
+        self.sky.draw(&self.view_matrix, &self.projection_matrix);
+
+        pass.features = None;
+        pass.object = None;
+        pass.object_lights = None;
+        pass.state.invalidate();
+    }
+
+    // Skips uploads the previous draw already made with the same program
+    fn upload_object(
+        &self,
+        variant: &MainProgram,
+        pass: &mut MainPassState,
+        model: &Mat4,
+        normal: &Mat4,
+        draw: &QueuedDraw,
+    ) {
+        if pass.object != Some((*model, *normal)) {
+            self.upload_object_matrices(variant, model, normal);
+            pass.object = Some((*model, *normal));
+        }
+
+        let selected = self
+            .lights
+            .select(draw.bounds.as_ref(), self.view_pos.to_vec());
+
+        if pass.object_lights.as_ref() != Some(&selected) {
+            self.upload_object_lights(&selected);
+            pass.object_lights = Some(selected);
+        }
+    }
+
+    fn upload_object_matrices(&self, variant: &MainProgram, model: &Mat4, normal: &Mat4) {
+        let mv = self.view_matrix * model;
+        let mvp = self.projection_matrix * mv;
+
+        // Types and sizes are checked when the uniforms are looked up
+        variant.model_mat_unif.upload(*model).unwrap();
+        variant.modelview_mat_unif.upload(mv).unwrap();
+        variant.mvp_mat_unif.upload(mvp).unwrap();
+        variant.normal_mat_unif.upload(*normal).unwrap();
+    }
+}
+
+// Depth pass draw of a mesh with 32-bit indices, shadow program must be active
+fn draw_depth(model: &Mat4, vao: GLuint, element_count: GLsizei) {
+    unsafe {
+        gl::UniformMatrix4fv(
+            gpu::attrs::uniforms::MODEL_MAT_LOCATION,
+            1,
+            gl::FALSE,
+            model.as_ptr(),
+        );
+        gl::BindVertexArray(vao);
+        gl::DrawElements(
+            gl::TRIANGLES,
+            element_count,
+            gl::UNSIGNED_INT,
+            0 as *const GLvoid,
+        );
     }
 }
 
+// Terrain chunks share the splat material, chunk vertices are in world space
+fn draw_terrain_chunk(terrain: &mesh_data::Terrain, chunk: usize, state: &mut StateCache) {
+    let textures = &terrain.textures;
+    state.bind_texture_2d(gpu::attrs::SPLAT_TEXTURE_UNIT, textures.splat);
+    state.bind_texture_2d(gpu::attrs::SPECULAR_TEXTURE_UNIT, textures.specular);
+    state.bind_texture_2d(gpu::attrs::NORMAL_TEXTURE_UNIT, textures.normal);
+
+    for (i, layer) in textures.layers.iter().enumerate() {
+        state.bind_texture_2d(gpu::attrs::SPLAT_LAYER_TEXTURE_UNIT + i as GLuint, *layer);
+    }
+
+    let chunk = &terrain.chunks[chunk];
+    state.bind_vertex_array(chunk.vao);
+
+    unsafe {
+        gl::DrawElements(
+            gl::TRIANGLES,
+            chunk.element_count,
+            gl::UNSIGNED_INT,
+            0 as *const GLvoid,
+        );
+    }
+}
+
+// Index buffers are part of the vertex array, see VertexLayout::create_vao
 trait Draw<T> {
-    fn draw(&self, e: &T);
+    fn draw(&self, e: &T, state: &mut StateCache);
 }
 
 impl Draw<gpu::basic_mesh::Mesh> for Render3D {
-    fn draw(&self, e: &gpu::basic_mesh::Mesh) {
+    fn draw(&self, e: &gpu::basic_mesh::Mesh, state: &mut StateCache) {
         unsafe {
-            gl::ActiveTexture(gl::TEXTURE0 + gpu::attrs::DIFFUSE_TEXTURE_UNIT);
-            gl::BindTexture(gl::TEXTURE_2D, e.textures.diffuse);
-            gl::ActiveTexture(gl::TEXTURE0 + gpu::attrs::SPECULAR_TEXTURE_UNIT);
-            gl::BindTexture(gl::TEXTURE_2D, e.textures.specular);
+            state.bind_texture_2d(gpu::attrs::DIFFUSE_TEXTURE_UNIT, e.textures.diffuse);
+            state.bind_texture_2d(gpu::attrs::SPECULAR_TEXTURE_UNIT, e.textures.specular);
 
-            gl::BindVertexArray(e.vao);
-            gl::BindBuffer(gl::ELEMENT_ARRAY_BUFFER, e.buffers.index);
+            state.bind_vertex_array(e.vao);
             gl::DrawElements(
                 gl::TRIANGLES,
                 e.element_count,
@@ -477,22 +1986,89 @@ impl Draw<gpu::basic_mesh::Mesh> for Render3D {
 }
 
 impl Draw<gpu::normal_mapped_mesh::Mesh> for Render3D {
-    fn draw(&self, e: &gpu::normal_mapped_mesh::Mesh) {
+    fn draw(&self, e: &gpu::normal_mapped_mesh::Mesh, state: &mut StateCache) {
+        unsafe {
+            state.bind_texture_2d(gpu::attrs::DIFFUSE_TEXTURE_UNIT, e.textures.diffuse);
+            state.bind_texture_2d(gpu::attrs::SPECULAR_TEXTURE_UNIT, e.textures.specular);
+            state.bind_texture_2d(gpu::attrs::NORMAL_TEXTURE_UNIT, e.textures.normal);
+
+            state.bind_vertex_array(e.vao);
+            gl::DrawElements(
+                gl::TRIANGLES,
+                e.element_count,
+                gl::UNSIGNED_INT,
+                0 as *const GLvoid,
+            );
+        }
+    }
+}
+
+impl Draw<gpu::pbr_mesh::Mesh> for Render3D {
+    fn draw(&self, e: &gpu::pbr_mesh::Mesh, state: &mut StateCache) {
         unsafe {
-            gl::ActiveTexture(gl::TEXTURE0 + gpu::attrs::DIFFUSE_TEXTURE_UNIT);
-            gl::BindTexture(gl::TEXTURE_2D, e.textures.diffuse);
-            gl::ActiveTexture(gl::TEXTURE0 + gpu::attrs::SPECULAR_TEXTURE_UNIT);
-            gl::BindTexture(gl::TEXTURE_2D, e.textures.specular);
-            gl::ActiveTexture(gl::TEXTURE0 + gpu::attrs::NORMAL_TEXTURE_UNIT);
-            gl::BindTexture(gl::TEXTURE_2D, e.textures.normal);
-
-            gl::BindVertexArray(e.vao);
-            gl::BindBuffer(gl::ELEMENT_ARRAY_BUFFER, e.buffers.index);
+            state.bind_texture_2d(gpu::attrs::DIFFUSE_TEXTURE_UNIT, e.textures.albedo);
+            state.bind_texture_2d(gpu::attrs::NORMAL_TEXTURE_UNIT, e.textures.normal);
+            state.bind_texture_2d(gpu::attrs::MATERIAL_TEXTURE_UNIT, e.textures.material);
+
+            state.bind_vertex_array(e.vao);
+            gl::DrawElements(
+                gl::TRIANGLES,
+                e.element_count,
+                gl::UNSIGNED_INT,
+                0 as *const GLvoid,
+            );
+        }
+    }
+}
+
+impl Draw<gpu::compressed_mesh::Mesh> for Render3D {
+    fn draw(&self, e: &gpu::compressed_mesh::Mesh, state: &mut StateCache) {
+        unsafe {
+            gl::Uniform3fv(
+                gpu::attrs::POSITION_BOUNDS_MIN_LOCATION,
+                1,
+                e.position_min.as_ptr(),
+            );
+            gl::Uniform3fv(
+                gpu::attrs::POSITION_BOUNDS_EXTENT_LOCATION,
+                1,
+                e.position_extent.as_ptr(),
+            );
+            gl::Uniform4fv(gpu::attrs::UV_BOUNDS_LOCATION, 1, e.uv_bounds.as_ptr());
+
+            state.bind_texture_2d(gpu::attrs::DIFFUSE_TEXTURE_UNIT, e.textures.diffuse);
+            state.bind_texture_2d(gpu::attrs::SPECULAR_TEXTURE_UNIT, e.textures.specular);
+            state.bind_texture_2d(gpu::attrs::NORMAL_TEXTURE_UNIT, e.textures.normal);
+
+            state.bind_vertex_array(e.vao);
             gl::DrawElements(
+                gl::TRIANGLES,
+                e.element_count,
+                e.index_type,
+                0 as *const GLvoid,
+            );
+        }
+    }
+}
+
+impl Draw<gpu::instanced_mesh::Mesh> for Render3D {
+    fn draw(&self, e: &gpu::instanced_mesh::Mesh, state: &mut StateCache) {
+        if e.instance_count == 0 {
+            return;
+        }
+
+        unsafe {
+            state.bind_texture_2d(gpu::attrs::DIFFUSE_TEXTURE_UNIT, e.textures.diffuse);
+            state.bind_texture_2d(gpu::attrs::SPECULAR_TEXTURE_UNIT, e.textures.specular);
+            state.bind_texture_2d(gpu::attrs::NORMAL_TEXTURE_UNIT, e.textures.normal);
+
+            state.bind_vertex_array(e.vao);
+            gl::DrawElementsInstanced(
                 gl::TRIANGLES,
                 e.element_count,
                 gl::UNSIGNED_INT,
                 0 as *const GLvoid,
+                e.instance_count,
             );
         }
     }
@@ -0,0 +1,419 @@
+use crate::core::pipeline::gpu;
+use crate::core::pipeline::mgl::shader::ShaderProgram;
+use crate::core::pipeline::render_graph::{RenderGraph, ResourceId};
+use crate::core::pipeline::render_target::{RenderTarget, RenderTargetConfig};
+use gl::types::*;
+
+// HDR post processing. The scene is rendered into a floating point target,
+// bright parts are blurred into bloom through a chain of half sized
+// targets, then the tonemap pass applies exposure and maps the result into
+// the window.
+//
+// Auto exposure builds a histogram of log luminance with a compute shader,
+// a second dispatch averages it and moves the adapted luminance towards the
+// average. Both stay on the GPU, the tonemap pass reads the adapted
+// luminance from a storage buffer.
+
+pub const MAX_BLOOM_LEVELS: usize = 8;
+
+// Format of the scene target
+pub const HDR_FORMAT: GLenum = gl::RGBA16F;
+const BLOOM_FORMAT: GLenum = gl::R11F_G11F_B10F;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Tonemapper {
+    Reinhard = 0,
+    Aces = 1,
+    Filmic = 2,
+}
+
+impl Tonemapper {
+    pub fn next(&self) -> Tonemapper {
+        match self {
+            Tonemapper::Reinhard => Tonemapper::Aces,
+            Tonemapper::Aces => Tonemapper::Filmic,
+            Tonemapper::Filmic => Tonemapper::Reinhard,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Exposure {
+    // Scale applied to the scene before tonemapping
+    Manual(f32),
+    // Maps the adapted average luminance to exposure_key
+    Auto,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct PostConfig {
+    pub tonemapper: Tonemapper,
+    pub exposure: Exposure,
+    pub exposure_key: f32,
+    // Luminance range covered by the histogram in log2 units
+    pub min_log_luminance: f32,
+    pub max_log_luminance: f32,
+    // How fast the eye adapts, larger is faster
+    pub adaptation_rate: f32,
+    pub bloom: bool,
+    // Brightness where bloom starts and the width of the soft knee as a
+    // fraction of the threshold
+    pub bloom_threshold: f32,
+    pub bloom_knee: f32,
+    pub bloom_intensity: f32,
+    pub bloom_levels: usize,
+}
+
+impl Default for PostConfig {
+    fn default() -> Self {
+        Self {
+            tonemapper: Tonemapper::Aces,
+            exposure: Exposure::Auto,
+            exposure_key: 0.18,
+            min_log_luminance: -8.0,
+            max_log_luminance: 4.0,
+            adaptation_rate: 1.5,
+            bloom: true,
+            bloom_threshold: 1.0,
+            bloom_knee: 0.5,
+            bloom_intensity: 0.05,
+            bloom_levels: 5,
+        }
+    }
+}
+
+pub struct PostPrograms {
+    pub bloom_downsample: ShaderProgram,
+    pub bloom_upsample: ShaderProgram,
+    pub tonemap: ShaderProgram,
+    pub histogram: ShaderProgram,
+    pub exposure: ShaderProgram,
+}
+
+pub struct PostProcess {
+    programs: PostPrograms,
+    // Fullscreen triangles are generated from gl_VertexID
+    empty_vao: GLuint,
+    histogram_buffer: GLuint,
+    exposure_buffer: GLuint,
+    pub config: PostConfig,
+}
+
+fn draw_fullscreen(vao: GLuint) {
+    unsafe {
+        gl::BindVertexArray(vao);
+        gl::DrawArrays(gl::TRIANGLES, 0, 3);
+    }
+}
+
+fn bind_source(texture: GLuint, width: u32, height: u32) {
+    unsafe {
+        gl::BindTextureUnit(gpu::attrs::POST_SOURCE_TEXTURE_UNIT, texture);
+        gl::Uniform1i(
+            gpu::attrs::POST_SOURCE_SAMPLER_LOCATION,
+            gpu::attrs::POST_SOURCE_TEXTURE_UNIT as GLint,
+        );
+        gl::Uniform2f(
+            gpu::attrs::POST_SOURCE_TEXEL_LOCATION,
+            1.0 / width as f32,
+            1.0 / height as f32,
+        );
+    }
+}
+
+#[allow(dead_code)]
+impl PostProcess {
+    pub fn new(programs: PostPrograms) -> Self {
+        let mut empty_vao = 0;
+        let mut buffers = [0; 2];
+        let histogram = [0u32; 256];
+
+        unsafe {
+            gl::CreateVertexArrays(1, &mut empty_vao);
+            gl::CreateBuffers(2, buffers.as_mut_ptr());
+            gl::NamedBufferStorage(
+                buffers[0],
+                std::mem::size_of_val(&histogram) as GLsizeiptr,
+                histogram.as_ptr() as *const GLvoid,
+                0,
+            );
+            // Negative until the first average replaces it
+            let adapted = -1.0f32;
+            gl::NamedBufferStorage(
+                buffers[1],
+                std::mem::size_of::<f32>() as GLsizeiptr,
+                &adapted as *const f32 as *const GLvoid,
+                0,
+            );
+        }
+
+        Self {
+            programs: programs,
+            empty_vao: empty_vao,
+            histogram_buffer: buffers[0],
+            exposure_buffer: buffers[1],
+            config: PostConfig::default(),
+        }
+    }
+
+    // Config of the scene target the passes expect as input
+    pub fn scene_target_config(width: u32, height: u32, samples: u32) -> RenderTargetConfig {
+        RenderTargetConfig {
+            width: width,
+            height: height,
+            samples: samples,
+            color_formats: vec![HDR_FORMAT],
+            depth: true,
+        }
+    }
+
+    // Adds the bloom, exposure and tonemap passes reading the HDR scene
+    // and writing output, output is drawn as the default framebuffer with
+    // the given size
+    pub fn add_passes<'a>(
+        &'a self,
+        graph: &mut RenderGraph<'a>,
+        scene: ResourceId,
+        output: ResourceId,
+        output_size: (u32, u32),
+        delta_time: f32,
+    ) {
+        let config = self.config;
+        let levels = config.bloom_levels.max(1).min(MAX_BLOOM_LEVELS);
+        let (width, height) = output_size;
+
+        let bloom: Vec<ResourceId> = if config.bloom {
+            (0..levels)
+                .map(|i| {
+                    graph.create_target(
+                        &format!("bloom{}", i),
+                        RenderTargetConfig {
+                            width: (width >> (i + 1)).max(1),
+                            height: (height >> (i + 1)).max(1),
+                            samples: 1,
+                            color_formats: vec![BLOOM_FORMAT],
+                            depth: false,
+                        },
+                    )
+                })
+                .collect()
+        } else {
+            vec![]
+        };
+
+        let bloom_result = bloom.first().cloned();
+
+        if !bloom.is_empty() {
+            let chain = bloom.clone();
+            graph.add_pass(
+                "bloom",
+                |pass| {
+                    pass.read(scene);
+                    for level in chain.iter() {
+                        pass.write(*level);
+                    }
+                },
+                move |ctx| {
+                    let scene = ctx.target(scene).unwrap();
+                    let chain: Vec<&RenderTarget> =
+                        bloom.iter().map(|l| ctx.target(*l).unwrap()).collect();
+                    self.draw_bloom(scene, &chain);
+                },
+            );
+        }
+
+        let exposure = graph.import("exposure");
+
+        if config.exposure == Exposure::Auto {
+            graph.add_pass(
+                "exposure",
+                |pass| {
+                    pass.read(scene);
+                    pass.write(exposure);
+                },
+                move |ctx| self.update_exposure(ctx.target(scene).unwrap(), delta_time),
+            );
+        }
+
+        graph.add_pass(
+            "tonemap",
+            |pass| {
+                pass.read(scene);
+                pass.read(exposure);
+                if let Some(b) = bloom_result {
+                    pass.read(b);
+                }
+                pass.write(output);
+            },
+            move |ctx| {
+                let bloom = bloom_result.and_then(|b| ctx.target(b));
+                self.draw_tonemap(ctx.target(scene).unwrap(), bloom, output_size);
+            },
+        );
+    }
+
+    fn draw_bloom(&self, scene: &RenderTarget, chain: &[&RenderTarget]) {
+        let config = &self.config;
+
+        unsafe {
+            gl::Disable(gl::DEPTH_TEST);
+        }
+
+        self.programs.bloom_downsample.set_active();
+
+        for (i, level) in chain.iter().enumerate() {
+            let (source, w, h) = if i == 0 {
+                (
+                    scene.color_texture(0).unwrap(),
+                    scene.width(),
+                    scene.height(),
+                )
+            } else {
+                let prev = chain[i - 1];
+                (prev.color_texture(0).unwrap(), prev.width(), prev.height())
+            };
+
+            level.bind();
+            bind_source(source, w, h);
+
+            unsafe {
+                gl::Uniform1i(gpu::attrs::BLOOM_APPLY_THRESHOLD_FLAG, (i == 0) as GLint);
+                gl::Uniform2f(
+                    gpu::attrs::BLOOM_THRESHOLD_LOCATION,
+                    config.bloom_threshold,
+                    config.bloom_knee,
+                );
+            }
+
+            draw_fullscreen(self.empty_vao);
+        }
+
+        // Every level is added on top of the next larger one
+        self.programs.bloom_upsample.set_active();
+
+        unsafe {
+            gl::Enable(gl::BLEND);
+            gl::BlendFunc(gl::ONE, gl::ONE);
+        }
+
+        for i in (1..chain.len()).rev() {
+            let source = chain[i];
+            chain[i - 1].bind();
+            bind_source(
+                source.color_texture(0).unwrap(),
+                source.width(),
+                source.height(),
+            );
+            draw_fullscreen(self.empty_vao);
+        }
+
+        unsafe {
+            gl::Disable(gl::BLEND);
+            gl::Enable(gl::DEPTH_TEST);
+        }
+    }
+
+    fn update_exposure(&self, scene: &RenderTarget, delta_time: f32) {
+        let config = &self.config;
+        let range = (config.max_log_luminance - config.min_log_luminance).max(0.001);
+        let adaptation = 1.0 - (-delta_time * config.adaptation_rate).exp();
+
+        unsafe {
+            gl::BindBufferBase(
+                gl::SHADER_STORAGE_BUFFER,
+                gpu::attrs::HISTOGRAM_BUFFER_BINDING,
+                self.histogram_buffer,
+            );
+            gl::BindBufferBase(
+                gl::SHADER_STORAGE_BUFFER,
+                gpu::attrs::EXPOSURE_BUFFER_BINDING,
+                self.exposure_buffer,
+            );
+
+            self.programs.histogram.set_active();
+            bind_source(
+                scene.color_texture(0).unwrap(),
+                scene.width(),
+                scene.height(),
+            );
+            gl::Uniform1f(
+                gpu::attrs::MIN_LOG_LUMINANCE_LOCATION,
+                config.min_log_luminance,
+            );
+            gl::Uniform1f(gpu::attrs::LOG_LUMINANCE_RANGE_LOCATION, range);
+            gl::DispatchCompute((scene.width() + 15) / 16, (scene.height() + 15) / 16, 1);
+            gl::MemoryBarrier(gl::SHADER_STORAGE_BARRIER_BIT);
+
+            self.programs.exposure.set_active();
+            gl::Uniform1f(
+                gpu::attrs::MIN_LOG_LUMINANCE_LOCATION,
+                config.min_log_luminance,
+            );
+            gl::Uniform1f(gpu::attrs::LOG_LUMINANCE_RANGE_LOCATION, range);
+            gl::Uniform1f(
+                gpu::attrs::PIXEL_COUNT_LOCATION,
+                (scene.width() * scene.height()) as f32,
+            );
+            gl::Uniform1f(gpu::attrs::EXPOSURE_ADAPTATION_LOCATION, adaptation);
+            gl::DispatchCompute(1, 1, 1);
+            gl::MemoryBarrier(gl::SHADER_STORAGE_BARRIER_BIT);
+        }
+    }
+
+    fn draw_tonemap(&self, scene: &RenderTarget, bloom: Option<&RenderTarget>, size: (u32, u32)) {
+        let config = &self.config;
+        let scene_texture = scene.color_texture(0).unwrap();
+
+        unsafe {
+            gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+            gl::Viewport(0, 0, size.0 as GLsizei, size.1 as GLsizei);
+            gl::Disable(gl::DEPTH_TEST);
+            gl::BindBufferBase(
+                gl::SHADER_STORAGE_BUFFER,
+                gpu::attrs::EXPOSURE_BUFFER_BINDING,
+                self.exposure_buffer,
+            );
+        }
+
+        self.programs.tonemap.set_active();
+        bind_source(scene_texture, scene.width(), scene.height());
+
+        let (exposure, auto) = match config.exposure {
+            Exposure::Manual(e) => (e, false),
+            Exposure::Auto => (1.0, true),
+        };
+
+        unsafe {
+            // Without bloom the sampler still needs a 2D texture
+            let bloom_texture = bloom.map_or(scene_texture, |b| b.color_texture(0).unwrap());
+            let intensity = bloom.map_or(0.0, |_| config.bloom_intensity);
+
+            gl::BindTextureUnit(gpu::attrs::BLOOM_TEXTURE_UNIT, bloom_texture);
+            gl::Uniform1i(
+                gpu::attrs::BLOOM_SAMPLER_LOCATION,
+                gpu::attrs::BLOOM_TEXTURE_UNIT as GLint,
+            );
+            gl::Uniform1f(gpu::attrs::BLOOM_INTENSITY_LOCATION, intensity);
+            gl::Uniform1i(gpu::attrs::TONEMAPPER_LOCATION, config.tonemapper as GLint);
+            gl::Uniform1f(gpu::attrs::EXPOSURE_LOCATION, exposure);
+            gl::Uniform1i(gpu::attrs::AUTO_EXPOSURE_FLAG, auto as GLint);
+            gl::Uniform1f(gpu::attrs::EXPOSURE_KEY_LOCATION, config.exposure_key);
+        }
+
+        draw_fullscreen(self.empty_vao);
+
+        unsafe {
+            gl::Enable(gl::DEPTH_TEST);
+        }
+    }
+}
+
+impl Drop for PostProcess {
+    fn drop(&mut self) {
+        unsafe {
+            gl::DeleteVertexArrays(1, &self.empty_vao);
+            gl::DeleteBuffers(1, &self.histogram_buffer);
+            gl::DeleteBuffers(1, &self.exposure_buffer);
+        }
+    }
+}
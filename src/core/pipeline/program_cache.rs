@@ -0,0 +1,252 @@
+use crate::core::pipeline::mgl::shader::{ShaderIssue, ShaderProgram};
+use gl::types::*;
+use std::ffi::CStr;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// Linked programs are stored on disk with glGetProgramBinary and reloaded
+// with glProgramBinary on the next launch. Files are named after a hash of
+// the preprocessed sources of every stage and the driver strings, so edited
+// shaders or a driver update simply miss the cache. A binary the driver
+// rejects anyway is deleted and the program is compiled from source.
+//
+// File layout: MAGIC, binary format (u32 little endian), binary data.
+
+const MAGIC: &[u8; 4] = b"DKPB";
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+// 64-bit FNV-1a
+fn fnv1a(hash: u64, bytes: &[u8]) -> u64 {
+    bytes
+        .iter()
+        .fold(hash, |h, b| (h ^ *b as u64).wrapping_mul(FNV_PRIME))
+}
+
+fn gl_string(name: GLenum) -> String {
+    unsafe {
+        let ptr = gl::GetString(name);
+
+        if ptr.is_null() {
+            return String::new();
+        }
+
+        CStr::from_ptr(ptr as *const _)
+            .to_string_lossy()
+            .into_owned()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ProgramBinaryCache {
+    dir: PathBuf,
+    // Vendor, renderer and version strings of the current context
+    driver: String,
+    // False when the driver offers no program binary formats
+    supported: bool,
+}
+
+#[allow(dead_code)]
+impl ProgramBinaryCache {
+    // Requires a current GL context, the directory is created on first store
+    pub fn new(dir: &Path) -> Self {
+        let mut format_count: GLint = 0;
+
+        unsafe {
+            gl::GetIntegerv(gl::NUM_PROGRAM_BINARY_FORMATS, &mut format_count);
+        }
+
+        Self {
+            dir: dir.to_path_buf(),
+            driver: format!(
+                "{}\n{}\n{}",
+                gl_string(gl::VENDOR),
+                gl_string(gl::RENDERER),
+                gl_string(gl::VERSION)
+            ),
+            supported: format_count > 0,
+        }
+    }
+
+    pub fn is_supported(&self) -> bool {
+        self.supported
+    }
+
+    // Stages are given as (preprocessed source, shader type) pairs
+    pub fn key(&self, stages: &[(&str, GLenum)]) -> u64 {
+        let mut hash = fnv1a(FNV_OFFSET_BASIS, self.driver.as_bytes());
+
+        for (source, kind) in stages {
+            hash = fnv1a(hash, &kind.to_le_bytes());
+            hash = fnv1a(hash, source.as_bytes());
+            // Keeps "ab" + "c" apart from "a" + "bc"
+            hash = fnv1a(hash, &[0]);
+        }
+
+        hash
+    }
+
+    fn path(&self, key: u64) -> PathBuf {
+        self.dir.join(format!("{:016x}.bin", key))
+    }
+
+    pub fn load(&self, key: u64) -> Option<ShaderProgram> {
+        if !self.supported {
+            return None;
+        }
+
+        let path = self.path(key);
+        let data = fs::read(&path).ok()?;
+
+        if data.len() <= 8 || &data[0..4] != MAGIC {
+            let _ = fs::remove_file(&path);
+            return None;
+        }
+
+        let mut format = [0u8; 4];
+        format.copy_from_slice(&data[4..8]);
+
+        match ShaderProgram::from_binary(u32::from_le_bytes(format), &data[8..]) {
+            Ok(program) => Some(program),
+            Err(_) => {
+                println!("Discarding stale program binary: {}", path.display());
+                let _ = fs::remove_file(&path);
+                None
+            }
+        }
+    }
+
+    // Failing to write the cache only costs a recompile on the next launch
+    pub fn store(&self, key: u64, program: &ShaderProgram) {
+        if !self.supported {
+            return;
+        }
+
+        let (format, binary) = match program.binary() {
+            Some(b) => b,
+            None => return,
+        };
+
+        if let Err(e) = self.write(key, format, &binary) {
+            println!("Failed writing program binary: {}", e);
+        }
+    }
+
+    // Writes a temporary file and renames it into place, so a crash or a
+    // second instance never leaves a half written binary under the key
+    fn write(&self, key: u64, format: GLenum, binary: &[u8]) -> std::io::Result<()> {
+        let mut data = Vec::with_capacity(binary.len() + 8);
+        data.extend_from_slice(MAGIC);
+        data.extend_from_slice(&format.to_le_bytes());
+        data.extend_from_slice(binary);
+
+        let path = self.path(key);
+        let temp = self
+            .dir
+            .join(format!("{:016x}.{}.tmp", key, std::process::id()));
+
+        let result = fs::create_dir_all(&self.dir)
+            .and_then(|_| fs::write(&temp, data))
+            .and_then(|_| fs::rename(&temp, &path));
+
+        if result.is_err() {
+            let _ = fs::remove_file(&temp);
+        }
+
+        result
+    }
+
+    // Loads the program from the cache, or links it with link_stages and
+    // stores the result
+    pub fn get_or_link<F>(
+        &self,
+        stages: &[(&str, GLenum)],
+        link_stages: F,
+    ) -> Result<ShaderProgram, ShaderIssue>
+    where
+        F: FnOnce() -> Result<ShaderProgram, ShaderIssue>,
+    {
+        let key = self.key(stages);
+
+        if let Some(program) = self.load(key) {
+            return Ok(program);
+        }
+
+        let program = link_stages()?;
+        self.store(key, &program);
+        Ok(program)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cache(name: &str, driver: &str) -> ProgramBinaryCache {
+        ProgramBinaryCache {
+            dir: std::env::temp_dir().join(format!(
+                "darkest_program_cache_{}_{}",
+                std::process::id(),
+                name
+            )),
+            driver: driver.to_owned(),
+            supported: true,
+        }
+    }
+
+    #[test]
+    fn key_depends_on_driver_and_stages() {
+        let a = cache("key_a", "vendor\nrenderer\n4.5");
+        let b = cache("key_b", "vendor\nrenderer\n4.6");
+        let stages = [("void main() {}", gl::VERTEX_SHADER)];
+
+        assert_eq!(a.key(&stages), a.key(&stages));
+        assert_ne!(a.key(&stages), b.key(&stages));
+        assert_ne!(
+            a.key(&stages),
+            a.key(&[("void main() {}", gl::FRAGMENT_SHADER)])
+        );
+        // Stage boundaries are part of the key
+        assert_ne!(
+            a.key(&[("ab", gl::VERTEX_SHADER), ("c", gl::VERTEX_SHADER)]),
+            a.key(&[("a", gl::VERTEX_SHADER), ("bc", gl::VERTEX_SHADER)])
+        );
+    }
+
+    #[test]
+    fn invalid_headers_are_discarded() {
+        let cache = cache("headers", "driver");
+        fs::create_dir_all(&cache.dir).unwrap();
+
+        let cases: [&[u8]; 3] = [b"", b"DKPB\x01\0\0\0", b"XXXX\x01\0\0\0binary"];
+
+        for (key, data) in cases.iter().enumerate() {
+            let path = cache.path(key as u64);
+            fs::write(&path, data).unwrap();
+
+            // Rejected before the driver sees the binary
+            assert!(cache.load(key as u64).is_none());
+            assert!(!path.exists());
+        }
+
+        assert!(cache.load(99).is_none());
+        fs::remove_dir_all(&cache.dir).unwrap();
+    }
+
+    #[test]
+    fn write_replaces_the_file_without_leftovers() {
+        let cache = cache("write", "driver");
+
+        cache.write(7, 0x1234, b"old binary").unwrap();
+        cache.write(7, 0x1234, b"new").unwrap();
+
+        let mut expected = MAGIC.to_vec();
+        expected.extend_from_slice(&0x1234u32.to_le_bytes());
+        expected.extend_from_slice(b"new");
+        assert_eq!(fs::read(cache.path(7)).unwrap(), expected);
+        assert_eq!(fs::read_dir(&cache.dir).unwrap().count(), 1);
+
+        fs::remove_dir_all(&cache.dir).unwrap();
+    }
+}
@@ -0,0 +1,1041 @@
+use crate::core::pipeline::render_target::{RenderTarget, RenderTargetConfig, RenderTargetError};
+use gl::types::*;
+use std::fmt::Write;
+
+// Frame graph of render passes. Every pass declares the resources it reads
+// and writes, compile() then
+//
+//  - orders passes so writers run before readers of a resource,
+//  - culls passes whose writes never reach an output,
+//  - assigns transient resources to physical slots, resources with the same
+//    description whose lifetimes don't overlap share one slot.
+//
+// The graph is meant to be rebuilt every frame, pass callbacks borrow the
+// frame's state. Physical targets and buffers live in a TransientPool that
+// is kept between frames.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ResourceId(usize);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct PassId(usize);
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ResourceDesc {
+    // Allocated by the graph for the frame
+    Target(RenderTargetConfig),
+    // Storage buffer of the given size in bytes, allocated by the graph
+    Buffer(usize),
+    // Owned outside of the graph, e.g. the window or a shadow map
+    Imported,
+}
+
+#[derive(Debug)]
+pub enum RenderGraphError {
+    // Passes depend on each other in a loop
+    Cycle(Vec<String>),
+    // A transient resource is read but no pass writes it
+    UnwrittenResource(String),
+    Target(RenderTargetError),
+}
+
+impl std::fmt::Display for RenderGraphError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Cycle(passes) => write!(f, "Render passes form a cycle: {}", passes.join(", ")),
+            Self::UnwrittenResource(name) => {
+                write!(f, "Resource {} is read but never written", name)
+            }
+            Self::Target(e) => write!(f, "Failed creating transient target: {}", e),
+        }
+    }
+}
+
+impl From<RenderTargetError> for RenderGraphError {
+    fn from(e: RenderTargetError) -> Self {
+        Self::Target(e)
+    }
+}
+
+struct Resource {
+    name: String,
+    desc: ResourceDesc,
+    output: bool,
+}
+
+pub type PassCallback<'a> = Box<dyn FnOnce(&PassContext) + 'a>;
+
+struct Pass<'a> {
+    name: String,
+    reads: Vec<ResourceId>,
+    writes: Vec<ResourceId>,
+    // Kept even if nothing reads its writes
+    side_effects: bool,
+    callback: Option<PassCallback<'a>>,
+}
+
+// Collects the declarations of one pass, see RenderGraph::add_pass
+pub struct PassBuilder {
+    reads: Vec<ResourceId>,
+    writes: Vec<ResourceId>,
+    side_effects: bool,
+}
+
+#[allow(dead_code)]
+impl PassBuilder {
+    pub fn read(&mut self, id: ResourceId) -> ResourceId {
+        if !self.reads.contains(&id) {
+            self.reads.push(id);
+        }
+        id
+    }
+
+    pub fn write(&mut self, id: ResourceId) -> ResourceId {
+        if !self.writes.contains(&id) {
+            self.writes.push(id);
+        }
+        id
+    }
+
+    pub fn side_effects(&mut self) {
+        self.side_effects = true;
+    }
+}
+
+pub struct RenderGraph<'a> {
+    resources: Vec<Resource>,
+    passes: Vec<Pass<'a>>,
+}
+
+// Result of RenderGraph::compile
+#[derive(Debug, Clone)]
+pub struct CompiledGraph {
+    // Passes to run in order
+    pub order: Vec<PassId>,
+    pub culled: Vec<PassId>,
+    // Physical slot of every resource, None for imported and unused ones
+    pub slots: Vec<Option<usize>>,
+    // Description of every physical slot
+    pub slot_descs: Vec<ResourceDesc>,
+}
+
+#[allow(dead_code)]
+impl<'a> RenderGraph<'a> {
+    pub fn new() -> Self {
+        Self {
+            resources: vec![],
+            passes: vec![],
+        }
+    }
+
+    fn add_resource(&mut self, name: &str, desc: ResourceDesc) -> ResourceId {
+        self.resources.push(Resource {
+            name: name.to_owned(),
+            desc: desc,
+            output: false,
+        });
+        ResourceId(self.resources.len() - 1)
+    }
+
+    pub fn create_target(&mut self, name: &str, config: RenderTargetConfig) -> ResourceId {
+        self.add_resource(name, ResourceDesc::Target(config))
+    }
+
+    pub fn create_buffer(&mut self, name: &str, size: usize) -> ResourceId {
+        self.add_resource(name, ResourceDesc::Buffer(size))
+    }
+
+    pub fn import(&mut self, name: &str) -> ResourceId {
+        self.add_resource(name, ResourceDesc::Imported)
+    }
+
+    // Passes writing the resource, directly or through other passes, are
+    // never culled
+    pub fn mark_output(&mut self, id: ResourceId) {
+        self.resources[id.0].output = true;
+    }
+
+    pub fn add_pass<S, F>(&mut self, name: &str, setup: S, callback: F) -> PassId
+    where
+        S: FnOnce(&mut PassBuilder),
+        F: FnOnce(&PassContext) + 'a,
+    {
+        let mut builder = PassBuilder {
+            reads: vec![],
+            writes: vec![],
+            side_effects: false,
+        };
+
+        setup(&mut builder);
+
+        self.passes.push(Pass {
+            name: name.to_owned(),
+            reads: builder.reads,
+            writes: builder.writes,
+            side_effects: builder.side_effects,
+            callback: Some(Box::new(callback)),
+        });
+
+        PassId(self.passes.len() - 1)
+    }
+
+    // Edges from every pass to the passes that have to run after it
+    fn dependencies(&self) -> Vec<Vec<usize>> {
+        let mut after = vec![vec![]; self.passes.len()];
+
+        // Every write starts a new version of the resource. A reader depends
+        // on the last writer declared before it and the next writer waits
+        // for all readers of the previous version. Readers declared before
+        // any writer read what the first writer produces.
+        for (r, _) in self.resources.iter().enumerate() {
+            let id = ResourceId(r);
+            let mut last_writer: Option<usize> = None;
+            // Readers of the current version
+            let mut readers: Vec<usize> = vec![];
+
+            for (p, pass) in self.passes.iter().enumerate() {
+                if pass.reads.contains(&id) {
+                    if let Some(w) = last_writer {
+                        after[w].push(p);
+                    }
+                    readers.push(p);
+                }
+
+                if !pass.writes.contains(&id) {
+                    continue;
+                }
+
+                readers.retain(|reader| *reader != p);
+
+                match last_writer {
+                    Some(w) => {
+                        after[w].push(p);
+
+                        for reader in readers.drain(..) {
+                            after[reader].push(p);
+                        }
+                    }
+                    // Early readers stay readers of the first version
+                    None => {
+                        for reader in readers.iter() {
+                            after[p].push(*reader);
+                        }
+                    }
+                }
+
+                last_writer = Some(p);
+            }
+        }
+
+        after
+    }
+
+    pub fn compile(&self) -> Result<CompiledGraph, RenderGraphError> {
+        for (r, res) in self.resources.iter().enumerate() {
+            let id = ResourceId(r);
+            let read = self.passes.iter().any(|p| p.reads.contains(&id));
+            let written = self.passes.iter().any(|p| p.writes.contains(&id));
+
+            if read && !written && res.desc != ResourceDesc::Imported {
+                return Err(RenderGraphError::UnwrittenResource(res.name.clone()));
+            }
+        }
+
+        // Kahn's algorithm, ready passes are taken in declaration order
+        let after = self.dependencies();
+        let mut incoming = vec![0; self.passes.len()];
+
+        for edges in after.iter() {
+            for p in edges {
+                incoming[*p] += 1;
+            }
+        }
+
+        let mut sorted = vec![];
+        let mut ready: Vec<usize> = (0..self.passes.len())
+            .filter(|p| incoming[*p] == 0)
+            .collect();
+
+        while !ready.is_empty() {
+            let p = ready.remove(0);
+            sorted.push(p);
+
+            for next in after[p].iter() {
+                incoming[*next] -= 1;
+                if incoming[*next] == 0 {
+                    let at = ready.iter().position(|r| r > next).unwrap_or(ready.len());
+                    ready.insert(at, *next);
+                }
+            }
+        }
+
+        if sorted.len() != self.passes.len() {
+            return Err(RenderGraphError::Cycle(
+                (0..self.passes.len())
+                    .filter(|p| !sorted.contains(p))
+                    .map(|p| self.passes[p].name.clone())
+                    .collect(),
+            ));
+        }
+
+        // Walk back from the outputs
+        let mut live_resources: Vec<bool> = self.resources.iter().map(|r| r.output).collect();
+        let mut live_passes = vec![false; self.passes.len()];
+
+        for p in sorted.iter().rev() {
+            let pass = &self.passes[*p];
+
+            if pass.side_effects || pass.writes.iter().any(|w| live_resources[w.0]) {
+                live_passes[*p] = true;
+
+                for r in pass.reads.iter() {
+                    live_resources[r.0] = true;
+                }
+            }
+        }
+
+        let order: Vec<usize> = sorted.iter().cloned().filter(|p| live_passes[*p]).collect();
+        let culled = sorted
+            .iter()
+            .cloned()
+            .filter(|p| !live_passes[*p])
+            .collect::<Vec<_>>();
+
+        // First and last position in order of every used resource
+        let mut lifetimes: Vec<Option<(usize, usize)>> = vec![None; self.resources.len()];
+
+        for (i, p) in order.iter().enumerate() {
+            let pass = &self.passes[*p];
+
+            for r in pass.reads.iter().chain(pass.writes.iter()) {
+                lifetimes[r.0] = match lifetimes[r.0] {
+                    Some((first, _)) => Some((first, i)),
+                    None => Some((i, i)),
+                };
+            }
+        }
+
+        // Outputs are used after the graph ran, their slots are never
+        // handed to later resources
+        for (r, res) in self.resources.iter().enumerate() {
+            if let (true, Some((first, _))) = (res.output, lifetimes[r]) {
+                lifetimes[r] = Some((first, order.len()));
+            }
+        }
+
+        let mut by_first_use: Vec<usize> = (0..self.resources.len())
+            .filter(|r| lifetimes[*r].is_some())
+            .filter(|r| self.resources[*r].desc != ResourceDesc::Imported)
+            .collect();
+        by_first_use.sort_by_key(|r| lifetimes[*r].unwrap().0);
+
+        let mut slots = vec![None; self.resources.len()];
+        let mut slot_descs: Vec<ResourceDesc> = vec![];
+        // Position of the last pass using each slot
+        let mut slot_ends: Vec<usize> = vec![];
+
+        for r in by_first_use {
+            let (first, last) = lifetimes[r].unwrap();
+            let desc = &self.resources[r].desc;
+
+            let reusable =
+                (0..slot_descs.len()).find(|s| slot_descs[*s] == *desc && slot_ends[*s] < first);
+
+            let slot = match reusable {
+                Some(s) => s,
+                None => {
+                    slot_descs.push(desc.clone());
+                    slot_ends.push(0);
+                    slot_descs.len() - 1
+                }
+            };
+
+            slot_ends[slot] = last;
+            slots[r] = Some(slot);
+        }
+
+        Ok(CompiledGraph {
+            order: order.into_iter().map(PassId).collect(),
+            culled: culled.into_iter().map(PassId).collect(),
+            slots: slots,
+            slot_descs: slot_descs,
+        })
+    }
+
+    // Runs the passes of the compiled graph with physical resources from
+    // the pool
+    pub fn execute(
+        mut self,
+        compiled: &CompiledGraph,
+        pool: &mut TransientPool,
+    ) -> Result<(), RenderGraphError> {
+        pool.acquire(&compiled.slot_descs)?;
+
+        let ctx = PassContext {
+            slots: compiled.slots.clone(),
+            pool: pool,
+        };
+
+        for p in compiled.order.iter() {
+            if let Some(callback) = self.passes[p.0].callback.take() {
+                callback(&ctx);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn resource_label(&self, r: usize, compiled: &CompiledGraph) -> String {
+        let res = &self.resources[r];
+
+        let kind = match &res.desc {
+            ResourceDesc::Target(c) => format!(
+                "{}x{} x{} {} color",
+                c.width,
+                c.height,
+                c.samples,
+                c.color_formats.len()
+            ),
+            ResourceDesc::Buffer(size) => format!("{} bytes", size),
+            ResourceDesc::Imported => "imported".to_owned(),
+        };
+
+        match compiled.slots[r] {
+            Some(slot) => format!("{} ({}, slot {})", res.name, kind, slot),
+            None => format!("{} ({})", res.name, kind),
+        }
+    }
+
+    // Plain text listing of the compiled graph
+    pub fn dump(&self, compiled: &CompiledGraph) -> String {
+        let mut out = String::new();
+        let names = |ids: &[ResourceId]| {
+            ids.iter()
+                .map(|r| self.resources[r.0].name.as_str())
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+
+        for (i, p) in compiled.order.iter().enumerate() {
+            let pass = &self.passes[p.0];
+            let _ = writeln!(
+                out,
+                "{}: {} reads [{}] writes [{}]",
+                i,
+                pass.name,
+                names(&pass.reads),
+                names(&pass.writes)
+            );
+        }
+
+        for p in compiled.culled.iter() {
+            let _ = writeln!(out, "culled: {}", self.passes[p.0].name);
+        }
+
+        for r in 0..self.resources.len() {
+            let _ = writeln!(out, "resource {}", self.resource_label(r, compiled));
+        }
+
+        out
+    }
+
+    // Graphviz graph, culled passes are dashed and outputs are doubled
+    pub fn to_dot(&self, compiled: &CompiledGraph) -> String {
+        let mut out = String::from("digraph render_graph {\n  rankdir=LR;\n");
+
+        for (p, pass) in self.passes.iter().enumerate() {
+            let style = if compiled.culled.contains(&PassId(p)) {
+                "dashed"
+            } else {
+                "solid"
+            };
+            let _ = writeln!(
+                out,
+                "  p{} [label=\"{}\", shape=ellipse, style={}];",
+                p, pass.name, style
+            );
+        }
+
+        for (r, res) in self.resources.iter().enumerate() {
+            let peripheries = if res.output { 2 } else { 1 };
+            let _ = writeln!(
+                out,
+                "  r{} [label=\"{}\", shape=box, peripheries={}];",
+                r,
+                self.resource_label(r, compiled),
+                peripheries
+            );
+        }
+
+        for (p, pass) in self.passes.iter().enumerate() {
+            for r in pass.reads.iter() {
+                let _ = writeln!(out, "  r{} -> p{};", r.0, p);
+            }
+
+            for r in pass.writes.iter() {
+                let _ = writeln!(out, "  p{} -> r{};", p, r.0);
+            }
+        }
+
+        out.push_str("}\n");
+        out
+    }
+}
+
+// Physical resources handed to pass callbacks
+pub struct PassContext<'p> {
+    slots: Vec<Option<usize>>,
+    pool: &'p TransientPool,
+}
+
+#[allow(dead_code)]
+impl<'p> PassContext<'p> {
+    pub fn target(&self, id: ResourceId) -> Option<&RenderTarget> {
+        let slot = self.slots[id.0]?;
+        self.pool
+            .targets
+            .get(self.pool.slot_map[slot])
+            .map(|t| &t.1)
+    }
+
+    pub fn buffer(&self, id: ResourceId) -> Option<GLuint> {
+        let slot = self.slots[id.0]?;
+        self.pool.buffers.get(self.pool.slot_map[slot]).map(|b| b.1)
+    }
+}
+
+// Targets and buffers backing the slots of the last executed graph, kept
+// so the next frame can reuse them
+pub struct TransientPool {
+    targets: Vec<(RenderTargetConfig, RenderTarget)>,
+    buffers: Vec<(usize, GLuint)>,
+    // Index into targets or buffers for every slot, depending on its kind
+    slot_map: Vec<usize>,
+}
+
+#[allow(dead_code)]
+impl TransientPool {
+    pub fn new() -> Self {
+        Self {
+            targets: vec![],
+            buffers: vec![],
+            slot_map: vec![],
+        }
+    }
+
+    // Matches slots with resources of the previous frame, creates missing
+    // ones and frees the rest
+    fn acquire(&mut self, slot_descs: &[ResourceDesc]) -> Result<(), RenderGraphError> {
+        let mut targets: Vec<_> = self.targets.drain(..).map(Some).collect();
+        let mut buffers: Vec<_> = self.buffers.drain(..).map(Some).collect();
+        self.slot_map.clear();
+
+        for desc in slot_descs {
+            match desc {
+                ResourceDesc::Target(config) => {
+                    let found = targets
+                        .iter_mut()
+                        .find(|t| t.as_ref().map_or(false, |t| t.0 == *config))
+                        .and_then(|t| t.take());
+
+                    let entry = match found {
+                        Some(t) => t,
+                        None => match RenderTarget::new(config.clone()) {
+                            Ok(t) => (config.clone(), t),
+                            Err(e) => {
+                                // Keep whatever was not matched yet, Drop or
+                                // the next frame takes care of it
+                                self.targets.extend(targets.into_iter().flatten());
+                                self.buffers.extend(buffers.into_iter().flatten());
+                                self.slot_map.clear();
+                                return Err(e.into());
+                            }
+                        },
+                    };
+
+                    self.targets.push(entry);
+                    self.slot_map.push(self.targets.len() - 1);
+                }
+                ResourceDesc::Buffer(size) => {
+                    let found = buffers
+                        .iter_mut()
+                        .find(|b| b.as_ref().map_or(false, |b| b.0 == *size))
+                        .and_then(|b| b.take());
+
+                    let entry = match found {
+                        Some(b) => b,
+                        None => (*size, create_buffer(*size)),
+                    };
+
+                    self.buffers.push(entry);
+                    self.slot_map.push(self.buffers.len() - 1);
+                }
+                ResourceDesc::Imported => self.slot_map.push(0),
+            }
+        }
+
+        // Unmatched targets are dropped here
+        for (_, id) in buffers.into_iter().flatten() {
+            unsafe {
+                gl::DeleteBuffers(1, &id);
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn target_count(&self) -> usize {
+        self.targets.len()
+    }
+
+    pub fn buffer_count(&self) -> usize {
+        self.buffers.len()
+    }
+}
+
+fn create_buffer(size: usize) -> GLuint {
+    let mut id = 0;
+
+    unsafe {
+        gl::CreateBuffers(1, &mut id);
+        gl::NamedBufferStorage(
+            id,
+            size as GLsizeiptr,
+            std::ptr::null(),
+            gl::DYNAMIC_STORAGE_BIT,
+        );
+    }
+
+    id
+}
+
+impl Drop for TransientPool {
+    fn drop(&mut self) {
+        for (_, id) in self.buffers.iter() {
+            unsafe {
+                gl::DeleteBuffers(1, id);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(graph: &RenderGraph, passes: &[PassId]) -> Vec<String> {
+        passes
+            .iter()
+            .map(|p| graph.passes[p.0].name.clone())
+            .collect()
+    }
+
+    #[test]
+    fn diamond_runs_writers_before_readers() {
+        let mut graph = RenderGraph::new();
+        let depth = graph.create_buffer("depth", 64);
+        let lit = graph.create_buffer("lit", 64);
+        let ao = graph.create_buffer("ao", 64);
+        let out = graph.import("backbuffer");
+        graph.mark_output(out);
+
+        // Declared out of order on purpose
+        graph.add_pass(
+            "compose",
+            |b| {
+                b.read(lit);
+                b.read(ao);
+                b.write(out);
+            },
+            |_| {},
+        );
+        graph.add_pass(
+            "lighting",
+            |b| {
+                b.read(depth);
+                b.write(lit);
+            },
+            |_| {},
+        );
+        graph.add_pass(
+            "ssao",
+            |b| {
+                b.read(depth);
+                b.write(ao);
+            },
+            |_| {},
+        );
+        graph.add_pass(
+            "depth_prepass",
+            |b| {
+                b.write(depth);
+            },
+            |_| {},
+        );
+
+        let compiled = graph.compile().unwrap();
+
+        assert_eq!(
+            names(&graph, &compiled.order),
+            vec!["depth_prepass", "lighting", "ssao", "compose"]
+        );
+        assert!(compiled.culled.is_empty());
+        assert_eq!(compiled.slots[out.0], None);
+        // depth is still read by ssao while lit and ao are alive
+        assert_eq!(compiled.slot_descs.len(), 3);
+    }
+
+    #[test]
+    fn cycles_are_reported() {
+        let mut graph = RenderGraph::new();
+        let a = graph.create_buffer("a", 16);
+        let b = graph.create_buffer("b", 16);
+        let c = graph.create_buffer("c", 16);
+        graph.mark_output(c);
+
+        graph.add_pass(
+            "source",
+            |p| {
+                p.write(c);
+            },
+            |_| {},
+        );
+        graph.add_pass(
+            "first",
+            |p| {
+                p.read(b);
+                p.write(a);
+            },
+            |_| {},
+        );
+        graph.add_pass(
+            "second",
+            |p| {
+                p.read(a);
+                p.write(b);
+            },
+            |_| {},
+        );
+
+        match graph.compile() {
+            Err(RenderGraphError::Cycle(passes)) => assert_eq!(passes, vec!["first", "second"]),
+            other => panic!("{:?}", other.map(|c| c.order)),
+        }
+    }
+
+    #[test]
+    fn passes_reading_and_writing_a_resource_chain() {
+        let mut graph = RenderGraph::new();
+        let color = graph.create_buffer("color", 64);
+        let out = graph.import("backbuffer");
+        graph.mark_output(out);
+
+        graph.add_pass(
+            "scene",
+            |p| {
+                p.write(color);
+            },
+            |_| {},
+        );
+        graph.add_pass(
+            "blur_h",
+            |p| {
+                p.read(color);
+                p.write(color);
+            },
+            |_| {},
+        );
+        graph.add_pass(
+            "blur_v",
+            |p| {
+                p.read(color);
+                p.write(color);
+            },
+            |_| {},
+        );
+        graph.add_pass(
+            "present",
+            |p| {
+                p.read(color);
+                p.write(out);
+            },
+            |_| {},
+        );
+
+        let compiled = graph.compile().unwrap();
+
+        assert_eq!(
+            names(&graph, &compiled.order),
+            vec!["scene", "blur_h", "blur_v", "present"]
+        );
+    }
+
+    #[test]
+    fn later_writers_wait_for_readers_of_the_previous_version() {
+        let mut graph = RenderGraph::new();
+        let shared = graph.create_buffer("shared", 64);
+        let extra = graph.create_buffer("extra", 64);
+        let copy = graph.create_buffer("copy", 64);
+        let out = graph.import("backbuffer");
+        graph.mark_output(out);
+
+        graph.add_pass(
+            "first",
+            |p| {
+                p.write(shared);
+            },
+            |_| {},
+        );
+        graph.add_pass(
+            "copy",
+            |p| {
+                p.read(shared);
+                p.read(extra);
+                p.write(copy);
+            },
+            |_| {},
+        );
+        graph.add_pass(
+            "second",
+            |p| {
+                p.write(shared);
+            },
+            |_| {},
+        );
+        graph.add_pass(
+            "extra",
+            |p| {
+                p.write(extra);
+            },
+            |_| {},
+        );
+        graph.add_pass(
+            "present",
+            |p| {
+                p.read(shared);
+                p.read(copy);
+                p.write(out);
+            },
+            |_| {},
+        );
+
+        let compiled = graph.compile().unwrap();
+
+        // copy waits for extra, second must not overwrite shared before
+        // copy read the version of first
+        assert_eq!(
+            names(&graph, &compiled.order),
+            vec!["first", "extra", "copy", "second", "present"]
+        );
+    }
+
+    #[test]
+    fn reading_unwritten_transients_fails() {
+        let mut graph = RenderGraph::new();
+        let missing = graph.create_buffer("missing", 16);
+        let imported = graph.import("shadow_map");
+
+        graph.add_pass(
+            "reader",
+            |p| {
+                p.read(imported);
+                p.read(missing);
+                p.side_effects();
+            },
+            |_| {},
+        );
+
+        match graph.compile() {
+            Err(RenderGraphError::UnwrittenResource(name)) => assert_eq!(name, "missing"),
+            other => panic!("{:?}", other.map(|c| c.order)),
+        }
+    }
+
+    #[test]
+    fn passes_not_reaching_an_output_are_culled() {
+        let mut graph = RenderGraph::new();
+        let scene = graph.create_buffer("scene", 64);
+        let debug = graph.create_buffer("debug", 64);
+        let debug_view = graph.create_buffer("debug_view", 64);
+        let out = graph.import("backbuffer");
+        graph.mark_output(out);
+
+        graph.add_pass(
+            "scene",
+            |p| {
+                p.write(scene);
+            },
+            |_| {},
+        );
+        graph.add_pass(
+            "debug",
+            |p| {
+                p.write(debug);
+            },
+            |_| {},
+        );
+        graph.add_pass(
+            "debug_view",
+            |p| {
+                p.read(debug);
+                p.write(debug_view);
+            },
+            |_| {},
+        );
+        graph.add_pass("timestamps", |p| p.side_effects(), |_| {});
+        graph.add_pass(
+            "present",
+            |p| {
+                p.read(scene);
+                p.write(out);
+            },
+            |_| {},
+        );
+
+        let compiled = graph.compile().unwrap();
+
+        assert_eq!(
+            names(&graph, &compiled.order),
+            vec!["scene", "timestamps", "present"]
+        );
+        assert_eq!(names(&graph, &compiled.culled), vec!["debug", "debug_view"]);
+        // Resources of culled passes get no slot
+        assert_eq!(compiled.slots[debug.0], None);
+        assert_eq!(compiled.slots[debug_view.0], None);
+    }
+
+    #[test]
+    fn resources_with_disjoint_lifetimes_share_slots() {
+        let mut graph = RenderGraph::new();
+        let half = RenderTargetConfig {
+            width: 512,
+            height: 384,
+            ..RenderTargetConfig::default()
+        };
+        let a = graph.create_target("a", half.clone());
+        let b = graph.create_target("b", half.clone());
+        let c = graph.create_target("c", half.clone());
+        let full = graph.create_target("full", RenderTargetConfig::default());
+        let out = graph.import("backbuffer");
+        graph.mark_output(out);
+
+        graph.add_pass(
+            "write_a",
+            |p| {
+                p.write(a);
+            },
+            |_| {},
+        );
+        graph.add_pass(
+            "a_to_b",
+            |p| {
+                p.read(a);
+                p.write(b);
+            },
+            |_| {},
+        );
+        graph.add_pass(
+            "b_to_c",
+            |p| {
+                p.read(b);
+                p.write(c);
+            },
+            |_| {},
+        );
+        graph.add_pass(
+            "c_to_full",
+            |p| {
+                p.read(c);
+                p.write(full);
+            },
+            |_| {},
+        );
+        graph.add_pass(
+            "present",
+            |p| {
+                p.read(full);
+                p.write(out);
+            },
+            |_| {},
+        );
+
+        let compiled = graph.compile().unwrap();
+
+        // a ends before c starts, b overlaps both and full differs in size
+        assert_eq!(compiled.slots[a.0], Some(0));
+        assert_eq!(compiled.slots[b.0], Some(1));
+        assert_eq!(compiled.slots[c.0], Some(0));
+        assert_eq!(compiled.slots[full.0], Some(2));
+        assert_eq!(compiled.slots[out.0], None);
+        assert_eq!(
+            compiled.slot_descs,
+            vec![
+                ResourceDesc::Target(half.clone()),
+                ResourceDesc::Target(half),
+                ResourceDesc::Target(RenderTargetConfig::default()),
+            ]
+        );
+    }
+
+    #[test]
+    fn transient_outputs_are_not_aliased() {
+        let mut graph = RenderGraph::new();
+        let history = graph.create_buffer("history", 64);
+        let temp = graph.create_buffer("temp", 64);
+        let out = graph.import("backbuffer");
+        graph.mark_output(history);
+        graph.mark_output(out);
+
+        graph.add_pass(
+            "history",
+            |p| {
+                p.write(history);
+            },
+            |_| {},
+        );
+        graph.add_pass(
+            "temp",
+            |p| {
+                p.write(temp);
+            },
+            |_| {},
+        );
+        graph.add_pass(
+            "present",
+            |p| {
+                p.read(temp);
+                p.write(out);
+            },
+            |_| {},
+        );
+
+        let compiled = graph.compile().unwrap();
+
+        assert_eq!(compiled.slots[history.0], Some(0));
+        assert_eq!(compiled.slots[temp.0], Some(1));
+    }
+
+    #[test]
+    fn failed_acquire_keeps_unmatched_resources() {
+        let mut pool = TransientPool::new();
+        // Stand-ins for buffers of the previous frame, never touched by GL here
+        pool.buffers.push((64, 7));
+        pool.buffers.push((128, 8));
+
+        let invalid = RenderTargetConfig {
+            width: 0,
+            ..RenderTargetConfig::default()
+        };
+        let result = pool.acquire(&[ResourceDesc::Target(invalid)]);
+
+        assert!(match result {
+            Err(RenderGraphError::Target(RenderTargetError::InvalidConfig(_))) => true,
+            _ => false,
+        });
+        assert_eq!(pool.buffers, vec![(64, 7), (128, 8)]);
+        assert!(pool.slot_map.is_empty());
+
+        // Nothing to delete without a context
+        pool.buffers.clear();
+    }
+}
@@ -0,0 +1,357 @@
+use gl::types::*;
+use std::collections::HashMap;
+
+// Draws of a frame are pushed with a packed 64-bit sort key and sorted
+// before submission, so draws sharing a program and material end up next
+// to each other and StateCache can skip the binds between them.
+//
+// Key layout, most significant bits first:
+//
+//   opaque:      pass (4) | 0 (1) | shader (8) | material (19) | depth (32)
+//   translucent: pass (4) | 1 (1) | inverted depth (32) | shader (8) | material (19)
+//
+// Opaque draws are grouped by state and go front to back inside a group,
+// translucent draws go back to front regardless of state so blending
+// composes correctly. Depth is the view space distance, the bits of a
+// non-negative f32 sort like its value.
+
+const PASS_BITS: u32 = 4;
+const SHADER_BITS: u32 = 8;
+const MATERIAL_BITS: u32 = 19;
+const DEPTH_BITS: u32 = 32;
+
+const PASS_SHIFT: u32 = 64 - PASS_BITS;
+const TRANSLUCENT_SHIFT: u32 = PASS_SHIFT - 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct SortKey(pub u64);
+
+fn depth_bits(depth: f32) -> u64 {
+    // NaN and negative depths sort as 0, max() could keep the sign of -0.0
+    if depth > 0.0 {
+        depth.to_bits() as u64
+    } else {
+        0
+    }
+}
+
+fn mask(value: u32, bits: u32) -> u64 {
+    (value & ((1 << bits) - 1)) as u64
+}
+
+#[allow(dead_code)]
+impl SortKey {
+    pub fn opaque(pass: u32, shader: u32, material: u32, depth: f32) -> Self {
+        Self(
+            mask(pass, PASS_BITS) << PASS_SHIFT
+                | mask(shader, SHADER_BITS) << (MATERIAL_BITS + DEPTH_BITS)
+                | mask(material, MATERIAL_BITS) << DEPTH_BITS
+                | depth_bits(depth),
+        )
+    }
+
+    pub fn translucent(pass: u32, shader: u32, material: u32, depth: f32) -> Self {
+        Self(
+            mask(pass, PASS_BITS) << PASS_SHIFT
+                | 1 << TRANSLUCENT_SHIFT
+                | (!depth_bits(depth) & 0xffff_ffff) << (SHADER_BITS + MATERIAL_BITS)
+                | mask(shader, SHADER_BITS) << MATERIAL_BITS
+                | mask(material, MATERIAL_BITS),
+        )
+    }
+
+    pub fn pass(&self) -> u32 {
+        (self.0 >> PASS_SHIFT) as u32
+    }
+
+    pub fn is_translucent(&self) -> bool {
+        (self.0 >> TRANSLUCENT_SHIFT) & 1 == 1
+    }
+
+    pub fn shader(&self) -> u32 {
+        let shift = if self.is_translucent() {
+            MATERIAL_BITS
+        } else {
+            MATERIAL_BITS + DEPTH_BITS
+        };
+
+        mask((self.0 >> shift) as u32, SHADER_BITS) as u32
+    }
+
+    pub fn material(&self) -> u32 {
+        let shift = if self.is_translucent() { 0 } else { DEPTH_BITS };
+        mask((self.0 >> shift) as u32, MATERIAL_BITS) as u32
+    }
+}
+
+// Draws of one frame, T says what to draw and is interpreted by whoever
+// submits the queue
+#[derive(Debug)]
+pub struct RenderQueue<T> {
+    items: Vec<(SortKey, T)>,
+    // Texture sets seen this frame, their index is the material id
+    materials: HashMap<[GLuint; 4], u32>,
+}
+
+#[allow(dead_code)]
+impl<T> RenderQueue<T> {
+    pub fn new() -> Self {
+        Self {
+            items: vec![],
+            materials: HashMap::new(),
+        }
+    }
+
+    // Keeps the allocations for the next frame
+    pub fn clear(&mut self) {
+        self.items.clear();
+        self.materials.clear();
+    }
+
+    pub fn push(&mut self, key: SortKey, item: T) {
+        self.items.push((key, item));
+    }
+
+    // Material id of a set of textures, unused slots are 0. Ids are handed
+    // out in order of first use and only stay valid until clear.
+    pub fn material(&mut self, textures: [GLuint; 4]) -> u32 {
+        let next = self.materials.len() as u32;
+        *self.materials.entry(textures).or_insert(next)
+    }
+
+    // Stable, draws with equal keys keep their submission order
+    pub fn sort(&mut self) {
+        self.items.sort_by_key(|(key, _)| *key);
+    }
+
+    pub fn items(&self) -> &[(SortKey, T)] {
+        &self.items
+    }
+
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+}
+
+// Units above this are bound without caching
+pub const MAX_CACHED_TEXTURE_UNITS: usize = 32;
+
+// Sentinel for state that is not known yet, no GL object has this name
+const UNKNOWN: GLuint = GLuint::MAX;
+
+// Remembers what the previous draws bound and skips binding it again. Only
+// state changed through the cache is tracked, so it lives for one run over
+// a queue and anything binding behind its back has to invalidate it.
+#[derive(Debug)]
+pub struct StateCache {
+    textures: [GLuint; MAX_CACHED_TEXTURE_UNITS],
+    vertex_array: GLuint,
+    blending: Option<bool>,
+    pub texture_binds: u32,
+}
+
+#[allow(dead_code)]
+impl StateCache {
+    pub fn new() -> Self {
+        Self {
+            textures: [UNKNOWN; MAX_CACHED_TEXTURE_UNITS],
+            vertex_array: UNKNOWN,
+            blending: None,
+            texture_binds: 0,
+        }
+    }
+
+    pub fn invalidate(&mut self) {
+        self.textures = [UNKNOWN; MAX_CACHED_TEXTURE_UNITS];
+        self.vertex_array = UNKNOWN;
+        self.blending = None;
+    }
+
+    pub fn bind_texture_2d(&mut self, unit: GLuint, texture: GLuint) {
+        if let Some(bound) = self.textures.get_mut(unit as usize) {
+            if *bound == texture {
+                return;
+            }
+
+            *bound = texture;
+        }
+
+        self.texture_binds += 1;
+
+        unsafe {
+            gl::ActiveTexture(gl::TEXTURE0 + unit);
+            gl::BindTexture(gl::TEXTURE_2D, texture);
+        }
+    }
+
+    pub fn bind_vertex_array(&mut self, vao: GLuint) {
+        if self.vertex_array == vao {
+            return;
+        }
+
+        self.vertex_array = vao;
+
+        unsafe {
+            gl::BindVertexArray(vao);
+        }
+    }
+
+    // Translucent draws blend over the scene without writing depth
+    pub fn set_blending(&mut self, enabled: bool) {
+        if self.blending == Some(enabled) {
+            return;
+        }
+
+        self.blending = Some(enabled);
+
+        unsafe {
+            if enabled {
+                gl::Enable(gl::BLEND);
+                gl::BlendFunc(gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA);
+                gl::DepthMask(gl::FALSE);
+            } else {
+                gl::Disable(gl::BLEND);
+                gl::DepthMask(gl::TRUE);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Keys of a queue after sorting, items are the push order
+    fn sorted(keys: &[SortKey]) -> Vec<usize> {
+        let mut queue = RenderQueue::new();
+        for (i, key) in keys.iter().enumerate() {
+            queue.push(*key, i);
+        }
+        queue.sort();
+        queue.items().iter().map(|(_, i)| *i).collect()
+    }
+
+    #[test]
+    fn fields_round_trip() {
+        for &(pass, shader, material) in [
+            (0, 0, 0),
+            (1, 17, 4),
+            (15, 255, (1 << MATERIAL_BITS) - 1),
+            (7, 128, 1 << (MATERIAL_BITS - 1)),
+        ]
+        .iter()
+        {
+            for &depth in [0.0, 0.5, 7.25, 1e30].iter() {
+                let key = SortKey::opaque(pass, shader, material, depth);
+                assert!(!key.is_translucent());
+                assert_eq!(key.pass(), pass);
+                assert_eq!(key.shader(), shader);
+                assert_eq!(key.material(), material);
+
+                let key = SortKey::translucent(pass, shader, material, depth);
+                assert!(key.is_translucent());
+                assert_eq!(key.pass(), pass);
+                assert_eq!(key.shader(), shader);
+                assert_eq!(key.material(), material);
+            }
+        }
+    }
+
+    #[test]
+    fn fields_do_not_overflow() {
+        let key = SortKey::opaque(0x13, 0x1ff, 1 << MATERIAL_BITS | 5, std::f32::MAX);
+        assert_eq!(key.pass(), 3);
+        assert_eq!(key.shader(), 0xff);
+        assert_eq!(key.material(), 5);
+        assert!(!key.is_translucent());
+
+        let key = SortKey::translucent(0x10, 0x100, 1 << MATERIAL_BITS, std::f32::MAX);
+        assert_eq!(key.pass(), 0);
+        assert_eq!(key.shader(), 0);
+        assert_eq!(key.material(), 0);
+        assert!(key.is_translucent());
+    }
+
+    #[test]
+    fn opaque_front_to_back_within_state_group() {
+        let keys = [
+            SortKey::opaque(0, 1, 2, 30.0),
+            SortKey::opaque(0, 1, 2, 0.5),
+            SortKey::opaque(0, 1, 2, 10.0),
+            SortKey::opaque(0, 1, 2, 0.0),
+        ];
+        assert_eq!(sorted(&keys), vec![3, 1, 2, 0]);
+    }
+
+    #[test]
+    fn opaque_groups_by_state_before_depth() {
+        let keys = [
+            SortKey::opaque(0, 2, 0, 1.0),
+            SortKey::opaque(0, 1, 1, 5.0),
+            SortKey::opaque(0, 1, 0, 100.0),
+            SortKey::opaque(0, 1, 1, 2.0),
+            SortKey::opaque(0, 1, 0, 3.0),
+        ];
+        // Shader first, then material, then depth
+        assert_eq!(sorted(&keys), vec![4, 2, 3, 1, 0]);
+    }
+
+    #[test]
+    fn translucent_back_to_front() {
+        let keys = [
+            SortKey::translucent(0, 1, 0, 1.0),
+            SortKey::translucent(0, 2, 3, 50.0),
+            SortKey::translucent(0, 1, 0, 10.0),
+            SortKey::translucent(0, 0, 9, 0.25),
+        ];
+        // State does not matter, only depth
+        assert_eq!(sorted(&keys), vec![1, 2, 0, 3]);
+    }
+
+    #[test]
+    fn passes_and_translucency_order() {
+        let keys = [
+            SortKey::translucent(0, 0, 0, 100.0),
+            SortKey::opaque(1, 0, 0, 0.0),
+            SortKey::opaque(0, 255, 1000, 1000.0),
+        ];
+        // Opaque before translucent within a pass, passes in order
+        assert_eq!(sorted(&keys), vec![2, 0, 1]);
+    }
+
+    #[test]
+    fn invalid_depths_sort_as_zero() {
+        for &depth in [-5.0, std::f32::NAN, -0.0].iter() {
+            assert_eq!(
+                SortKey::opaque(0, 1, 2, depth),
+                SortKey::opaque(0, 1, 2, 0.0)
+            );
+            assert_eq!(
+                SortKey::translucent(0, 1, 2, depth),
+                SortKey::translucent(0, 1, 2, 0.0)
+            );
+        }
+    }
+
+    #[test]
+    fn equal_keys_keep_submission_order() {
+        let key = SortKey::opaque(0, 1, 1, 1.0);
+        let keys = [key, SortKey::opaque(0, 0, 0, 0.0), key, key];
+        assert_eq!(sorted(&keys), vec![1, 0, 2, 3]);
+    }
+
+    #[test]
+    fn materials_are_numbered_by_first_use() {
+        let mut queue: RenderQueue<()> = RenderQueue::new();
+        assert_eq!(queue.material([1, 2, 0, 0]), 0);
+        assert_eq!(queue.material([3, 2, 0, 0]), 1);
+        assert_eq!(queue.material([1, 2, 0, 0]), 0);
+
+        queue.clear();
+        assert_eq!(queue.material([3, 2, 0, 0]), 0);
+    }
+}
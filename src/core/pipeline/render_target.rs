@@ -0,0 +1,409 @@
+use gl::types::*;
+
+// Offscreen framebuffers. Every target owns sampleable color textures and an
+// optional depth texture. With more than one sample the scene is drawn into
+// multisampled renderbuffers instead and resolve() blits them into the
+// textures, so the textures always hold the final single sampled image.
+
+pub const MAX_COLOR_ATTACHMENTS: usize = 4;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RenderTargetConfig {
+    pub width: u32,
+    pub height: u32,
+    // 1 disables MSAA, larger counts are clamped to GL_MAX_SAMPLES
+    pub samples: u32,
+    // Internal format of every color attachment in attachment order
+    pub color_formats: Vec<GLenum>,
+    pub depth: bool,
+}
+
+impl Default for RenderTargetConfig {
+    fn default() -> Self {
+        Self {
+            width: 1024,
+            height: 768,
+            samples: 1,
+            color_formats: vec![gl::RGBA8],
+            depth: true,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum RenderTargetError {
+    InvalidConfig(String),
+    Incomplete(GLenum),
+}
+
+impl std::fmt::Display for RenderTargetError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::InvalidConfig(msg) => write!(f, "Invalid render target: {}", msg),
+            Self::Incomplete(status) => write!(f, "Framebuffer incomplete: 0x{:x}", status),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct RenderTarget {
+    config: RenderTargetConfig,
+    // Framebuffer with the sampleable textures attached
+    framebuffer: GLuint,
+    color_textures: Vec<GLuint>,
+    // 0 without a depth attachment
+    depth_texture: GLuint,
+    // Multisampled framebuffer resolved into framebuffer, 0 without MSAA
+    msaa_framebuffer: GLuint,
+    msaa_renderbuffers: Vec<GLuint>,
+}
+
+fn check_status(framebuffer: GLuint) -> Result<(), RenderTargetError> {
+    let status = unsafe { gl::CheckNamedFramebufferStatus(framebuffer, gl::FRAMEBUFFER) };
+
+    if status != gl::FRAMEBUFFER_COMPLETE {
+        return Err(RenderTargetError::Incomplete(status));
+    }
+
+    Ok(())
+}
+
+fn draw_buffers(framebuffer: GLuint, count: usize) {
+    let buffers: Vec<GLenum> = (0..count)
+        .map(|i| gl::COLOR_ATTACHMENT0 + i as GLenum)
+        .collect();
+
+    unsafe {
+        if buffers.is_empty() {
+            gl::NamedFramebufferDrawBuffer(framebuffer, gl::NONE);
+            gl::NamedFramebufferReadBuffer(framebuffer, gl::NONE);
+        } else {
+            gl::NamedFramebufferDrawBuffers(framebuffer, count as GLsizei, buffers.as_ptr());
+        }
+    }
+}
+
+#[allow(dead_code)]
+impl RenderTarget {
+    pub fn new(config: RenderTargetConfig) -> Result<Self, RenderTargetError> {
+        if config.width == 0 || config.height == 0 {
+            return Err(RenderTargetError::InvalidConfig(format!(
+                "size {}x{}",
+                config.width, config.height
+            )));
+        }
+
+        if config.color_formats.len() > MAX_COLOR_ATTACHMENTS {
+            return Err(RenderTargetError::InvalidConfig(format!(
+                "{} color attachments, at most {} are supported",
+                config.color_formats.len(),
+                MAX_COLOR_ATTACHMENTS
+            )));
+        }
+
+        let mut max_samples = 1;
+
+        unsafe {
+            gl::GetIntegerv(gl::MAX_SAMPLES, &mut max_samples);
+        }
+
+        let samples = config.samples.max(1).min(max_samples.max(1) as u32);
+        let (width, height) = (config.width as GLsizei, config.height as GLsizei);
+
+        let mut target = Self {
+            config: RenderTargetConfig {
+                samples: samples,
+                ..config
+            },
+            framebuffer: 0,
+            color_textures: vec![],
+            depth_texture: 0,
+            msaa_framebuffer: 0,
+            msaa_renderbuffers: vec![],
+        };
+
+        unsafe {
+            gl::CreateFramebuffers(1, &mut target.framebuffer);
+
+            for (i, format) in target.config.color_formats.iter().enumerate() {
+                let mut texture = 0;
+                gl::CreateTextures(gl::TEXTURE_2D, 1, &mut texture);
+                gl::TextureStorage2D(texture, 1, *format, width, height);
+                gl::TextureParameteri(texture, gl::TEXTURE_MIN_FILTER, gl::LINEAR as GLint);
+                gl::TextureParameteri(texture, gl::TEXTURE_MAG_FILTER, gl::LINEAR as GLint);
+                gl::TextureParameteri(texture, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE as GLint);
+                gl::TextureParameteri(texture, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE as GLint);
+                gl::NamedFramebufferTexture(
+                    target.framebuffer,
+                    gl::COLOR_ATTACHMENT0 + i as GLenum,
+                    texture,
+                    0,
+                );
+                target.color_textures.push(texture);
+            }
+
+            if target.config.depth {
+                gl::CreateTextures(gl::TEXTURE_2D, 1, &mut target.depth_texture);
+                gl::TextureStorage2D(
+                    target.depth_texture,
+                    1,
+                    gl::DEPTH_COMPONENT32F,
+                    width,
+                    height,
+                );
+                // Depth can only be blitted and sampled without filtering
+                gl::TextureParameteri(
+                    target.depth_texture,
+                    gl::TEXTURE_MIN_FILTER,
+                    gl::NEAREST as GLint,
+                );
+                gl::TextureParameteri(
+                    target.depth_texture,
+                    gl::TEXTURE_MAG_FILTER,
+                    gl::NEAREST as GLint,
+                );
+                gl::NamedFramebufferTexture(
+                    target.framebuffer,
+                    gl::DEPTH_ATTACHMENT,
+                    target.depth_texture,
+                    0,
+                );
+            }
+        }
+
+        draw_buffers(target.framebuffer, target.color_textures.len());
+        check_status(target.framebuffer)?;
+
+        if samples > 1 {
+            let color_formats = target.config.color_formats.clone();
+            let depth = target.config.depth;
+
+            unsafe {
+                gl::CreateFramebuffers(1, &mut target.msaa_framebuffer);
+
+                let mut attach = |format: GLenum, attachment: GLenum| {
+                    let mut renderbuffer = 0;
+                    gl::CreateRenderbuffers(1, &mut renderbuffer);
+                    gl::NamedRenderbufferStorageMultisample(
+                        renderbuffer,
+                        samples as GLsizei,
+                        format,
+                        width,
+                        height,
+                    );
+                    gl::NamedFramebufferRenderbuffer(
+                        target.msaa_framebuffer,
+                        attachment,
+                        gl::RENDERBUFFER,
+                        renderbuffer,
+                    );
+                    target.msaa_renderbuffers.push(renderbuffer);
+                };
+
+                for (i, format) in color_formats.iter().enumerate() {
+                    attach(*format, gl::COLOR_ATTACHMENT0 + i as GLenum);
+                }
+
+                if depth {
+                    attach(gl::DEPTH_COMPONENT32F, gl::DEPTH_ATTACHMENT);
+                }
+            }
+
+            draw_buffers(target.msaa_framebuffer, target.color_textures.len());
+            check_status(target.msaa_framebuffer)?;
+        }
+
+        Ok(target)
+    }
+
+    pub fn config(&self) -> &RenderTargetConfig {
+        &self.config
+    }
+
+    pub fn width(&self) -> u32 {
+        self.config.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.config.height
+    }
+
+    pub fn samples(&self) -> u32 {
+        self.config.samples
+    }
+
+    pub fn color_texture(&self, index: usize) -> Option<GLuint> {
+        self.color_textures.get(index).cloned()
+    }
+
+    pub fn depth_texture(&self) -> Option<GLuint> {
+        if self.depth_texture == 0 {
+            None
+        } else {
+            Some(self.depth_texture)
+        }
+    }
+
+    // Framebuffer draws go to, the multisampled one with MSAA
+    pub fn draw_framebuffer(&self) -> GLuint {
+        if self.msaa_framebuffer != 0 {
+            self.msaa_framebuffer
+        } else {
+            self.framebuffer
+        }
+    }
+
+    // Binds the target for drawing and covers it with the viewport
+    pub fn bind(&self) {
+        unsafe {
+            gl::BindFramebuffer(gl::FRAMEBUFFER, self.draw_framebuffer());
+            gl::Viewport(
+                0,
+                0,
+                self.config.width as GLsizei,
+                self.config.height as GLsizei,
+            );
+        }
+    }
+
+    pub fn clear(&self, color: [f32; 4]) {
+        let framebuffer = self.draw_framebuffer();
+
+        unsafe {
+            for i in 0..self.color_textures.len() {
+                gl::ClearNamedFramebufferfv(framebuffer, gl::COLOR, i as GLint, color.as_ptr());
+            }
+
+            if self.config.depth {
+                let depth = 1.0f32;
+                gl::ClearNamedFramebufferfv(framebuffer, gl::DEPTH, 0, &depth);
+            }
+        }
+    }
+
+    // Copies the multisampled attachments into the textures, nothing to do
+    // without MSAA
+    pub fn resolve(&self) {
+        if self.msaa_framebuffer == 0 {
+            return;
+        }
+
+        let (w, h) = (self.config.width as GLint, self.config.height as GLint);
+
+        unsafe {
+            for i in 0..self.color_textures.len() {
+                let attachment = gl::COLOR_ATTACHMENT0 + i as GLenum;
+                gl::NamedFramebufferReadBuffer(self.msaa_framebuffer, attachment);
+                gl::NamedFramebufferDrawBuffer(self.framebuffer, attachment);
+                gl::BlitNamedFramebuffer(
+                    self.msaa_framebuffer,
+                    self.framebuffer,
+                    0,
+                    0,
+                    w,
+                    h,
+                    0,
+                    0,
+                    w,
+                    h,
+                    gl::COLOR_BUFFER_BIT,
+                    gl::NEAREST,
+                );
+            }
+
+            if self.config.depth {
+                gl::BlitNamedFramebuffer(
+                    self.msaa_framebuffer,
+                    self.framebuffer,
+                    0,
+                    0,
+                    w,
+                    h,
+                    0,
+                    0,
+                    w,
+                    h,
+                    gl::DEPTH_BUFFER_BIT,
+                    gl::NEAREST,
+                );
+            }
+        }
+
+        draw_buffers(self.framebuffer, self.color_textures.len());
+    }
+
+    // Recreates the attachments when the size changes, their contents are
+    // lost and texture names change
+    pub fn resize(&mut self, width: u32, height: u32) -> Result<(), RenderTargetError> {
+        if width == self.config.width && height == self.config.height {
+            return Ok(());
+        }
+
+        *self = Self::new(RenderTargetConfig {
+            width: width,
+            height: height,
+            ..self.config.clone()
+        })?;
+
+        Ok(())
+    }
+
+    // Blits the first color attachment into the default framebuffer,
+    // stretched over the given window size
+    pub fn present(&self, width: u32, height: u32) {
+        if self.color_textures.is_empty() {
+            return;
+        }
+
+        unsafe {
+            gl::NamedFramebufferReadBuffer(self.framebuffer, gl::COLOR_ATTACHMENT0);
+            gl::BlitNamedFramebuffer(
+                self.framebuffer,
+                0,
+                0,
+                0,
+                self.config.width as GLint,
+                self.config.height as GLint,
+                0,
+                0,
+                width as GLint,
+                height as GLint,
+                gl::COLOR_BUFFER_BIT,
+                gl::LINEAR,
+            );
+        }
+    }
+}
+
+impl Drop for RenderTarget {
+    fn drop(&mut self) {
+        unsafe {
+            gl::DeleteFramebuffers(1, &self.framebuffer);
+            gl::DeleteTextures(
+                self.color_textures.len() as GLsizei,
+                self.color_textures.as_ptr(),
+            );
+
+            if self.depth_texture != 0 {
+                gl::DeleteTextures(1, &self.depth_texture);
+            }
+
+            if self.msaa_framebuffer != 0 {
+                gl::DeleteFramebuffers(1, &self.msaa_framebuffer);
+                gl::DeleteRenderbuffers(
+                    self.msaa_renderbuffers.len() as GLsizei,
+                    self.msaa_renderbuffers.as_ptr(),
+                );
+            }
+        }
+    }
+}
+
+// Binds the window framebuffer again after drawing into a target
+#[allow(dead_code)]
+pub fn bind_default(width: u32, height: u32) {
+    unsafe {
+        gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+        gl::Viewport(0, 0, width as GLsizei, height as GLsizei);
+    }
+}
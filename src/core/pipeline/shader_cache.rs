@@ -0,0 +1,540 @@
+use crate::core::pipeline::mgl::shader::{Shader, ShaderIssue, ShaderProgram};
+use crate::core::pipeline::program_cache::ProgramBinaryCache;
+use crate::resource::{BufferLoader, BufferLoaderError};
+use gl::types::*;
+use std::collections::HashMap;
+use std::ffi::CString;
+use std::path::{Component, Path, PathBuf};
+
+// GLSL sources are expanded once when loaded: `#include "file"` is replaced
+// by the file, looked up relative to the including file. Every expanded file
+// gets its own source string number in #line directives so compile errors
+// point at the right file, see ShaderSource::files.
+//
+// Programs are compiled per set of ShaderFeatures, each feature turns into a
+// #define after the #version line. With a ProgramBinaryCache linked
+// permutations are stored on disk and reloaded on the next launch.
+
+#[derive(Debug)]
+pub enum PreprocessError {
+    FailedLoadingResource(BufferLoaderError),
+    MalformedInclude { file: PathBuf, line: usize },
+    IncludeCycle(PathBuf),
+    MissingVersion(PathBuf),
+}
+
+impl std::fmt::Display for PreprocessError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::FailedLoadingResource(e) => write!(f, "Failed loading shader source: {:?}", e),
+            Self::MalformedInclude { file, line } => {
+                write!(f, "{}:{}: Malformed #include", file.display(), line)
+            }
+            Self::IncludeCycle(file) => write!(f, "{}: Included recursively", file.display()),
+            Self::MissingVersion(file) => {
+                write!(f, "{}: Shader does not start with #version", file.display())
+            }
+        }
+    }
+}
+
+impl From<BufferLoaderError> for PreprocessError {
+    fn from(e: BufferLoaderError) -> Self {
+        Self::FailedLoadingResource(e)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct ShaderFeatures(u32);
+
+#[allow(dead_code)]
+impl ShaderFeatures {
+    pub const NONE: ShaderFeatures = ShaderFeatures(0);
+    pub const NORMAL_MAP: ShaderFeatures = ShaderFeatures(1 << 0);
+    pub const BLINN: ShaderFeatures = ShaderFeatures(1 << 1);
+    pub const SHADOWS: ShaderFeatures = ShaderFeatures(1 << 2);
+    // Metallic-roughness materials, BLINN has no effect with it
+    pub const PBR: ShaderFeatures = ShaderFeatures(1 << 3);
+
+    const DEFINES: [(ShaderFeatures, &'static str); 4] = [
+        (Self::NORMAL_MAP, "USE_NORMALMAP"),
+        (Self::BLINN, "USE_BLINN"),
+        (Self::SHADOWS, "USE_SHADOWS"),
+        (Self::PBR, "USE_PBR"),
+    ];
+
+    pub fn bits(&self) -> u32 {
+        self.0
+    }
+
+    pub fn contains(&self, other: ShaderFeatures) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    // Copy with the given features added or removed
+    pub fn with(&self, other: ShaderFeatures, enabled: bool) -> ShaderFeatures {
+        if enabled {
+            ShaderFeatures(self.0 | other.0)
+        } else {
+            ShaderFeatures(self.0 & !other.0)
+        }
+    }
+
+    pub fn defines(&self) -> Vec<&'static str> {
+        Self::DEFINES
+            .iter()
+            .filter(|(f, _)| self.contains(*f))
+            .map(|(_, d)| *d)
+            .collect()
+    }
+}
+
+impl std::ops::BitOr for ShaderFeatures {
+    type Output = ShaderFeatures;
+
+    fn bitor(self, other: ShaderFeatures) -> ShaderFeatures {
+        ShaderFeatures(self.0 | other.0)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ShaderSource {
+    // #version line of the root file
+    version: String,
+    // Everything after the #version line with includes expanded
+    body: String,
+    // Expanded files, the index is the source string number used by #line
+    pub files: Vec<PathBuf>,
+}
+
+// Collapses "." and ".." without touching the file system, so one file
+// reached through different relative paths is recognized as the same file
+fn normalize_path(path: &Path) -> PathBuf {
+    let mut out = PathBuf::new();
+
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => match out.components().next_back() {
+                Some(Component::Normal(_)) => {
+                    out.pop();
+                }
+                // Nothing is above the root
+                Some(Component::RootDir) | Some(Component::Prefix(_)) => {}
+                _ => out.push(".."),
+            },
+            other => out.push(other.as_os_str()),
+        }
+    }
+
+    out
+}
+
+impl ShaderSource {
+    pub fn load(loader: &BufferLoader, path: &Path) -> Result<Self, PreprocessError> {
+        let mut source = Self {
+            version: String::new(),
+            body: String::new(),
+            files: vec![],
+        };
+        let mut stack = vec![];
+        let body = source.expand(loader, path, &mut stack)?;
+
+        let mut lines = body.splitn(2, '\n');
+        let first = lines.next().unwrap_or("");
+
+        if !first.trim_start().starts_with("#version") {
+            return Err(PreprocessError::MissingVersion(path.to_path_buf()));
+        }
+
+        source.version = first.to_owned();
+        source.body = lines.next().unwrap_or("").to_owned();
+        Ok(source)
+    }
+
+    fn expand(
+        &mut self,
+        loader: &BufferLoader,
+        path: &Path,
+        stack: &mut Vec<PathBuf>,
+    ) -> Result<String, PreprocessError> {
+        let path = &normalize_path(path);
+        if stack.iter().any(|p| p == path) {
+            return Err(PreprocessError::IncludeCycle(path.to_path_buf()));
+        }
+
+        let text = loader.load_string(path)?;
+        let file_number = self.files.len();
+        self.files.push(path.to_path_buf());
+        stack.push(path.to_path_buf());
+
+        let mut out = String::with_capacity(text.len());
+
+        for (i, line) in text.lines().enumerate() {
+            let trimmed = line.trim_start();
+
+            if !trimmed.starts_with("#include") {
+                out.push_str(line);
+                out.push('\n');
+                continue;
+            }
+
+            let malformed = || PreprocessError::MalformedInclude {
+                file: path.to_path_buf(),
+                line: i + 1,
+            };
+
+            let name = trimmed["#include".len()..].trim();
+            if name.len() < 2 || !name.starts_with('"') || !name.ends_with('"') {
+                return Err(malformed());
+            }
+
+            let include = match path.parent() {
+                Some(dir) => dir.join(&name[1..name.len() - 1]),
+                None => PathBuf::from(&name[1..name.len() - 1]),
+            };
+
+            let included_number = self.files.len();
+            out.push_str(&format!("#line 1 {}\n", included_number));
+            out.push_str(&self.expand(loader, &include, stack)?);
+            // Back to the line after the #include
+            out.push_str(&format!("#line {} {}\n", i + 2, file_number));
+        }
+
+        stack.pop();
+        Ok(out)
+    }
+
+    // Full source with a #define per feature and any extra defines
+    pub fn with_defines(&self, features: ShaderFeatures, extra: &[(&str, &str)]) -> String {
+        let mut out = self.version.clone();
+        out.push('\n');
+
+        for d in features.defines() {
+            out.push_str(&format!("#define {}\n", d));
+        }
+
+        for (name, value) in extra {
+            out.push_str(&format!("#define {} {}\n", name, value));
+        }
+
+        // The body starts at line 2 of the root file
+        out.push_str("#line 2 0\n");
+        out.push_str(&self.body);
+        out
+    }
+
+    // Compiles the sources of a program, linked programs come from binaries
+    // when possible
+    pub fn link(
+        stages: &[(&ShaderSource, GLenum)],
+        features: ShaderFeatures,
+        binaries: Option<&ProgramBinaryCache>,
+    ) -> Result<ShaderProgram, ShaderIssue> {
+        let link_stages = || {
+            let mut shaders = vec![];
+
+            for (source, kind) in stages {
+                shaders.push(source.compile(*kind, features)?);
+            }
+
+            ShaderProgram::from_shaders(&shaders)
+        };
+
+        match binaries {
+            Some(cache) => {
+                let texts: Vec<_> = stages
+                    .iter()
+                    .map(|(source, kind)| (source.with_defines(features, &[]), *kind))
+                    .collect();
+                let keyed: Vec<_> = texts.iter().map(|(t, k)| (t.as_str(), *k)).collect();
+                cache.get_or_link(&keyed, link_stages)
+            }
+            None => link_stages(),
+        }
+    }
+
+    // Compiles the source as a single stage with the given features, compile
+    // errors name the root file and the defines
+    pub fn compile(&self, kind: GLenum, features: ShaderFeatures) -> Result<Shader, ShaderIssue> {
+        let text = CString::new(self.with_defines(features, &[]))
+            .map_err(|e| ShaderIssue::StringConversionError(e.to_string()))?;
+
+        Shader::from_source(&text, kind).map_err(|e| match e {
+            ShaderIssue::CompileError(msg) => ShaderIssue::CompileError(format!(
+                "{} {:?}: {}",
+                self.files[0].display(),
+                features.defines(),
+                msg
+            )),
+            other => other,
+        })
+    }
+}
+
+// Constructed once for every compiled permutation, lets owners keep
+// uniforms looked up from the program next to it
+pub trait ProgramVariant: Sized {
+    fn from_program(program: ShaderProgram) -> Result<Self, ShaderIssue>;
+}
+
+impl ProgramVariant for ShaderProgram {
+    fn from_program(program: ShaderProgram) -> Result<Self, ShaderIssue> {
+        Ok(program)
+    }
+}
+
+pub struct ShaderCache<V: ProgramVariant = ShaderProgram> {
+    stages: Vec<(ShaderSource, GLenum)>,
+    variants: HashMap<ShaderFeatures, V>,
+    binaries: Option<ProgramBinaryCache>,
+}
+
+#[allow(dead_code)]
+impl<V: ProgramVariant> ShaderCache<V> {
+    // Stages are given as (source path, shader type) pairs, nothing is
+    // compiled until a permutation is requested
+    pub fn load(loader: &BufferLoader, stages: &[(&str, GLenum)]) -> Result<Self, PreprocessError> {
+        let mut loaded = vec![];
+
+        for (path, kind) in stages {
+            loaded.push((ShaderSource::load(loader, Path::new(path))?, *kind));
+        }
+
+        Ok(Self {
+            stages: loaded,
+            variants: HashMap::new(),
+            binaries: None,
+        })
+    }
+
+    pub fn with_binary_cache(mut self, binaries: ProgramBinaryCache) -> Self {
+        self.binaries = Some(binaries);
+        self
+    }
+
+    // Compiles the permutation unless it is cached already
+    pub fn compile(&mut self, features: ShaderFeatures) -> Result<&V, ShaderIssue> {
+        if !self.variants.contains_key(&features) {
+            let stages: Vec<_> = self.stages.iter().map(|(s, k)| (s, *k)).collect();
+            let program = ShaderSource::link(&stages, features, self.binaries.as_ref())?;
+            let variant = V::from_program(program)?;
+            self.variants.insert(features, variant);
+        }
+
+        Ok(&self.variants[&features])
+    }
+
+    pub fn get(&self, features: ShaderFeatures) -> Option<&V> {
+        self.variants.get(&features)
+    }
+
+    pub fn variants(&self) -> impl Iterator<Item = &V> {
+        self.variants.values()
+    }
+
+    pub fn len(&self) -> usize {
+        self.variants.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    // Shader files in a fresh directory under the system temp dir
+    fn loader(name: &str, files: &[(&str, &str)]) -> BufferLoader {
+        let root = std::env::temp_dir().join(format!(
+            "darkest_shader_cache_{}_{}",
+            std::process::id(),
+            name
+        ));
+        let _ = fs::remove_dir_all(&root);
+
+        for (path, text) in files {
+            let full = root.join(path);
+            fs::create_dir_all(full.parent().unwrap()).unwrap();
+            fs::write(full, text).unwrap();
+        }
+
+        BufferLoader::with_root(root).unwrap()
+    }
+
+    fn load(loader: &BufferLoader, path: &str) -> Result<ShaderSource, PreprocessError> {
+        ShaderSource::load(loader, Path::new(path))
+    }
+
+    #[test]
+    fn paths_are_normalized() {
+        let cases = [
+            ("shaders/lib/../a.glsl", "shaders/a.glsl"),
+            ("shaders/./a.glsl", "shaders/a.glsl"),
+            ("shaders/../../a.glsl", "../a.glsl"),
+            ("../shaders/a.glsl", "../shaders/a.glsl"),
+            ("/../a.glsl", "/a.glsl"),
+        ];
+
+        for (path, expected) in cases.iter() {
+            assert_eq!(normalize_path(Path::new(path)), PathBuf::from(expected));
+        }
+    }
+
+    #[test]
+    fn includes_are_expanded_with_line_directives() {
+        let loader = loader(
+            "expand",
+            &[
+                (
+                    "shaders/main.glsl",
+                    "#version 450\nfloat a;\n#include \"lib/common.glsl\"\nfloat b;\n",
+                ),
+                (
+                    "shaders/lib/common.glsl",
+                    "float c;\n  #include \"../util.glsl\"\n",
+                ),
+                ("shaders/util.glsl", "float d;\n"),
+            ],
+        );
+
+        let source = load(&loader, "shaders/main.glsl").unwrap();
+
+        assert_eq!(
+            source.files,
+            vec![
+                PathBuf::from("shaders/main.glsl"),
+                PathBuf::from("shaders/lib/common.glsl"),
+                PathBuf::from("shaders/util.glsl"),
+            ]
+        );
+        assert_eq!(source.version, "#version 450");
+        assert_eq!(
+            source.body,
+            "float a;\n\
+             #line 1 1\n\
+             float c;\n\
+             #line 1 2\n\
+             float d;\n\
+             #line 3 1\n\
+             #line 4 0\n\
+             float b;\n"
+        );
+        assert_eq!(
+            source.with_defines(
+                ShaderFeatures::NORMAL_MAP | ShaderFeatures::SHADOWS,
+                &[("LIGHTS", "4")]
+            ),
+            format!(
+                "#version 450\n\
+                 #define USE_NORMALMAP\n\
+                 #define USE_SHADOWS\n\
+                 #define LIGHTS 4\n\
+                 #line 2 0\n{}",
+                source.body
+            )
+        );
+    }
+
+    #[test]
+    fn files_may_be_included_more_than_once() {
+        let loader = loader(
+            "diamond",
+            &[
+                (
+                    "main.glsl",
+                    "#version 450\n#include \"a.glsl\"\n#include \"b.glsl\"\n",
+                ),
+                ("a.glsl", "#include \"common.glsl\"\n"),
+                ("b.glsl", "#include \"./common.glsl\"\n"),
+                ("common.glsl", "float c;\n"),
+            ],
+        );
+
+        let source = load(&loader, "main.glsl").unwrap();
+        assert_eq!(source.files.len(), 5);
+        assert_eq!(source.body.matches("float c;").count(), 2);
+    }
+
+    #[test]
+    fn include_cycles_are_detected() {
+        let loader = loader(
+            "cycle",
+            &[
+                (
+                    "shaders/a.glsl",
+                    "#version 450\n#include \"../shaders/a.glsl\"\n",
+                ),
+                ("shaders/b.glsl", "#version 450\n#include \"c.glsl\"\n"),
+                ("shaders/c.glsl", "#include \"lib/../b.glsl\"\n"),
+            ],
+        );
+
+        match load(&loader, "shaders/a.glsl") {
+            Err(PreprocessError::IncludeCycle(path)) => {
+                assert_eq!(path, PathBuf::from("shaders/a.glsl"))
+            }
+            other => panic!("{:?}", other),
+        }
+        match load(&loader, "./shaders/b.glsl") {
+            Err(PreprocessError::IncludeCycle(path)) => {
+                assert_eq!(path, PathBuf::from("shaders/b.glsl"))
+            }
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn malformed_includes_report_their_line() {
+        let loader = loader(
+            "malformed",
+            &[
+                ("unquoted.glsl", "#version 450\n\n#include common.glsl\n"),
+                ("empty.glsl", "#version 450\n#include \"\n"),
+                ("bare.glsl", "#version 450\n#include\n"),
+            ],
+        );
+
+        for (file, line) in &[("unquoted.glsl", 3), ("empty.glsl", 2), ("bare.glsl", 2)] {
+            match load(&loader, file) {
+                Err(PreprocessError::MalformedInclude { file: f, line: l }) => {
+                    assert_eq!((f, l), (PathBuf::from(file), *line))
+                }
+                other => panic!("{:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn missing_files_and_versions_are_errors() {
+        let loader = loader(
+            "missing",
+            &[
+                ("no_version.glsl", "void main() {}\n"),
+                ("broken.glsl", "#version 450\n#include \"nowhere.glsl\"\n"),
+            ],
+        );
+
+        match load(&loader, "no_version.glsl") {
+            Err(PreprocessError::MissingVersion(_)) => {}
+            other => panic!("{:?}", other),
+        }
+        match load(&loader, "broken.glsl") {
+            Err(PreprocessError::FailedLoadingResource(_)) => {}
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn features_map_to_defines() {
+        let features = ShaderFeatures::PBR | ShaderFeatures::NORMAL_MAP;
+
+        assert_eq!(features.defines(), vec!["USE_NORMALMAP", "USE_PBR"]);
+        assert!(features.contains(ShaderFeatures::PBR));
+        assert!(!features.contains(ShaderFeatures::BLINN));
+        assert_eq!(
+            features.with(ShaderFeatures::PBR, false),
+            ShaderFeatures::NORMAL_MAP
+        );
+        assert!(ShaderFeatures::NONE.defines().is_empty());
+    }
+}
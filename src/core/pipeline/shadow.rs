@@ -0,0 +1,520 @@
+use cgmath::prelude::*;
+use gl::types::*;
+
+type Mat4 = cgmath::Matrix4<f32>;
+type Vec3 = cgmath::Vector3<f32>;
+type Vec4 = cgmath::Vector4<f32>;
+type Point3 = cgmath::Point3<f32>;
+
+// Cascaded shadow maps for the sun. The camera frustum is split along its
+// depth, every split gets an orthographic light projection fitted around it
+// and rendered into one layer of a depth texture array.
+
+pub const MAX_CASCADES: usize = 4;
+
+#[derive(Debug, Clone, Copy)]
+pub struct ShadowConfig {
+    pub enabled: bool,
+    pub cascade_count: usize,
+    // Width and height of every cascade layer
+    pub resolution: u32,
+    // Shadows end at this view distance even if the far plane is further
+    pub max_distance: f32,
+    // Blend between uniform (0) and logarithmic (1) split distances
+    pub split_lambda: f32,
+    // Constant and slope scaled depth bias in normalized depth units
+    pub depth_bias: f32,
+    pub slope_bias: f32,
+    // Receiver offset along the normal in world units
+    pub normal_offset: f32,
+    // PCF kernel covers (2r+1)^2 texels
+    pub pcf_radius: i32,
+    // Face size of point light shadow cubes
+    pub point_resolution: u32,
+    // Width and height of every spot light shadow map
+    pub spot_resolution: u32,
+}
+
+impl Default for ShadowConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            cascade_count: MAX_CASCADES,
+            resolution: 2048,
+            max_distance: 100.0,
+            split_lambda: 0.75,
+            depth_bias: 0.0005,
+            slope_bias: 0.002,
+            normal_offset: 0.02,
+            pcf_radius: 1,
+            point_resolution: 1024,
+            spot_resolution: 1024,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Cascade {
+    pub view_proj: Mat4,
+    // View space distance where the cascade ends
+    pub split_far: f32,
+}
+
+// Near and far planes of a perspective projection made by cgmath::perspective
+pub fn projection_depth_range(proj: &Mat4) -> (f32, f32) {
+    let (a, b) = (proj.z.z, proj.w.z);
+    (b / (a - 1.0), b / (a + 1.0))
+}
+
+// Far distance of every split, the first split starts at near
+pub fn split_distances(near: f32, far: f32, count: usize, lambda: f32) -> Vec<f32> {
+    (1..=count)
+        .map(|i| {
+            let t = i as f32 / count as f32;
+            let log = near * (far / near).powf(t);
+            let uniform = near + (far - near) * t;
+            lambda * log + (1.0 - lambda) * uniform
+        })
+        .collect()
+}
+
+fn frustum_corners(view: &Mat4, proj: &Mat4) -> Option<([Vec3; 4], [Vec3; 4])> {
+    let inv = (proj * view).invert()?;
+    let unproject = |x: f32, y: f32, z: f32| {
+        let p = inv * Vec4::new(x, y, z, 1.0);
+        p.truncate() / p.w
+    };
+
+    let xy = [(-1.0, -1.0), (1.0, -1.0), (1.0, 1.0), (-1.0, 1.0)];
+    let mut near = [Vec3::zero(); 4];
+    let mut far = [Vec3::zero(); 4];
+
+    for (i, (x, y)) in xy.iter().enumerate() {
+        near[i] = unproject(*x, *y, -1.0);
+        far[i] = unproject(*x, *y, 1.0);
+    }
+
+    Some((near, far))
+}
+
+// Fits one orthographic light projection per split. A bounding sphere keeps
+// the projection size constant while the camera rotates and the origin is
+// snapped to whole texels so shadow edges do not shimmer when it moves.
+pub fn compute_cascades(
+    view: &Mat4,
+    proj: &Mat4,
+    light_dir: Vec3,
+    config: &ShadowConfig,
+) -> Vec<Cascade> {
+    let count = config.cascade_count.max(1).min(MAX_CASCADES);
+    let (near, full_far) = projection_depth_range(proj);
+    let far = full_far.min(config.max_distance);
+
+    // Corners in view space so the sphere radius only depends on the
+    // projection and can not flicker between quantization steps as the
+    // camera moves
+    let (near_corners, far_corners, inv_view) =
+        match (frustum_corners(&Mat4::identity(), proj), view.invert()) {
+            (Some((n, f)), Some(inv)) => (n, f, inv),
+            _ => return vec![],
+        };
+
+    let depth_to_t = |d: f32| (d - near) / (full_far - near);
+
+    let dir = if light_dir.magnitude2() > 0.0 {
+        light_dir.normalize()
+    } else {
+        Vec3::new(0.0, -1.0, 0.0)
+    };
+    let up = if dir.y.abs() > 0.99 {
+        Vec3::unit_z()
+    } else {
+        Vec3::unit_y()
+    };
+
+    let mut split_near = near;
+
+    split_distances(near, far, count, config.split_lambda)
+        .into_iter()
+        .map(|split_far| {
+            let (t0, t1) = (depth_to_t(split_near), depth_to_t(split_far));
+            split_near = split_far;
+
+            let mut corners = vec![];
+            for i in 0..4 {
+                let ray = far_corners[i] - near_corners[i];
+                corners.push(near_corners[i] + ray * t0);
+                corners.push(near_corners[i] + ray * t1);
+            }
+
+            let view_center = corners.iter().fold(Vec3::zero(), |acc, c| acc + c) / 8.0;
+            let radius = corners
+                .iter()
+                .map(|c| (c - view_center).magnitude())
+                .fold(0.0f32, f32::max);
+            // Quantize so small floating point changes do not resize the projection
+            let radius = (radius * 16.0).ceil() / 16.0;
+            let center = (inv_view * view_center.extend(1.0)).truncate();
+
+            // Casters in front of the near plane are kept by depth clamping
+            let eye = Point3::from_vec(center - dir * radius);
+            let light_view = Mat4::look_at_rh(eye, Point3::from_vec(center), up);
+            let light_proj = cgmath::ortho(-radius, radius, -radius, radius, 0.0, radius * 2.0);
+
+            let mut view_proj = light_proj * light_view;
+
+            let half_res = config.resolution as f32 * 0.5;
+            let origin = view_proj * Vec4::new(0.0, 0.0, 0.0, 1.0);
+            let (ox, oy) = (origin.x * half_res, origin.y * half_res);
+            view_proj.w.x += (ox.round() - ox) / half_res;
+            view_proj.w.y += (oy.round() - oy) / half_res;
+
+            Cascade {
+                view_proj: view_proj,
+                split_far: split_far,
+            }
+        })
+        .collect()
+}
+
+// Depth texture array with one layer per cascade and a framebuffer to
+// render into the layers
+#[derive(Debug)]
+pub struct ShadowMap {
+    pub texture: GLuint,
+    pub framebuffer: GLuint,
+    pub resolution: u32,
+    pub layers: usize,
+}
+
+impl ShadowMap {
+    pub fn new(resolution: u32, layers: usize) -> Self {
+        let mut texture = 0;
+        let mut framebuffer = 0;
+
+        unsafe {
+            gl::CreateTextures(gl::TEXTURE_2D_ARRAY, 1, &mut texture);
+            gl::TextureStorage3D(
+                texture,
+                1,
+                gl::DEPTH_COMPONENT32F,
+                resolution as GLsizei,
+                resolution as GLsizei,
+                layers as GLsizei,
+            );
+
+            gl::TextureParameteri(texture, gl::TEXTURE_MIN_FILTER, gl::LINEAR as GLint);
+            gl::TextureParameteri(texture, gl::TEXTURE_MAG_FILTER, gl::LINEAR as GLint);
+            gl::TextureParameteri(texture, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_BORDER as GLint);
+            gl::TextureParameteri(texture, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_BORDER as GLint);
+            // Everything outside of the map is lit
+            let border = [1.0f32, 1.0, 1.0, 1.0];
+            gl::TextureParameterfv(texture, gl::TEXTURE_BORDER_COLOR, border.as_ptr());
+            // Hardware depth comparison for sampler2DArrayShadow
+            gl::TextureParameteri(
+                texture,
+                gl::TEXTURE_COMPARE_MODE,
+                gl::COMPARE_REF_TO_TEXTURE as GLint,
+            );
+            gl::TextureParameteri(texture, gl::TEXTURE_COMPARE_FUNC, gl::LEQUAL as GLint);
+
+            gl::CreateFramebuffers(1, &mut framebuffer);
+            gl::NamedFramebufferDrawBuffer(framebuffer, gl::NONE);
+            gl::NamedFramebufferReadBuffer(framebuffer, gl::NONE);
+        }
+
+        Self {
+            texture: texture,
+            framebuffer: framebuffer,
+            resolution: resolution,
+            layers: layers,
+        }
+    }
+
+    // Binds the framebuffer with the given layer as depth attachment
+    pub fn bind_layer(&self, layer: usize) {
+        unsafe {
+            gl::NamedFramebufferTextureLayer(
+                self.framebuffer,
+                gl::DEPTH_ATTACHMENT,
+                self.texture,
+                0,
+                layer as GLint,
+            );
+            gl::BindFramebuffer(gl::FRAMEBUFFER, self.framebuffer);
+            gl::Viewport(0, 0, self.resolution as GLsizei, self.resolution as GLsizei);
+            gl::Clear(gl::DEPTH_BUFFER_BIT);
+        }
+    }
+}
+
+impl Drop for ShadowMap {
+    fn drop(&mut self) {
+        unsafe {
+            gl::DeleteFramebuffers(1, &self.framebuffer);
+            gl::DeleteTextures(1, &self.texture);
+        }
+    }
+}
+
+// Omnidirectional shadows for point lights. Every shadow casting light owns
+// one cube of a cube map array, all six faces are rendered in a single pass
+// by the geometry shader of point_shadow_geom.glsl. Faces store the distance
+// to the light divided by its shadow range so lookups compare linear
+// distances instead of projected depth.
+
+pub const MAX_POINT_SHADOWS: usize = 4;
+pub const POINT_SHADOW_NEAR: f32 = 0.05;
+
+// View projection of every cube face in GL face order (+X, -X, +Y, -Y, +Z, -Z)
+pub fn cube_face_view_projs(position: Vec3, far: f32) -> [Mat4; 6] {
+    let faces = [
+        (Vec3::unit_x(), -Vec3::unit_y()),
+        (-Vec3::unit_x(), -Vec3::unit_y()),
+        (Vec3::unit_y(), Vec3::unit_z()),
+        (-Vec3::unit_y(), -Vec3::unit_z()),
+        (Vec3::unit_z(), -Vec3::unit_y()),
+        (-Vec3::unit_z(), -Vec3::unit_y()),
+    ];
+
+    let proj = cgmath::perspective(cgmath::Deg(90.0), 1.0, POINT_SHADOW_NEAR, far);
+    let eye = Point3::from_vec(position);
+    let mut mats = [Mat4::identity(); 6];
+
+    for (i, (dir, up)) in faces.iter().enumerate() {
+        mats[i] = proj * Mat4::look_at_rh(eye, eye + dir, *up);
+    }
+
+    mats
+}
+
+#[derive(Debug)]
+pub struct PointShadowMap {
+    pub texture: GLuint,
+    pub framebuffer: GLuint,
+    pub resolution: u32,
+    // Number of cubes, the array holds six layers per cube
+    pub cubes: usize,
+}
+
+impl PointShadowMap {
+    pub fn new(resolution: u32, cubes: usize) -> Self {
+        let mut texture = 0;
+        let mut framebuffer = 0;
+
+        unsafe {
+            gl::CreateTextures(gl::TEXTURE_CUBE_MAP_ARRAY, 1, &mut texture);
+            gl::TextureStorage3D(
+                texture,
+                1,
+                gl::DEPTH_COMPONENT32F,
+                resolution as GLsizei,
+                resolution as GLsizei,
+                (cubes * 6) as GLsizei,
+            );
+
+            gl::TextureParameteri(texture, gl::TEXTURE_MIN_FILTER, gl::LINEAR as GLint);
+            gl::TextureParameteri(texture, gl::TEXTURE_MAG_FILTER, gl::LINEAR as GLint);
+            gl::TextureParameteri(texture, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE as GLint);
+            gl::TextureParameteri(texture, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE as GLint);
+            gl::TextureParameteri(texture, gl::TEXTURE_WRAP_R, gl::CLAMP_TO_EDGE as GLint);
+            // Hardware comparison for samplerCubeArrayShadow
+            gl::TextureParameteri(
+                texture,
+                gl::TEXTURE_COMPARE_MODE,
+                gl::COMPARE_REF_TO_TEXTURE as GLint,
+            );
+            gl::TextureParameteri(texture, gl::TEXTURE_COMPARE_FUNC, gl::LEQUAL as GLint);
+
+            // Layered attachment, the geometry shader picks the layer
+            gl::CreateFramebuffers(1, &mut framebuffer);
+            gl::NamedFramebufferTexture(framebuffer, gl::DEPTH_ATTACHMENT, texture, 0);
+            gl::NamedFramebufferDrawBuffer(framebuffer, gl::NONE);
+            gl::NamedFramebufferReadBuffer(framebuffer, gl::NONE);
+        }
+
+        Self {
+            texture: texture,
+            framebuffer: framebuffer,
+            resolution: resolution,
+            cubes: cubes,
+        }
+    }
+
+    // Binds the framebuffer and clears every cube
+    pub fn bind(&self) {
+        unsafe {
+            gl::BindFramebuffer(gl::FRAMEBUFFER, self.framebuffer);
+            gl::Viewport(0, 0, self.resolution as GLsizei, self.resolution as GLsizei);
+            gl::Clear(gl::DEPTH_BUFFER_BIT);
+        }
+    }
+}
+
+impl Drop for PointShadowMap {
+    fn drop(&mut self) {
+        unsafe {
+            gl::DeleteFramebuffers(1, &self.framebuffer);
+            gl::DeleteTextures(1, &self.texture);
+        }
+    }
+}
+
+// Spot light shadows reuse ShadowMap, every shadow casting spot light owns
+// one layer rendered with a perspective projection covering its cone. The
+// same projection places the cookie texture of the light.
+
+pub const MAX_SPOT_SHADOWS: usize = 4;
+pub const SPOT_SHADOW_NEAR: f32 = 0.1;
+
+pub fn spot_view_proj(
+    position: Vec3,
+    direction: Vec3,
+    outer_angle: cgmath::Rad<f32>,
+    range: f32,
+) -> Mat4 {
+    let dir = if direction.magnitude2() > 0.0 {
+        direction.normalize()
+    } else {
+        -Vec3::unit_y()
+    };
+    let up = if dir.y.abs() > 0.99 {
+        Vec3::unit_z()
+    } else {
+        Vec3::unit_y()
+    };
+
+    // Keeps the cone inside the projection even for very wide lights
+    let fov = cgmath::Rad((outer_angle.0 * 2.0).max(0.01).min(3.0));
+    let far = range.max(SPOT_SHADOW_NEAR * 2.0);
+    let proj = cgmath::perspective(fov, 1.0, SPOT_SHADOW_NEAR, far);
+    let eye = Point3::from_vec(position);
+
+    proj * Mat4::look_at_rh(eye, eye + dir, up)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn camera(eye: Point3, yaw: f32) -> Mat4 {
+        let dir = Vec3::new(yaw.sin(), -0.3, -yaw.cos());
+        Mat4::look_at_rh(eye, eye + dir, Vec3::unit_y())
+    }
+
+    fn projection() -> Mat4 {
+        cgmath::perspective(cgmath::Deg(60.0), 16.0 / 9.0, 0.1, 500.0)
+    }
+
+    fn is_whole(v: f32) -> bool {
+        (v - v.round()).abs() < 1e-2
+    }
+
+    #[test]
+    fn depth_range_matches_the_projection() {
+        let (near, far) = projection_depth_range(&projection());
+        assert!((near - 0.1).abs() < 1e-4);
+        assert!((far - 500.0).abs() < 0.5);
+    }
+
+    #[test]
+    fn split_distances_blend_uniform_and_logarithmic() {
+        let uniform = split_distances(1.0, 100.0, 4, 0.0);
+        let log = split_distances(1.0, 100.0, 4, 1.0);
+
+        for (a, b) in uniform.iter().zip([25.75, 50.5, 75.25, 100.0].iter()) {
+            assert!((a - b).abs() < 1e-3);
+        }
+        for (a, b) in log.iter().zip([3.1623, 10.0, 31.623, 100.0].iter()) {
+            assert!((a - b).abs() < 1e-3);
+        }
+
+        let mixed = split_distances(1.0, 100.0, 4, 0.75);
+        for i in 0..4 {
+            assert!(mixed[i] > log[i] - 1e-4 && mixed[i] < uniform[i] + 1e-4);
+        }
+        assert!(mixed.windows(2).all(|w| w[0] < w[1]));
+    }
+
+    #[test]
+    fn cascades_end_at_the_shadow_distance() {
+        let config = ShadowConfig::default();
+        let cascades = compute_cascades(
+            &camera(Point3::new(0.0, 5.0, 0.0), 0.0),
+            &projection(),
+            Vec3::new(1.0, -2.0, 0.5),
+            &config,
+        );
+
+        let (near, _) = projection_depth_range(&projection());
+        let expected = split_distances(near, config.max_distance, 4, config.split_lambda);
+        let splits: Vec<f32> = cascades.iter().map(|c| c.split_far).collect();
+        assert_eq!(splits, expected);
+
+        let config = ShadowConfig {
+            cascade_count: 9,
+            ..ShadowConfig::default()
+        };
+        let cascades = compute_cascades(
+            &camera(Point3::new(0.0, 5.0, 0.0), 0.0),
+            &projection(),
+            Vec3::new(1.0, -2.0, 0.5),
+            &config,
+        );
+        assert_eq!(cascades.len(), MAX_CASCADES);
+    }
+
+    #[test]
+    fn cascades_cover_their_split() {
+        let view = camera(Point3::new(3.0, 5.0, -2.0), 0.7);
+        let config = ShadowConfig::default();
+        let cascades = compute_cascades(&view, &projection(), Vec3::new(0.0, -1.0, 0.0), &config);
+        let (near_corners, far_corners) = frustum_corners(&view, &projection()).unwrap();
+        let (near, far) = projection_depth_range(&projection());
+
+        let mut split_near = near;
+        for cascade in &cascades {
+            for i in 0..4 {
+                let ray = far_corners[i] - near_corners[i];
+                for d in &[split_near, cascade.split_far] {
+                    let corner = near_corners[i] + ray * ((d - near) / (far - near));
+                    let clip = cascade.view_proj * corner.extend(1.0);
+                    assert!(clip.x.abs() <= 1.0 && clip.y.abs() <= 1.0);
+                    assert!(clip.z >= -1.0 && clip.z <= 1.0);
+                }
+            }
+            split_near = cascade.split_far;
+        }
+    }
+
+    #[test]
+    fn projections_are_snapped_to_texels() {
+        let config = ShadowConfig::default();
+        let half_res = config.resolution as f32 * 0.5;
+        let light = Vec3::new(0.4, -1.0, 0.2);
+        let proj = projection();
+
+        let at = |eye: Point3, yaw: f32| compute_cascades(&camera(eye, yaw), &proj, light, &config);
+        let base = at(Point3::new(0.0, 5.0, 0.0), 0.3);
+        let moved = at(Point3::new(0.013, 5.0, 0.021), 0.3);
+        let turned = at(Point3::new(0.0, 5.0, 0.0), 1.9);
+
+        for ((a, b), c) in base.iter().zip(moved.iter()).zip(turned.iter()) {
+            // The world origin always lands on a texel corner
+            let origin = a.view_proj * Vec4::unit_w();
+            assert!(is_whole(origin.x * half_res) && is_whole(origin.y * half_res));
+
+            // Moving the camera shifts fixed points by whole texels only
+            let p = Vec4::new(12.5, -3.0, 7.25, 1.0);
+            let (pa, pb) = (a.view_proj * p, b.view_proj * p);
+            assert!(is_whole((pa.x - pb.x) * half_res));
+            assert!(is_whole((pa.y - pb.y) * half_res));
+
+            // Turning the camera keeps the projection size
+            assert!(
+                (a.view_proj.x.truncate().magnitude() - c.view_proj.x.truncate().magnitude()).abs()
+                    < 1e-6
+            );
+        }
+    }
+}
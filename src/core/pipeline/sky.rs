@@ -0,0 +1,265 @@
+use crate::core::pipeline::gpu;
+use crate::core::pipeline::gpu::textures::Cubemap;
+use crate::core::pipeline::mgl::shader::ShaderProgram;
+use crate::core::pipeline::post;
+use crate::core::pipeline::shadow;
+use cgmath::prelude::*;
+use gl::types::*;
+
+type Mat4 = cgmath::Matrix4<f32>;
+type Vec3 = cgmath::Vector3<f32>;
+type Vec4 = cgmath::Vector4<f32>;
+
+// Sky behind the scene and the environment reflected by materials. Both
+// read one cube map, loaded from DDS files or baked from a procedural
+// gradient sky. The skybox is drawn after the opaque geometry on the far
+// plane, so only pixels no mesh covered run the fragment shader.
+
+// Face size of baked procedural skies
+const PROCEDURAL_SIZE: i32 = 128;
+
+#[derive(Debug, Clone, Copy)]
+pub struct ProceduralSky {
+    pub zenith: Vec3,
+    pub horizon: Vec3,
+    pub ground: Vec3,
+    // Direction the sunlight travels in, like DirLight::direction
+    pub sun_direction: Vec3,
+    pub sun_color: Vec3,
+    // Angular radius of the sun disc
+    pub sun_size: cgmath::Rad<f32>,
+}
+
+impl Default for ProceduralSky {
+    fn default() -> Self {
+        Self {
+            zenith: Vec3::new(0.15, 0.35, 0.75),
+            horizon: Vec3::new(0.7, 0.8, 0.9),
+            ground: Vec3::new(0.25, 0.22, 0.2),
+            sun_direction: Vec3::new(1.0, -1.0, 0.0),
+            sun_color: Vec3::new(20.0, 18.0, 15.0),
+            sun_size: cgmath::Deg(1.0).into(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct SkyConfig {
+    // Disabled leaves the clear color behind the scene and turns off
+    // reflections
+    pub enabled: bool,
+    // Scale of the skybox color
+    pub intensity: f32,
+    // Scale of the environment reflected by the specular map
+    pub reflection_strength: f32,
+}
+
+impl Default for SkyConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            intensity: 1.0,
+            reflection_strength: 0.15,
+        }
+    }
+}
+
+pub struct SkyPrograms {
+    pub skybox: ShaderProgram,
+    pub procedural: ShaderProgram,
+}
+
+pub struct Sky {
+    programs: SkyPrograms,
+    // Fullscreen triangles are generated from gl_VertexID
+    empty_vao: GLuint,
+    // Procedural skies are baked through it face by face
+    framebuffer: GLuint,
+    environment: Cubemap,
+    // None when the environment was loaded from a file
+    procedural: Option<ProceduralSky>,
+    pub config: SkyConfig,
+}
+
+fn draw_fullscreen(vao: GLuint) {
+    unsafe {
+        gl::BindVertexArray(vao);
+        gl::DrawArrays(gl::TRIANGLES, 0, 3);
+    }
+}
+
+fn upload_vec3(location: GLint, v: Vec3) {
+    unsafe {
+        gl::Uniform3f(location, v.x, v.y, v.z);
+    }
+}
+
+#[allow(dead_code)]
+impl Sky {
+    // Starts with the default procedural sky
+    pub fn new(programs: SkyPrograms) -> Self {
+        let mut empty_vao = 0;
+        let mut framebuffer = 0;
+
+        unsafe {
+            gl::CreateVertexArrays(1, &mut empty_vao);
+            gl::CreateFramebuffers(1, &mut framebuffer);
+            // Filtering across faces hides the seams of small mip levels
+            gl::Enable(gl::TEXTURE_CUBE_MAP_SEAMLESS);
+        }
+
+        let mut sky = Self {
+            programs: programs,
+            empty_vao: empty_vao,
+            framebuffer: framebuffer,
+            environment: Cubemap::new_renderable(PROCEDURAL_SIZE, post::HDR_FORMAT),
+            // Renderable already, baked below
+            procedural: Some(ProceduralSky::default()),
+            config: SkyConfig::default(),
+        };
+
+        sky.set_procedural(ProceduralSky::default());
+        sky
+    }
+
+    pub fn environment(&self) -> &Cubemap {
+        &self.environment
+    }
+
+    pub fn procedural(&self) -> Option<&ProceduralSky> {
+        self.procedural.as_ref()
+    }
+
+    pub fn set_cubemap(&mut self, cubemap: Cubemap) {
+        self.environment = cubemap;
+        self.procedural = None;
+    }
+
+    // Bakes the sky into a new environment cube map
+    pub fn set_procedural(&mut self, sky: ProceduralSky) {
+        let mut viewport = [0 as GLint; 4];
+        let mut framebuffer: GLint = 0;
+
+        unsafe {
+            gl::GetIntegerv(gl::VIEWPORT, viewport.as_mut_ptr());
+            gl::GetIntegerv(gl::DRAW_FRAMEBUFFER_BINDING, &mut framebuffer);
+        }
+
+        // Cube maps from files are compressed and can not be rendered to
+        if self.procedural.is_none() {
+            self.environment = Cubemap::new_renderable(PROCEDURAL_SIZE, post::HDR_FORMAT);
+        }
+
+        let faces = shadow::cube_face_view_projs(Vec3::zero(), 10.0);
+        self.programs.procedural.set_active();
+
+        upload_vec3(gpu::attrs::SKY_ZENITH_COLOR_LOCATION, sky.zenith);
+        upload_vec3(gpu::attrs::SKY_HORIZON_COLOR_LOCATION, sky.horizon);
+        upload_vec3(gpu::attrs::SKY_GROUND_COLOR_LOCATION, sky.ground);
+        upload_vec3(gpu::attrs::SKY_SUN_DIRECTION_LOCATION, sky.sun_direction);
+        upload_vec3(gpu::attrs::SKY_SUN_COLOR_LOCATION, sky.sun_color);
+
+        unsafe {
+            gl::Uniform1f(gpu::attrs::SKY_INTENSITY_LOCATION, 1.0);
+            gl::Uniform1f(
+                gpu::attrs::SKY_SUN_COS_RADIUS_LOCATION,
+                sky.sun_size.0.cos(),
+            );
+            gl::BindFramebuffer(gl::FRAMEBUFFER, self.framebuffer);
+            gl::Viewport(0, 0, self.environment.size, self.environment.size);
+            gl::Disable(gl::DEPTH_TEST);
+        }
+
+        for (face, view_proj) in faces.iter().enumerate() {
+            let inverse = view_proj.invert().unwrap_or_else(Mat4::identity);
+
+            unsafe {
+                gl::NamedFramebufferTextureLayer(
+                    self.framebuffer,
+                    gl::COLOR_ATTACHMENT0,
+                    self.environment.id,
+                    0,
+                    face as GLint,
+                );
+                gl::UniformMatrix4fv(
+                    gpu::attrs::SKY_INV_VIEW_PROJ_LOCATION,
+                    1,
+                    gl::FALSE,
+                    inverse.as_ptr(),
+                );
+            }
+
+            draw_fullscreen(self.empty_vao);
+        }
+
+        unsafe {
+            gl::GenerateTextureMipmap(self.environment.id);
+            gl::Enable(gl::DEPTH_TEST);
+            gl::BindFramebuffer(gl::FRAMEBUFFER, framebuffer as GLuint);
+            gl::Viewport(viewport[0], viewport[1], viewport[2], viewport[3]);
+        }
+
+        self.procedural = Some(sky);
+    }
+
+    // Draws the environment where the depth buffer is still cleared, call
+    // after the opaque geometry. Changes the active program.
+    pub fn draw(&self, view: &Mat4, projection: &Mat4) {
+        if !self.config.enabled {
+            return;
+        }
+
+        // The sky is infinitely far away, the camera position does not move it
+        let mut rotation = *view;
+        rotation.w = Vec4::unit_w();
+        let inverse = (projection * rotation)
+            .invert()
+            .unwrap_or_else(Mat4::identity);
+
+        self.programs.skybox.set_active();
+
+        unsafe {
+            gl::UniformMatrix4fv(
+                gpu::attrs::SKY_INV_VIEW_PROJ_LOCATION,
+                1,
+                gl::FALSE,
+                inverse.as_ptr(),
+            );
+            gl::BindTextureUnit(gpu::attrs::ENVIRONMENT_TEXTURE_UNIT, self.environment.id);
+            gl::Uniform1i(
+                gpu::attrs::SKY_ENVIRONMENT_SAMPLER_LOCATION,
+                gpu::attrs::ENVIRONMENT_TEXTURE_UNIT as GLint,
+            );
+            gl::Uniform1f(gpu::attrs::SKY_INTENSITY_LOCATION, self.config.intensity);
+
+            // The triangle lies on the far plane where the cleared depth is
+            gl::DepthFunc(gl::LEQUAL);
+            gl::DepthMask(gl::FALSE);
+        }
+
+        draw_fullscreen(self.empty_vao);
+
+        unsafe {
+            gl::DepthMask(gl::TRUE);
+            gl::DepthFunc(gl::LESS);
+        }
+    }
+
+    // Scale of the reflections of the main program, 0 with the sky disabled
+    pub fn reflection_strength(&self) -> f32 {
+        if self.config.enabled {
+            self.config.reflection_strength
+        } else {
+            0.0
+        }
+    }
+}
+
+impl Drop for Sky {
+    fn drop(&mut self) {
+        unsafe {
+            gl::DeleteVertexArrays(1, &self.empty_vao);
+            gl::DeleteFramebuffers(1, &self.framebuffer);
+        }
+    }
+}
@@ -0,0 +1,669 @@
+use crate::core::app;
+use crate::core::geometry::Aabb;
+use crate::core::pipeline::mgl::attr::mesh3d::{IndexedMesh, VertexAttributes};
+use crate::resource::BufferLoaderError;
+use cgmath::prelude::*;
+use std::path::Path;
+
+type Vec2 = cgmath::Vector2<f32>;
+type Vec3 = cgmath::Vector3<f32>;
+
+// Terrain is generated from a 16-bit heightmap and split into square chunks.
+// Every chunk keeps its full resolution vertices, only the index buffer changes
+// with the level of detail. Edges bordering a coarser chunk snap their vertices
+// onto the coarser grid so neighbouring chunks never leave cracks.
+
+#[derive(Debug)]
+#[allow(dead_code)]
+pub enum TerrainError {
+    InvalidHeightmap(String),
+    InvalidConfig(String),
+    FailedLoadingResource(BufferLoaderError),
+}
+
+impl_error_conv!(BufferLoaderError, TerrainError, FailedLoadingResource);
+
+pub struct Heightmap {
+    width: usize,
+    height: usize,
+    samples: Vec<u16>,
+}
+
+#[allow(dead_code)]
+impl Heightmap {
+    // Headerless little-endian 16-bit samples (.r16/.raw)
+    pub fn from_r16_bytes(bytes: &[u8], width: usize, height: usize) -> Result<Self, TerrainError> {
+        if width < 2 || height < 2 {
+            return Err(TerrainError::InvalidHeightmap(format!(
+                "Heightmap needs at least 2x2 samples, got {}x{}",
+                width, height
+            )));
+        }
+
+        if bytes.len() != width * height * 2 {
+            return Err(TerrainError::InvalidHeightmap(format!(
+                "Expected {} bytes for {}x{} 16-bit heightmap, got {}",
+                width * height * 2,
+                width,
+                height,
+                bytes.len()
+            )));
+        }
+
+        Ok(Self {
+            width: width,
+            height: height,
+            samples: bytes
+                .chunks_exact(2)
+                .map(|b| u16::from_le_bytes([b[0], b[1]]))
+                .collect(),
+        })
+    }
+
+    // Binary PGM (P5) with a maximum value above 255, samples are big-endian
+    pub fn from_pgm16_bytes(bytes: &[u8]) -> Result<Self, TerrainError> {
+        let invalid = |msg: &str| Err(TerrainError::InvalidHeightmap(msg.to_owned()));
+
+        let mut fields: Vec<usize> = vec![];
+        let mut pos = 0;
+
+        if bytes.len() < 2 || &bytes[0..2] != b"P5" {
+            return invalid("PGM heightmap must start with P5");
+        }
+        pos += 2;
+
+        // width, height and maxval separated by whitespace and optional comments
+        while fields.len() < 3 {
+            while pos < bytes.len() && bytes[pos].is_ascii_whitespace() {
+                pos += 1;
+            }
+            if pos < bytes.len() && bytes[pos] == b'#' {
+                while pos < bytes.len() && bytes[pos] != b'\n' {
+                    pos += 1;
+                }
+                continue;
+            }
+            let start = pos;
+            while pos < bytes.len() && bytes[pos].is_ascii_digit() {
+                pos += 1;
+            }
+            if start == pos {
+                return invalid("Malformed PGM header");
+            }
+            let field = std::str::from_utf8(&bytes[start..pos])
+                .ok()
+                .and_then(|text| text.parse().ok());
+            match field {
+                Some(value) => fields.push(value),
+                None => return invalid("PGM header value out of range"),
+            }
+        }
+
+        // single whitespace byte separates header from data
+        pos += 1;
+
+        let (width, height, maxval) = (fields[0], fields[1], fields[2]);
+        if maxval < 256 || maxval > 65535 {
+            return invalid("PGM heightmap must be 16-bit (maxval above 255)");
+        }
+
+        let data_len = match width.checked_mul(height).and_then(|n| n.checked_mul(2)) {
+            Some(len) => len,
+            None => return invalid("PGM heightmap dimensions are too large"),
+        };
+
+        let data = &bytes[pos.min(bytes.len())..];
+        if data.len() < data_len {
+            return invalid("PGM heightmap data is truncated");
+        }
+
+        let le: Vec<u8> = data[..data_len]
+            .chunks_exact(2)
+            .flat_map(|b| vec![b[1], b[0]])
+            .collect();
+
+        Self::from_r16_bytes(&le, width, height)
+    }
+
+    pub fn load_r16<P: AsRef<Path>>(
+        app: &app::AppCore,
+        p: P,
+        width: usize,
+        height: usize,
+    ) -> Result<Self, TerrainError> {
+        Self::from_r16_bytes(&app.buffer_loader.load_bytes(p.as_ref())?, width, height)
+    }
+
+    pub fn load_pgm16<P: AsRef<Path>>(app: &app::AppCore, p: P) -> Result<Self, TerrainError> {
+        Self::from_pgm16_bytes(&app.buffer_loader.load_bytes(p.as_ref())?)
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    // Normalized sample, coordinates outside of the map are clamped to the border
+    pub fn sample(&self, x: isize, z: isize) -> f32 {
+        let x = x.max(0).min(self.width as isize - 1) as usize;
+        let z = z.max(0).min(self.height as isize - 1) as usize;
+        self.samples[z * self.width + x] as f32 / std::u16::MAX as f32
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct TerrainConfig {
+    // World units between two heightmap samples
+    pub cell_size: f32,
+    // World height of a sample with the maximum value
+    pub height_scale: f32,
+    // Cells along one side of a chunk, must be a power of two
+    pub chunk_cells: usize,
+    pub lod_count: usize,
+    // Distance at which chunks switch from LOD 0 to LOD 1, doubled for every next level
+    pub lod_distance: f32,
+}
+
+impl Default for TerrainConfig {
+    fn default() -> Self {
+        Self {
+            cell_size: 1.0,
+            height_scale: 64.0,
+            chunk_cells: 32,
+            lod_count: 4,
+            lod_distance: 48.0,
+        }
+    }
+}
+
+// Grid step of the neighbouring chunks in cells, ordered north (-z), south (+z),
+// west (-x) and east (+x). Zero means there is no neighbour on that side.
+type EdgeSteps = [usize; 4];
+
+const NORTH: usize = 0;
+const SOUTH: usize = 1;
+const WEST: usize = 2;
+const EAST: usize = 3;
+
+pub struct TerrainChunk {
+    // Full resolution vertices, indices match the current LOD
+    pub mesh: IndexedMesh,
+    pub bounds: Aabb,
+    pub lod: usize,
+    edge_steps: EdgeSteps,
+}
+
+pub struct Terrain {
+    heightmap: Heightmap,
+    config: TerrainConfig,
+    chunks_x: usize,
+    chunks_z: usize,
+    chunks: Vec<TerrainChunk>,
+}
+
+#[allow(dead_code)]
+impl Terrain {
+    pub fn new(heightmap: Heightmap, config: TerrainConfig) -> Result<Self, TerrainError> {
+        if !config.chunk_cells.is_power_of_two() || config.chunk_cells < 2 {
+            return Err(TerrainError::InvalidConfig(format!(
+                "Chunk size must be a power of two, got {}",
+                config.chunk_cells
+            )));
+        }
+
+        let mut config = config;
+        // Coarsest level draws a chunk as a single quad
+        let max_lods = config.chunk_cells.trailing_zeros() as usize + 1;
+        config.lod_count = config.lod_count.max(1).min(max_lods);
+
+        let cells = config.chunk_cells;
+        let chunks_x = (heightmap.width() - 1 + cells - 1) / cells;
+        let chunks_z = (heightmap.height() - 1 + cells - 1) / cells;
+
+        let mut terrain = Self {
+            heightmap: heightmap,
+            config: config,
+            chunks_x: chunks_x,
+            chunks_z: chunks_z,
+            chunks: Vec::with_capacity(chunks_x * chunks_z),
+        };
+
+        for cz in 0..chunks_z {
+            for cx in 0..chunks_x {
+                let mut mesh = terrain.build_chunk_vertices(cx, cz);
+                let bounds = Aabb::from_points(mesh.attributes.positions.iter());
+                let edge_steps = terrain.neighbour_steps_for(cx, cz, &|_, _| Some(0));
+                mesh.attributes.indices = chunk_indices(cells, 0, edge_steps);
+
+                terrain.chunks.push(TerrainChunk {
+                    mesh: mesh,
+                    bounds: bounds,
+                    lod: 0,
+                    edge_steps: edge_steps,
+                });
+            }
+        }
+
+        Ok(terrain)
+    }
+
+    pub fn config(&self) -> &TerrainConfig {
+        &self.config
+    }
+
+    pub fn chunks(&self) -> &[TerrainChunk] {
+        &self.chunks
+    }
+
+    pub fn chunk_grid(&self) -> (usize, usize) {
+        (self.chunks_x, self.chunks_z)
+    }
+
+    pub fn size(&self) -> Vec2 {
+        Vec2::new(
+            (self.heightmap.width() - 1) as f32 * self.config.cell_size,
+            (self.heightmap.height() - 1) as f32 * self.config.cell_size,
+        )
+    }
+
+    fn sample_height(&self, x: isize, z: isize) -> f32 {
+        self.heightmap.sample(x, z) * self.config.height_scale
+    }
+
+    // Normal of the heightfield at a sample computed with central differences
+    fn sample_normal(&self, x: isize, z: isize) -> Vec3 {
+        let dx = (self.sample_height(x + 1, z) - self.sample_height(x - 1, z))
+            / (2.0 * self.config.cell_size);
+        let dz = (self.sample_height(x, z + 1) - self.sample_height(x, z - 1))
+            / (2.0 * self.config.cell_size);
+        Vec3::new(-dx, 1.0, -dz).normalize()
+    }
+
+    // Chunks reaching past the heightmap border repeat the border samples
+    fn build_chunk_vertices(&self, cx: usize, cz: usize) -> IndexedMesh {
+        let cells = self.config.chunk_cells;
+        let side = cells + 1;
+        let cs = self.config.cell_size;
+        let uv_scale = Vec2::new(
+            1.0 / (self.heightmap.width() - 1) as f32,
+            1.0 / (self.heightmap.height() - 1) as f32,
+        );
+
+        let mut attrs = VertexAttributes {
+            indices: vec![],
+            positions: Vec::with_capacity(side * side),
+            normals: Vec::with_capacity(side * side),
+            uvs: Vec::with_capacity(side * side),
+            tangents: Vec::with_capacity(side * side),
+            bitangents: Vec::with_capacity(side * side),
+            colors: vec![],
+            uvs2: vec![],
+        };
+
+        for z in 0..side {
+            for x in 0..side {
+                let sx = (cx * cells + x) as isize;
+                let sz = (cz * cells + z) as isize;
+
+                let normal = self.sample_normal(sx, sz);
+                let dx =
+                    (self.sample_height(sx + 1, sz) - self.sample_height(sx - 1, sz)) / (2.0 * cs);
+                let dz =
+                    (self.sample_height(sx, sz + 1) - self.sample_height(sx, sz - 1)) / (2.0 * cs);
+
+                attrs.positions.push(Vec3::new(
+                    sx as f32 * cs,
+                    self.sample_height(sx, sz),
+                    sz as f32 * cs,
+                ));
+                attrs.normals.push(normal);
+                // Texture U runs along +x and V along +z
+                attrs
+                    .uvs
+                    .push(Vec2::new(sx as f32 * uv_scale.x, sz as f32 * uv_scale.y));
+                attrs.tangents.push(Vec3::new(1.0, dx, 0.0).normalize());
+                attrs.bitangents.push(Vec3::new(0.0, dz, 1.0).normalize());
+            }
+        }
+
+        IndexedMesh::new(attrs)
+    }
+
+    fn neighbour_steps_for(
+        &self,
+        cx: usize,
+        cz: usize,
+        step_of: &dyn Fn(usize, usize) -> Option<usize>,
+    ) -> EdgeSteps {
+        let lookup = |x: isize, z: isize| -> usize {
+            if x < 0 || z < 0 || x >= self.chunks_x as isize || z >= self.chunks_z as isize {
+                return 0;
+            }
+            step_of(x as usize, z as usize).map_or(0, |lod| 1 << lod)
+        };
+
+        let (x, z) = (cx as isize, cz as isize);
+        let mut steps = [0; 4];
+        steps[NORTH] = lookup(x, z - 1);
+        steps[SOUTH] = lookup(x, z + 1);
+        steps[WEST] = lookup(x - 1, z);
+        steps[EAST] = lookup(x + 1, z);
+        steps
+    }
+
+    fn lod_for_distance(&self, distance: f32) -> usize {
+        let mut lod = 0;
+        let mut limit = self.config.lod_distance;
+        while lod + 1 < self.config.lod_count && distance > limit {
+            lod += 1;
+            limit *= 2.0;
+        }
+        lod
+    }
+
+    // Selects LODs from the distance to the camera and regenerates the indices
+    // of every chunk whose LOD or neighbourhood changed. Returns indices of the
+    // chunks that need their index buffer re-uploaded.
+    pub fn update_lod(&mut self, camera: Vec3) -> Vec<usize> {
+        let lods: Vec<usize> = self
+            .chunks
+            .iter()
+            .map(|c| self.lod_for_distance(c.bounds.distance_squared_to_point(camera).sqrt()))
+            .collect();
+
+        let chunks_x = self.chunks_x;
+        let step_of = |x: usize, z: usize| Some(lods[z * chunks_x + x]);
+        let cells = self.config.chunk_cells;
+
+        let mut changed = vec![];
+        for i in 0..self.chunks.len() {
+            let edge_steps = self.neighbour_steps_for(i % chunks_x, i / chunks_x, &step_of);
+            let chunk = &mut self.chunks[i];
+
+            if chunk.lod != lods[i] || chunk.edge_steps != edge_steps {
+                chunk.lod = lods[i];
+                chunk.edge_steps = edge_steps;
+                chunk.mesh.attributes.indices = chunk_indices(cells, lods[i], edge_steps);
+                changed.push(i);
+            }
+        }
+
+        changed
+    }
+
+    // Bilinearly interpolated height at world x/z, None outside of the terrain
+    pub fn height_at(&self, x: f32, z: f32) -> Option<f32> {
+        let (fx, fz, sx, sz) = self.cell_coords(x, z)?;
+
+        let h00 = self.sample_height(sx, sz);
+        let h10 = self.sample_height(sx + 1, sz);
+        let h01 = self.sample_height(sx, sz + 1);
+        let h11 = self.sample_height(sx + 1, sz + 1);
+
+        let top = h00 + (h10 - h00) * fx;
+        let bottom = h01 + (h11 - h01) * fx;
+        Some(top + (bottom - top) * fz)
+    }
+
+    pub fn normal_at(&self, x: f32, z: f32) -> Option<Vec3> {
+        let (fx, fz, sx, sz) = self.cell_coords(x, z)?;
+
+        let n00 = self.sample_normal(sx, sz);
+        let n10 = self.sample_normal(sx + 1, sz);
+        let n01 = self.sample_normal(sx, sz + 1);
+        let n11 = self.sample_normal(sx + 1, sz + 1);
+
+        let top = n00.lerp(n10, fx);
+        let bottom = n01.lerp(n11, fx);
+        Some(top.lerp(bottom, fz).normalize())
+    }
+
+    fn cell_coords(&self, x: f32, z: f32) -> Option<(f32, f32, isize, isize)> {
+        let gx = x / self.config.cell_size;
+        let gz = z / self.config.cell_size;
+        let max_x = (self.heightmap.width() - 1) as f32;
+        let max_z = (self.heightmap.height() - 1) as f32;
+
+        if gx < 0.0 || gz < 0.0 || gx > max_x || gz > max_z {
+            return None;
+        }
+
+        let sx = gx.floor().min(max_x - 1.0);
+        let sz = gz.floor().min(max_z - 1.0);
+        Some((gx - sx, gz - sz, sx as isize, sz as isize))
+    }
+}
+
+// Builds the index buffer of a chunk grid with (cells + 1)^2 vertices at the
+// given LOD. Vertices on an edge whose neighbour uses a coarser step are snapped
+// down onto that step, producing degenerate triangles instead of T-junctions.
+fn chunk_indices(cells: usize, lod: usize, edge_steps: EdgeSteps) -> Vec<u32> {
+    let side = cells + 1;
+    let step = 1 << lod;
+
+    let snap = |x: usize, z: usize| -> u32 {
+        let mut x = x;
+        let mut z = z;
+
+        if z == 0 && edge_steps[NORTH] > step {
+            x -= x % edge_steps[NORTH];
+        } else if z == cells && edge_steps[SOUTH] > step {
+            x -= x % edge_steps[SOUTH];
+        }
+
+        if x == 0 && edge_steps[WEST] > step {
+            z -= z % edge_steps[WEST];
+        } else if x == cells && edge_steps[EAST] > step {
+            z -= z % edge_steps[EAST];
+        }
+
+        (z * side + x) as u32
+    };
+
+    let quads = cells / step;
+    let mut indices = Vec::with_capacity(quads * quads * 6);
+
+    for qz in 0..quads {
+        for qx in 0..quads {
+            let (x0, x1) = (qx * step, (qx + 1) * step);
+            let (z0, z1) = (qz * step, (qz + 1) * step);
+
+            indices.extend_from_slice(&[
+                snap(x0, z0),
+                snap(x0, z1),
+                snap(x1, z0),
+                snap(x1, z0),
+                snap(x0, z1),
+                snap(x1, z1),
+            ]);
+        }
+    }
+
+    indices
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeSet;
+
+    fn pgm(header: &str, samples: &[u16]) -> Vec<u8> {
+        let mut bytes = header.as_bytes().to_vec();
+        for s in samples {
+            bytes.extend_from_slice(&s.to_be_bytes());
+        }
+        bytes
+    }
+
+    fn invalid_heightmap(result: Result<Heightmap, TerrainError>) -> bool {
+        match result {
+            Err(TerrainError::InvalidHeightmap(_)) => true,
+            _ => false,
+        }
+    }
+
+    fn flat_heightmap(width: usize, height: usize) -> Heightmap {
+        Heightmap::from_r16_bytes(&vec![0; width * height * 2], width, height).unwrap()
+    }
+
+    #[test]
+    fn pgm_header_with_comments() {
+        let bytes = pgm(
+            "P5\n# exported heightmap\n2 3 # size\n65535\n",
+            &[0, 65535, 1, 2, 3, 32768],
+        );
+        let map = Heightmap::from_pgm16_bytes(&bytes).unwrap();
+
+        assert_eq!((map.width(), map.height()), (2, 3));
+        assert_eq!(map.sample(0, 0), 0.0);
+        assert_eq!(map.sample(1, 0), 1.0);
+        assert_eq!(map.sample(1, 2), 32768.0 / 65535.0);
+        // Clamped to the border
+        assert_eq!(map.sample(5, -3), 1.0);
+    }
+
+    #[test]
+    fn malformed_pgm_headers_are_rejected() {
+        let samples = [0u16; 4];
+
+        assert!(invalid_heightmap(Heightmap::from_pgm16_bytes(b"")));
+        assert!(invalid_heightmap(Heightmap::from_pgm16_bytes(&pgm(
+            "P2\n2 2\n65535\n",
+            &samples
+        ))));
+        assert!(invalid_heightmap(Heightmap::from_pgm16_bytes(&pgm(
+            "P5\n2 x\n65535\n",
+            &samples
+        ))));
+        assert!(invalid_heightmap(Heightmap::from_pgm16_bytes(b"P5\n2 2")));
+        // 8-bit maps are not supported
+        assert!(invalid_heightmap(Heightmap::from_pgm16_bytes(&pgm(
+            "P5\n2 2\n255\n",
+            &samples
+        ))));
+        assert!(invalid_heightmap(Heightmap::from_pgm16_bytes(&pgm(
+            "P5\n99999999999999999999999 2\n65535\n",
+            &samples
+        ))));
+        assert!(invalid_heightmap(Heightmap::from_pgm16_bytes(&pgm(
+            "P5\n4294967296 4294967296\n65535\n",
+            &samples
+        ))));
+        assert!(invalid_heightmap(Heightmap::from_pgm16_bytes(&pgm(
+            "P5\n2 2\n65535\n",
+            &samples[..3]
+        ))));
+        // Too small to build a terrain grid from
+        assert!(invalid_heightmap(Heightmap::from_pgm16_bytes(&pgm(
+            "P5\n1 1\n65535\n",
+            &samples[..1]
+        ))));
+    }
+
+    #[test]
+    fn height_at_interpolates_up_to_the_edges() {
+        let samples: Vec<u8> = [0u16, 100, 200, 300]
+            .iter()
+            .flat_map(|s| s.to_le_bytes().to_vec())
+            .collect();
+        let map = Heightmap::from_r16_bytes(&samples, 2, 2).unwrap();
+        let config = TerrainConfig {
+            cell_size: 2.0,
+            height_scale: std::u16::MAX as f32,
+            chunk_cells: 2,
+            ..TerrainConfig::default()
+        };
+        let terrain = Terrain::new(map, config).unwrap();
+        let close = |a: Option<f32>, b: f32| (a.unwrap() - b).abs() < 1e-3;
+
+        assert!(close(terrain.height_at(0.0, 0.0), 0.0));
+        assert!(close(terrain.height_at(2.0, 0.0), 100.0));
+        assert!(close(terrain.height_at(0.0, 2.0), 200.0));
+        // The far corner lies on the last cell, not past it
+        assert!(close(terrain.height_at(2.0, 2.0), 300.0));
+        assert!(close(terrain.height_at(1.0, 0.0), 50.0));
+        assert!(close(terrain.height_at(2.0, 1.0), 200.0));
+        assert!(close(terrain.height_at(1.0, 1.0), 150.0));
+
+        assert!(terrain.height_at(-0.01, 1.0).is_none());
+        assert!(terrain.height_at(1.0, 2.01).is_none());
+    }
+
+    // Vertices along one edge of a chunk used by non-degenerate triangles,
+    // as offsets along that edge
+    fn edge_vertices(indices: &[u32], cells: usize, edge: usize) -> BTreeSet<usize> {
+        let side = cells + 1;
+        let mut result = BTreeSet::new();
+
+        for tri in indices.chunks_exact(3) {
+            if tri[0] == tri[1] || tri[1] == tri[2] || tri[0] == tri[2] {
+                continue;
+            }
+            for &i in tri {
+                let (x, z) = (i as usize % side, i as usize / side);
+                match edge {
+                    NORTH if z == 0 => result.insert(x),
+                    SOUTH if z == cells => result.insert(x),
+                    WEST if x == 0 => result.insert(z),
+                    EAST if x == cells => result.insert(z),
+                    _ => false,
+                };
+            }
+        }
+
+        result
+    }
+
+    #[test]
+    fn neighbouring_lods_share_edge_vertices() {
+        let cells = 4;
+        let config = TerrainConfig {
+            chunk_cells: cells,
+            lod_count: 3,
+            lod_distance: 3.0,
+            ..TerrainConfig::default()
+        };
+        let mut terrain = Terrain::new(flat_heightmap(13, 9), config).unwrap();
+        assert_eq!(terrain.chunk_grid(), (3, 2));
+
+        terrain.update_lod(Vec3::new(0.0, 0.0, 0.0));
+        let lods: Vec<usize> = terrain.chunks().iter().map(|c| c.lod).collect();
+        assert_eq!(lods, vec![0, 1, 2, 1, 1, 2]);
+
+        let chunks = terrain.chunks();
+        for z in 0..2 {
+            for x in 0..3 {
+                let chunk = &chunks[z * 3 + x].mesh.attributes.indices;
+                if x + 1 < 3 {
+                    let east = &chunks[z * 3 + x + 1].mesh.attributes.indices;
+                    assert_eq!(
+                        edge_vertices(chunk, cells, EAST),
+                        edge_vertices(east, cells, WEST)
+                    );
+                }
+                if z + 1 < 2 {
+                    let south = &chunks[(z + 1) * 3 + x].mesh.attributes.indices;
+                    assert_eq!(
+                        edge_vertices(chunk, cells, SOUTH),
+                        edge_vertices(south, cells, NORTH)
+                    );
+                }
+            }
+        }
+
+        // The finest chunk keeps its full resolution away from coarser edges
+        assert_eq!(
+            edge_vertices(&chunks[0].mesh.attributes.indices, cells, WEST).len(),
+            cells + 1
+        );
+    }
+
+    #[test]
+    fn coarsest_lod_is_a_single_quad() {
+        let indices = chunk_indices(8, 3, [0; 4]);
+        assert_eq!(indices, vec![0, 72, 8, 8, 72, 80]);
+    }
+}
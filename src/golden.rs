@@ -0,0 +1,966 @@
+use crate::core::app;
+use crate::core::pipeline::capture::{self, CaptureError};
+use crate::core::pipeline::light_info::{DirLight, Light, PointLight, SpotLight};
+use crate::core::pipeline::post::{Exposure, Tonemapper};
+use crate::core::pipeline::render_graph::{RenderGraphError, TransientPool};
+use crate::core::pipeline::Pipeline3D;
+use crate::helpers;
+use crate::helpers::mesh3d::TextureLoadError;
+use cgmath::prelude::SquareMatrix;
+use cgmath::Matrix;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+type Mat4 = cgmath::Matrix4<f32>;
+type Vec3 = cgmath::Vector3<f32>;
+type Point3 = cgmath::Point3<f32>;
+
+// Golden image tests. A scene file describes models, lights, camera and
+// pipeline settings, the scene is rendered headless for a fixed number of
+// frames with a fixed time step and the window is compared against a
+// reference image. Run them on a software driver (Mesa llvmpipe) so the
+// references do not depend on the GPU, see tests/golden/run.sh.
+//
+// Scene files have one directive per line, # starts a comment:
+//
+//   size <width> <height>
+//   frames <count>
+//   camera <eye x y z> <target x y z> <fov degrees>
+//   clear <r> <g> <b>
+//   model <obj> <diffuse dds> <specular dds> <normal dds> <x y z> <scale> [<yaw degrees>]
+//   pbr_model <obj> <albedo dds> <normal dds> <material dds> <x y z> <scale> [<yaw degrees>]
+//   dir_light <direction x y z> <intensity> [shadow]
+//   point_light <x y z> <intensity> <range> [shadow]
+//   spot_light <x y z> <direction x y z> <intensity> <range> [shadow]
+//   blinn on|off
+//   shadows on|off
+//   sky on|off
+//   tonemapper reinhard|aces|filmic
+//   exposure auto|<scale>
+//   tolerance channel <max difference 0-255> | perceptual <threshold 0-1>
+//   max_failing <fraction of pixels allowed to fail>
+//   reference <png, relative to the scene file>
+
+// Failed runs write the actual and diff images here
+pub const OUTPUT_DIR: &str = "target/golden";
+
+// Fixed time step of every rendered frame
+const FRAME_TIME: f32 = 1.0 / 60.0;
+
+// Largest YIQ distance between two colors, red and cyan
+const MAX_YIQ_DELTA: f32 = 35215.0;
+
+#[derive(Debug)]
+pub enum GoldenError {
+    Io(PathBuf, std::io::Error),
+    Parse {
+        file: PathBuf,
+        line: usize,
+        message: String,
+    },
+    Capture(CaptureError),
+    Render(RenderGraphError),
+    Texture(TextureLoadError),
+    MissingReference(PathBuf),
+    SizeMismatch {
+        expected: (u32, u32),
+        actual: (u32, u32),
+    },
+}
+
+impl std::fmt::Display for GoldenError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io(path, e) => write!(f, "I/O error on {}: {}", path.display(), e),
+            Self::Parse {
+                file,
+                line,
+                message,
+            } => write!(f, "{}:{}: {}", file.display(), line, message),
+            Self::Capture(e) => write!(f, "{}", e),
+            Self::Render(e) => write!(f, "Failed rendering scene: {:?}", e),
+            Self::Texture(e) => write!(f, "Failed loading texture: {:?}", e),
+            Self::MissingReference(path) => write!(
+                f,
+                "Reference image {} does not exist, create it with --update-golden",
+                path.display()
+            ),
+            Self::SizeMismatch { expected, actual } => write!(
+                f,
+                "Reference is {}x{} but the scene renders {}x{}",
+                expected.0, expected.1, actual.0, actual.1
+            ),
+        }
+    }
+}
+
+impl From<CaptureError> for GoldenError {
+    fn from(e: CaptureError) -> Self {
+        Self::Capture(e)
+    }
+}
+
+impl From<RenderGraphError> for GoldenError {
+    fn from(e: RenderGraphError) -> Self {
+        Self::Render(e)
+    }
+}
+
+impl From<TextureLoadError> for GoldenError {
+    fn from(e: TextureLoadError) -> Self {
+        Self::Texture(e)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Metric {
+    // Largest allowed difference of any channel
+    PerChannel(u8),
+    // Threshold of the YIQ color distance, 0 is exact and 1 allows anything
+    Perceptual(f32),
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Tolerance {
+    pub metric: Metric,
+    // Fraction of pixels that may exceed the metric
+    pub max_failing: f32,
+}
+
+impl Default for Tolerance {
+    fn default() -> Self {
+        Self {
+            metric: Metric::Perceptual(0.1),
+            max_failing: 0.001,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum SceneMaterial {
+    NormalMapped {
+        diffuse: PathBuf,
+        specular: PathBuf,
+        normal: PathBuf,
+    },
+    Pbr {
+        albedo: PathBuf,
+        normal: PathBuf,
+        material: PathBuf,
+    },
+}
+
+#[derive(Debug, Clone)]
+pub struct SceneModel {
+    pub obj: PathBuf,
+    pub material: SceneMaterial,
+    pub position: Vec3,
+    pub scale: f32,
+    pub yaw: cgmath::Deg<f32>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Scene {
+    pub size: (u32, u32),
+    pub frames: u32,
+    pub eye: Point3,
+    pub target: Point3,
+    pub fov: cgmath::Deg<f32>,
+    pub clear_color: [f32; 4],
+    pub models: Vec<SceneModel>,
+    pub lights: Vec<Light>,
+    pub blinn: bool,
+    pub shadows: bool,
+    // The clear color stays visible unless enabled
+    pub sky: bool,
+    pub tonemapper: Option<Tonemapper>,
+    pub exposure: Option<Exposure>,
+    pub tolerance: Tolerance,
+    pub reference: PathBuf,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Comparison {
+    pub failing_pixels: usize,
+    pub total_pixels: usize,
+    // Largest difference of any channel
+    pub max_channel_delta: u8,
+}
+
+impl Comparison {
+    pub fn failing_fraction(&self) -> f32 {
+        self.failing_pixels as f32 / self.total_pixels.max(1) as f32
+    }
+
+    pub fn passes(&self, tolerance: &Tolerance) -> bool {
+        self.failing_fraction() <= tolerance.max_failing
+    }
+}
+
+struct Parser<'a> {
+    file: &'a Path,
+    line: usize,
+    words: std::str::SplitWhitespace<'a>,
+}
+
+impl<'a> Parser<'a> {
+    fn error(&self, message: String) -> GoldenError {
+        GoldenError::Parse {
+            file: self.file.to_path_buf(),
+            line: self.line,
+            message: message,
+        }
+    }
+
+    fn word(&mut self, what: &str) -> Result<&'a str, GoldenError> {
+        match self.words.next() {
+            Some(w) => Ok(w),
+            None => Err(self.error(format!("Expected {}", what))),
+        }
+    }
+
+    fn parse<T: std::str::FromStr>(&mut self, what: &str) -> Result<T, GoldenError> {
+        let word = self.word(what)?;
+        word.parse()
+            .map_err(|_| self.error(format!("Invalid {}: {}", what, word)))
+    }
+
+    fn vec3(&mut self, what: &str) -> Result<Vec3, GoldenError> {
+        Ok(Vec3::new(
+            self.parse(what)?,
+            self.parse(what)?,
+            self.parse(what)?,
+        ))
+    }
+
+    fn switch(&mut self, what: &str) -> Result<bool, GoldenError> {
+        match self.word(what)? {
+            "on" => Ok(true),
+            "off" => Ok(false),
+            w => Err(self.error(format!("Expected on or off for {}: {}", what, w))),
+        }
+    }
+
+    // Optional trailing "shadow" flag of lights
+    fn casts_shadow(&mut self) -> Result<bool, GoldenError> {
+        match self.words.next() {
+            None => Ok(false),
+            Some("shadow") => Ok(true),
+            Some(w) => Err(self.error(format!("Unexpected {}", w))),
+        }
+    }
+
+    fn finish(&mut self) -> Result<(), GoldenError> {
+        match self.words.next() {
+            None => Ok(()),
+            Some(w) => Err(self.error(format!("Unexpected {}", w))),
+        }
+    }
+}
+
+#[allow(dead_code)]
+impl Scene {
+    pub fn load(path: &Path) -> Result<Self, GoldenError> {
+        let source =
+            fs::read_to_string(path).map_err(|e| GoldenError::Io(path.to_path_buf(), e))?;
+        let dir = path.parent().unwrap_or(Path::new("."));
+
+        let mut scene = Self {
+            size: (320, 240),
+            frames: 1,
+            eye: Point3::new(0.0, 0.0, 5.0),
+            target: Point3::new(0.0, 0.0, 0.0),
+            fov: cgmath::Deg(60.0),
+            clear_color: [0.0, 0.0, 0.0, 1.0],
+            models: vec![],
+            lights: vec![],
+            blinn: false,
+            shadows: true,
+            sky: false,
+            tonemapper: None,
+            exposure: None,
+            tolerance: Tolerance::default(),
+            reference: path.with_extension("png"),
+        };
+
+        for (i, line) in source.lines().enumerate() {
+            let line = line.split('#').next().unwrap();
+            let mut p = Parser {
+                file: path,
+                line: i + 1,
+                words: line.split_whitespace(),
+            };
+
+            let directive = match p.words.next() {
+                Some(d) => d,
+                None => continue,
+            };
+
+            match directive {
+                "size" => {
+                    scene.size = (p.parse("width")?, p.parse("height")?);
+
+                    if scene.size.0 == 0 || scene.size.1 == 0 {
+                        return Err(p.error("Size must not be zero".to_owned()));
+                    }
+                }
+                "frames" => {
+                    scene.frames = p.parse::<u32>("frame count")?.max(1);
+                }
+                "camera" => {
+                    let eye = p.vec3("eye")?;
+                    let target = p.vec3("target")?;
+                    scene.eye = Point3::new(eye.x, eye.y, eye.z);
+                    scene.target = Point3::new(target.x, target.y, target.z);
+                    scene.fov = cgmath::Deg(p.parse("fov")?);
+                }
+                "clear" => {
+                    let c = p.vec3("clear color")?;
+                    scene.clear_color = [c.x, c.y, c.z, 1.0];
+                }
+                "model" | "pbr_model" => {
+                    let obj = PathBuf::from(p.word("obj path")?);
+                    let material = if directive == "model" {
+                        SceneMaterial::NormalMapped {
+                            diffuse: PathBuf::from(p.word("diffuse map")?),
+                            specular: PathBuf::from(p.word("specular map")?),
+                            normal: PathBuf::from(p.word("normal map")?),
+                        }
+                    } else {
+                        SceneMaterial::Pbr {
+                            albedo: PathBuf::from(p.word("albedo map")?),
+                            normal: PathBuf::from(p.word("normal map")?),
+                            material: PathBuf::from(p.word("material map")?),
+                        }
+                    };
+                    let position = p.vec3("position")?;
+                    let scale = p.parse("scale")?;
+                    let yaw = match p.words.next() {
+                        Some(w) => w
+                            .parse()
+                            .map_err(|_| p.error(format!("Invalid yaw: {}", w)))?,
+                        None => 0.0,
+                    };
+
+                    scene.models.push(SceneModel {
+                        obj: obj,
+                        material: material,
+                        position: position,
+                        scale: scale,
+                        yaw: cgmath::Deg(yaw),
+                    });
+                }
+                "dir_light" => {
+                    let direction = p.vec3("direction")?;
+                    let intensity = p.parse("intensity")?;
+                    scene.lights.push(Light::Directional(DirLight {
+                        direction: direction,
+                        intensity: intensity,
+                        casts_shadow: p.casts_shadow()?,
+                        ..DirLight::default()
+                    }));
+                }
+                "point_light" => {
+                    let position = p.vec3("position")?;
+                    let intensity = p.parse("intensity")?;
+                    let range = p.parse("range")?;
+                    scene.lights.push(Light::Point(PointLight {
+                        position: position,
+                        intensity: intensity,
+                        range: range,
+                        casts_shadow: p.casts_shadow()?,
+                        ..PointLight::default()
+                    }));
+                }
+                "spot_light" => {
+                    let position = p.vec3("position")?;
+                    let direction = p.vec3("direction")?;
+                    let intensity = p.parse("intensity")?;
+                    let range = p.parse("range")?;
+                    scene.lights.push(Light::Spot(SpotLight {
+                        position: position,
+                        direction: direction,
+                        intensity: intensity,
+                        range: range,
+                        casts_shadow: p.casts_shadow()?,
+                        ..SpotLight::default()
+                    }));
+                }
+                "blinn" => scene.blinn = p.switch("blinn")?,
+                "shadows" => scene.shadows = p.switch("shadows")?,
+                "sky" => scene.sky = p.switch("sky")?,
+                "tonemapper" => {
+                    scene.tonemapper = Some(match p.word("tonemapper")? {
+                        "reinhard" => Tonemapper::Reinhard,
+                        "aces" => Tonemapper::Aces,
+                        "filmic" => Tonemapper::Filmic,
+                        w => return Err(p.error(format!("Unknown tonemapper: {}", w))),
+                    });
+                }
+                "exposure" => {
+                    scene.exposure = Some(match p.word("exposure")? {
+                        "auto" => Exposure::Auto,
+                        w => Exposure::Manual(
+                            w.parse()
+                                .map_err(|_| p.error(format!("Invalid exposure: {}", w)))?,
+                        ),
+                    });
+                }
+                "tolerance" => {
+                    scene.tolerance.metric = match p.word("metric")? {
+                        "channel" => Metric::PerChannel(p.parse("channel difference")?),
+                        "perceptual" => Metric::Perceptual(p.parse("threshold")?),
+                        w => return Err(p.error(format!("Unknown metric: {}", w))),
+                    };
+                }
+                "max_failing" => {
+                    scene.tolerance.max_failing = p.parse("fraction")?;
+                }
+                "reference" => {
+                    scene.reference = dir.join(p.word("reference path")?);
+                }
+                d => return Err(p.error(format!("Unknown directive: {}", d))),
+            }
+
+            p.finish()?;
+        }
+
+        Ok(scene)
+    }
+
+    // Name used for the files written on failure
+    pub fn name(&self) -> String {
+        self.reference
+            .file_stem()
+            .map_or("golden".to_owned(), |s| s.to_string_lossy().into_owned())
+    }
+}
+
+fn yiq(p: &[u8]) -> (f32, f32, f32) {
+    let (r, g, b) = (p[0] as f32, p[1] as f32, p[2] as f32);
+    (
+        r * 0.298_895_31 + g * 0.586_622_47 + b * 0.114_482_23,
+        r * 0.595_977_99 - g * 0.274_176_10 - b * 0.321_801_89,
+        r * 0.211_470_17 - g * 0.522_617_11 + b * 0.311_146_94,
+    )
+}
+
+// Squared YIQ distance weighted by how sensitive the eye is to each axis,
+// from "Measuring perceived color difference using YIQ NTSC transmission
+// color space in mobile applications" (Kotsarenko, Ramos)
+fn yiq_delta(a: &[u8], b: &[u8]) -> f32 {
+    let (ya, ia, qa) = yiq(a);
+    let (yb, ib, qb) = yiq(b);
+    let (y, i, q) = (ya - yb, ia - ib, qa - qb);
+    0.5053 * y * y + 0.299 * i * i + 0.1957 * q * q
+}
+
+// Compares two RGBA8 images of the same size and builds a diff image, the
+// reference is shown faded with failing pixels in red and pixels that
+// differ within the tolerance in yellow
+pub fn compare(actual: &[u8], reference: &[u8], metric: Metric) -> (Comparison, Vec<u8>) {
+    let mut diff = Vec::with_capacity(reference.len());
+    let mut result = Comparison {
+        failing_pixels: 0,
+        total_pixels: reference.len() / 4,
+        max_channel_delta: 0,
+    };
+
+    for (a, r) in actual.chunks(4).zip(reference.chunks(4)) {
+        let channel_delta = a
+            .iter()
+            .zip(r.iter())
+            .map(|(a, r)| (*a as i16 - *r as i16).abs() as u8)
+            .max()
+            .unwrap_or(0);
+        result.max_channel_delta = result.max_channel_delta.max(channel_delta);
+
+        let failing = match metric {
+            Metric::PerChannel(max) => channel_delta > max,
+            // Alpha is ignored, the window is always opaque
+            Metric::Perceptual(threshold) => {
+                yiq_delta(a, r) > MAX_YIQ_DELTA * threshold * threshold
+            }
+        };
+
+        if failing {
+            result.failing_pixels += 1;
+            diff.extend_from_slice(&[255, 0, 0, 255]);
+        } else if channel_delta > 0 {
+            diff.extend_from_slice(&[255, 255, 0, 255]);
+        } else {
+            let faded = (255.0 - (255.0 - yiq(r).0) * 0.1) as u8;
+            diff.extend_from_slice(&[faded, faded, faded, 255]);
+        }
+    }
+
+    (result, diff)
+}
+
+fn load_scene(app: &app::AppCore, p3d: &mut Pipeline3D, scene: &Scene) -> Result<(), GoldenError> {
+    let mut meshes = vec![];
+    let mut pbr_meshes = vec![];
+
+    for m in scene.models.iter() {
+        let mesh = helpers::mesh3d::load_obj(app, &m.obj).pop().unwrap();
+
+        match &m.material {
+            SceneMaterial::NormalMapped {
+                diffuse,
+                specular,
+                normal,
+            } => {
+                let light_maps = helpers::mesh3d::load_dds_normal_mapped_lightmaps(
+                    app, diffuse, specular, normal,
+                );
+                meshes.push((light_maps, mesh));
+            }
+            SceneMaterial::Pbr {
+                albedo,
+                normal,
+                material,
+            } => {
+                let light_maps =
+                    helpers::mesh3d::load_dds_pbr_lightmaps(app, albedo, normal, material)?;
+                pbr_meshes.push((light_maps, mesh));
+            }
+        }
+    }
+
+    let data: Vec<_> = meshes.iter().map(|(lm, mesh)| (lm, mesh)).collect();
+    let mut ids = p3d.prepare_normal_mapped_textured_meshes(&data).into_iter();
+    let data: Vec<_> = pbr_meshes.iter().map(|(lm, mesh)| (lm, mesh)).collect();
+    let mut pbr_ids = p3d.prepare_pbr_meshes(&data).into_iter();
+
+    for m in scene.models.iter() {
+        let id = match m.material {
+            SceneMaterial::NormalMapped { .. } => ids.next().unwrap(),
+            SceneMaterial::Pbr { .. } => pbr_ids.next().unwrap(),
+        };
+
+        let model_mat = Mat4::from_translation(m.position)
+            * Mat4::from_angle_y(m.yaw)
+            * Mat4::from_scale(m.scale);
+        p3d.update_model_matrix(id.clone(), model_mat);
+        p3d.update_normal_matrix(id, model_mat.transpose().invert().unwrap());
+    }
+
+    for light in scene.lights.iter() {
+        p3d.add_light(light.clone());
+    }
+
+    p3d.set_blinn_enabled(scene.blinn);
+    p3d.set_shadows_enabled(scene.shadows);
+
+    let mut sky = *p3d.sky_config();
+    sky.enabled = scene.sky;
+    p3d.set_sky_config(sky);
+
+    let mut config = *p3d.post_config();
+
+    if let Some(tonemapper) = scene.tonemapper {
+        config.tonemapper = tonemapper;
+    }
+
+    if let Some(exposure) = scene.exposure {
+        config.exposure = exposure;
+    }
+
+    p3d.set_post_config(config);
+
+    let (width, height) = scene.size;
+    let aspect = width as f32 / height as f32;
+
+    p3d.update_view_pos(scene.eye);
+    p3d.update_view_matrix(Mat4::look_at_rh(
+        scene.eye,
+        scene.target,
+        Vec3::new(0.0, 1.0, 0.0),
+    ));
+    p3d.update_projection_matrix(cgmath::perspective(scene.fov, aspect, 0.1, 1000.0));
+
+    Ok(())
+}
+
+// Renders the scene into the window of app, which has to be created with
+// the size of the scene, and compares the last frame with the reference.
+// With update the reference is overwritten instead. Returns whether the
+// test passed.
+pub fn run(
+    app: &app::AppCore,
+    p3d: &mut Pipeline3D,
+    scene: &Scene,
+    update: bool,
+) -> Result<bool, GoldenError> {
+    load_scene(app, p3d, scene)?;
+
+    let (width, height) = scene.size;
+    let mut pool = TransientPool::new();
+
+    for _ in 0..scene.frames {
+        p3d.render_frame(&mut pool, scene.size, FRAME_TIME, scene.clear_color, false)?;
+    }
+
+    let actual = capture::read_framebuffer(width, height);
+
+    if update {
+        if let Some(dir) = scene.reference.parent() {
+            fs::create_dir_all(dir).map_err(|e| GoldenError::Io(dir.to_path_buf(), e))?;
+        }
+
+        capture::write_png(&scene.reference, width, height, &actual)?;
+        println!("Updated reference: {}", scene.reference.display());
+        return Ok(true);
+    }
+
+    if !scene.reference.exists() {
+        return Err(GoldenError::MissingReference(scene.reference.clone()));
+    }
+
+    let (ref_width, ref_height, reference) = capture::read_png(&scene.reference)?;
+
+    if (ref_width, ref_height) != scene.size {
+        return Err(GoldenError::SizeMismatch {
+            expected: (ref_width, ref_height),
+            actual: scene.size,
+        });
+    }
+
+    let (result, diff) = compare(&actual, &reference, scene.tolerance.metric);
+    let passed = result.passes(&scene.tolerance);
+
+    println!(
+        "{}: {} ({} of {} pixels failing, {:.4}% allowed, largest channel difference {})",
+        scene.name(),
+        if passed { "PASSED" } else { "FAILED" },
+        result.failing_pixels,
+        result.total_pixels,
+        scene.tolerance.max_failing * 100.0,
+        result.max_channel_delta
+    );
+
+    if !passed {
+        let dir = Path::new(OUTPUT_DIR);
+        fs::create_dir_all(dir).map_err(|e| GoldenError::Io(dir.to_path_buf(), e))?;
+
+        let actual_path = dir.join(format!("{}.actual.png", scene.name()));
+        let diff_path = dir.join(format!("{}.diff.png", scene.name()));
+        capture::write_png(&actual_path, width, height, &actual)?;
+        capture::write_png(&diff_path, width, height, &diff)?;
+
+        println!(
+            "Wrote {} and {}",
+            actual_path.display(),
+            diff_path.display()
+        );
+    }
+
+    Ok(passed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Writes the scene into its own temporary directory
+    fn scene_file(name: &str, source: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("darkest_golden_{}_{}", std::process::id(), name));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(format!("{}.scene", name));
+        fs::write(&path, source).unwrap();
+        path
+    }
+
+    fn load(name: &str, source: &str) -> Result<Scene, GoldenError> {
+        let path = scene_file(name, source);
+        let scene = Scene::load(&path);
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+        scene
+    }
+
+    // Line and message of a parse error
+    fn parse_error(name: &str, source: &str) -> (usize, String) {
+        match load(name, source) {
+            Err(GoldenError::Parse { line, message, .. }) => (line, message),
+            r => panic!("Expected a parse error, got {:?}", r),
+        }
+    }
+
+    fn failing(actual: &[u8], reference: &[u8], metric: Metric) -> usize {
+        compare(actual, reference, metric).0.failing_pixels
+    }
+
+    fn image(pixels: &[[u8; 4]]) -> Vec<u8> {
+        pixels.iter().flat_map(|p| p.iter().cloned()).collect()
+    }
+
+    #[test]
+    fn empty_scene_uses_defaults() {
+        let path = scene_file("defaults", "# nothing but a comment\n\n");
+        let scene = Scene::load(&path).unwrap();
+
+        assert_eq!(scene.size, (320, 240));
+        assert_eq!(scene.frames, 1);
+        assert_eq!(scene.eye, Point3::new(0.0, 0.0, 5.0));
+        assert_eq!(scene.clear_color, [0.0, 0.0, 0.0, 1.0]);
+        assert!(scene.models.is_empty());
+        assert!(scene.lights.is_empty());
+        assert!(!scene.blinn);
+        assert!(scene.shadows);
+        assert!(!scene.sky);
+        assert_eq!(scene.tonemapper, None);
+        assert_eq!(scene.exposure, None);
+        assert_eq!(scene.tolerance.metric, Metric::Perceptual(0.1));
+        assert_eq!(scene.tolerance.max_failing, 0.001);
+        assert_eq!(scene.reference, path.with_extension("png"));
+        assert_eq!(scene.name(), "defaults");
+
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn scene_directives() {
+        let path = scene_file(
+            "directives",
+            "size 64 32\n\
+             frames 0\n\
+             camera 1 2 3  0 1 0  45 # trailing comment\n\
+             clear 0.5 0.25 0\n\
+             model a.obj d.dds s.dds n.dds  1 0 0  0.5  30\n\
+             pbr_model b.obj a.dds n.dds m.dds  -1 0 0  2\n\
+             dir_light 1 -1 0  0.5 shadow\n\
+             point_light 0 3 2  4  25\n\
+             spot_light 0 4 1  0 -1 0  2  20 shadow\n\
+             blinn on\n\
+             shadows off\n\
+             sky on\n\
+             tonemapper filmic\n\
+             exposure 1.5\n\
+             tolerance channel 3\n\
+             max_failing 0.01\n\
+             reference refs/out.png\n",
+        );
+        let scene = Scene::load(&path).unwrap();
+
+        assert_eq!(scene.size, (64, 32));
+        // Zero frames would never render anything
+        assert_eq!(scene.frames, 1);
+        assert_eq!(scene.eye, Point3::new(1.0, 2.0, 3.0));
+        assert_eq!(scene.target, Point3::new(0.0, 1.0, 0.0));
+        assert_eq!(scene.fov, cgmath::Deg(45.0));
+        assert_eq!(scene.clear_color, [0.5, 0.25, 0.0, 1.0]);
+
+        assert_eq!(scene.models.len(), 2);
+        let m = &scene.models[0];
+        assert_eq!(m.obj, PathBuf::from("a.obj"));
+        assert_eq!(m.position, Vec3::new(1.0, 0.0, 0.0));
+        assert_eq!(m.scale, 0.5);
+        assert_eq!(m.yaw, cgmath::Deg(30.0));
+        match &m.material {
+            SceneMaterial::NormalMapped {
+                diffuse,
+                specular,
+                normal,
+            } => {
+                assert_eq!(diffuse, &PathBuf::from("d.dds"));
+                assert_eq!(specular, &PathBuf::from("s.dds"));
+                assert_eq!(normal, &PathBuf::from("n.dds"));
+            }
+            m => panic!("Expected a normal mapped material, got {:?}", m),
+        }
+
+        let m = &scene.models[1];
+        assert_eq!(m.scale, 2.0);
+        assert_eq!(m.yaw, cgmath::Deg(0.0));
+        match &m.material {
+            SceneMaterial::Pbr { material, .. } => {
+                assert_eq!(material, &PathBuf::from("m.dds"))
+            }
+            m => panic!("Expected a PBR material, got {:?}", m),
+        }
+
+        assert_eq!(scene.lights.len(), 3);
+        match &scene.lights[0] {
+            Light::Directional(d) => {
+                assert_eq!(d.direction, Vec3::new(1.0, -1.0, 0.0));
+                assert_eq!(d.intensity, 0.5);
+                assert!(d.casts_shadow);
+            }
+            l => panic!("Expected a directional light, got {:?}", l),
+        }
+        match &scene.lights[1] {
+            Light::Point(p) => {
+                assert_eq!(p.position, Vec3::new(0.0, 3.0, 2.0));
+                assert_eq!(p.intensity, 4.0);
+                assert_eq!(p.range, 25.0);
+                assert!(!p.casts_shadow);
+            }
+            l => panic!("Expected a point light, got {:?}", l),
+        }
+        match &scene.lights[2] {
+            Light::Spot(s) => {
+                assert_eq!(s.direction, Vec3::new(0.0, -1.0, 0.0));
+                assert_eq!(s.range, 20.0);
+                assert!(s.casts_shadow);
+            }
+            l => panic!("Expected a spot light, got {:?}", l),
+        }
+
+        assert!(scene.blinn);
+        assert!(!scene.shadows);
+        assert!(scene.sky);
+        assert_eq!(scene.tonemapper, Some(Tonemapper::Filmic));
+        assert_eq!(scene.exposure, Some(Exposure::Manual(1.5)));
+        assert_eq!(scene.tolerance.metric, Metric::PerChannel(3));
+        assert_eq!(scene.tolerance.max_failing, 0.01);
+        // References are relative to the scene file
+        assert_eq!(scene.reference, path.parent().unwrap().join("refs/out.png"));
+        assert_eq!(scene.name(), "out");
+
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn scene_errors() {
+        let (line, message) = parse_error("unknown", "size 4 4\n\nfog on\n");
+        assert_eq!(line, 3);
+        assert_eq!(message, "Unknown directive: fog");
+
+        let (line, message) = parse_error("missing", "camera 0 0 5  0 0\n");
+        assert_eq!(line, 1);
+        assert_eq!(message, "Expected target");
+
+        let (_, message) = parse_error("invalid", "size 4 tall\n");
+        assert_eq!(message, "Invalid height: tall");
+
+        let (_, message) = parse_error("zero", "size 0 4\n");
+        assert_eq!(message, "Size must not be zero");
+
+        let (_, message) = parse_error("trailing", "frames 2 3\n");
+        assert_eq!(message, "Unexpected 3");
+
+        let (_, message) = parse_error("flag", "point_light 0 0 0  1  5 shadows\n");
+        assert_eq!(message, "Unexpected shadows");
+
+        let (_, message) = parse_error("switch", "blinn yes\n");
+        assert_eq!(message, "Expected on or off for blinn: yes");
+
+        let (_, message) = parse_error("yaw", "model a b c d  0 0 0  1  left\n");
+        assert_eq!(message, "Invalid yaw: left");
+
+        let (_, message) = parse_error("tonemapper", "tonemapper linear\n");
+        assert_eq!(message, "Unknown tonemapper: linear");
+
+        let (_, message) = parse_error("exposure", "exposure bright\n");
+        assert_eq!(message, "Invalid exposure: bright");
+
+        let (_, message) = parse_error("metric", "tolerance exact 0\n");
+        assert_eq!(message, "Unknown metric: exact");
+
+        let (_, message) = parse_error("channel", "tolerance channel 256\n");
+        assert_eq!(message, "Invalid channel difference: 256");
+
+        let path = std::env::temp_dir().join("darkest_golden_missing.scene");
+        match Scene::load(&path) {
+            Err(GoldenError::Io(p, _)) => assert_eq!(p, path),
+            r => panic!("Expected an I/O error, got {:?}", r),
+        }
+    }
+
+    #[test]
+    fn identical_images_pass() {
+        let a = image(&[[0, 0, 0, 255], [255, 255, 255, 255], [10, 200, 30, 255]]);
+        let (result, diff) = compare(&a, &a, Metric::PerChannel(0));
+
+        assert_eq!(result.failing_pixels, 0);
+        assert_eq!(result.total_pixels, 3);
+        assert_eq!(result.max_channel_delta, 0);
+        assert!(result.passes(&Tolerance {
+            metric: Metric::PerChannel(0),
+            max_failing: 0.0,
+        }));
+
+        // Matching pixels show the reference faded towards white
+        assert_eq!(&diff[0..4], &[229, 229, 229, 255]);
+        assert_eq!(&diff[4..8], &[255, 255, 255, 255]);
+        assert_eq!(diff.len(), a.len());
+    }
+
+    #[test]
+    fn per_channel_tolerance() {
+        let reference = image(&[[100, 100, 100, 255]; 4]);
+        let actual = image(&[
+            [100, 100, 100, 255],
+            [102, 100, 100, 255],
+            [100, 97, 100, 255],
+            [100, 100, 110, 255],
+        ]);
+
+        let (result, diff) = compare(&actual, &reference, Metric::PerChannel(2));
+        assert_eq!(result.failing_pixels, 2);
+        assert_eq!(result.max_channel_delta, 10);
+        assert_eq!(result.failing_fraction(), 0.5);
+
+        // Within tolerance is yellow, failing is red
+        assert_eq!(&diff[4..8], &[255, 255, 0, 255]);
+        assert_eq!(&diff[8..12], &[255, 0, 0, 255]);
+        assert_eq!(&diff[12..16], &[255, 0, 0, 255]);
+
+        let (result, _) = compare(&actual, &reference, Metric::PerChannel(10));
+        assert_eq!(result.failing_pixels, 0);
+    }
+
+    #[test]
+    fn perceptual_tolerance() {
+        let red = image(&[[255, 0, 0, 255]]);
+        let cyan = image(&[[0, 255, 255, 255]]);
+
+        // Red and cyan are as far apart as colors get
+        assert!((yiq_delta(&red, &cyan) - MAX_YIQ_DELTA).abs() < 1.0);
+        assert_eq!(failing(&red, &cyan, Metric::Perceptual(0.99)), 1);
+        assert_eq!(failing(&red, &cyan, Metric::Perceptual(1.0)), 0);
+
+        // The eye is less sensitive to blue than to green
+        let gray = image(&[[128, 128, 128, 255]]);
+        let bluer = image(&[[128, 128, 148, 255]]);
+        let greener = image(&[[128, 148, 128, 255]]);
+        assert!(yiq_delta(&gray, &bluer) < yiq_delta(&gray, &greener));
+        assert_eq!(failing(&bluer, &gray, Metric::Perceptual(0.05)), 0);
+        assert_eq!(failing(&greener, &gray, Metric::Perceptual(0.05)), 1);
+
+        // Alpha does not matter
+        let transparent = image(&[[128, 128, 128, 0]]);
+        let (result, _) = compare(&transparent, &gray, Metric::Perceptual(0.0));
+        assert_eq!(result.failing_pixels, 0);
+        assert_eq!(result.max_channel_delta, 255);
+    }
+
+    #[test]
+    fn failing_fraction_against_max_failing() {
+        let reference = image(&[[0, 0, 0, 255]; 1000]);
+        let mut actual = reference.clone();
+
+        for i in 0..3 {
+            actual[i * 4 * 100] = 255;
+        }
+
+        let (result, _) = compare(&actual, &reference, Metric::PerChannel(0));
+        assert_eq!(result.failing_pixels, 3);
+        assert_eq!(result.total_pixels, 1000);
+
+        let tolerance = |max_failing| Tolerance {
+            metric: Metric::PerChannel(0),
+            max_failing: max_failing,
+        };
+        assert!(result.passes(&tolerance(0.003)));
+        assert!(!result.passes(&tolerance(0.002)));
+
+        let empty = Comparison {
+            failing_pixels: 0,
+            total_pixels: 0,
+            max_channel_delta: 0,
+        };
+        assert_eq!(empty.failing_fraction(), 0.0);
+    }
+}
@@ -2,12 +2,40 @@ use crate::core::pipeline::mgl::attr::mesh3d;
 use std::path::Path;
 
 use crate::core::app;
+use crate::core::pipeline::gpu;
 use crate::core::pipeline::mgl;
 use crate::core::pipeline::mgl::s3tc;
+use crate::resource::BufferLoaderError;
 
+type Vector4 = cgmath::Vector4<f32>;
 type Vector3 = cgmath::Vector3<f32>;
 type Vector2 = cgmath::Vector2<f32>;
 
+#[derive(Debug)]
+#[allow(dead_code)]
+pub enum TextureLoadError {
+    FailedLoadingResource(BufferLoaderError),
+    InvalidImage(s3tc::ImageError),
+}
+
+impl From<BufferLoaderError> for TextureLoadError {
+    fn from(e: BufferLoaderError) -> Self {
+        Self::FailedLoadingResource(e)
+    }
+}
+
+impl From<s3tc::ImageError> for TextureLoadError {
+    fn from(e: s3tc::ImageError) -> Self {
+        Self::InvalidImage(e)
+    }
+}
+
+fn load_dds<P: AsRef<Path>>(app: &app::AppCore, path: P) -> Result<s3tc::Image, TextureLoadError> {
+    Ok(s3tc::Image::from_dds_buffer(
+        app.buffer_loader.load_bytes(path.as_ref())?,
+    )?)
+}
+
 #[allow(dead_code)]
 pub fn create_plane() -> mgl::attr::mesh3d::IndexedMesh {
     mgl::attr::mesh3d::IndexedMesh {
@@ -38,6 +66,8 @@ pub fn create_plane() -> mgl::attr::mesh3d::IndexedMesh {
 
             tangents: vec![],
             bitangents: vec![],
+            colors: vec![],
+            uvs2: vec![],
         },
     }
 }
@@ -72,6 +102,8 @@ pub fn create_plane_with_tangents() -> mgl::attr::mesh3d::IndexedMesh {
 
             tangents: vec![],
             bitangents: vec![],
+            colors: vec![],
+            uvs2: vec![],
         },
     };
 
@@ -111,6 +143,45 @@ pub fn load_dds_normal_mapped_lightmaps<P: AsRef<Path>>(
     }
 }
 
+// The material map holds occlusion, roughness and metallic in R, G and B
+#[allow(dead_code)]
+pub fn load_dds_pbr_lightmaps<P: AsRef<Path>>(
+    app: &app::AppCore,
+    albedo: P,
+    norm: P,
+    material: P,
+) -> Result<mesh3d::lightmaps::Pbr, TextureLoadError> {
+    Ok(mgl::attr::mesh3d::lightmaps::Pbr {
+        albedo: load_dds(app, albedo)?,
+        normal: load_dds(app, norm)?,
+        material: load_dds(app, material)?,
+    })
+}
+
+// Single DDS file holding all six faces
+#[allow(dead_code)]
+pub fn load_dds_cubemap<P: AsRef<Path>>(
+    app: &app::AppCore,
+    path: P,
+) -> Result<gpu::textures::Cubemap, TextureLoadError> {
+    Ok(gpu::textures::Cubemap::from_dds(&load_dds(app, path)?)?)
+}
+
+// One DDS file per face in the order +X, -X, +Y, -Y, +Z, -Z
+#[allow(dead_code)]
+pub fn load_dds_cubemap_faces<P: AsRef<Path>>(
+    app: &app::AppCore,
+    faces: [P; 6],
+) -> Result<gpu::textures::Cubemap, TextureLoadError> {
+    let mut images = vec![];
+
+    for f in faces.iter() {
+        images.push(load_dds(app, f)?);
+    }
+
+    Ok(gpu::textures::Cubemap::from_faces(&images)?)
+}
+
 struct MakeVector3Iter<'a, I: Iterator<Item = &'a f32>> {
     iter: I,
 }
@@ -161,7 +232,12 @@ pub fn load_obj<P: AsRef<Path>>(app: &app::AppCore, p: P) -> Vec<mesh3d::Indexed
     let root = p.as_ref().parent();
     let (models, _materials) = tobj::load_obj_buf(
         &mut app.buffer_loader.prepare_buf_reader(p.as_ref()).unwrap(),
-        &tobj::LoadOptions::default(),
+        // Vertex attributes are uploaded as one stream with a single index
+        &tobj::LoadOptions {
+            single_index: true,
+            triangulate: true,
+            ..Default::default()
+        },
         |f| {
             let mut p = std::path::PathBuf::from(root.as_ref().unwrap().to_str().unwrap());
             p.push(f);
@@ -186,10 +262,15 @@ pub fn load_obj<P: AsRef<Path>>(app: &app::AppCore, p: P) -> Vec<mesh3d::Indexed
                         .collect(),
                     tangents: vec![],
                     bitangents: vec![],
+                    // OBJ vertex colors are RGB only
+                    colors: MakeVector3Iter::from(mesh.vertex_color.iter())
+                        .map(|c| Vector4::new(c.x, c.y, c.z, 1.0))
+                        .collect(),
+                    uvs2: vec![],
                 },
             };
 
-            im.generate_tangents();
+            im.generate_vertex_tangents();
             im
         })
         .collect()
@@ -0,0 +1,42 @@
+pub mod obj;
+pub mod ply;
+pub mod stl;
+
+use crate::core::pipeline::mgl::attr::mesh3d;
+use crate::resource::BufferLoaderError;
+
+type Vector2 = cgmath::Vector2<f32>;
+
+#[derive(Debug)]
+#[allow(dead_code)]
+pub enum MeshImportError {
+    InvalidData(String),
+    UnexpectedEof,
+    FailedLoadingResource(BufferLoaderError),
+}
+
+impl From<BufferLoaderError> for MeshImportError {
+    fn from(err: BufferLoaderError) -> Self {
+        MeshImportError::FailedLoadingResource(err)
+    }
+}
+
+type MeshImportResult<T> = Result<T, MeshImportError>;
+
+// Fills in attributes the source format did not provide so the mesh can be
+// uploaded like any OBJ mesh. Tangents need UVs and are left empty without them.
+fn complete_attributes(mut im: mesh3d::IndexedMesh, has_uvs: bool) -> mesh3d::IndexedMesh {
+    let vertex_count = im.attributes.positions.len();
+
+    if im.attributes.normals.len() != vertex_count {
+        im.generate_normals();
+    }
+
+    if has_uvs {
+        im.generate_vertex_tangents();
+    } else {
+        im.attributes.uvs = vec![Vector2::new(0.0, 0.0); vertex_count];
+    }
+
+    im
+}
@@ -0,0 +1,260 @@
+use crate::core::pipeline::mgl::attr::mesh3d;
+use std::fs::File;
+use std::io;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+// Wavefront OBJ/MTL writer so processed meshes can be inspected in external tools.
+// UVs are flipped back to the OBJ convention undone by load_obj.
+
+pub struct ObjMaterial {
+    pub name: String,
+    pub ambient: [f32; 3],
+    pub diffuse: [f32; 3],
+    pub specular: [f32; 3],
+    pub shininess: f32,
+    // Texture paths are written as given, relative to the .mtl file
+    pub diffuse_map: Option<String>,
+    pub specular_map: Option<String>,
+    pub normal_map: Option<String>,
+}
+
+impl Default for ObjMaterial {
+    fn default() -> Self {
+        Self {
+            name: "default".to_owned(),
+            ambient: [0.0, 0.0, 0.0],
+            diffuse: [0.8, 0.8, 0.8],
+            specular: [0.5, 0.5, 0.5],
+            shininess: 32.0,
+            diffuse_map: None,
+            specular_map: None,
+            normal_map: None,
+        }
+    }
+}
+
+pub fn write_obj<W: Write>(
+    out: &mut W,
+    mesh: &mesh3d::IndexedMesh,
+    object_name: &str,
+    mtl_lib: Option<&str>,
+    material: Option<&str>,
+) -> io::Result<()> {
+    let attrs = &mesh.attributes;
+    let vertex_count = attrs.positions.len();
+    let has_uvs = attrs.uvs.len() == vertex_count && vertex_count > 0;
+    let has_normals = attrs.normals.len() == vertex_count && vertex_count > 0;
+    // Vertex colors use the common "v x y z r g b" extension, alpha is dropped
+    let has_colors = attrs.colors.len() == vertex_count && vertex_count > 0;
+
+    writeln!(out, "# darkest mesh export")?;
+
+    if let Some(lib) = mtl_lib {
+        writeln!(out, "mtllib {}", lib)?;
+    }
+
+    writeln!(out, "o {}", object_name)?;
+
+    for (i, p) in attrs.positions.iter().enumerate() {
+        if has_colors {
+            let c = attrs.colors[i];
+            writeln!(out, "v {} {} {} {} {} {}", p.x, p.y, p.z, c.x, c.y, c.z)?;
+        } else {
+            writeln!(out, "v {} {} {}", p.x, p.y, p.z)?;
+        }
+    }
+
+    if has_uvs {
+        for uv in &attrs.uvs {
+            writeln!(out, "vt {} {}", uv.x, 1.0 - uv.y)?;
+        }
+    }
+
+    if has_normals {
+        for n in &attrs.normals {
+            writeln!(out, "vn {} {} {}", n.x, n.y, n.z)?;
+        }
+    }
+
+    if let Some(mat) = material {
+        writeln!(out, "usemtl {}", mat)?;
+    }
+
+    writeln!(out, "s off")?;
+
+    // OBJ indices are 1-based and every attribute shares the vertex index
+    for tri in attrs.indices.chunks_exact(3) {
+        write!(out, "f")?;
+        for i in tri {
+            let i = i + 1;
+            match (has_uvs, has_normals) {
+                (true, true) => write!(out, " {}/{}/{}", i, i, i)?,
+                (true, false) => write!(out, " {}/{}", i, i)?,
+                (false, true) => write!(out, " {}//{}", i, i)?,
+                (false, false) => write!(out, " {}", i)?,
+            }
+        }
+        writeln!(out)?;
+    }
+
+    Ok(())
+}
+
+pub fn write_mtl<W: Write>(out: &mut W, material: &ObjMaterial) -> io::Result<()> {
+    let [ar, ag, ab] = material.ambient;
+    let [dr, dg, db] = material.diffuse;
+    let [sr, sg, sb] = material.specular;
+
+    writeln!(out, "newmtl {}", material.name)?;
+    writeln!(out, "Ka {} {} {}", ar, ag, ab)?;
+    writeln!(out, "Kd {} {} {}", dr, dg, db)?;
+    writeln!(out, "Ks {} {} {}", sr, sg, sb)?;
+    writeln!(out, "Ns {}", material.shininess)?;
+    writeln!(out, "illum 2")?;
+
+    if let Some(map) = &material.diffuse_map {
+        writeln!(out, "map_Kd {}", map)?;
+    }
+    if let Some(map) = &material.specular_map {
+        writeln!(out, "map_Ks {}", map)?;
+    }
+    if let Some(map) = &material.normal_map {
+        writeln!(out, "map_Bump {}", map)?;
+    }
+
+    Ok(())
+}
+
+// Writes the mesh to the given .obj path, with a material the .mtl file is
+// written next to it using the same file stem.
+#[allow(dead_code)]
+pub fn save_obj<P: AsRef<Path>>(
+    path: P,
+    mesh: &mesh3d::IndexedMesh,
+    material: Option<&ObjMaterial>,
+) -> io::Result<()> {
+    let path = path.as_ref();
+    let stem = path
+        .file_stem()
+        .map_or("mesh".to_owned(), |s| s.to_string_lossy().into_owned());
+
+    let mtl_name = format!("{}.mtl", stem);
+
+    if let Some(mat) = material {
+        let mut mtl_out = BufWriter::new(File::create(path.with_file_name(&mtl_name))?);
+        write_mtl(&mut mtl_out, mat)?;
+        mtl_out.flush()?;
+    }
+
+    let mut out = BufWriter::new(File::create(path)?);
+    write_obj(
+        &mut out,
+        mesh,
+        &stem,
+        material.map(|_| mtl_name.as_str()),
+        material.map(|m| m.name.as_str()),
+    )?;
+    out.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    type Vector2 = cgmath::Vector2<f32>;
+    type Vector3 = cgmath::Vector3<f32>;
+    type Vector4 = cgmath::Vector4<f32>;
+
+    fn triangle_mesh() -> mesh3d::IndexedMesh {
+        mesh3d::IndexedMesh::new(mesh3d::VertexAttributes {
+            indices: vec![0, 1, 2, 2, 1, 3],
+            positions: vec![
+                Vector3::new(0.0, 0.0, 0.0),
+                Vector3::new(1.0, 0.0, 0.0),
+                Vector3::new(0.0, 1.0, 0.0),
+                Vector3::new(-1.5, 2.25, 8.0),
+            ],
+            normals: vec![Vector3::new(0.0, 0.0, 1.0); 4],
+            uvs: vec![
+                Vector2::new(0.0, 0.0),
+                Vector2::new(1.0, 0.25),
+                Vector2::new(0.5, 1.0),
+                Vector2::new(2.0, -1.0),
+            ],
+            tangents: vec![],
+            bitangents: vec![],
+            colors: vec![Vector4::new(1.0, 0.5, 0.25, 1.0); 4],
+            uvs2: vec![],
+        })
+    }
+
+    fn flat(v: &[Vector3]) -> Vec<f32> {
+        v.iter().flat_map(|v| vec![v.x, v.y, v.z]).collect()
+    }
+
+    #[test]
+    fn written_obj_reads_back() {
+        let mesh = triangle_mesh();
+        let mut out = vec![];
+        write_obj(&mut out, &mesh, "tri", Some("tri.mtl"), Some("stone")).unwrap();
+
+        let (models, _) = tobj::load_obj_buf(
+            &mut io::BufReader::new(&out[..]),
+            &tobj::LoadOptions::default(),
+            |_| Err(tobj::LoadError::OpenFileFailed),
+        )
+        .unwrap();
+
+        assert_eq!(models.len(), 1);
+        let read = &models[0].mesh;
+        let attrs = &mesh.attributes;
+
+        assert_eq!(models[0].name, "tri");
+        assert_eq!(read.indices, attrs.indices);
+        assert_eq!(read.positions, flat(&attrs.positions));
+        assert_eq!(read.normals, flat(&attrs.normals));
+        assert_eq!(read.vertex_color, vec![1.0, 0.5, 0.25].repeat(4));
+        // load_obj flips V back the same way
+        let uvs: Vec<f32> = read
+            .texcoords
+            .chunks_exact(2)
+            .flat_map(|uv| vec![uv[0], 1.0 - uv[1]])
+            .collect();
+        let expected: Vec<f32> = attrs.uvs.iter().flat_map(|uv| vec![uv.x, uv.y]).collect();
+        assert_eq!(uvs, expected);
+    }
+
+    #[test]
+    fn face_format_follows_available_attributes() {
+        let mut mesh = triangle_mesh();
+        mesh.attributes.uvs.clear();
+        mesh.attributes.colors.clear();
+
+        let mut out = vec![];
+        write_obj(&mut out, &mesh, "tri", None, None).unwrap();
+        let text = String::from_utf8(out).unwrap();
+
+        assert!(text.contains("f 1//1 2//2 3//3\n"));
+        assert!(text.contains("v 0 1 0\n"));
+        assert!(!text.contains("vt "));
+        assert!(!text.contains("mtllib"));
+    }
+
+    #[test]
+    fn material_lists_the_maps_it_has() {
+        let material = ObjMaterial {
+            name: "stone".to_owned(),
+            diffuse_map: Some("stone.png".to_owned()),
+            ..ObjMaterial::default()
+        };
+        let mut out = vec![];
+        write_mtl(&mut out, &material).unwrap();
+        let text = String::from_utf8(out).unwrap();
+
+        assert!(text.starts_with("newmtl stone\n"));
+        assert!(text.contains("map_Kd stone.png\n"));
+        assert!(!text.contains("map_Ks"));
+        assert!(!text.contains("map_Bump"));
+    }
+}
@@ -0,0 +1,617 @@
+use super::{complete_attributes, MeshImportError, MeshImportResult};
+use crate::core::app;
+use crate::core::pipeline::mgl::attr::mesh3d;
+use std::path::Path;
+
+type Vector3 = cgmath::Vector3<f32>;
+type Vector2 = cgmath::Vector2<f32>;
+type Vector4 = cgmath::Vector4<f32>;
+
+// Stanford PLY reader supporting ascii, binary_little_endian and
+// binary_big_endian encodings. Only the vertex and face elements are used,
+// any other element is parsed and skipped.
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Encoding {
+    Ascii,
+    BinaryLittleEndian,
+    BinaryBigEndian,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum ScalarType {
+    I8,
+    U8,
+    I16,
+    U16,
+    I32,
+    U32,
+    F32,
+    F64,
+}
+
+impl ScalarType {
+    fn parse(name: &str) -> MeshImportResult<Self> {
+        Ok(match name {
+            "char" | "int8" => Self::I8,
+            "uchar" | "uint8" => Self::U8,
+            "short" | "int16" => Self::I16,
+            "ushort" | "uint16" => Self::U16,
+            "int" | "int32" => Self::I32,
+            "uint" | "uint32" => Self::U32,
+            "float" | "float32" => Self::F32,
+            "double" | "float64" => Self::F64,
+            _ => {
+                return Err(MeshImportError::InvalidData(format!(
+                    "Unknown PLY property type: {}",
+                    name
+                )))
+            }
+        })
+    }
+
+    // Scale that maps integer color channels to [0,1]
+    fn color_scale(&self) -> f64 {
+        match self {
+            Self::U8 => 1.0 / 255.0,
+            Self::U16 => 1.0 / 65535.0,
+            _ => 1.0,
+        }
+    }
+
+    fn size(&self) -> usize {
+        match self {
+            Self::I8 | Self::U8 => 1,
+            Self::I16 | Self::U16 => 2,
+            Self::I32 | Self::U32 | Self::F32 => 4,
+            Self::F64 => 8,
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Property {
+    Scalar {
+        name: String,
+        ty: ScalarType,
+    },
+    List {
+        name: String,
+        count_ty: ScalarType,
+        item_ty: ScalarType,
+    },
+}
+
+impl Property {
+    fn name(&self) -> &str {
+        match self {
+            Self::Scalar { name, .. } => name,
+            Self::List { name, .. } => name,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Element {
+    name: String,
+    count: usize,
+    properties: Vec<Property>,
+}
+
+struct Header {
+    encoding: Encoding,
+    elements: Vec<Element>,
+    body_offset: usize,
+}
+
+fn parse_header(bytes: &[u8]) -> MeshImportResult<Header> {
+    const END: &[u8] = b"end_header";
+
+    let end = bytes
+        .windows(END.len())
+        .position(|w| w == END)
+        .ok_or_else(|| MeshImportError::InvalidData("PLY header is not terminated".to_owned()))?;
+
+    // Body starts after the line break following end_header
+    let mut body_offset = end + END.len();
+    while body_offset < bytes.len() && bytes[body_offset] != b'\n' {
+        body_offset += 1;
+    }
+    body_offset += 1;
+
+    let text = String::from_utf8_lossy(&bytes[..end]);
+    let mut lines = text.lines();
+
+    if lines.next().map(|l| l.trim()) != Some("ply") {
+        return Err(MeshImportError::InvalidData(
+            "File does not start with PLY magic".to_owned(),
+        ));
+    }
+
+    let mut encoding = None;
+    let mut elements: Vec<Element> = vec![];
+
+    for line in lines {
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        match tokens.as_slice() {
+            ["format", fmt, _version] => {
+                encoding = Some(match *fmt {
+                    "ascii" => Encoding::Ascii,
+                    "binary_little_endian" => Encoding::BinaryLittleEndian,
+                    "binary_big_endian" => Encoding::BinaryBigEndian,
+                    _ => {
+                        return Err(MeshImportError::InvalidData(format!(
+                            "Unknown PLY format: {}",
+                            fmt
+                        )))
+                    }
+                });
+            }
+            ["element", name, count] => {
+                elements.push(Element {
+                    name: name.to_string(),
+                    count: count.parse().map_err(|_| {
+                        MeshImportError::InvalidData(format!("Invalid element count: {}", count))
+                    })?,
+                    properties: vec![],
+                });
+            }
+            ["property", "list", count_ty, item_ty, name] => {
+                let element = elements.last_mut().ok_or_else(|| {
+                    MeshImportError::InvalidData("PLY property before element".to_owned())
+                })?;
+                element.properties.push(Property::List {
+                    name: name.to_string(),
+                    count_ty: ScalarType::parse(count_ty)?,
+                    item_ty: ScalarType::parse(item_ty)?,
+                });
+            }
+            ["property", ty, name] => {
+                let element = elements.last_mut().ok_or_else(|| {
+                    MeshImportError::InvalidData("PLY property before element".to_owned())
+                })?;
+                element.properties.push(Property::Scalar {
+                    name: name.to_string(),
+                    ty: ScalarType::parse(ty)?,
+                });
+            }
+            ["comment", ..] | ["obj_info", ..] | [] => {}
+            _ => {
+                return Err(MeshImportError::InvalidData(format!(
+                    "Unexpected PLY header line: {}",
+                    line
+                )))
+            }
+        }
+    }
+
+    Ok(Header {
+        encoding: encoding
+            .ok_or_else(|| MeshImportError::InvalidData("PLY format line missing".to_owned()))?,
+        elements: elements,
+        body_offset: body_offset,
+    })
+}
+
+trait ValueReader {
+    fn read(&mut self, ty: ScalarType) -> MeshImportResult<f64>;
+}
+
+struct AsciiReader<'a> {
+    tokens: std::str::SplitAsciiWhitespace<'a>,
+}
+
+impl<'a> ValueReader for AsciiReader<'a> {
+    fn read(&mut self, _ty: ScalarType) -> MeshImportResult<f64> {
+        let token = self.tokens.next().ok_or(MeshImportError::UnexpectedEof)?;
+        token
+            .parse::<f64>()
+            .map_err(|_| MeshImportError::InvalidData(format!("Invalid PLY value: {}", token)))
+    }
+}
+
+struct BinaryReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    big_endian: bool,
+}
+
+impl<'a> ValueReader for BinaryReader<'a> {
+    fn read(&mut self, ty: ScalarType) -> MeshImportResult<f64> {
+        let size = ty.size();
+        if self.pos + size > self.data.len() {
+            return Err(MeshImportError::UnexpectedEof);
+        }
+
+        let mut raw = [0u8; 8];
+        raw[..size].copy_from_slice(&self.data[self.pos..self.pos + size]);
+        if self.big_endian {
+            raw[..size].reverse();
+        }
+        self.pos += size;
+
+        let b2 = [raw[0], raw[1]];
+        let b4 = [raw[0], raw[1], raw[2], raw[3]];
+
+        Ok(match ty {
+            ScalarType::I8 => raw[0] as i8 as f64,
+            ScalarType::U8 => raw[0] as f64,
+            ScalarType::I16 => i16::from_le_bytes(b2) as f64,
+            ScalarType::U16 => u16::from_le_bytes(b2) as f64,
+            ScalarType::I32 => i32::from_le_bytes(b4) as f64,
+            ScalarType::U32 => u32::from_le_bytes(b4) as f64,
+            ScalarType::F32 => f32::from_le_bytes(b4) as f64,
+            ScalarType::F64 => f64::from_le_bytes(raw),
+        })
+    }
+}
+
+// Indices into a vertex record for the attributes we understand
+#[derive(Default)]
+struct VertexSlots {
+    position: [Option<usize>; 3],
+    normal: [Option<usize>; 3],
+    uv: [Option<usize>; 2],
+    color: [Option<usize>; 4],
+}
+
+impl VertexSlots {
+    fn from_element(element: &Element) -> Self {
+        let mut slots = Self::default();
+        for (i, p) in element.properties.iter().enumerate() {
+            match p.name() {
+                "x" => slots.position[0] = Some(i),
+                "y" => slots.position[1] = Some(i),
+                "z" => slots.position[2] = Some(i),
+                "nx" => slots.normal[0] = Some(i),
+                "ny" => slots.normal[1] = Some(i),
+                "nz" => slots.normal[2] = Some(i),
+                "u" | "s" | "texture_u" | "texture_s" => slots.uv[0] = Some(i),
+                "v" | "t" | "texture_v" | "texture_t" => slots.uv[1] = Some(i),
+                "red" | "r" => slots.color[0] = Some(i),
+                "green" | "g" => slots.color[1] = Some(i),
+                "blue" | "b" => slots.color[2] = Some(i),
+                "alpha" | "a" => slots.color[3] = Some(i),
+                _ => {}
+            }
+        }
+        slots
+    }
+}
+
+pub fn parse_ply(bytes: &[u8]) -> MeshImportResult<mesh3d::IndexedMesh> {
+    let header = parse_header(bytes)?;
+    let body = &bytes[header.body_offset.min(bytes.len())..];
+
+    let ascii_body;
+    let mut reader: Box<dyn ValueReader + '_> = match header.encoding {
+        Encoding::Ascii => {
+            ascii_body = String::from_utf8_lossy(body);
+            Box::new(AsciiReader {
+                tokens: ascii_body.split_ascii_whitespace(),
+            })
+        }
+        enc => Box::new(BinaryReader {
+            data: body,
+            pos: 0,
+            big_endian: enc == Encoding::BinaryBigEndian,
+        }),
+    };
+
+    let mut positions: Vec<Vector3> = vec![];
+    let mut normals: Vec<Vector3> = vec![];
+    let mut uvs: Vec<Vector2> = vec![];
+    let mut colors: Vec<Vector4> = vec![];
+    let mut indices: Vec<u32> = vec![];
+    let mut has_normals = false;
+    let mut has_uvs = false;
+    let mut has_colors = false;
+
+    for element in &header.elements {
+        let is_vertex = element.name == "vertex";
+        let is_face = element.name == "face";
+        let slots = VertexSlots::from_element(element);
+
+        if is_vertex {
+            if slots.position.iter().any(|s| s.is_none()) {
+                return Err(MeshImportError::InvalidData(
+                    "PLY vertex element lacks x, y or z".to_owned(),
+                ));
+            }
+            has_normals = slots.normal.iter().all(|s| s.is_some());
+            has_uvs = slots.uv.iter().all(|s| s.is_some());
+            has_colors = slots.color[..3].iter().all(|s| s.is_some());
+            // The count comes from the file, every vertex takes at least
+            // three bytes of the body so do not reserve more than that
+            positions.reserve(element.count.min(body.len() / 3));
+        }
+
+        // Nothing to read, a huge count would only spin
+        if element.properties.is_empty() {
+            continue;
+        }
+
+        let mut values: Vec<f64> = vec![0.0; element.properties.len()];
+        let mut face: Vec<u32> = vec![];
+
+        for _ in 0..element.count {
+            for (i, property) in element.properties.iter().enumerate() {
+                match property {
+                    Property::Scalar { ty, .. } => values[i] = reader.read(*ty)?,
+                    Property::List {
+                        name,
+                        count_ty,
+                        item_ty,
+                    } => {
+                        let count = reader.read(*count_ty)? as usize;
+                        let wanted =
+                            is_face && (name == "vertex_indices" || name == "vertex_index");
+                        face.clear();
+                        for _ in 0..count {
+                            let v = reader.read(*item_ty)?;
+                            if wanted {
+                                // A cast would clamp negative indices to 0
+                                if v < 0.0 {
+                                    return Err(MeshImportError::InvalidData(format!(
+                                        "Negative PLY face index: {}",
+                                        v
+                                    )));
+                                }
+                                face.push(v as u32);
+                            }
+                        }
+                        // Triangulate polygons as a fan around the first vertex
+                        for k in 1..face.len().saturating_sub(1) {
+                            indices.extend_from_slice(&[face[0], face[k], face[k + 1]]);
+                        }
+                    }
+                }
+            }
+
+            if is_vertex {
+                let get = |slot: Option<usize>| values[slot.unwrap()] as f32;
+                positions.push(Vector3::new(
+                    get(slots.position[0]),
+                    get(slots.position[1]),
+                    get(slots.position[2]),
+                ));
+                if has_normals {
+                    normals.push(Vector3::new(
+                        get(slots.normal[0]),
+                        get(slots.normal[1]),
+                        get(slots.normal[2]),
+                    ));
+                }
+                if has_uvs {
+                    // Flip V the same way load_obj does
+                    uvs.push(Vector2::new(get(slots.uv[0]), 1.0 - get(slots.uv[1])));
+                }
+                if has_colors {
+                    let channel = |slot: Option<usize>| match slot {
+                        Some(i) => match &element.properties[i] {
+                            Property::Scalar { ty, .. } => (values[i] * ty.color_scale()) as f32,
+                            Property::List { .. } => 1.0,
+                        },
+                        None => 1.0,
+                    };
+                    colors.push(Vector4::new(
+                        channel(slots.color[0]),
+                        channel(slots.color[1]),
+                        channel(slots.color[2]),
+                        channel(slots.color[3]),
+                    ));
+                }
+            }
+        }
+    }
+
+    if let Some(bad) = indices.iter().find(|i| **i as usize >= positions.len()) {
+        return Err(MeshImportError::InvalidData(format!(
+            "PLY face references vertex {} of {}",
+            bad,
+            positions.len()
+        )));
+    }
+
+    let im = mesh3d::IndexedMesh::new(mesh3d::VertexAttributes {
+        indices: indices,
+        positions: positions,
+        normals: normals,
+        uvs: uvs,
+        tangents: vec![],
+        bitangents: vec![],
+        colors: colors,
+        uvs2: vec![],
+    });
+
+    Ok(complete_attributes(im, has_uvs))
+}
+
+#[allow(dead_code)]
+pub fn load_ply<P: AsRef<Path>>(app: &app::AppCore, p: P) -> MeshImportResult<mesh3d::IndexedMesh> {
+    parse_ply(&app.buffer_loader.load_bytes(p.as_ref())?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const POSITIONS: [[f32; 3]; 4] = [
+        [0.0, 0.0, 0.0],
+        [1.5, 0.0, -2.0],
+        [1.5, 3.0, -2.0],
+        [0.0, 3.0, 0.25],
+    ];
+    const UVS: [[f32; 2]; 4] = [[0.0, 0.0], [1.0, 0.0], [1.0, 1.0], [0.0, 1.0]];
+
+    fn header(format: &str, vertex_count: usize) -> String {
+        format!(
+            "ply\nformat {} 1.0\ncomment test quad\n\
+             element vertex {}\nproperty float x\nproperty float y\nproperty float z\n\
+             property float u\nproperty float v\n\
+             property uchar red\nproperty uchar green\nproperty uchar blue\n\
+             element face 1\nproperty list uchar int vertex_indices\n\
+             element edge 1\nproperty int vertex1\nproperty int vertex2\n\
+             end_header\n",
+            format, vertex_count
+        )
+    }
+
+    fn ascii_quad() -> Vec<u8> {
+        let mut text = header("ascii", 4);
+        for (p, uv) in POSITIONS.iter().zip(UVS.iter()) {
+            text += &format!("{} {} {} {} {} 255 0 51\n", p[0], p[1], p[2], uv[0], uv[1]);
+        }
+        text += "4 0 1 2 3\n0 2\n";
+        text.into_bytes()
+    }
+
+    fn binary_quad(big_endian: bool) -> Vec<u8> {
+        let format = if big_endian {
+            "binary_big_endian"
+        } else {
+            "binary_little_endian"
+        };
+        let mut bytes = header(format, 4).into_bytes();
+        let f32_bytes = |v: f32| {
+            if big_endian {
+                v.to_be_bytes()
+            } else {
+                v.to_le_bytes()
+            }
+        };
+        let i32_bytes = |v: i32| {
+            if big_endian {
+                v.to_be_bytes()
+            } else {
+                v.to_le_bytes()
+            }
+        };
+
+        for (p, uv) in POSITIONS.iter().zip(UVS.iter()) {
+            for v in p.iter().chain(uv.iter()) {
+                bytes.extend_from_slice(&f32_bytes(*v));
+            }
+            bytes.extend_from_slice(&[255, 0, 51]);
+        }
+        bytes.push(4);
+        for i in 0..4 {
+            bytes.extend_from_slice(&i32_bytes(i));
+        }
+        bytes.extend_from_slice(&i32_bytes(0));
+        bytes.extend_from_slice(&i32_bytes(2));
+        bytes
+    }
+
+    fn assert_quad(im: &mesh3d::IndexedMesh) {
+        let attrs = &im.attributes;
+        let positions: Vec<[f32; 3]> = attrs.positions.iter().map(|p| [p.x, p.y, p.z]).collect();
+
+        assert_eq!(positions, POSITIONS.to_vec());
+        // The quad is split into a fan around its first vertex
+        assert_eq!(attrs.indices, vec![0, 1, 2, 0, 2, 3]);
+        assert_eq!(attrs.normals.len(), 4);
+        assert_eq!(attrs.tangents.len(), attrs.positions.len());
+        for (uv, expected) in attrs.uvs.iter().zip(UVS.iter()) {
+            assert_eq!([uv.x, uv.y], [expected[0], 1.0 - expected[1]]);
+        }
+        for c in &attrs.colors {
+            assert_eq!([c.x, c.y, c.z, c.w], [1.0, 0.0, 0.2, 1.0]);
+        }
+    }
+
+    fn is_eof(result: MeshImportResult<mesh3d::IndexedMesh>) -> bool {
+        match result {
+            Err(MeshImportError::UnexpectedEof) => true,
+            _ => false,
+        }
+    }
+
+    fn is_invalid(result: MeshImportResult<mesh3d::IndexedMesh>) -> bool {
+        match result {
+            Err(MeshImportError::InvalidData(_)) => true,
+            _ => false,
+        }
+    }
+
+    #[test]
+    fn every_encoding_reads_the_same_mesh() {
+        assert_quad(&parse_ply(&ascii_quad()).unwrap());
+        assert_quad(&parse_ply(&binary_quad(false)).unwrap());
+        assert_quad(&parse_ply(&binary_quad(true)).unwrap());
+    }
+
+    #[test]
+    fn missing_normals_are_generated() {
+        let im = parse_ply(&ascii_quad()).unwrap();
+        for n in &im.attributes.normals {
+            assert!((n.x * n.x + n.y * n.y + n.z * n.z - 1.0).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn truncated_bodies_are_rejected() {
+        let ascii = ascii_quad();
+        let binary = binary_quad(false);
+
+        assert!(is_eof(parse_ply(&ascii[..ascii.len() - 8])));
+        assert!(is_eof(parse_ply(&binary[..binary.len() - 1])));
+        assert!(is_eof(parse_ply(
+            header("binary_little_endian", 4).as_bytes()
+        )));
+    }
+
+    #[test]
+    fn malformed_headers_are_rejected() {
+        let valid = String::from_utf8(ascii_quad()).unwrap();
+        let cases = [
+            valid.replacen("ply", "plx", 1),
+            valid.replacen("end_header", "end_headr", 1),
+            valid.replacen("format ascii 1.0\n", "", 1),
+            valid.replacen("ascii", "binary_middle_endian", 1),
+            valid.replacen("property float z", "property quad z", 1),
+            valid.replacen("property float z", "property float w", 1),
+            valid.replacen("element face 1", "element face -1", 1),
+            valid.replacen("comment test quad", "property float stray\ncomment", 1),
+        ];
+
+        for case in cases.iter() {
+            assert!(is_invalid(parse_ply(case.as_bytes())), "{}", case);
+        }
+    }
+
+    #[test]
+    fn out_of_range_face_indices_are_rejected() {
+        let text = String::from_utf8(ascii_quad())
+            .unwrap()
+            .replacen("4 0 1 2 3", "3 0 1 9", 1);
+        assert!(is_invalid(parse_ply(text.as_bytes())));
+
+        let text = String::from_utf8(ascii_quad())
+            .unwrap()
+            .replacen("4 0 1 2 3", "3 0 -1 2", 1);
+        assert!(is_invalid(parse_ply(text.as_bytes())));
+    }
+
+    #[test]
+    fn huge_element_counts_fail_without_allocating() {
+        let text = "ply\nformat binary_little_endian 1.0\n\
+                    element vertex 4611686018427387903\n\
+                    property float x\nproperty float y\nproperty float z\n\
+                    element empty 18446744073709551615\nend_header\n\
+                    \x00\x00\x00\x00";
+        assert!(is_eof(parse_ply(text.as_bytes())));
+
+        let text = "ply\nformat ascii 1.0\nelement empty 18446744073709551615\n\
+                    element vertex 1\nproperty float x\nproperty float y\nproperty float z\n\
+                    end_header\n1 2 3\n";
+        assert_eq!(
+            parse_ply(text.as_bytes())
+                .unwrap()
+                .attributes
+                .positions
+                .len(),
+            1
+        );
+    }
+}
@@ -0,0 +1,273 @@
+use super::{complete_attributes, MeshImportError, MeshImportResult};
+use crate::core::app;
+use crate::core::pipeline::mgl::attr::mesh3d;
+use cgmath::prelude::*;
+use std::path::Path;
+
+type Vector3 = cgmath::Vector3<f32>;
+
+// STL reader for both ascii and binary files. STL stores independent facets so
+// every triangle gets its own three vertices carrying the facet normal.
+
+const BINARY_HEADER_SIZE: usize = 80;
+const BINARY_FACET_SIZE: usize = 50;
+
+fn facet_normal(stored: Vector3, v: &[Vector3; 3]) -> Vector3 {
+    // Many exporters write zero normals, derive them from the winding instead
+    if stored.magnitude2() > std::f32::EPSILON {
+        return stored.normalize();
+    }
+
+    let n = (v[1] - v[0]).cross(v[2] - v[0]);
+    if n.magnitude2() > 0.0 {
+        n.normalize()
+    } else {
+        Vector3::new(0.0, 0.0, 1.0)
+    }
+}
+
+fn push_facet(attrs: &mut mesh3d::VertexAttributes, normal: Vector3, v: [Vector3; 3]) {
+    let n = facet_normal(normal, &v);
+    let base = attrs.positions.len() as u32;
+
+    attrs.positions.extend_from_slice(&v);
+    attrs.normals.extend_from_slice(&[n, n, n]);
+    attrs.indices.extend_from_slice(&[base, base + 1, base + 2]);
+}
+
+fn empty_attributes() -> mesh3d::VertexAttributes {
+    mesh3d::VertexAttributes {
+        indices: vec![],
+        positions: vec![],
+        normals: vec![],
+        uvs: vec![],
+        tangents: vec![],
+        bitangents: vec![],
+        colors: vec![],
+        uvs2: vec![],
+    }
+}
+
+fn is_binary(bytes: &[u8]) -> bool {
+    if bytes.len() < BINARY_HEADER_SIZE + 4 {
+        return false;
+    }
+
+    let count = u32::from_le_bytes([bytes[80], bytes[81], bytes[82], bytes[83]]) as usize;
+
+    // Binary files may also start with "solid", the size is the reliable check
+    bytes.len() == BINARY_HEADER_SIZE + 4 + count * BINARY_FACET_SIZE
+}
+
+fn parse_binary(bytes: &[u8]) -> MeshImportResult<mesh3d::IndexedMesh> {
+    let count = u32::from_le_bytes([bytes[80], bytes[81], bytes[82], bytes[83]]) as usize;
+    let mut attrs = empty_attributes();
+
+    let read_vec = |offset: usize| {
+        let f = |o: usize| f32::from_le_bytes([bytes[o], bytes[o + 1], bytes[o + 2], bytes[o + 3]]);
+        Vector3::new(f(offset), f(offset + 4), f(offset + 8))
+    };
+
+    for i in 0..count {
+        let base = BINARY_HEADER_SIZE + 4 + i * BINARY_FACET_SIZE;
+        push_facet(
+            &mut attrs,
+            read_vec(base),
+            [
+                read_vec(base + 12),
+                read_vec(base + 24),
+                read_vec(base + 36),
+            ],
+        );
+    }
+
+    Ok(mesh3d::IndexedMesh::new(attrs))
+}
+
+fn next_vec(tokens: &mut std::str::SplitAsciiWhitespace) -> MeshImportResult<Vector3> {
+    let mut c = [0.0f32; 3];
+    for v in c.iter_mut() {
+        let t = tokens.next().ok_or(MeshImportError::UnexpectedEof)?;
+        *v = t
+            .parse()
+            .map_err(|_| MeshImportError::InvalidData(format!("Invalid STL number: {}", t)))?;
+    }
+    Ok(Vector3::new(c[0], c[1], c[2]))
+}
+
+fn parse_ascii(bytes: &[u8]) -> MeshImportResult<mesh3d::IndexedMesh> {
+    let text = String::from_utf8_lossy(bytes);
+    let mut tokens = text.split_ascii_whitespace();
+    let mut attrs = empty_attributes();
+
+    if tokens.next() != Some("solid") {
+        return Err(MeshImportError::InvalidData(
+            "ASCII STL must start with \"solid\"".to_owned(),
+        ));
+    }
+
+    let mut normal = Vector3::zero();
+    let mut verts: Vec<Vector3> = Vec::with_capacity(3);
+
+    while let Some(token) = tokens.next() {
+        match token {
+            "facet" => {
+                if tokens.next() != Some("normal") {
+                    return Err(MeshImportError::InvalidData(
+                        "Expected \"normal\" after \"facet\"".to_owned(),
+                    ));
+                }
+                normal = next_vec(&mut tokens)?;
+                verts.clear();
+            }
+            "vertex" => verts.push(next_vec(&mut tokens)?),
+            "endloop" => {
+                // Non-triangular loops are triangulated as a fan
+                for k in 1..verts.len().saturating_sub(1) {
+                    push_facet(&mut attrs, normal, [verts[0], verts[k], verts[k + 1]]);
+                }
+            }
+            "endsolid" => return Ok(mesh3d::IndexedMesh::new(attrs)),
+            _ => {}
+        }
+    }
+
+    // Also catches truncated binary files whose header starts with "solid"
+    Err(MeshImportError::UnexpectedEof)
+}
+
+pub fn parse_stl(bytes: &[u8]) -> MeshImportResult<mesh3d::IndexedMesh> {
+    let im = if is_binary(bytes) {
+        parse_binary(bytes)?
+    } else {
+        parse_ascii(bytes)?
+    };
+
+    Ok(complete_attributes(im, false))
+}
+
+#[allow(dead_code)]
+pub fn load_stl<P: AsRef<Path>>(app: &app::AppCore, p: P) -> MeshImportResult<mesh3d::IndexedMesh> {
+    parse_stl(&app.buffer_loader.load_bytes(p.as_ref())?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Two facets of a unit square, the second with a zero normal
+    const FACETS: [([f32; 3], [[f32; 3]; 3]); 2] = [
+        (
+            [0.0, 0.0, 1.0],
+            [[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [1.0, 1.0, 0.0]],
+        ),
+        (
+            [0.0, 0.0, 0.0],
+            [[0.0, 0.0, 0.0], [1.0, 1.0, 0.0], [0.0, 1.0, 0.0]],
+        ),
+    ];
+
+    fn ascii_stl() -> Vec<u8> {
+        let mut text = "solid square\n".to_owned();
+        for (n, v) in FACETS.iter() {
+            text += &format!("facet normal {} {} {}\nouter loop\n", n[0], n[1], n[2]);
+            for p in v.iter() {
+                text += &format!("vertex {} {} {}\n", p[0], p[1], p[2]);
+            }
+            text += "endloop\nendfacet\n";
+        }
+        text += "endsolid square\n";
+        text.into_bytes()
+    }
+
+    fn binary_stl() -> Vec<u8> {
+        // Binary headers starting with "solid" must not confuse detection
+        let mut bytes = b"solid exported by a careless tool".to_vec();
+        bytes.resize(BINARY_HEADER_SIZE, 0);
+        bytes.extend_from_slice(&(FACETS.len() as u32).to_le_bytes());
+
+        for (n, v) in FACETS.iter() {
+            for c in n.iter().chain(v.iter().flat_map(|p| p.iter())) {
+                bytes.extend_from_slice(&c.to_le_bytes());
+            }
+            bytes.extend_from_slice(&[0, 0]);
+        }
+        bytes
+    }
+
+    fn assert_square(im: &mesh3d::IndexedMesh) {
+        let attrs = &im.attributes;
+        let positions: Vec<[f32; 3]> = attrs.positions.iter().map(|p| [p.x, p.y, p.z]).collect();
+        let expected: Vec<[f32; 3]> = FACETS.iter().flat_map(|(_, v)| v.to_vec()).collect();
+
+        assert_eq!(positions, expected);
+        assert_eq!(attrs.indices, vec![0, 1, 2, 3, 4, 5]);
+        // The zero normal is derived from the winding
+        for n in &attrs.normals {
+            assert_eq!(*n, Vector3::new(0.0, 0.0, 1.0));
+        }
+        assert_eq!(attrs.uvs.len(), 6);
+    }
+
+    fn error_of(bytes: &[u8]) -> MeshImportError {
+        parse_stl(bytes).err().expect("malformed STL was accepted")
+    }
+
+    #[test]
+    fn ascii_and_binary_read_the_same_mesh() {
+        assert_square(&parse_stl(&ascii_stl()).unwrap());
+        assert_square(&parse_stl(&binary_stl()).unwrap());
+    }
+
+    #[test]
+    fn ascii_polygons_are_triangulated() {
+        let text = "solid quad\nfacet normal 0 0 1\nouter loop\n\
+                    vertex 0 0 0\nvertex 1 0 0\nvertex 1 1 0\nvertex 0 1 0\n\
+                    endloop\nendfacet\nendsolid quad\n";
+        let im = parse_stl(text.as_bytes()).unwrap();
+        assert_eq!(im.attributes.positions.len(), 6);
+    }
+
+    #[test]
+    fn truncated_and_malformed_files_are_rejected() {
+        let ascii = ascii_stl();
+        let binary = binary_stl();
+
+        // A truncated binary file no longer matches its facet count and is
+        // read as ascii because of its header
+        match error_of(&binary[..binary.len() - 10]) {
+            MeshImportError::UnexpectedEof => {}
+            err => panic!("{:?}", err),
+        }
+
+        let mut headerless = binary.clone();
+        headerless[..5].copy_from_slice(b"\0\0\0\0\0");
+        headerless.pop();
+        match error_of(&headerless) {
+            MeshImportError::InvalidData(_) => {}
+            err => panic!("{:?}", err),
+        }
+
+        match error_of(&ascii[..40]) {
+            MeshImportError::UnexpectedEof => {}
+            err => panic!("{:?}", err),
+        }
+
+        let text = String::from_utf8(ascii).unwrap();
+        match error_of(
+            text.replacen("vertex 1 0 0", "vertex 1 zero 0", 1)
+                .as_bytes(),
+        ) {
+            MeshImportError::InvalidData(_) => {}
+            err => panic!("{:?}", err),
+        }
+        match error_of(text.replacen("facet normal", "facet", 1).as_bytes()) {
+            MeshImportError::InvalidData(_) => {}
+            err => panic!("{:?}", err),
+        }
+        match error_of(b"") {
+            MeshImportError::InvalidData(_) => {}
+            err => panic!("{:?}", err),
+        }
+    }
+}
@@ -1 +1,2 @@
 pub mod mesh3d;
+pub mod mesh_io;
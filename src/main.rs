@@ -3,16 +3,21 @@ extern crate cgmath;
 extern crate cenum;
 
 mod core;
+mod golden;
 mod helpers;
 mod resource;
 
-use sdl2::event::Event;
+use sdl2::event::{Event, WindowEvent};
 use sdl2::keyboard::Keycode;
 use std::io;
 use std::path::PathBuf;
 
 pub use crate::core::app;
+use crate::core::pipeline::capture;
+use crate::core::pipeline::light_info::{DirLight, Light, PointLight, SpotLight};
 use crate::core::pipeline::mgl::s3tc;
+use crate::core::pipeline::post::Exposure;
+use crate::core::pipeline::render_graph::TransientPool;
 use crate::core::pipeline::Pipeline3D;
 
 use cgmath::prelude::*;
@@ -88,6 +93,11 @@ fn process_args() -> app::Arguments {
     let mut args = app::Arguments {
         game_dir: None,
         print_errors: app::ErrorGroups::NOTHING,
+        headless: false,
+        frames: None,
+        screenshot: None,
+        golden: None,
+        update_golden: false,
     };
 
     while let Some(arg) = cmd_args.next() {
@@ -106,6 +116,31 @@ fn process_args() -> app::Arguments {
             "--print-gl-errors" => {
                 args.print_errors.enable(app::ErrorGroups::GL_ERRORS);
             }
+            "--headless" => {
+                args.headless = true;
+            }
+            "--frames" => match cmd_args.next().map(|n| n.parse::<u32>()) {
+                Some(Ok(n)) if n > 0 => args.frames = Some(n),
+                _ => panic!("--frames requires a positive frame count!"),
+            },
+            "--screenshot" => {
+                if let Some(path) = cmd_args.next() {
+                    args.screenshot = Some(PathBuf::from(path));
+                } else {
+                    panic!("No path specified for screenshot!");
+                }
+            }
+            "--golden" => {
+                if let Some(path) = cmd_args.next() {
+                    args.golden = Some(PathBuf::from(path));
+                    args.headless = true;
+                } else {
+                    panic!("No scene specified for golden test!");
+                }
+            }
+            "--update-golden" => {
+                args.update_golden = true;
+            }
             _ => {
                 println!("Unexpected command line arguments: {}", arg);
             }
@@ -118,14 +153,26 @@ fn process_args() -> app::Arguments {
 fn main() -> io::Result<()> {
     let app_args = process_args();
 
+    let golden_scene = app_args.golden.as_ref().map(|path| {
+        golden::Scene::load(path).unwrap_or_else(|e| panic!("Invalid golden scene: {}", e))
+    });
+
     let app_cfg = app::AppConfig {
         args: app_args.clone(),
-        window_size: (1024, 768),
+        window_size: golden_scene.as_ref().map_or((1024, 768), |s| s.size),
         window_title: ("darkest v0.1.0".to_owned()),
     };
 
     let mut app = app::AppCore::init(app_cfg).unwrap();
 
+    // Headless runs render a single frame unless told otherwise
+    let frame_limit = match (app_args.frames, app_args.headless) {
+        (Some(n), _) => Some(n),
+        (None, true) => Some(1),
+        (None, false) => None,
+    };
+    let mut frame_count = 0;
+
     use crate::core::pipeline::InitError;
     let mut p3d = Pipeline3D::create_and_prepare(&app)
         .map_err(|e| match e {
@@ -143,6 +190,9 @@ fn main() -> io::Result<()> {
             InitError::ShaderIssue(issue) => {
                 panic!("Core shader issue: {}", issue)
             }
+            InitError::ShaderPreprocess(e) => {
+                panic!("Core shader source issue: {}", e)
+            }
             _ => {}
         })
         .unwrap();
@@ -162,6 +212,18 @@ fn main() -> io::Result<()> {
         }
     }
 
+    if let Some(scene) = golden_scene {
+        let code = match golden::run(&app, &mut p3d, &scene, app_args.update_golden) {
+            Ok(true) => 0,
+            Ok(false) => 1,
+            Err(e) => {
+                println!("{}: {}", scene.name(), e);
+                2
+            }
+        };
+        std::process::exit(code);
+    }
+
     let model_ids = {
         let cube = helpers::mesh3d::load_obj(&app, "./assets/cube.obj")
             .pop()
@@ -183,6 +245,15 @@ fn main() -> io::Result<()> {
         p3d.prepare_normal_mapped_textured_meshes(&[(&light_maps, &cube), (&light_maps, &susane)])
     };
 
+    p3d.add_light(Light::Directional(DirLight::default()));
+    let lamp_id = p3d.add_light(Light::Point(PointLight::default()));
+    p3d.add_light(Light::Spot(SpotLight {
+        position: Vec3::new(0.0, 4.0, 1.0),
+        direction: Vec3::new(0.0, -1.0, -0.25),
+        casts_shadow: true,
+        ..SpotLight::default()
+    }));
+
     let cube_id = model_ids[0].clone();
     let susane_id = model_ids[1].clone();
 
@@ -190,13 +261,19 @@ fn main() -> io::Result<()> {
 
     println!("MODEL_IDS = {:?}", model_ids);
 
+    let clear_color = [0.12, 0.0, 0.20, 1.0];
+
     unsafe {
-        gl::ClearColor(0.12, 0.0, 0.20, 1.0);
+        gl::ClearColor(clear_color[0], clear_color[1], clear_color[2], clear_color[3]);
     }
 
+    let mut window_size = app.sdl_window.size();
+    let mut graph_pool = TransientPool::new();
+    let mut dump_graph = false;
+
     let timer = std::time::Instant::now();
+    let mut last_frame = timer.elapsed();
 
-    let mut enable_blinn = false;
     let mut view_drag_enabled = false;
 
     let mut view_rotation = Vec3::new(0.0, 0.0, 0.0);
@@ -208,10 +285,18 @@ fn main() -> io::Result<()> {
         let delta_time = timer.elapsed();
 
         let mut view_drag_amount = Vec2::new(0.0, 0.0);
+        let mut take_screenshot = false;
+        let mut print_stats = false;
 
         for event in app.sdl_event_pump.poll_iter() {
             match event {
                 Event::Quit { .. } => break 'main_loop,
+                Event::Window {
+                    win_event: WindowEvent::SizeChanged(w, h),
+                    ..
+                } if w > 0 && h > 0 => {
+                    window_size = (w as u32, h as u32);
+                }
                 Event::KeyDown { keycode, .. } => {
                     if let Some(k) = keycode {
                         match k {
@@ -219,24 +304,75 @@ fn main() -> io::Result<()> {
                                 break 'main_loop;
                             }
                             Keycode::R => {}
+                            Keycode::F12 => {
+                                take_screenshot = true;
+                            }
+                            Keycode::G => {
+                                dump_graph = true;
+                            }
                             Keycode::B => {
-                                if enable_blinn {
-                                    unsafe {
-                                        gl::Uniform1i(
-                                            core::pipeline::gpu::attrs::USE_BLINN_FLAG,
-                                            0,
-                                        );
-                                    }
-                                    enable_blinn = false;
+                                let enabled = !p3d.blinn_enabled();
+                                p3d.set_blinn_enabled(enabled);
+                                println!("Blinn-Phong enabled: {}", enabled);
+                            }
+                            Keycode::C => {
+                                let enabled = !p3d.culling_enabled();
+                                p3d.set_culling_enabled(enabled);
+                                println!("Frustum culling enabled: {}", enabled);
+                            }
+                            Keycode::F => {
+                                print_stats = true;
+                            }
+                            Keycode::H => {
+                                let enabled = !p3d.shadows_enabled();
+                                p3d.set_shadows_enabled(enabled);
+                                println!("Shadows enabled: {}", enabled);
+                            }
+                            Keycode::K => {
+                                let mut config = *p3d.sky_config();
+                                config.enabled = !config.enabled;
+                                println!("Sky enabled: {}", config.enabled);
+                                p3d.set_sky_config(config);
+                            }
+                            Keycode::P => {
+                                if let Some(Light::Point(lamp)) = p3d.light_mut(lamp_id) {
+                                    lamp.casts_shadow = !lamp.casts_shadow;
+                                    println!("Lamp casts shadow: {}", lamp.casts_shadow);
+                                }
+                            }
+                            Keycode::T => {
+                                let mut config = *p3d.post_config();
+                                config.tonemapper = config.tonemapper.next();
+                                println!("Tonemapper: {:?}", config.tonemapper);
+                                p3d.set_post_config(config);
+                            }
+                            Keycode::X => {
+                                let mut config = *p3d.post_config();
+                                config.exposure = match config.exposure {
+                                    Exposure::Auto => Exposure::Manual(1.0),
+                                    Exposure::Manual(_) => Exposure::Auto,
+                                };
+                                println!("Exposure: {:?}", config.exposure);
+                                p3d.set_post_config(config);
+                            }
+                            Keycode::Minus | Keycode::Equals => {
+                                let mut config = *p3d.post_config();
+                                if let Exposure::Manual(e) = config.exposure {
+                                    let e = if k == Keycode::Minus { e * 0.5 } else { e * 2.0 };
+                                    config.exposure = Exposure::Manual(e);
+                                    println!("Exposure: {:?}", config.exposure);
+                                    p3d.set_post_config(config);
+                                }
+                            }
+                            Keycode::LeftBracket | Keycode::RightBracket => {
+                                let mut config = *p3d.shadow_config();
+                                if k == Keycode::LeftBracket {
+                                    config.depth_bias *= 0.5;
                                 } else {
-                                    unsafe {
-                                        gl::Uniform1i(
-                                            core::pipeline::gpu::attrs::USE_BLINN_FLAG,
-                                            1,
-                                        );
-                                    }
-                                    enable_blinn = true;
+                                    config.depth_bias *= 2.0;
                                 }
+                                println!("Shadow depth bias: {}", config.depth_bias);
+                                p3d.set_shadow_config(config);
                             }
 
                             _ => {}
@@ -328,9 +464,52 @@ fn main() -> io::Result<()> {
         p3d.update_model_matrix(susane_id, susane_model_mat);
         p3d.update_normal_matrix(susane_id, susane_normal_mat);
 
-        p3d.draw_textured_meshes();
+        // Fixed steps keep headless captures reproducible
+        let frame_time = if app_args.headless {
+            1.0 / 60.0
+        } else {
+            (delta_time - last_frame).as_secs_f32()
+        };
+        last_frame = delta_time;
+
+        p3d.render_frame(&mut graph_pool, window_size, frame_time, clear_color, dump_graph)
+            .unwrap();
+        dump_graph = false;
+
+        if print_stats {
+            println!("{:?}", p3d.frame_stats());
+        }
+
+        frame_count += 1;
+        let last_frame_reached = frame_limit.map_or(false, |n| frame_count >= n);
+
+        if take_screenshot || (last_frame_reached && app_args.screenshot.is_some()) {
+            let path = match (&app_args.screenshot, take_screenshot) {
+                (Some(p), false) => p.clone(),
+                _ => {
+                    let secs = std::time::SystemTime::now()
+                        .duration_since(std::time::UNIX_EPOCH)
+                        .map_or(0, |d| d.as_secs());
+                    PathBuf::from(format!("screenshot-{}.png", secs))
+                }
+            };
+
+            match capture::save_screenshot(&path, window_size.0, window_size.1) {
+                Ok(()) => println!("Saved screenshot: {}", path.display()),
+                Err(e) => println!("{}", e),
+            }
+        }
+
+        if last_frame_reached {
+            break 'main_loop;
+        }
 
         app.sdl_window.gl_swap_window();
+
+        if app_args.headless {
+            continue;
+        }
+
         // Limit the framerate to 60 FPS
         let time_end = timer.elapsed();
         let frame_duration = time_end - delta_time;
@@ -84,13 +84,21 @@ impl BufferLoader {
         Ok(Self { root: r })
     }
 
+    // Directory every relative path is resolved against
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
     pub fn load_bytes(&self, file_path: &Path) -> BufferLoaderResult<Vec<u8>> {
         let full_path = self
             .root
             .join(prepare_full_path!(self.root.clone(), file_path));
-        let mut file = File::open(full_path).unwrap();
         let mut data = vec![];
-        file.read_to_end(&mut data).unwrap();
+
+        if let Err(e) = File::open(full_path).and_then(|mut f| f.read_to_end(&mut data)) {
+            return buffer_load_err(Some(file_path.to_path_buf()), e);
+        }
+
         Ok(data)
     }
 
@@ -144,3 +152,34 @@ impl BufferLoader {
         Ok(Box::new(BufReader::new(file)))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn load_bytes_reports_missing_files() {
+        let root = env::temp_dir().join(format!("darkest_resource_{}", std::process::id()));
+        std::fs::create_dir_all(&root).unwrap();
+        std::fs::write(root.join("data.bin"), [1u8, 2, 3]).unwrap();
+
+        let loader = BufferLoader::with_root(root.clone()).unwrap();
+        assert_eq!(
+            loader.load_bytes(Path::new("data.bin")).unwrap(),
+            vec![1, 2, 3]
+        );
+
+        match loader.load_bytes(Path::new("missing.bin")) {
+            Err(BufferLoaderError::IoError {
+                io_error,
+                file_path,
+            }) => {
+                assert_eq!(io_error.kind(), io::ErrorKind::NotFound);
+                assert_eq!(file_path, Some(PathBuf::from("missing.bin")));
+            }
+            r => panic!("Expected an I/O error, got {:?}", r.map(|d| d.len())),
+        }
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,41 @@
+#!/bin/sh
+# Renders every scene in tests/golden on Mesa's llvmpipe and compares it
+# with its reference image. Failing scenes leave <name>.actual.png and
+# <name>.diff.png in target/golden.
+#
+# Usage: tests/golden/run.sh [--update-golden] [scene...]
+#
+# --update-golden rewrites the references instead of comparing, commit the
+# new PNGs together with the shader change that caused them. References
+# have to be produced with the software driver, images from a GPU differ.
+# The committed ones come from Mesa 22.3 llvmpipe (LLVM 15). Scenes sample
+# the DXT1 test patterns in tests/golden/textures.
+
+root=$(cd "$(dirname "$0")/../.." && pwd)
+golden_dir="$root/tests/golden"
+
+update=""
+if [ "$1" = "--update-golden" ]; then
+    update="--update-golden"
+    shift
+fi
+
+scenes="$*"
+if [ -z "$scenes" ]; then
+    scenes=$(ls "$golden_dir"/*.scene)
+fi
+
+export LIBGL_ALWAYS_SOFTWARE=1
+export GALLIUM_DRIVER=llvmpipe
+export MESA_GL_VERSION_OVERRIDE=4.5
+export MESA_GLSL_VERSION_OVERRIDE=450
+
+cd "$root" || exit 2
+cargo build --release || exit 2
+
+failed=0
+for scene in $scenes; do
+    ./target/release/darkest -d "$root" --golden "$scene" $update || failed=1
+done
+
+exit $failed